@@ -0,0 +1,57 @@
+//! Times the filters on a gray scan against the same scan stored as RGB,
+//! which the single channel path should beat about threefold. Fails when a
+//! filter isn't at least twice as fast on gray. Run with
+//! `cargo bench --bench grayscale`.
+
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, GrayImage, Luma};
+use image_denoising::algorithms::border::BorderMode;
+use image_denoising::algorithms::denoise::{denoise_image, DenoiseType};
+
+const WIDTH: u32 = 2000;
+const HEIGHT: u32 = 1500;
+const RUNS: usize = 3;
+const MIN_SPEEDUP: f64 = 2.0;
+
+// Fastest of `RUNS` runs of `denoise_type` on `img`
+fn fastest(img: &DynamicImage, denoise_type: DenoiseType) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            std::hint::black_box(denoise_image(img, denoise_type, 5, 0.1, 50, 1e-4, BorderMode::Mirror));
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    // Paper with lines of text on it, and some grain
+    let scan = GrayImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let ink = y % 40 < 12 && (x / 7 + y / 3) % 5 != 0;
+        let grain = (x.wrapping_mul(2_654_435_761) ^ y.wrapping_mul(40_503)) % 23;
+        Luma([if ink { 30 + grain } else { 220 + grain } as u8])
+    });
+    let gray = DynamicImage::ImageLuma8(scan);
+    let rgb = DynamicImage::ImageRgb8(gray.to_rgb8());
+
+    let mut slow = Vec::new();
+    for denoise_type in [DenoiseType::MeanFilter, DenoiseType::GaussianFilter, DenoiseType::MedianFilter, DenoiseType::BilateralFilter] {
+        let (gray_time, rgb_time) = (fastest(&gray, denoise_type), fastest(&rgb, denoise_type));
+        let speedup = rgb_time.as_secs_f64() / gray_time.as_secs_f64();
+        println!(
+            "{:?} {}x{}: {:.1} ms gray, {:.1} ms RGB, {:.1}x",
+            denoise_type,
+            WIDTH,
+            HEIGHT,
+            gray_time.as_secs_f64() * 1000.0,
+            rgb_time.as_secs_f64() * 1000.0,
+            speedup
+        );
+        if speedup < MIN_SPEEDUP {
+            slow.push(denoise_type);
+        }
+    }
+    assert!(slow.is_empty(), "gray scans weren't {}x faster with {:?}", MIN_SPEEDUP, slow);
+}
@@ -1,48 +1,528 @@
-use image::DynamicImage;
+use image::{DynamicImage, GenericImageView, Pixel};
 
-pub fn analyze_image(img: &DynamicImage) -> (f32, f32) {
-    let img = img.to_rgb8();
+use super::colorspace::srgb_to_linear;
+use super::denoise::DenoiseType;
+use super::focus::sharpness_metric;
+use super::sample::PixelFormat;
+use super::white_balance::WhiteBalance;
+
+/// Settings picked by `analyze_image`, along with the measurements they
+/// were derived from so the decision can be shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoAdjustment {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub denoise_type: DenoiseType,
+    pub kernel_size: usize,
+    pub sharpness: f32,
+    /// Luma percentiles mapped to black and white by the contrast stretch
+    pub black_point: u8,
+    pub white_point: u8,
+    /// Estimated standard deviation of gaussian-like noise, in 8-bit units
+    pub noise_sigma: f32,
+    /// Share of pixels that look like salt-and-pepper outliers
+    pub impulse_fraction: f32,
+    /// `sharpness_metric` of the image, low when blurry
+    pub blur_metric: f32,
+    pub color: ColorAnalysis,
+}
+
+/// Per-channel statistics and the white balance removing a color cast.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ColorAnalysis {
+    /// Red, green and blue means in 8-bit units, near-clipped pixels left out
+    pub means: [f32; 3],
+    /// Per-channel percentiles at `CLIP_FRACTION` and its complement
+    pub low: [u8; 3],
+    pub high: [u8; 3],
+    /// Color of the light estimated from the near-neutral surfaces, linear
+    /// and scaled to a mean of 1. All ones when there was too little to go on.
+    pub illuminant: [f32; 3],
+    /// Whether both the means and the illuminant stray far enough from
+    /// gray to call it a cast
+    pub cast: bool,
+    /// Correction of the cast, neutral when there is none
+    pub white_balance: WhiteBalance,
+}
+
+// Share of pixels allowed to clip at either end of the contrast stretch
+const CLIP_FRACTION: f64 = 0.01;
+// Largest contrast factor auto contrast picks, 3x is a contrast slider of 2/3
+const MAX_STRETCH: f32 = 3.0;
+// Residual statistics are gathered on at most this many pixels
+const MAX_SAMPLES: u32 = 1_000_000;
+// A pixel this far from its 3x3 median counts as an impulse outlier
+const IMPULSE_THRESHOLD: f32 = 64.0;
+// Sharpness metric above which an image is considered sharp enough
+const SHARP_METRIC: f32 = 0.01;
+// Pixels with a channel at or above this, or all below `DARK_LEVEL`, say
+// nothing reliable about the light's color
+const CLIP_LEVEL: u8 = 250;
+const DARK_LEVEL: u8 = 8;
+// Largest linear saturation, (max - min) / max, of a surface that may be
+// gray under a colored light. Saturated subjects like a red flower are
+// left out so they aren't mistaken for a cast.
+const MAX_NEUTRAL_SATURATION: f32 = 0.5;
+// Share of the pixels that have to be near-neutral for an estimate
+const MIN_NEUTRAL_FRACTION: f32 = 0.05;
+// Minkowski norm of the gray-edge estimate, high norms follow the strongest edges
+const EDGE_NORM: i32 = 6;
+// Relative deviation from gray, of the means and of the illuminant, above
+// which the image counts as having a cast
+const CAST_THRESHOLD: f32 = 0.04;
+
+pub fn analyze_image(img: &DynamicImage) -> AutoAdjustment {
+    let (black_point, white_point) = percentiles(&luma_histogram(img), CLIP_FRACTION);
+    let (brightness, contrast) = percentile_stretch(black_point, white_point);
+    let noise = analyze_noise(img);
+
+    // Median residuals are near zero except at impulses, while a mean filter
+    // smears each impulse over its neighbours. Gaussian-like noise leaves
+    // both residuals at a similar level.
+    let impulse = noise.impulse_fraction > 0.002 && noise.median_residual < 0.75 * noise.mean_residual;
+    let (denoise_type, kernel_size) = if impulse {
+        let kernel_size = if noise.impulse_fraction < 0.05 {
+            3
+        } else if noise.impulse_fraction < 0.2 {
+            5
+        } else {
+            7
+        };
+        (DenoiseType::MedianFilter, kernel_size)
+    } else if noise.sigma >= 8.0 {
+        // The kernel size is unused by non-local means, but a larger one is
+        // the better start should the user switch to another filter
+        (DenoiseType::NonLocalMeans, if noise.sigma >= 16.0 { 7 } else { 5 })
+    } else {
+        // Clean or lightly noisy: only a light blur, anything more is mush
+        (DenoiseType::GaussianFilter, if noise.sigma >= 4.0 { 5 } else { 3 })
+    };
+
+    let blur_metric = sharpness_metric(img);
+    let sharpness = (1.0 - blur_metric / SHARP_METRIC).clamp(0.0, 1.0);
+    let color = analyze_color(img);
+
+    AutoAdjustment {
+        brightness,
+        contrast,
+        denoise_type,
+        kernel_size,
+        sharpness,
+        black_point,
+        white_point,
+        noise_sigma: noise.sigma,
+        impulse_fraction: noise.impulse_fraction,
+        blur_metric,
+        color,
+    }
+}
+
+/// Looks for a color cast. The light's color is estimated with the
+/// gray-edge method (van de Weijer et al., "Edge-Based Color Constancy")
+/// on the near-neutral surfaces only: their edges average out to gray
+/// under white light whatever the scene, unlike the plain channel means,
+/// which a frame filling colored subject pulls towards its own color.
+pub fn analyze_color(img: &DynamicImage) -> ColorAnalysis {
+    let mut histograms = [[0u64; 256]; 3];
+    let mut sums = [0u64; 3];
+    let mut counted = 0u64;
+    for (_, _, pixel) in img.pixels() {
+        let rgb = pixel.to_rgb().0;
+        for (histogram, value) in histograms.iter_mut().zip(rgb) {
+            histogram[value as usize] += 1;
+        }
+        if is_usable(rgb) {
+            for (sum, value) in sums.iter_mut().zip(rgb) {
+                *sum += value as u64;
+            }
+            counted += 1;
+        }
+    }
+    let means = sums.map(|sum| sum as f32 / counted.max(1) as f32);
+    let bounds = histograms.map(|histogram| percentiles(&histogram, CLIP_FRACTION));
+
+    let mut analysis = ColorAnalysis {
+        means,
+        low: bounds.map(|(low, _)| low),
+        high: bounds.map(|(_, high)| high),
+        illuminant: [1.0; 3],
+        cast: false,
+        white_balance: WhiteBalance::default(),
+    };
+    if matches!(PixelFormat::of(img), PixelFormat::Luma8 | PixelFormat::Luma16) {
+        return analysis;
+    }
+    let Some(illuminant) = estimate_illuminant(img) else {
+        return analysis;
+    };
+    analysis.illuminant = illuminant;
+    analysis.cast = deviation(means) > CAST_THRESHOLD && deviation(illuminant) > CAST_THRESHOLD;
+    if analysis.cast {
+        analysis.white_balance = WhiteBalance::from_gains(illuminant.map(|light| 1.0 / light));
+    }
+    analysis
+}
+
+fn is_usable(rgb: [u8; 3]) -> bool {
+    rgb.iter().all(|&value| value < CLIP_LEVEL) && rgb.iter().any(|&value| value >= DARK_LEVEL)
+}
+
+// Largest relative distance of a channel from the mean of all three
+fn deviation(values: [f32; 3]) -> f32 {
+    let mean = values.iter().sum::<f32>() / 3.0;
+    values.iter().map(|value| (value / mean.max(f32::EPSILON) - 1.0).abs()).fold(0.0, f32::max)
+}
+
+// Gray-edge estimate of the light's linear color over the near-neutral
+// pixels, on a sparse grid for large images. Falls back to their mean
+// when the image has no edges. `None` when too few pixels are neutral.
+fn estimate_illuminant(img: &DynamicImage) -> Option<[f32; 3]> {
     let (width, height) = img.dimensions();
-    
-    // Calculate average brightness and standard deviation
-    let mut total_brightness = 0.0;
-    let mut total_pixels = 0;
-    let mut brightness_values = Vec::new();
-    
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let brightness = (pixel[0] as f32 + pixel[1] as f32 + pixel[2] as f32) / (3.0 * 255.0);
-            total_brightness += brightness;
-            brightness_values.push(brightness);
-            total_pixels += 1;
-        }
-    }
-    
-    let avg_brightness = total_brightness / total_pixels as f32;
-    
-    // Calculate standard deviation
-    let variance = brightness_values.iter()
-        .map(|&b| (b - avg_brightness).powi(2))
-        .sum::<f32>() / total_pixels as f32;
-    let std_dev = variance.sqrt();
-    
-    // Calculate auto brightness adjustment
-    // Target brightness is 0.5 (middle gray)
-    let brightness_adjust = (0.5 - avg_brightness) * 2.0; // Scale to [-1, 1] range
-    
-    // Calculate auto contrast adjustment
-    // Target standard deviation is 0.2
-    let contrast_adjust = if std_dev < 0.1 {
-        // Low contrast image, increase contrast
-        0.5
-    } else if std_dev > 0.3 {
-        // High contrast image, decrease contrast
-        -0.3
+    if width < 2 || height < 2 {
+        return None;
+    }
+    let linear: Vec<f32> = (0..=255).map(|value| srgb_to_linear(value as f32 / 255.0)).collect();
+    // Linear color of the pixel, if it is usable and near-neutral
+    let neutral_at = |x: u32, y: u32| {
+        let rgb = img.get_pixel(x, y).to_rgb().0;
+        let color = rgb.map(|value| linear[value as usize]);
+        let brightest = color.iter().copied().fold(0.0, f32::max);
+        let darkest = color.iter().copied().fold(1.0, f32::min);
+        (is_usable(rgb) && (brightest - darkest) / brightest <= MAX_NEUTRAL_SATURATION).then_some(color)
+    };
+
+    let step = (((width - 1) as f32 * (height - 1) as f32 / MAX_SAMPLES as f32).sqrt().ceil() as u32).max(1);
+    let mut samples = 0usize;
+    let mut neutral = 0usize;
+    let mut edges = [0.0f64; 3];
+    let mut sums = [0.0f64; 3];
+    for y in (0..height - 1).step_by(step as usize) {
+        for x in (0..width - 1).step_by(step as usize) {
+            samples += 1;
+            let Some(center) = neutral_at(x, y) else {
+                continue;
+            };
+            neutral += 1;
+            // Edges into saturated surfaces would carry their color
+            let neighbours = neutral_at(x + 1, y).zip(neutral_at(x, y + 1));
+            for c in 0..3 {
+                sums[c] += center[c] as f64;
+                if let Some((right, below)) = neighbours {
+                    let gradient = ((right[c] - center[c]).powi(2) + (below[c] - center[c]).powi(2)).sqrt();
+                    edges[c] += (gradient as f64).powi(EDGE_NORM);
+                }
+            }
+        }
+    }
+    if (neutral as f32) < MIN_NEUTRAL_FRACTION * samples as f32 {
+        return None;
+    }
+
+    let estimate = if edges.iter().all(|&energy| energy > 1e-12) {
+        edges.map(|energy| energy.powf(1.0 / EDGE_NORM as f64) as f32)
     } else {
-        // Normal contrast image, slight adjustment
-        0.1
+        sums.map(|sum| sum as f32)
+    };
+    let mean = estimate.iter().sum::<f32>() / 3.0;
+    (mean > 0.0).then(|| estimate.map(|value| value / mean))
+}
+
+// Luma histogram of the whole image
+fn luma_histogram(img: &DynamicImage) -> [u64; 256] {
+    let mut histogram = [0u64; 256];
+    for (_, _, pixel) in img.pixels() {
+        histogram[pixel.to_luma()[0] as usize] += 1;
+    }
+    histogram
+}
+
+// Luma values below which `fraction` and `1 - fraction` of the pixels lie
+fn percentiles(histogram: &[u64; 256], fraction: f64) -> (u8, u8) {
+    let total = histogram.iter().sum::<u64>().max(1) as f64;
+    let value_at = |target: f64| {
+        let mut count = 0;
+        for (value, &bin) in histogram.iter().enumerate() {
+            count += bin;
+            if count as f64 >= target {
+                return value as u8;
+            }
+        }
+        255
+    };
+    (value_at(fraction * total), value_at((1.0 - fraction) * total))
+}
+
+/// Brightness and contrast slider values stretching the luma range from
+/// `black_point` to `white_point` over the full range. The stretch is
+/// capped at `MAX_STRETCH` so nearly flat images don't turn into noise.
+pub fn percentile_stretch(black_point: u8, white_point: u8) -> (f32, f32) {
+    let (low, high) = (black_point as f32, white_point.max(black_point) as f32);
+    let factor = (255.0 / (high - low).max(1.0)).min(MAX_STRETCH);
+
+    // Contrast pivots around 128 after brightness has been added, so the
+    // middle of the range is moved there first
+    let brightness = (128.0 - (low + high) / 2.0) / 127.5;
+    // Inverse of the factor `contrast_value` derives from the slider
+    let contrast = (factor - 1.0) / 3.0;
+    (brightness.clamp(-1.0, 1.0), contrast)
+}
+
+struct NoiseStats {
+    sigma: f32,
+    impulse_fraction: f32,
+    median_residual: f32,
+    mean_residual: f32,
+}
+
+fn analyze_noise(img: &DynamicImage) -> NoiseStats {
+    let (width, height) = img.dimensions();
+    if width < 5 || height < 5 {
+        return NoiseStats {
+            sigma: 0.0,
+            impulse_fraction: 0.0,
+            median_residual: 0.0,
+            mean_residual: 0.0,
+        };
+    }
+
+    // Large images are sampled on a sparse grid, each sample still looks at
+    // its full resolution neighbourhood
+    let step = (((width - 4) as f32 * (height - 4) as f32 / MAX_SAMPLES as f32).sqrt().ceil() as u32).max(1);
+    // Read straight from the source, a converted copy of a large scan would
+    // take tens of megabytes
+    let value = |x: u32, y: u32| img.get_pixel(x, y).to_luma()[0] as f32;
+    let neighbourhood = |x: u32, y: u32| {
+        let mut values = [0.0f32; 9];
+        for (i, value_at) in values.iter_mut().enumerate() {
+            *value_at = value(x + i as u32 % 3 - 1, y + i as u32 / 3 - 1);
+        }
+        values
     };
-    
-    (brightness_adjust, contrast_adjust)
-} 
\ No newline at end of file
+    let median_at = |x: u32, y: u32| {
+        let mut values = neighbourhood(x, y);
+        values.sort_by(f32::total_cmp);
+        values[4]
+    };
+
+    let mut samples = 0;
+    let mut immerkaer_sum = 0.0;
+    let mut outliers = 0;
+    let mut median_residual_sum = 0.0;
+    let mut mean_residual_sum = 0.0;
+
+    for y in (2..height - 2).step_by(step as usize) {
+        for x in (2..width - 2).step_by(step as usize) {
+            let values = neighbourhood(x, y);
+            let center = values[4];
+
+            // Immerkær's estimator: a mask cancelling smooth image structure,
+            // leaving mostly noise
+            let corners = values[0] + values[2] + values[6] + values[8];
+            let edges = values[1] + values[3] + values[5] + values[7];
+            immerkaer_sum += (corners - 2.0 * edges + 4.0 * center).abs();
+
+            let median_residual = (center - median_at(x, y)).abs();
+            if median_residual > IMPULSE_THRESHOLD {
+                outliers += 1;
+            }
+            median_residual_sum += median_residual;
+            mean_residual_sum += (center - values.iter().sum::<f32>() / 9.0).abs();
+
+            samples += 1;
+        }
+    }
+
+    let samples_f = samples as f32;
+
+    NoiseStats {
+        sigma: (std::f32::consts::FRAC_PI_2).sqrt() * immerkaer_sum / (6.0 * samples_f),
+        impulse_fraction: outliers as f32 / samples_f,
+        median_residual: median_residual_sum / samples_f,
+        mean_residual: mean_residual_sum / samples_f,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::algorithms::benchmark::add_gaussian_noise;
+    use crate::algorithms::border::BorderMode;
+    use crate::algorithms::colorspace::linear_to_srgb;
+    use crate::algorithms::denoise::denoise_image;
+    use crate::algorithms::white_balance::apply_white_balance;
+
+    // Gray squares of 16 pixels over most of the range, sharp edged and
+    // otherwise flat, so noise and blur are all there is to find
+    fn clean() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(96, 96, |x, y| {
+            let value = if (x / 16 + y / 16) % 2 == 0 { 40 } else { 210 };
+            Rgb([value; 3])
+        }))
+    }
+
+    fn blurry() -> DynamicImage {
+        let blurred = denoise_image(&clean(), DenoiseType::GaussianFilter, 7, 0.0, 0, 0.0, BorderMode::Mirror);
+        denoise_image(&blurred, DenoiseType::GaussianFilter, 7, 0.0, 0, 0.0, BorderMode::Mirror)
+    }
+
+    // Every 10th pixel turned black or white
+    fn salt_and_pepper() -> DynamicImage {
+        let mut img = clean().to_rgb8();
+        for (i, pixel) in img.pixels_mut().enumerate().filter(|(i, _)| i % 10 == 3) {
+            *pixel = Rgb([if i % 20 == 3 { 0 } else { 255 }; 3]);
+        }
+        DynamicImage::ImageRgb8(img)
+    }
+
+    #[test]
+    fn clean_images_get_a_light_touch() {
+        let picked = analyze_image(&clean());
+        assert_eq!((picked.denoise_type, picked.kernel_size), (DenoiseType::GaussianFilter, 3));
+        assert!(picked.noise_sigma < 4.0, "{:?}", picked);
+        assert_eq!(picked.sharpness, 0.0);
+        assert!(!picked.color.cast);
+    }
+
+    #[test]
+    fn noisy_images_get_non_local_means() {
+        let picked = analyze_image(&add_gaussian_noise(&clean(), 20.0, 3));
+        assert_eq!((picked.denoise_type, picked.kernel_size), (DenoiseType::NonLocalMeans, 5));
+        // Measured on the luma, which averages the channels' independent
+        // noise down to about two thirds
+        assert!((12.0..16.0).contains(&picked.noise_sigma), "{:?}", picked);
+        // Noise isn't detail worth sharpening
+        assert_eq!(picked.sharpness, 0.0);
+    }
+
+    #[test]
+    fn salt_and_pepper_gets_a_median() {
+        let picked = analyze_image(&salt_and_pepper());
+        assert_eq!((picked.denoise_type, picked.kernel_size), (DenoiseType::MedianFilter, 5));
+        assert!((0.05..0.2).contains(&picked.impulse_fraction), "{:?}", picked);
+    }
+
+    #[test]
+    fn blurry_images_get_sharpened() {
+        let picked = analyze_image(&blurry());
+        assert!(picked.blur_metric < SHARP_METRIC, "{:?}", picked);
+        assert!(picked.sharpness > 0.2, "{:?}", picked);
+        assert_eq!(picked.denoise_type, DenoiseType::GaussianFilter);
+    }
+
+    #[test]
+    fn low_contrast_images_get_stretched() {
+        let flat = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 64, |x, _| Rgb([(110 + x / 2) as u8; 3])));
+        let picked = analyze_image(&flat);
+        assert_eq!((picked.black_point, picked.white_point), (110, 141));
+        assert!(picked.contrast > 0.5, "{:?}", picked);
+        assert!(picked.brightness.abs() < 0.02, "{:?}", picked);
+    }
+
+    // Gray image with `count` pixels of each luma value in `levels`
+    fn with_levels(levels: &[(u8, u32)]) -> DynamicImage {
+        let values: Vec<u8> = levels.iter().flat_map(|&(value, count)| std::iter::repeat_n(value, count as usize)).collect();
+        DynamicImage::ImageLuma8(image::GrayImage::from_raw(values.len() as u32, 1, values).unwrap())
+    }
+
+    #[test]
+    fn normal_histograms_are_left_alone() {
+        let levels: Vec<(u8, u32)> = (0..=255).map(|value| (value, 10)).collect();
+        let picked = analyze_image(&with_levels(&levels));
+        assert_eq!((picked.black_point, picked.white_point), (2, 253));
+        assert!(picked.contrast.abs() < 0.01, "{:?}", picked);
+        assert!(picked.brightness.abs() < 0.01, "{:?}", picked);
+    }
+
+    #[test]
+    fn low_contrast_histograms_are_stretched_to_the_full_range() {
+        let levels: Vec<(u8, u32)> = (100..=160).map(|value| (value, 10)).collect();
+        let picked = analyze_image(&with_levels(&levels));
+        assert_eq!((picked.black_point, picked.white_point), (100, 160));
+        // 60 levels spread over 255 is a factor of 4.25, capped at 3
+        assert_eq!(picked.contrast, (MAX_STRETCH - 1.0) / 3.0);
+        assert!((picked.brightness - (128.0 - 130.0) / 127.5).abs() < 1e-6, "{:?}", picked);
+    }
+
+    #[test]
+    fn bimodal_histograms_stretch_between_their_peaks() {
+        // A dark and a light peak, a few stray pixels beyond each
+        let picked = analyze_image(&with_levels(&[(5, 3), (40, 600), (120, 50), (210, 600), (250, 3)]));
+        assert_eq!((picked.black_point, picked.white_point), (40, 210));
+        assert!((picked.contrast - (255.0 / 170.0 - 1.0) / 3.0).abs() < 1e-6, "{:?}", picked);
+        assert!((picked.brightness - (128.0 - 125.0) / 127.5).abs() < 1e-6, "{:?}", picked);
+    }
+
+    #[test]
+    fn flat_images_are_not_blown_into_noise() {
+        let (_, contrast) = percentile_stretch(128, 128);
+        assert_eq!(contrast, (MAX_STRETCH - 1.0) / 3.0);
+        let (_, contrast) = percentile_stretch(200, 100);
+        assert_eq!(contrast, (MAX_STRETCH - 1.0) / 3.0);
+    }
+
+    // Squares of 16 pixels in grays over most of the range
+    fn gray_squares() -> RgbImage {
+        RgbImage::from_fn(96, 96, |x, y| Rgb([(30 + (x / 16 * 37 + y / 16 * 53) % 200) as u8; 3]))
+    }
+
+    // `img` lit by a light of linear color `light`
+    fn lit_by(img: &RgbImage, light: [f32; 3]) -> DynamicImage {
+        let mut lit = img.clone();
+        for pixel in lit.pixels_mut() {
+            for (value, gain) in pixel.0.iter_mut().zip(light) {
+                *value = (linear_to_srgb(srgb_to_linear(*value as f32 / 255.0) * gain) * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+        DynamicImage::ImageRgb8(lit)
+    }
+
+    #[test]
+    fn neutral_images_keep_unity_gains() {
+        let squares = DynamicImage::ImageRgb8(gray_squares());
+        // Noise strengthens the edges of each channel on its own, which
+        // the estimate mustn't take for a cast
+        for (img, spread) in [(squares.clone(), 0.01), (add_gaussian_noise(&squares, 6.0, 5), CAST_THRESHOLD)] {
+            let color = analyze_color(&img);
+            assert!(!color.cast, "{:?}", color);
+            for value in color.illuminant {
+                assert!((value - 1.0).abs() < spread, "{:?}", color);
+            }
+            for gain in color.white_balance.gains() {
+                assert!((gain - 1.0).abs() < 0.01, "{:?}", color);
+            }
+        }
+    }
+
+    #[test]
+    fn casts_are_measured_and_removed() {
+        let green = lit_by(&gray_squares(), [0.75, 1.25, 0.8]);
+        let color = analyze_color(&green);
+        assert!(color.cast, "{:?}", color);
+        assert!(color.white_balance.tint > 0.3, "a green cast needs magenta: {:?}", color);
+
+        let corrected = analyze_color(&apply_white_balance(&green, color.white_balance));
+        assert!(!corrected.cast, "{:?}", corrected);
+        for value in corrected.illuminant {
+            assert!((value - 1.0).abs() < 0.05, "{:?}", corrected);
+        }
+    }
+
+    #[test]
+    fn red_flowers_are_not_grayed() {
+        // Shaded red petals over nine tenths of the frame, on a gray ground
+        let flower = DynamicImage::ImageRgb8(RgbImage::from_fn(96, 96, |x, y| {
+            if y < 86 {
+                let shade = 120 + (x * 7 + y * 3) % 120;
+                Rgb([shade as u8, (shade / 8) as u8, (shade / 6) as u8])
+            } else {
+                Rgb([(60 + x) as u8; 3])
+            }
+        }));
+        let color = analyze_color(&flower);
+        assert!(color.means[0] > 3.0 * color.means[1], "{:?}", color);
+        assert!(!color.cast, "{:?}", color);
+        assert!(color.white_balance.is_neutral(), "{:?}", color);
+    }
+}
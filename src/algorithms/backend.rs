@@ -0,0 +1,90 @@
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+use super::border::BorderMode;
+use super::denoise::DenoiseType;
+use super::progress::Progress;
+use super::sharpness::SharpenKernel;
+
+/// Where the filters that have a GPU version run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Backend {
+    #[default]
+    Cpu,
+    /// Compute shaders for mean, gaussian, bilateral and sharpen, see
+    /// `gpu::filter_image`. Everything else, and everything when there is no
+    /// usable GPU, still runs on the CPU.
+    Gpu,
+}
+
+impl Backend {
+    /// Whether this build includes the GPU backend, see the `gpu` feature.
+    pub const GPU_BUILT: bool = cfg!(feature = "gpu");
+}
+
+/// A filter the GPU backend implements, with its parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "gpu"), allow(dead_code))]
+pub enum GpuFilter {
+    Mean { radius: u32 },
+    Gaussian { radius: u32 },
+    Bilateral { radius: u32 },
+    Sharpen { amount: f32, kernel: SharpenKernel },
+}
+
+/// Denoises `img` on the GPU if `backend` asks for it and `denoise_type`
+/// has a GPU version, counting as one unit of progress. Returns `None`
+/// when the CPU filter has to run instead.
+pub fn denoise_on_gpu(
+    backend: Backend,
+    img: &DynamicImage,
+    denoise_type: DenoiseType,
+    kernel_size: usize,
+    border: BorderMode,
+    progress: &Progress,
+) -> Option<DynamicImage> {
+    let radius = (kernel_size / 2) as u32;
+    let filter = match denoise_type {
+        DenoiseType::MeanFilter => GpuFilter::Mean { radius },
+        DenoiseType::GaussianFilter => GpuFilter::Gaussian { radius },
+        DenoiseType::BilateralFilter => GpuFilter::Bilateral { radius },
+        _ => return None,
+    };
+    let denoised = run_on_gpu(backend, img, filter, border, progress)?;
+    progress.add_total(1);
+    progress.advance(1);
+    Some(denoised)
+}
+
+/// Sharpens `img` on the GPU if `backend` asks for it, `None` when the CPU
+/// has to do it instead.
+pub fn sharpen_on_gpu(
+    backend: Backend,
+    img: &DynamicImage,
+    amount: f32,
+    kernel: SharpenKernel,
+    border: BorderMode,
+    progress: &Progress,
+) -> Option<DynamicImage> {
+    run_on_gpu(backend, img, GpuFilter::Sharpen { amount, kernel }, border, progress)
+}
+
+#[cfg(feature = "gpu")]
+fn run_on_gpu(backend: Backend, img: &DynamicImage, filter: GpuFilter, border: BorderMode, progress: &Progress) -> Option<DynamicImage> {
+    if backend != Backend::Gpu {
+        return None;
+    }
+    let result = super::gpu::filter_image(img, filter, border);
+    if result.is_none() {
+        progress.add_note("No usable GPU, filtered on the CPU instead".to_string());
+    }
+    result
+}
+
+#[cfg(not(feature = "gpu"))]
+fn run_on_gpu(backend: Backend, _img: &DynamicImage, _filter: GpuFilter, _border: BorderMode, progress: &Progress) -> Option<DynamicImage> {
+    if backend == Backend::Gpu {
+        progress.add_note("Built without GPU support, filtered on the CPU instead".to_string());
+    }
+    None
+}
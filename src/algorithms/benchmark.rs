@@ -0,0 +1,221 @@
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+use super::backend::Backend;
+use super::border::BorderMode;
+use super::blur::gaussian_blur;
+use super::channels::ChannelView;
+use super::denoise::DenoiseType;
+use super::pipeline::{default_detail_radius, Operation};
+use super::progress::Progress;
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// Longest side slow denoisers are benchmarked at, larger images are
+/// downscaled for them so a full comparison finishes in reasonable time.
+pub const SLOW_MAX_SIDE: u32 = 512;
+
+/// One denoiser with its parameters, as compared by `run_benchmark`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DenoiseSpec {
+    pub denoise_type: DenoiseType,
+    pub kernel_size: usize,
+    pub tv_lambda: f32,
+    pub tv_iterations: usize,
+    pub tv_tolerance: f32,
+}
+
+impl DenoiseSpec {
+    pub fn operation(&self) -> Operation {
+        Operation::Denoise {
+            denoise_type: self.denoise_type,
+            kernel_size: self.kernel_size,
+            tv_lambda: self.tv_lambda,
+            tv_iterations: self.tv_iterations,
+            tv_tolerance: self.tv_tolerance,
+            planes: None,
+            linear_light: false,
+            backend: Backend::Cpu,
+            border: BorderMode::default(),
+            detail: 0.0,
+            detail_radius: default_detail_radius(),
+        }
+    }
+}
+
+/// Outcome of running one `DenoiseSpec`.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub spec: DenoiseSpec,
+    pub image: DynamicImage,
+    pub duration: Duration,
+    /// Whether the spec ran on a copy downscaled to `SLOW_MAX_SIDE`
+    pub downscaled: bool,
+    /// Quality against the clean reference, when one was given
+    pub psnr: Option<f64>,
+    pub ssim: Option<f64>,
+}
+
+/// Runs every spec on `img` one after another, timing each. Slow denoisers
+/// run on a downscaled copy of large images, see `SLOW_MAX_SIDE`.
+///
+/// With a `reference`, the clean image `img` is a noisy copy of, results
+/// are scored against it. `progress` counts one unit per spec, cancelling
+/// takes effect between specs. Returns `None` if it was cancelled.
+pub fn run_benchmark(
+    img: &DynamicImage,
+    reference: Option<&DynamicImage>,
+    specs: &[DenoiseSpec],
+    progress: &Progress,
+) -> Option<Vec<BenchResult>> {
+    let small = (img.width().max(img.height()) > SLOW_MAX_SIDE).then(|| (slow_copy(img), reference.map(slow_copy)));
+
+    progress.add_total(specs.len());
+    let mut results = Vec::with_capacity(specs.len());
+    for spec in specs {
+        if progress.is_cancelled() {
+            return None;
+        }
+
+        let operation = spec.operation();
+        let (input, reference, downscaled) = match &small {
+            Some((small_img, small_reference)) if operation.is_expensive() => (small_img, small_reference.as_ref(), true),
+            _ => (img, reference, false),
+        };
+
+        let start_time = Instant::now();
+        let image = operation
+            .apply_with_progress(input, &Progress::new())
+            .expect("runs without a cancel request always complete");
+        let duration = start_time.elapsed();
+
+        results.push(BenchResult {
+            spec: *spec,
+            psnr: reference.map(|reference| psnr(&image, reference)),
+            ssim: reference.map(|reference| ssim(&image, reference)),
+            image,
+            duration,
+            downscaled,
+        });
+        progress.advance(1);
+    }
+    Some(results)
+}
+
+/// The copy of a large `img` slow denoisers run on, see `SLOW_MAX_SIDE`.
+pub fn slow_copy(img: &DynamicImage) -> DynamicImage {
+    img.resize(SLOW_MAX_SIDE, SLOW_MAX_SIDE, FilterType::Triangle)
+}
+
+/// The results as comma separated values with a header line.
+pub fn results_to_csv(results: &[BenchResult]) -> String {
+    let score = |value: Option<f64>, precision: usize| value.map(|value| format!("{:.*}", precision, value)).unwrap_or_default();
+    let mut csv = String::from("algorithm,seconds,width,height,psnr_db,ssim\n");
+    for result in results {
+        csv.push_str(&format!(
+            "{:?},{:.4},{},{},{},{}\n",
+            result.spec.denoise_type,
+            result.duration.as_secs_f64(),
+            result.image.width(),
+            result.image.height(),
+            score(result.psnr, 2),
+            score(result.ssim, 4),
+        ));
+    }
+    csv
+}
+
+/// Peak signal to noise ratio in dB over all color channels, infinite for
+/// identical images. Both images must have the same dimensions.
+pub fn psnr(img: &DynamicImage, reference: &DynamicImage) -> f64 {
+    let (a, b) = (img.to_rgb32f(), reference.to_rgb32f());
+    let squared_error: f64 = a
+        .as_raw()
+        .iter()
+        .zip(b.as_raw())
+        .map(|(&a, &b)| ((a - b) as f64).powi(2))
+        .sum();
+    let mse = squared_error / a.as_raw().len() as f64;
+    10.0 * (1.0 / mse).log10()
+}
+
+/// Mean structural similarity of the luma of two images, 1 for identical
+/// images, using the usual gaussian window of standard deviation 1.5.
+/// Both images must have the same dimensions.
+pub fn ssim(img: &DynamicImage, reference: &DynamicImage) -> f64 {
+    const SIGMA: f32 = 1.5;
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    let (width, height) = (img.width(), img.height());
+    let x = img.to_luma32f().into_raw();
+    let y = reference.to_luma32f().into_raw();
+    let local_mean = |plane: &[f32]| gaussian_blur(plane, width, height, SIGMA);
+    let product = |a: &[f32], b: &[f32]| -> Vec<f32> { a.iter().zip(b).map(|(a, b)| a * b).collect() };
+
+    let (mean_x, mean_y) = (local_mean(&x), local_mean(&y));
+    let mean_xx = local_mean(&product(&x, &x));
+    let mean_yy = local_mean(&product(&y, &y));
+    let mean_xy = local_mean(&product(&x, &y));
+
+    let total: f64 = (0..x.len())
+        .map(|i| {
+            let (mx, my) = (mean_x[i], mean_y[i]);
+            let variance_x = mean_xx[i] - mx * mx;
+            let variance_y = mean_yy[i] - my * my;
+            let covariance = mean_xy[i] - mx * my;
+            let ssim = ((2.0 * mx * my + C1) * (2.0 * covariance + C2))
+                / ((mx * mx + my * my + C1) * (variance_x + variance_y + C2));
+            ssim as f64
+        })
+        .sum();
+    total / x.len() as f64
+}
+
+/// PSNR and SSIM of `img` against `reference` in `channel` alone, the
+/// plain `psnr` and `ssim` for `ChannelView::Rgb`.
+pub fn channel_scores(img: &DynamicImage, reference: &DynamicImage, channel: ChannelView) -> (f64, f64) {
+    if channel == ChannelView::Rgb {
+        return (psnr(img, reference), ssim(img, reference));
+    }
+    let (img, reference) = (channel.extract(img), channel.extract(reference));
+    (psnr(&img, &reference), ssim(&img, &reference))
+}
+
+/// Adds gaussian noise of standard deviation `sigma`, in 8-bit units, to
+/// every channel. The same `seed` always gives the same noise.
+pub fn add_gaussian_noise(img: &DynamicImage, sigma: f32, seed: u64) -> DynamicImage {
+    with_pixel_type!(img, |P| add_noise::<P>(img, sigma, seed))
+}
+
+fn add_noise<P: FilterPixel>(img: &DynamicImage, sigma: f32, seed: u64) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let mut buffer = P::from_dynamic(img);
+    let sigma = sigma * P::Subpixel::scale();
+    let mut state = seed;
+    for value in buffer.iter_mut() {
+        let noisy = value.to_f32() + sigma * standard_normal(&mut state);
+        *value = P::Subpixel::from_f32(noisy.round());
+    }
+    P::into_dynamic(buffer)
+}
+
+// Box-Muller transform of two uniform samples
+fn standard_normal(state: &mut u64) -> f32 {
+    let (u1, u2) = (uniform(state), uniform(state));
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+// SplitMix64, mapped to the open interval (0, 1)
+fn uniform(state: &mut u64) -> f32 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    ((z >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+}
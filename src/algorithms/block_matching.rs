@@ -0,0 +1,298 @@
+use std::f32::consts::PI;
+
+use super::progress::Progress;
+use super::sample::{Buffer, FilterPixel, Sample};
+
+/// Side of the square patches that are matched and transformed.
+pub const PATCH_SIZE: usize = 8;
+/// How far, in pixels, similar patches are looked for around a reference patch.
+pub const SEARCH_RADIUS: usize = 8;
+// Distance between reference patches, smaller is slower and smoother
+const STEP: usize = 3;
+// Most patches stacked into one group, a power of two for the Haar transform
+const MAX_GROUP: usize = 16;
+// Coefficients below this many noise sigmas are dropped
+const THRESHOLD: f32 = 2.7;
+// Two noisy copies of the same patch differ by 2 sigma² per pixel on
+// average, patches much further apart than that show different content
+const MATCH_FACTOR: f32 = 3.0;
+
+type Patch = [f32; PATCH_SIZE * PATCH_SIZE];
+
+/// Block-matching denoiser after BM3D, first (hard thresholding) stage only.
+///
+/// For each reference patch the most similar patches nearby are stacked
+/// into a group, transformed with a 2D DCT per patch and a Haar transform
+/// across the stack, and coefficients below the noise floor are zeroed.
+/// Repeating structure shows up in few large coefficients, noise spreads
+/// over all of them. The filtered patches are put back weighted by how
+/// sparse their group was.
+///
+/// The threshold follows the noise level estimated from the image itself.
+/// Color images are matched on luma and filtered as Y/Cb/Cr planes.
+pub fn block_matching<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
+    width: u32,
+    height: u32,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    let (width, height) = (width as usize, height as usize);
+    if width < PATCH_SIZE || height < PATCH_SIZE {
+        new_img.clone_from(img);
+        return;
+    }
+
+    let planes = to_planes(img);
+    let sigmas: Vec<f32> = planes.iter().map(|plane| noise_sigma(plane, width, height)).collect();
+    let max_distance = MATCH_FACTOR * sigmas[0] * sigmas[0];
+    let basis = dct_basis();
+
+    let mut sums = vec![vec![0.0f32; width * height]; planes.len()];
+    let mut weights = vec![vec![0.0f32; width * height]; planes.len()];
+
+    let rows = reference_positions(height);
+    let columns = reference_positions(width);
+    progress.add_total(rows.len());
+    for &y in &rows {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        for &x in &columns {
+            let group = find_similar(&planes[0], width, height, x, y, max_distance);
+
+            for (c, plane) in planes.iter().enumerate() {
+                let mut stack: Vec<Patch> = group
+                    .iter()
+                    .map(|&(px, py)| dct_2d(&basis, &extract(plane, width, px, py), false))
+                    .collect();
+                haar(&mut stack);
+
+                let threshold = THRESHOLD * sigmas[c];
+                let mut kept = 0;
+                for (k, patch) in stack.iter_mut().enumerate() {
+                    for (i, coefficient) in patch.iter_mut().enumerate() {
+                        // The mean of the group is never noise
+                        if (k, i) != (0, 0) && coefficient.abs() < threshold {
+                            *coefficient = 0.0;
+                        } else {
+                            kept += 1;
+                        }
+                    }
+                }
+                haar(&mut stack);
+
+                let weight = 1.0 / kept as f32;
+                for (patch, &(px, py)) in stack.iter().zip(&group) {
+                    let patch = dct_2d(&basis, patch, true);
+                    for (row, values) in patch.chunks_exact(PATCH_SIZE).enumerate() {
+                        let start = (py + row) * width + px;
+                        for (i, &value) in values.iter().enumerate() {
+                            sums[c][start + i] += weight * value;
+                            weights[c][start + i] += weight;
+                        }
+                    }
+                }
+            }
+        }
+        progress.advance(1);
+    }
+
+    let planes: Vec<Vec<f32>> = sums
+        .iter()
+        .zip(&weights)
+        .map(|(sums, weights)| sums.iter().zip(weights).map(|(sum, weight)| sum / weight).collect())
+        .collect();
+    from_planes(&planes, new_img);
+}
+
+// Top/left corners of the reference patches along one axis. The last patch
+// is moved flush with the border so every pixel is covered.
+fn reference_positions(len: usize) -> Vec<usize> {
+    let last = len - PATCH_SIZE;
+    let mut positions: Vec<usize> = (0..=last).step_by(STEP).collect();
+    if positions.last() != Some(&last) {
+        positions.push(last);
+    }
+    positions
+}
+
+// The patches within the search window closest to the one at (x, y), which
+// is always the first, as many as fit a power of two group
+fn find_similar(plane: &[f32], width: usize, height: usize, x: usize, y: usize, max_distance: f32) -> Vec<(usize, usize)> {
+    let reference = extract(plane, width, x, y);
+    let mut candidates = Vec::new();
+    for cy in y.saturating_sub(SEARCH_RADIUS)..=(y + SEARCH_RADIUS).min(height - PATCH_SIZE) {
+        for cx in x.saturating_sub(SEARCH_RADIUS)..=(x + SEARCH_RADIUS).min(width - PATCH_SIZE) {
+            if (cx, cy) == (x, y) {
+                continue;
+            }
+            let distance = distance(plane, width, &reference, cx, cy, max_distance);
+            if distance <= max_distance {
+                candidates.push((distance, cx, cy));
+            }
+        }
+    }
+
+    let size = (candidates.len() + 1).min(MAX_GROUP);
+    let size = 1 << size.ilog2();
+    if size > 1 && candidates.len() > size - 1 {
+        candidates.select_nth_unstable_by(size - 2, |a, b| a.0.total_cmp(&b.0));
+    }
+
+    let mut group = vec![(x, y)];
+    group.extend(candidates[..size - 1].iter().map(|&(_, cx, cy)| (cx, cy)));
+    group
+}
+
+// Mean squared difference per pixel, giving up once it exceeds `limit`
+fn distance(plane: &[f32], width: usize, reference: &Patch, x: usize, y: usize, limit: f32) -> f32 {
+    let limit = limit * (PATCH_SIZE * PATCH_SIZE) as f32;
+    let mut sum = 0.0;
+    for (row, expected) in reference.chunks_exact(PATCH_SIZE).enumerate() {
+        let start = (y + row) * width + x;
+        for (&value, &expected) in plane[start..start + PATCH_SIZE].iter().zip(expected) {
+            sum += (value - expected) * (value - expected);
+        }
+        if sum > limit {
+            return f32::INFINITY;
+        }
+    }
+    sum / (PATCH_SIZE * PATCH_SIZE) as f32
+}
+
+fn extract(plane: &[f32], width: usize, x: usize, y: usize) -> Patch {
+    let mut patch = [0.0; PATCH_SIZE * PATCH_SIZE];
+    for (row, values) in patch.chunks_exact_mut(PATCH_SIZE).enumerate() {
+        let start = (y + row) * width + x;
+        values.copy_from_slice(&plane[start..start + PATCH_SIZE]);
+    }
+    patch
+}
+
+// Orthonormal DCT-II matrix, `basis[k][n]` weighs sample n for frequency k
+fn dct_basis() -> [[f32; PATCH_SIZE]; PATCH_SIZE] {
+    let n = PATCH_SIZE as f32;
+    let mut basis = [[0.0; PATCH_SIZE]; PATCH_SIZE];
+    for (k, row) in basis.iter_mut().enumerate() {
+        let scale = if k == 0 { (1.0 / n).sqrt() } else { (2.0 / n).sqrt() };
+        for (i, value) in row.iter_mut().enumerate() {
+            *value = scale * (PI * (2 * i + 1) as f32 * k as f32 / (2.0 * n)).cos();
+        }
+    }
+    basis
+}
+
+// Separable 2D DCT of a patch, or its inverse
+fn dct_2d(basis: &[[f32; PATCH_SIZE]; PATCH_SIZE], patch: &Patch, inverse: bool) -> Patch {
+    let rows = transform_rows(basis, patch, inverse);
+    let columns = transform_rows(basis, &transpose(&rows), inverse);
+    transpose(&columns)
+}
+
+fn transform_rows(basis: &[[f32; PATCH_SIZE]; PATCH_SIZE], patch: &Patch, inverse: bool) -> Patch {
+    let mut result = [0.0; PATCH_SIZE * PATCH_SIZE];
+    for (input, output) in patch.chunks_exact(PATCH_SIZE).zip(result.chunks_exact_mut(PATCH_SIZE)) {
+        for (k, value) in output.iter_mut().enumerate() {
+            *value = if inverse {
+                input.iter().zip(basis).map(|(&coefficient, row)| coefficient * row[k]).sum()
+            } else {
+                input.iter().zip(&basis[k]).map(|(&sample, &weight)| sample * weight).sum()
+            };
+        }
+    }
+    result
+}
+
+fn transpose(patch: &Patch) -> Patch {
+    let mut result = [0.0; PATCH_SIZE * PATCH_SIZE];
+    for (i, value) in result.iter_mut().enumerate() {
+        *value = patch[(i % PATCH_SIZE) * PATCH_SIZE + i / PATCH_SIZE];
+    }
+    result
+}
+
+// Orthonormal Haar (Walsh-Hadamard) transform across a power of two stack
+// of patches. It is its own inverse.
+fn haar(stack: &mut [Patch]) {
+    let len = stack.len();
+    let mut half = 1;
+    while half < len {
+        for start in (0..len).step_by(2 * half) {
+            for i in start..start + half {
+                let (low, high) = stack.split_at_mut(i + half);
+                for (a, b) in low[i].iter_mut().zip(high[0].iter_mut()) {
+                    (*a, *b) = (*a + *b, *a - *b);
+                }
+            }
+        }
+        half *= 2;
+    }
+
+    let scale = 1.0 / (len as f32).sqrt();
+    for value in stack.iter_mut().flatten() {
+        *value *= scale;
+    }
+}
+
+// Immerkær's noise estimate over the whole plane
+fn noise_sigma(plane: &[f32], width: usize, height: usize) -> f32 {
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: usize, y: usize| plane[y * width + x];
+    let mut sum = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let corners = at(x - 1, y - 1) + at(x + 1, y - 1) + at(x - 1, y + 1) + at(x + 1, y + 1);
+            let edges = at(x, y - 1) + at(x - 1, y) + at(x + 1, y) + at(x, y + 1);
+            sum += (corners - 2.0 * edges + 4.0 * at(x, y)).abs() as f64;
+        }
+    }
+    (std::f32::consts::FRAC_PI_2).sqrt() * sum as f32 / (6.0 * ((width - 2) * (height - 2)) as f32)
+}
+
+// One plane per channel, color split into BT.601 luma and chroma so the
+// chroma planes can be filtered with their own, usually higher, threshold
+fn to_planes<P: FilterPixel>(img: &Buffer<P>) -> Vec<Vec<f32>>
+where
+    P::Subpixel: Sample,
+{
+    if P::CHANNEL_COUNT == 1 {
+        return vec![img.as_raw().iter().map(|value| value.to_f32()).collect()];
+    }
+
+    let len = (img.width() * img.height()) as usize;
+    let mut planes: Vec<Vec<f32>> = (0..3).map(|_| Vec::with_capacity(len)).collect();
+    for pixel in img.as_raw().chunks_exact(3) {
+        let [r, g, b] = [pixel[0].to_f32(), pixel[1].to_f32(), pixel[2].to_f32()];
+        planes[0].push(0.299 * r + 0.587 * g + 0.114 * b);
+        planes[1].push(-0.168736 * r - 0.331264 * g + 0.5 * b);
+        planes[2].push(0.5 * r - 0.418688 * g - 0.081312 * b);
+    }
+    planes
+}
+
+fn from_planes<P: FilterPixel>(planes: &[Vec<f32>], new_img: &mut Buffer<P>)
+where
+    P::Subpixel: Sample,
+{
+    let round = |value: f32| P::Subpixel::from_f32(value.round());
+    if P::CHANNEL_COUNT == 1 {
+        for (output, &value) in new_img.iter_mut().zip(&planes[0]) {
+            *output = round(value);
+        }
+        return;
+    }
+
+    for (i, output) in new_img.chunks_exact_mut(3).enumerate() {
+        let (luma, cb, cr) = (planes[0][i], planes[1][i], planes[2][i]);
+        output[0] = round(luma + 1.402 * cr);
+        output[1] = round(luma - 0.344136 * cb - 0.714136 * cr);
+        output[2] = round(luma + 1.772 * cb);
+    }
+}
@@ -0,0 +1,108 @@
+use rayon::prelude::*;
+
+/// Blurs a single-channel plane of `width` x `height` values with a gaussian
+/// of standard deviation `sigma`, as a horizontal then a vertical pass.
+/// Values beyond the edges repeat the nearest edge value.
+pub fn gaussian_blur(plane: &[f32], width: u32, height: u32, sigma: f32) -> Vec<f32> {
+    if sigma <= 0.0 || plane.is_empty() {
+        return plane.to_vec();
+    }
+
+    let kernel = gaussian_kernel(sigma);
+    let radius = (kernel.len() / 2) as i64;
+    let (width, height) = (width as usize, height as usize);
+    let clamp = |value: i64, len: usize| value.clamp(0, len as i64 - 1) as usize;
+
+    // Rows are independent in both passes
+    let mut horizontal = vec![0.0; plane.len()];
+    horizontal.par_chunks_mut(width).enumerate().for_each(|(y, out)| {
+        let row = &plane[y * width..(y + 1) * width];
+        for (x, value) in out.iter_mut().enumerate() {
+            *value = kernel
+                .iter()
+                .enumerate()
+                .map(|(i, weight)| row[clamp(x as i64 + i as i64 - radius, width)] * weight)
+                .sum();
+        }
+    });
+
+    let mut result = vec![0.0; plane.len()];
+    result.par_chunks_mut(width).enumerate().for_each(|(y, out)| {
+        for (x, value) in out.iter_mut().enumerate() {
+            *value = kernel
+                .iter()
+                .enumerate()
+                .map(|(i, weight)| horizontal[clamp(y as i64 + i as i64 - radius, height) * width + x] * weight)
+                .sum();
+        }
+    });
+    result
+}
+
+// Normalized 1D kernel reaching out to three standard deviations
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as i32;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    kernel.into_iter().map(|weight| weight / sum).collect()
+}
+
+/// Averages each value of a `width` x `height` plane with its neighbours
+/// up to `radius` away in both directions. Windows are cut off at the
+/// edges and average only the values inside the image.
+pub fn box_blur(plane: &[f32], width: u32, height: u32, radius: u32) -> Vec<f32> {
+    let horizontal = box_blur_pass(plane, width as usize, height as usize, radius as usize, 1, width as usize);
+    box_blur_pass(&horizontal, height as usize, width as usize, radius as usize, width as usize, 1)
+}
+
+// Running-sum box blur along one axis. `len` values are `step` apart in
+// each of `lines` lines, which start `line_step` apart.
+fn box_blur_pass(plane: &[f32], len: usize, lines: usize, radius: usize, step: usize, line_step: usize) -> Vec<f32> {
+    let mut result = vec![0.0; plane.len()];
+    for line in 0..lines {
+        let at = |i: usize| line * line_step + i * step;
+        // Sum in f64 so long lines don't accumulate rounding drift
+        let mut sum: f64 = (0..radius.min(len - 1) + 1).map(|i| plane[at(i)] as f64).sum();
+        for i in 0..len {
+            let first = i.saturating_sub(radius);
+            let last = (i + radius).min(len - 1);
+            result[at(i)] = (sum / (last - first + 1) as f64) as f32;
+            if i + radius + 1 < len {
+                sum += plane[at(i + radius + 1)] as f64;
+            }
+            if i >= radius {
+                sum -= plane[at(i - radius)] as f64;
+            }
+        }
+    }
+    result
+}
+
+/// Edge-preserving smoothing of `input` steered by the edges of `guide`
+/// (He et al., "Guided Image Filtering"). Larger `eps` smooths more across
+/// weak edges of the guide.
+pub fn guided_filter(guide: &[f32], input: &[f32], width: u32, height: u32, radius: u32, eps: f32) -> Vec<f32> {
+    let mean = |plane: &[f32]| box_blur(plane, width, height, radius);
+    let product = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).collect::<Vec<f32>>();
+
+    let mean_guide = mean(guide);
+    let mean_input = mean(input);
+    let mean_guide_input = mean(&product(guide, input));
+    let mean_guide_squared = mean(&product(guide, guide));
+
+    // Per window, the input is modelled as a * guide + b
+    let mut a = vec![0.0; guide.len()];
+    let mut b = vec![0.0; guide.len()];
+    for i in 0..guide.len() {
+        let covariance = mean_guide_input[i] - mean_guide[i] * mean_input[i];
+        let variance = mean_guide_squared[i] - mean_guide[i] * mean_guide[i];
+        a[i] = covariance / (variance + eps);
+        b[i] = mean_input[i] - a[i] * mean_guide[i];
+    }
+
+    let mean_a = mean(&a);
+    let mean_b = mean(&b);
+    (0..guide.len()).map(|i| mean_a[i] * guide[i] + mean_b[i]).collect()
+}
@@ -0,0 +1,101 @@
+use image::Pixel;
+use serde::{Deserialize, Serialize};
+
+use super::sample::Buffer;
+
+/// What the neighbourhood filters read for pixels beyond the image edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BorderMode {
+    /// The nearest edge pixel
+    Clamp,
+    /// The image reflected at its edge, with the edge pixel repeated: -1
+    /// reads 0 and -2 reads 1
+    #[default]
+    Mirror,
+    /// The opposite edge, as if the image were tiled
+    Wrap,
+    /// Nothing, the filter only averages over the neighbours inside the
+    /// image and normalizes by what it read
+    Skip,
+}
+
+impl BorderMode {
+    pub const ALL: [BorderMode; 4] = [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Skip];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BorderMode::Clamp => "Clamp",
+            BorderMode::Mirror => "Mirror",
+            BorderMode::Wrap => "Wrap",
+            BorderMode::Skip => "Skip",
+        }
+    }
+
+    /// Coordinate read for `coordinate` on an axis `len` pixels long, `None`
+    /// when it lies outside and is skipped. Works however far outside it is.
+    pub fn source(self, coordinate: i32, len: u32) -> Option<u32> {
+        let len = len as i32;
+        if (0..len).contains(&coordinate) {
+            return Some(coordinate as u32);
+        }
+        let source = match self {
+            BorderMode::Clamp => coordinate.clamp(0, len - 1),
+            BorderMode::Mirror => {
+                let folded = coordinate.rem_euclid(2 * len);
+                if folded < len { folded } else { 2 * len - 1 - folded }
+            }
+            BorderMode::Wrap => coordinate.rem_euclid(len),
+            BorderMode::Skip => return None,
+        };
+        Some(source as u32)
+    }
+
+    /// Like `source`, for filters that need a value everywhere: skipped
+    /// pixels read the mirrored ones, it is up to the filter to leave them
+    /// out of its result.
+    pub fn padded(self, coordinate: i32, len: u32) -> u32 {
+        self.source(coordinate, len)
+            .or_else(|| BorderMode::Mirror.source(coordinate, len))
+            .expect("mirroring always finds a pixel")
+    }
+
+    /// `source` of every coordinate from `-radius` to `len + radius - 1`,
+    /// all a filter reaching `radius` pixels reads on the axis. Coordinate
+    /// `c` is at index `c + radius`.
+    pub fn sources(self, len: u32, radius: usize) -> Vec<Option<u32>> {
+        let radius = radius as i32;
+        (-radius..len as i32 + radius).map(|coordinate| self.source(coordinate, len)).collect()
+    }
+
+    /// The pixel of `img` read at (`x`, `y`), `None` when it is skipped.
+    pub fn pixel<P: Pixel>(self, img: &Buffer<P>, x: i32, y: i32) -> Option<&P> {
+        let x = self.source(x, img.width())?;
+        let y = self.source(y, img.height())?;
+        Some(img.get_pixel(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_on_a_short_axis() {
+        // Coordinates -5 to 8 on an axis of 4 pixels
+        let sources = |mode: BorderMode| (-5..=8).map(|coordinate| mode.source(coordinate, 4)).collect::<Vec<_>>();
+        let all = |values: [u32; 14]| values.map(Some).to_vec();
+        assert_eq!(sources(BorderMode::Clamp), all([0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3]));
+        assert_eq!(sources(BorderMode::Mirror), all([3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0]));
+        assert_eq!(sources(BorderMode::Wrap), all([3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0]));
+        let inside = |coordinate: i32| (0..4).contains(&coordinate).then_some(coordinate as u32);
+        assert_eq!(sources(BorderMode::Skip), (-5..=8).map(inside).collect::<Vec<_>>());
+        assert_eq!(BorderMode::Skip.padded(-2, 4), 1);
+    }
+
+    #[test]
+    fn single_pixel_axes_always_read_it() {
+        for mode in [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap] {
+            assert!((-3..=3).all(|coordinate| mode.source(coordinate, 1) == Some(0)), "{:?}", mode);
+        }
+    }
+}
@@ -1,22 +1,36 @@
-use image::{DynamicImage, Rgb, ImageBuffer};
-
-pub fn adjust_brightness(img: &DynamicImage, brightness: f32) -> DynamicImage {
-    let img = img.to_rgb8();
-    let (width, height) = img.dimensions();
-    let mut new_img = ImageBuffer::new(width, height);
+use super::sample::Sample;
 
+/// `value` shifted by `brightness` (-1 to 1), where ±1 moves it by half the
+/// sample range.
+pub fn brightness_value<S: Sample>(value: S, brightness: f32) -> S {
     // Scale brightness from [-1, 1] to [-0.5, 0.5]
     let scaled_brightness = brightness * 0.5;
+    let offset = scaled_brightness * S::MAX_VALUE;
+    S::from_f32(value.to_f32() + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, ImageBuffer, Rgb};
+
+    use super::*;
+    use crate::algorithms::point_ops::{apply_point_ops, PointOp, PointOps};
 
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = img.get_pixel(x, y);
-            let r = (pixel[0] as f32 + scaled_brightness * 255.0).clamp(0.0, 255.0) as u8;
-            let g = (pixel[1] as f32 + scaled_brightness * 255.0).clamp(0.0, 255.0) as u8;
-            let b = (pixel[2] as f32 + scaled_brightness * 255.0).clamp(0.0, 255.0) as u8;
-            new_img.put_pixel(x, y, Rgb([r, g, b]));
+    #[test]
+    fn sixteen_bit_gradients_keep_every_level() {
+        // 4000 levels across a span 8-bit would squeeze into 61
+        let gradient = ImageBuffer::from_fn(4000, 2, |x, _| Rgb([20_000 + x as u16, 20_000 + x as u16, 30_000 - x as u16]));
+        let brightened = apply_point_ops(DynamicImage::ImageRgb16(gradient.clone()), &PointOps(vec![PointOp::Brightness(0.1)]));
+        let DynamicImage::ImageRgb16(brightened) = brightened else {
+            panic!("brightness reduced the gradient to {:?}", brightened.color());
+        };
+
+        let offset = brightness_value(0u16, 0.1);
+        assert_eq!(offset, 3276);
+        for (before, after) in gradient.pixels().zip(brightened.pixels()) {
+            for channel in 0..3 {
+                assert_eq!(after[channel], before[channel] + offset);
+            }
         }
     }
-
-    DynamicImage::ImageRgb8(new_img)
-} 
\ No newline at end of file
+}
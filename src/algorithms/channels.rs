@@ -0,0 +1,85 @@
+use image::{DynamicImage, ImageBuffer};
+
+use super::sample::{is_high_depth, Sample};
+
+/// What the image panes show: the image itself, or one of its channels in
+/// gray to see which one carries the noise. Display only, processing always
+/// works on the full image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelView {
+    #[default]
+    Rgb,
+    Red,
+    Green,
+    Blue,
+    /// Full range BT.601 luma, the plane separate-plane denoising filters
+    /// as luminance, see `rgb_to_ycbcr`
+    Luma,
+    /// Blue and red difference chroma, neutral at mid-gray
+    Cb,
+    Cr,
+}
+
+impl ChannelView {
+    pub const ALL: [ChannelView; 7] = [
+        ChannelView::Rgb,
+        ChannelView::Red,
+        ChannelView::Green,
+        ChannelView::Blue,
+        ChannelView::Luma,
+        ChannelView::Cb,
+        ChannelView::Cr,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ChannelView::Rgb => "RGB",
+            ChannelView::Red => "R",
+            ChannelView::Green => "G",
+            ChannelView::Blue => "B",
+            ChannelView::Luma => "Luma",
+            ChannelView::Cb => "Cb",
+            ChannelView::Cr => "Cr",
+        }
+    }
+
+    /// The channel of `img` as a gray image of the same bit depth, `img`
+    /// itself for `Rgb`. Gray images have the same value in every color
+    /// channel and neutral chroma.
+    pub fn extract(self, img: &DynamicImage) -> DynamicImage {
+        let (width, height) = (img.width(), img.height());
+        match self {
+            ChannelView::Rgb => img.clone(),
+            _ if is_high_depth(img) => {
+                let samples = self.extract_samples(img.to_rgb16().as_raw());
+                DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, samples).unwrap())
+            }
+            _ => {
+                let samples = self.extract_samples(img.to_rgb8().as_raw());
+                DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, samples).unwrap())
+            }
+        }
+    }
+
+    // One sample per pixel of the interleaved RGB `samples`
+    fn extract_samples<S: Sample>(self, samples: &[S]) -> Vec<S> {
+        // 128 for 8 bits, as in JPEG
+        let mid = (S::MAX_VALUE + 1.0) / 2.0;
+        samples
+            .chunks_exact(3)
+            .map(|pixel| {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(Sample::to_f32);
+                // The same coefficients as `rgb_to_ycbcr`
+                let value = match self {
+                    ChannelView::Rgb | ChannelView::Luma => 0.299 * r + 0.587 * g + 0.114 * b,
+                    ChannelView::Red => r,
+                    ChannelView::Green => g,
+                    ChannelView::Blue => b,
+                    ChannelView::Cb => mid - 0.168736 * r - 0.331264 * g + 0.5 * b,
+                    ChannelView::Cr => mid + 0.5 * r - 0.418688 * g - 0.081312 * b,
+                };
+                S::from_f32(value.round())
+            })
+            .collect()
+    }
+}
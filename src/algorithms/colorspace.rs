@@ -0,0 +1,210 @@
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, Rgb, Rgb32FImage};
+
+use super::sample::PixelFormat;
+
+/// An image split into full range BT.601 luma and chroma planes, as used by
+/// JPEG. All values are 0-1, with neutral chroma at 0.5.
+pub struct YCbCrPlanes {
+    pub width: u32,
+    pub height: u32,
+    pub y: Vec<f32>,
+    pub cb: Vec<f32>,
+    pub cr: Vec<f32>,
+}
+
+pub fn rgb_to_ycbcr(img: &Rgb32FImage) -> YCbCrPlanes {
+    let len = (img.width() * img.height()) as usize;
+    let mut planes = YCbCrPlanes {
+        width: img.width(),
+        height: img.height(),
+        y: Vec::with_capacity(len),
+        cb: Vec::with_capacity(len),
+        cr: Vec::with_capacity(len),
+    };
+    for &Rgb([r, g, b]) in img.pixels() {
+        planes.y.push(0.299 * r + 0.587 * g + 0.114 * b);
+        planes.cb.push(0.5 - 0.168736 * r - 0.331264 * g + 0.5 * b);
+        planes.cr.push(0.5 + 0.5 * r - 0.418688 * g - 0.081312 * b);
+    }
+    planes
+}
+
+pub fn ycbcr_to_rgb(planes: &YCbCrPlanes) -> Rgb32FImage {
+    ImageBuffer::from_fn(planes.width, planes.height, |x, y| {
+        let i = (y * planes.width + x) as usize;
+        let (luma, cb, cr) = (planes.y[i], planes.cb[i] - 0.5, planes.cr[i] - 0.5);
+        Rgb([
+            luma + 1.402 * cr,
+            luma - 0.344136 * cb - 0.714136 * cr,
+            luma + 1.772 * cb,
+        ])
+    })
+}
+
+/// The BT.601 luma of `img`, the Y of `rgb_to_ycbcr`, at its bit depth.
+/// Gray images are returned as they are, the alpha channel is dropped.
+pub fn to_grayscale(img: &DynamicImage) -> DynamicImage {
+    let luma = |[r, g, b]: [f32; 3]| 0.299 * r + 0.587 * g + 0.114 * b;
+    match PixelFormat::of(img) {
+        PixelFormat::Luma8 | PixelFormat::Luma16 => img.clone(),
+        PixelFormat::Rgb8 => {
+            let rgb = img.to_rgb8();
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+                Luma([luma(rgb.get_pixel(x, y).0.map(f32::from)).round() as u8])
+            }))
+        }
+        PixelFormat::Rgb16 => {
+            let rgb = img.to_rgb16();
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(rgb.width(), rgb.height(), |x, y| {
+                Luma([luma(rgb.get_pixel(x, y).0.map(f32::from)).round() as u16])
+            }))
+        }
+    }
+}
+
+/// A 0-1 plane as a 16-bit gray image, so the filters can run on it
+/// without losing noticeable precision.
+pub fn plane_to_image(plane: &[f32], width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| {
+        Luma([(plane[(y * width + x) as usize].clamp(0.0, 1.0) * 65535.0).round() as u16])
+    }))
+}
+
+pub fn image_to_plane(img: &DynamicImage) -> Vec<f32> {
+    img.to_luma16().into_raw().into_iter().map(|value| value as f32 / 65535.0).collect()
+}
+
+/// Decodes a 0-1 sRGB value to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a 0-1 linear light value with the sRGB transfer function.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// `img` decoded to linear light, with 16-bit samples so dark tones keep
+/// their precision. Gray images stay gray.
+pub fn to_linear(img: &DynamicImage) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    match PixelFormat::of(img) {
+        PixelFormat::Luma8 => DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(width, height, decode(&img.to_luma8(), 255)).unwrap(),
+        ),
+        PixelFormat::Luma16 => DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(width, height, decode(&img.to_luma16(), 65535)).unwrap(),
+        ),
+        PixelFormat::Rgb8 => DynamicImage::ImageRgb16(
+            ImageBuffer::from_raw(width, height, decode(&img.to_rgb8(), 255)).unwrap(),
+        ),
+        PixelFormat::Rgb16 => DynamicImage::ImageRgb16(
+            ImageBuffer::from_raw(width, height, decode(&img.to_rgb16(), 65535)).unwrap(),
+        ),
+    }
+}
+
+/// Encodes a result of `to_linear` back to sRGB in `format`.
+pub fn from_linear(img: &DynamicImage, format: PixelFormat) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    match format {
+        PixelFormat::Luma8 => DynamicImage::ImageLuma8(
+            ImageBuffer::from_raw(width, height, encode(&img.to_luma16(), 255.0).map(|value| value as u8).collect()).unwrap(),
+        ),
+        PixelFormat::Luma16 => DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(width, height, encode(&img.to_luma16(), 65535.0).map(|value| value as u16).collect()).unwrap(),
+        ),
+        PixelFormat::Rgb8 => DynamicImage::ImageRgb8(
+            ImageBuffer::from_raw(width, height, encode(&img.to_rgb16(), 255.0).map(|value| value as u8).collect()).unwrap(),
+        ),
+        PixelFormat::Rgb16 => DynamicImage::ImageRgb16(
+            ImageBuffer::from_raw(width, height, encode(&img.to_rgb16(), 65535.0).map(|value| value as u16).collect()).unwrap(),
+        ),
+    }
+}
+
+// sRGB samples up to `max` as 16-bit linear samples
+fn decode<T: Copy + Into<u32>>(samples: &[T], max: u32) -> Vec<u16> {
+    let lut: Vec<u16> = (0..=max)
+        .map(|value| (srgb_to_linear(value as f32 / max as f32) * 65535.0).round() as u16)
+        .collect();
+    samples.iter().map(|&value| lut[value.into() as usize]).collect()
+}
+
+// 16-bit linear samples as rounded sRGB values up to `max`
+fn encode(samples: &[u16], max: f32) -> impl Iterator<Item = f32> + '_ {
+    let lut: Vec<f32> = (0..=65535u32)
+        .map(|value| (linear_to_srgb(value as f32 / 65535.0) * max).round())
+        .collect();
+    samples.iter().map(move |&value| lut[value as usize])
+}
+
+/// Runs `filter` on `img` in linear light and encodes the result back to
+/// the working format of `img`. Averaging gamma encoded values darkens
+/// high contrast edges, averaging light does not.
+pub fn in_linear_light(img: &DynamicImage, filter: impl FnOnce(&DynamicImage) -> Option<DynamicImage>) -> Option<DynamicImage> {
+    let filtered = filter(&to_linear(img))?;
+    Some(from_linear(&filtered, PixelFormat::of(img)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every fifth level of each channel, with the extremes
+    fn colors() -> Rgb32FImage {
+        let levels: Vec<u32> = (0..=255).step_by(5).collect();
+        let count = levels.len() as u32;
+        ImageBuffer::from_fn(count * count, count, |x, y| {
+            let level = |i: u32| levels[i as usize] as f32 / 255.0;
+            Rgb([level(x / count), level(x % count), level(y)])
+        })
+    }
+
+    fn assert_within_one(before: &Rgb32FImage, after: &Rgb32FImage) {
+        let (before, after) = (DynamicImage::ImageRgb32F(before.clone()).to_rgb8(), DynamicImage::ImageRgb32F(after.clone()).to_rgb8());
+        for (original, converted) in before.pixels().zip(after.pixels()) {
+            let close = original.0.iter().zip(converted.0).all(|(&a, b)| a.abs_diff(b) <= 1);
+            assert!(close, "{:?} came back as {:?}", original, converted);
+        }
+    }
+
+    #[test]
+    fn ycbcr_round_trips_8_bit_colors() {
+        let img = colors();
+        assert_within_one(&img, &ycbcr_to_rgb(&rgb_to_ycbcr(&img)));
+    }
+
+    #[test]
+    fn ycbcr_round_trips_through_16_bit_planes() {
+        // The planes a filter sees and hands back
+        let img = colors();
+        let mut planes = rgb_to_ycbcr(&img);
+        for plane in [&mut planes.y, &mut planes.cb, &mut planes.cr] {
+            *plane = image_to_plane(&plane_to_image(plane, img.width(), img.height()));
+        }
+        assert_within_one(&img, &ycbcr_to_rgb(&planes));
+    }
+
+    #[test]
+    fn grayscale_uses_the_bt_601_weights() {
+        let primaries = DynamicImage::ImageRgb8(ImageBuffer::from_fn(4, 1, |x, _| match x {
+            0 => Rgb([255, 0, 0]),
+            1 => Rgb([0, 255, 0]),
+            2 => Rgb([0, 0, 255]),
+            _ => Rgb([200, 100, 50]),
+        }));
+        // 0.299, 0.587 and 0.114 of 255, and of the mix
+        assert_eq!(to_grayscale(&primaries).into_luma8().into_raw(), [76, 150, 29, 124]);
+        let deep = DynamicImage::ImageRgb16(primaries.to_rgb16());
+        assert_eq!(to_grayscale(&deep).into_luma16().into_raw(), [19_595, 38_469, 7_471, 31_919]);
+    }
+}
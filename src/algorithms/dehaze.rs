@@ -0,0 +1,123 @@
+use image::{DynamicImage, ImageBuffer, Primitive};
+
+use super::blur::guided_filter;
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+// Half the side of the patch the dark channel takes its minimum over
+pub const PATCH_RADIUS: u32 = 7;
+// Window radius of the guided filter refining the transmission map
+pub const GUIDED_RADIUS: u32 = 30;
+const GUIDED_EPS: f32 = 1e-3;
+// Share of the haze removed at full transmission estimate, leaving a little
+// keeps distant objects looking distant
+const OMEGA: f32 = 0.95;
+// Lower bound of the transmission. Bright skies and near-white images have
+// a dark channel close to the atmospheric light, and dividing by a tiny
+// transmission would blow them up or turn them gray.
+const MIN_TRANSMISSION: f32 = 0.25;
+// Share of the brightest dark channel pixels the atmospheric light is taken from
+const ATMOSPHERE_FRACTION: f32 = 0.001;
+
+/// Removes haze using the dark channel prior (He et al., "Single Image Haze
+/// Removal Using Dark Channel Prior"), blended with the original by
+/// `strength` (0-1).
+pub fn dehaze(img: &DynamicImage, strength: f32) -> DynamicImage {
+    if strength == 0.0 {
+        return img.clone();
+    }
+    with_pixel_type!(img, |P| dehaze_at::<P>(img, strength))
+}
+
+fn dehaze_at<P: FilterPixel>(img: &DynamicImage, strength: f32) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let img = P::from_dynamic(img);
+    let (width, height) = img.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let max = P::Subpixel::MAX_VALUE;
+
+    let normalized: Vec<[f32; 3]> = img
+        .pixels()
+        .map(|pixel| {
+            let mut values = [0.0; 3];
+            for (normalized, value) in values.iter_mut().zip(pixel.channels()) {
+                *normalized = value.to_f32() / max;
+            }
+            values
+        })
+        .collect();
+    let pixel_min = |values: &[f32; 3], scale: &[f32; 3]| {
+        values[..channels]
+            .iter()
+            .zip(scale)
+            .map(|(value, scale)| value / scale)
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    // Haze-free regions have some channel close to black nearby, so the
+    // brightest dark channel pixels are taken to be pure haze
+    let dark: Vec<f32> = normalized.iter().map(|values| pixel_min(values, &[1.0; 3])).collect();
+    let dark = patch_min(&dark, width, height, PATCH_RADIUS);
+    let mut order: Vec<usize> = (0..dark.len()).collect();
+    let count = ((dark.len() as f32 * ATMOSPHERE_FRACTION).ceil() as usize).max(1);
+    order.select_nth_unstable_by(dark.len() - count, |a, b| dark[*a].total_cmp(&dark[*b]));
+    let mut atmosphere = [0.0f32; 3];
+    for &i in &order[dark.len() - count..] {
+        for c in 0..channels {
+            atmosphere[c] += normalized[i][c] / count as f32;
+        }
+    }
+    for value in &mut atmosphere {
+        *value = value.max(1e-3);
+    }
+
+    let transmission: Vec<f32> = normalized.iter().map(|values| pixel_min(values, &atmosphere)).collect();
+    let transmission: Vec<f32> = patch_min(&transmission, width, height, PATCH_RADIUS)
+        .into_iter()
+        .map(|dark| 1.0 - OMEGA * dark)
+        .collect();
+    let guide: Vec<f32> = img.pixels().map(|pixel| pixel.to_luma()[0].to_f32() / max).collect();
+    let transmission = guided_filter(&guide, &transmission, width, height, GUIDED_RADIUS, GUIDED_EPS);
+
+    let mut new_img = ImageBuffer::new(width, height);
+    for (i, (x, y, _)) in img.enumerate_pixels().enumerate() {
+        let t = transmission[i].clamp(MIN_TRANSMISSION, 1.0);
+        let mut values = [P::Subpixel::DEFAULT_MIN_VALUE; 3];
+        for c in 0..channels {
+            let hazy = normalized[i][c];
+            let radiance = (hazy - atmosphere[c]) / t + atmosphere[c];
+            let blended = hazy + strength * (radiance - hazy);
+            values[c] = P::Subpixel::from_f32(blended * max + 0.5);
+        }
+        new_img.put_pixel(x, y, *P::from_slice(&values[..channels]));
+    }
+
+    P::into_dynamic(new_img)
+}
+
+// Minimum over the square of side 2 * `radius` + 1 around each value, as a
+// horizontal then a vertical pass
+fn patch_min(plane: &[f32], width: u32, height: u32, radius: u32) -> Vec<f32> {
+    let (width, height, radius) = (width as usize, height as usize, radius as usize);
+    let mut horizontal = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let row = &plane[y * width..(y + 1) * width];
+            horizontal[y * width + x] = row[x.saturating_sub(radius)..(x + radius + 1).min(width)]
+                .iter()
+                .copied()
+                .fold(f32::INFINITY, f32::min);
+        }
+    }
+
+    let mut result = vec![0.0; plane.len()];
+    for y in 0..height {
+        for x in 0..width {
+            result[y * width + x] = (y.saturating_sub(radius)..(y + radius + 1).min(height))
+                .map(|y| horizontal[y * width + x])
+                .fold(f32::INFINITY, f32::min);
+        }
+    }
+    result
+}
@@ -1,6 +1,14 @@
-use image::{DynamicImage, Rgb, ImageBuffer};
+use image::{DynamicImage, ImageBuffer};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+use super::block_matching::block_matching;
+use super::border::BorderMode;
+use super::colorspace::{image_to_plane, plane_to_image, rgb_to_ycbcr, ycbcr_to_rgb};
+use super::progress::Progress;
+use super::sample::{is_high_depth, with_pixel_type, Buffer, FilterPixel, PixelFormat, Sample};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DenoiseType {
     MeanFilter,
     GaussianFilter,
@@ -8,422 +16,783 @@ pub enum DenoiseType {
     BilateralFilter,
     NonLocalMeans,
     TotalVariation,
+    /// Total variation solved with Chambolle's projection, see `chambolle_tv`
+    ChambolleTV,
+    /// BM3D style collaborative filtering, see `block_matching`
+    BlockMatching,
+    /// Kernel size is the largest window, see `adaptive_median`
+    AdaptiveMedian,
 }
 
+impl DenoiseType {
+    /// Whether the filter averages neighbouring values, so that doing it in
+    /// linear light makes a difference. Median picks one of the values and
+    /// the slower filters are tuned for gamma encoded input.
+    pub fn filters_linear_light(self) -> bool {
+        matches!(self, DenoiseType::MeanFilter | DenoiseType::GaussianFilter | DenoiseType::BilateralFilter)
+    }
+
+    /// Whether the filter can run band by band on an image too large to
+    /// hold, see `StreamedBands`. The others need the whole image:
+    /// `TotalVariation` updates in place, carrying values down the whole
+    /// image within one sweep, `ChambolleTV` stops once the whole image
+    /// changes little enough and `BlockMatching` thresholds by the noise
+    /// level of the whole image.
+    pub fn streamable(self) -> bool {
+        !matches!(self, DenoiseType::TotalVariation | DenoiseType::ChambolleTV | DenoiseType::BlockMatching)
+    }
+}
+
+/// How strongly `denoise_ycbcr_with_progress` filters the luminance and the
+/// color, each from 0 (left untouched) to 1 (replaced by the filtered plane).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlaneStrengths {
+    pub luma: f32,
+    pub chroma: f32,
+    /// Kernel size of the Cb/Cr planes, `None` for the one the luminance
+    /// gets. Color noise is coarser than luminance noise
+    #[serde(default)]
+    pub chroma_kernel_size: Option<usize>,
+}
+
+impl PlaneStrengths {
+    /// Color only, the luminance keeps all of its detail.
+    pub const CHROMA_ONLY: PlaneStrengths = PlaneStrengths { luma: 0.0, chroma: 1.0, chroma_kernel_size: None };
+}
+
+/// Denoises `img`. The neighbourhood filters (mean, gaussian, median,
+/// adaptive median, bilateral and non-local means) read past the image
+/// edges as `border` says.
+#[allow(dead_code)] // Kept for callers that don't need progress reporting
 pub fn denoise_image(
     img: &DynamicImage,
     denoise_type: DenoiseType,
     kernel_size: usize,
     tv_lambda: f32,
     tv_iterations: usize,
+    tv_tolerance: f32,
+    border: BorderMode,
 ) -> DynamicImage {
-    let img = img.to_rgb8();
+    // A fresh progress tracker is never cancelled, so this always yields an image
+    denoise_image_with_progress(img, denoise_type, kernel_size, tv_lambda, tv_iterations, tv_tolerance, border, &Progress::new())
+        .expect("denoising without a cancel request always completes")
+}
+
+/// Same as `denoise_image`, but reports progress and stops early when
+/// `progress` is cancelled, in which case `None` is returned.
+///
+/// The result is in the working format of `img`: gray sources are filtered
+/// as a single channel and high bit depth ones with 16-bit samples.
+#[allow(clippy::too_many_arguments)]
+pub fn denoise_image_with_progress(
+    img: &DynamicImage,
+    denoise_type: DenoiseType,
+    kernel_size: usize,
+    tv_lambda: f32,
+    tv_iterations: usize,
+    tv_tolerance: f32,
+    border: BorderMode,
+    progress: &Progress,
+) -> Option<DynamicImage> {
+    with_pixel_type!(img, |P| denoise_at::<P>(img, denoise_type, kernel_size, tv_lambda, tv_iterations, tv_tolerance, border, progress))
+}
+
+/// Like `denoise_image_with_progress`, but filters the luminance and the
+/// color of `img` separately in YCbCr, each as strongly as `strengths` says.
+/// `kernel_size` is the luminance's, the color planes take
+/// `strengths.chroma_kernel_size` when it is set.
+/// Most high ISO noise is colored blotches, which strong color filtering
+/// removes while mild luminance filtering keeps the grain and the detail.
+/// Gray images only have their luminance filtered.
+#[allow(clippy::too_many_arguments)]
+pub fn denoise_ycbcr_with_progress(
+    img: &DynamicImage,
+    denoise_type: DenoiseType,
+    kernel_size: usize,
+    tv_lambda: f32,
+    tv_iterations: usize,
+    tv_tolerance: f32,
+    strengths: PlaneStrengths,
+    border: BorderMode,
+    progress: &Progress,
+) -> Option<DynamicImage> {
+    let (width, height) = (img.width(), img.height());
+    // Moves the plane towards its filtered version, not at all for 0
+    let denoise_plane = |plane: &mut [f32], strength: f32, kernel_size: usize| -> Option<()> {
+        if strength <= 0.0 {
+            return Some(());
+        }
+        let filtered = denoise_image_with_progress(
+            &plane_to_image(plane, width, height),
+            denoise_type,
+            kernel_size,
+            tv_lambda,
+            tv_iterations,
+            tv_tolerance,
+            border,
+            progress,
+        )?;
+        for (value, filtered) in plane.iter_mut().zip(image_to_plane(&filtered)) {
+            *value += strength.min(1.0) * (filtered - *value);
+        }
+        Some(())
+    };
+
+    if matches!(PixelFormat::of(img), PixelFormat::Luma8 | PixelFormat::Luma16) {
+        if strengths.luma <= 0.0 {
+            return Some(img.clone());
+        }
+        let mut plane = image_to_plane(img);
+        denoise_plane(&mut plane, strengths.luma, kernel_size)?;
+        let gray = plane_to_image(&plane, width, height);
+        return Some(if is_high_depth(img) { gray } else { DynamicImage::ImageLuma8(gray.to_luma8()) });
+    }
+    if strengths.luma <= 0.0 && strengths.chroma <= 0.0 {
+        return Some(img.clone());
+    }
+
+    let mut planes = rgb_to_ycbcr(&img.to_rgb32f());
+    denoise_plane(&mut planes.y, strengths.luma, kernel_size)?;
+    let chroma_kernel_size = strengths.chroma_kernel_size.unwrap_or(kernel_size);
+    for plane in [&mut planes.cb, &mut planes.cr] {
+        denoise_plane(plane, strengths.chroma, chroma_kernel_size)?;
+    }
+
+    let rgb = DynamicImage::ImageRgb32F(ycbcr_to_rgb(&planes));
+    Some(if is_high_depth(img) {
+        DynamicImage::ImageRgb16(rgb.to_rgb16())
+    } else {
+        DynamicImage::ImageRgb8(rgb.to_rgb8())
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn denoise_at<P: FilterPixel>(
+    img: &DynamicImage,
+    denoise_type: DenoiseType,
+    kernel_size: usize,
+    tv_lambda: f32,
+    tv_iterations: usize,
+    tv_tolerance: f32,
+    border: BorderMode,
+    progress: &Progress,
+) -> Option<DynamicImage>
+where
+    P::Subpixel: Sample,
+{
+    let img = P::from_dynamic(img);
     let (width, height) = (img.width(), img.height());
     let mut new_img = ImageBuffer::new(width, height);
     let radius = kernel_size / 2;
 
     match denoise_type {
-        DenoiseType::MeanFilter => mean_filter(&img, &mut new_img, width, height, radius),
-        DenoiseType::GaussianFilter => gaussian_filter(&img, &mut new_img, width, height, radius),
-        DenoiseType::MedianFilter => median_filter(&img, &mut new_img, width, height, radius),
-        DenoiseType::BilateralFilter => bilateral_filter(&img, &mut new_img, width, height, radius),
-        DenoiseType::NonLocalMeans => non_local_means(&img, &mut new_img, width, height),
-        DenoiseType::TotalVariation => total_variation(&img, &mut new_img, width, height, tv_lambda, tv_iterations),
+        DenoiseType::MeanFilter => mean_filter(&img, &mut new_img, width, height, radius, border, progress),
+        DenoiseType::GaussianFilter => gaussian_filter(&img, &mut new_img, width, height, radius, border, progress),
+        DenoiseType::MedianFilter => median_filter(&img, &mut new_img, width, height, radius, border, progress),
+        DenoiseType::BilateralFilter => bilateral_filter(&img, &mut new_img, width, height, radius, border, progress),
+        DenoiseType::NonLocalMeans => non_local_means(&img, &mut new_img, width, height, border, progress),
+        DenoiseType::TotalVariation => total_variation(&img, &mut new_img, width, height, progress),
+        DenoiseType::ChambolleTV => chambolle_tv(&img, &mut new_img, tv_lambda, tv_iterations, tv_tolerance, progress),
+        DenoiseType::BlockMatching => block_matching(&img, &mut new_img, width, height, progress),
+        DenoiseType::AdaptiveMedian => adaptive_median(&img, &mut new_img, width, height, radius, border, progress),
+    }
+
+    if progress.is_cancelled() {
+        return None;
     }
 
-    DynamicImage::ImageRgb8(new_img)
+    Some(P::into_dynamic(new_img))
 }
 
-fn mean_filter(
-    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    new_img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+fn mean_filter<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
     width: u32,
     height: u32,
     radius: usize,
-) {
-    for y in 0..height {
-        for x in 0..width {
-            let mut sum_r = 0;
-            let mut sum_g = 0;
-            let mut sum_b = 0;
-            let mut count = 0;
-            
-            for dy in 0..=radius*2 {
-                for dx in 0..=radius*2 {
-                    let nx = x as i32 + dx as i32 - radius as i32;
-                    let ny = y as i32 + dy as i32 - radius as i32;
-                    
-                    if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                        let pixel = img.get_pixel(nx as u32, ny as u32);
-                        sum_r += pixel[0] as u32;
-                        sum_g += pixel[1] as u32;
-                        sum_b += pixel[2] as u32;
-                        count += 1;
-                    }
+    border: BorderMode,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let row_len = width as usize * channels;
+    let (xs, ys) = (border.sources(width, radius), border.sources(height, radius));
+    let src = img.as_raw();
+    progress.add_total(height as usize * 2);
+
+    // The box is separable: sum along the rows, then add those sums up
+    // along the columns. Integer sums keep it exact
+    let mut row_sums = vec![0u32; src.len()];
+    for (line, sums) in src.chunks(row_len).zip(row_sums.chunks_mut(row_len)) {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        for (x, pixel_sums) in sums.chunks_mut(channels).enumerate() {
+            for sx in xs[x..=x + radius * 2].iter().flatten() {
+                let i = *sx as usize * channels;
+                for (sum, &value) in pixel_sums.iter_mut().zip(&line[i..i + channels]) {
+                    *sum += Into::<u32>::into(value);
                 }
             }
-            
-            let avg_r = (sum_r / count) as u8;
-            let avg_g = (sum_g / count) as u8;
-            let avg_b = (sum_b / count) as u8;
-            new_img.put_pixel(x, y, Rgb([avg_r, avg_g, avg_b]));
         }
+        progress.advance(1);
+    }
+
+    let x_counts: Vec<u32> = (0..width as usize)
+        .map(|x| xs[x..=x + radius * 2].iter().flatten().count() as u32)
+        .collect();
+    let mut sums = vec![0u32; row_len];
+    for (y, row) in new_img.chunks_mut(row_len).enumerate() {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        sums.fill(0);
+        let mut y_count = 0;
+        for sy in ys[y..=y + radius * 2].iter().flatten() {
+            let line = &row_sums[*sy as usize * row_len..][..row_len];
+            for (sum, &value) in sums.iter_mut().zip(line) {
+                *sum += value;
+            }
+            y_count += 1;
+        }
+
+        for ((pixel, pixel_sums), x_count) in row.chunks_mut(channels).zip(sums.chunks(channels)).zip(&x_counts) {
+            let count = x_count * y_count;
+            for (value, &sum) in pixel.iter_mut().zip(pixel_sums) {
+                *value = P::Subpixel::from_f32((sum / count) as f32);
+            }
+        }
+        progress.advance(1);
     }
 }
 
-fn gaussian_filter(
-    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    new_img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+fn gaussian_filter<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
     width: u32,
     height: u32,
     radius: usize,
-) {
+    border: BorderMode,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
     let sigma = radius as f32 / 2.0;
-    let mut kernel = vec![vec![0.0; radius*2+1]; radius*2+1];
-    let mut sum = 0.0;
 
-    // 生成高斯核
-    for y in 0..=radius*2 {
-        for x in 0..=radius*2 {
-            let dx = x as f32 - radius as f32;
-            let dy = y as f32 - radius as f32;
-            let value = (-(dx*dx + dy*dy) / (2.0 * sigma * sigma)).exp();
-            kernel[y][x] = value;
-            sum += value;
+    // 生成高斯核: exp(-(dx²+dy²)) is the product of the two axes, so one
+    // row of weights serves a pass along the rows and one along the columns
+    let kernel: Vec<f32> = (0..=radius*2)
+        .map(|i| {
+            let d = i as f32 - radius as f32;
+            (-(d*d) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+
+    // 应用高斯滤波
+    let channels = P::CHANNEL_COUNT as usize;
+    let row_len = width as usize * channels;
+    let (xs, ys) = (border.sources(width, radius), border.sources(height, radius));
+    let src = img.as_raw();
+    progress.add_total(height as usize * 2);
+
+    // Each pass divides by the weights it used, skipped pixels leave part
+    // of the kernel unused
+    let mut rows = vec![0.0f32; src.len()];
+    for (line, out) in src.chunks(row_len).zip(rows.chunks_mut(row_len)) {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        for (x, pixel) in out.chunks_mut(channels).enumerate() {
+            let mut weight_sum = 0.0;
+            for (sx, &weight) in xs[x..=x + radius * 2].iter().zip(&kernel) {
+                let Some(sx) = sx else { continue };
+                let i = *sx as usize * channels;
+                for (sum, value) in pixel.iter_mut().zip(&line[i..i + channels]) {
+                    *sum += value.to_f32() * weight;
+                }
+                weight_sum += weight;
+            }
+            for sum in pixel {
+                *sum /= weight_sum;
+            }
         }
+        progress.advance(1);
     }
 
-    // 归一化
-    for y in 0..=radius*2 {
-        for x in 0..=radius*2 {
-            kernel[y][x] /= sum;
+    let mut sums = vec![0.0f32; row_len];
+    for (y, row) in new_img.chunks_mut(row_len).enumerate() {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        sums.fill(0.0);
+        let mut weight_sum = 0.0;
+        for (sy, &weight) in ys[y..=y + radius * 2].iter().zip(&kernel) {
+            let Some(sy) = sy else { continue };
+            let line = &rows[*sy as usize * row_len..][..row_len];
+            for (sum, value) in sums.iter_mut().zip(line) {
+                *sum += value * weight;
+            }
+            weight_sum += weight;
         }
+
+        for (value, sum) in row.iter_mut().zip(&sums) {
+            *value = P::Subpixel::from_f32(sum / weight_sum);
+        }
+        progress.advance(1);
     }
+}
 
-    // 应用高斯滤波
-    for y in 0..height {
-        for x in 0..width {
-            let mut sum_r = 0.0;
-            let mut sum_g = 0.0;
-            let mut sum_b = 0.0;
-            
-            for dy in 0..=radius*2 {
-                for dx in 0..=radius*2 {
-                    let nx = x as i32 + dx as i32 - radius as i32;
-                    let ny = y as i32 + dy as i32 - radius as i32;
-                    
-                    if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                        let pixel = img.get_pixel(nx as u32, ny as u32);
-                        let weight = kernel[dy][dx];
-                        sum_r += pixel[0] as f32 * weight;
-                        sum_g += pixel[1] as f32 * weight;
-                        sum_b += pixel[2] as f32 * weight;
+fn median_filter<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
+    width: u32,
+    height: u32,
+    radius: usize,
+    border: BorderMode,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let (xs, ys) = (border.sources(width, radius), border.sources(height, radius));
+    let src = img.as_raw();
+    // Reused for every pixel, one window per channel
+    let mut values: [Vec<P::Subpixel>; 3] = [Vec::new(), Vec::new(), Vec::new()];
+    progress.add_total(height as usize);
+    for (y, row) in new_img.chunks_mut(width as usize * channels).enumerate() {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        for (x, pixel) in row.chunks_mut(channels).enumerate() {
+            values.iter_mut().for_each(Vec::clear);
+            for sy in ys[y..=y + radius * 2].iter().flatten() {
+                let line = *sy as usize * width as usize;
+                for sx in xs[x..=x + radius * 2].iter().flatten() {
+                    let i = (line + *sx as usize) * channels;
+                    for (values, &value) in values.iter_mut().zip(&src[i..i + channels]) {
+                        values.push(value);
                     }
                 }
             }
-            
-            let r = sum_r.clamp(0.0, 255.0) as u8;
-            let g = sum_g.clamp(0.0, 255.0) as u8;
-            let b = sum_b.clamp(0.0, 255.0) as u8;
-            new_img.put_pixel(x, y, Rgb([r, g, b]));
+
+            let median_index = values[0].len() / 2;
+            for (median, values) in pixel.iter_mut().zip(&mut values[..channels]) {
+                values.sort_unstable();
+                *median = values[median_index];
+            }
         }
+        progress.advance(1);
     }
 }
 
-fn median_filter(
-    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    new_img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+/// Median filter for dense impulse noise. The window around each pixel
+/// grows from 3×3 up to `max_radius` while its median is itself an extreme
+/// value, and the center is only replaced when it is one of the extremes.
+/// Pixels that aren't impulses are left untouched, keeping detail a large
+/// fixed median would erase.
+fn adaptive_median<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
     width: u32,
     height: u32,
-    radius: usize,
-) {
+    max_radius: usize,
+    border: BorderMode,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    let max_radius = max_radius.max(1) as i32;
+    let mut values = Vec::new();
+    progress.add_total(height as usize);
     for y in 0..height {
+        if progress.is_cancelled() {
+            return;
+        }
+
         for x in 0..width {
-            let mut r_values = Vec::new();
-            let mut g_values = Vec::new();
-            let mut b_values = Vec::new();
-            
-            for dy in 0..=radius*2 {
-                for dx in 0..=radius*2 {
-                    let nx = x as i32 + dx as i32 - radius as i32;
-                    let ny = y as i32 + dy as i32 - radius as i32;
-                    
-                    if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                        let pixel = img.get_pixel(nx as u32, ny as u32);
-                        r_values.push(pixel[0]);
-                        g_values.push(pixel[1]);
-                        b_values.push(pixel[2]);
+            let mut pixel = *img.get_pixel(x, y);
+            for (c, center) in pixel.channels_mut().iter_mut().enumerate() {
+                for radius in 1..=max_radius {
+                    values.clear();
+                    for ny in y as i32 - radius..=y as i32 + radius {
+                        for nx in x as i32 - radius..=x as i32 + radius {
+                            if let Some(pixel) = border.pixel(img, nx, ny) {
+                                values.push(pixel.channels()[c]);
+                            }
+                        }
+                    }
+                    values.sort();
+                    let (min, median, max) = (values[0], values[values.len() / 2], values[values.len() - 1]);
+
+                    if min < median && median < max {
+                        if *center == min || *center == max {
+                            *center = median;
+                        }
+                        break;
+                    }
+                    // The median is still an impulse at the largest window,
+                    // it is the best estimate available
+                    if radius == max_radius {
+                        *center = median;
                     }
                 }
             }
-            
-            r_values.sort();
-            g_values.sort();
-            b_values.sort();
-            
-            let median_index = r_values.len() / 2;
-            let r = r_values[median_index];
-            let g = g_values[median_index];
-            let b = b_values[median_index];
-            
-            new_img.put_pixel(x, y, Rgb([r, g, b]));
+            new_img.put_pixel(x, y, pixel);
         }
+        progress.advance(1);
     }
 }
 
 
-fn bilateral_filter(
-    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    new_img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+/// Range standard deviation of the bilateral filter, in 8-bit units.
+pub const BILATERAL_SIGMA_R: f32 = 30.0;
+
+fn bilateral_filter<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
     width: u32,
     height: u32,
     radius: usize,
-) {
+    border: BorderMode,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
     let sigma_d = radius as f32; // Spatial domain standard deviation
-    let sigma_r = 30.0; // Range domain standard deviation
+    let sigma_r = BILATERAL_SIGMA_R * P::Subpixel::scale(); // Range domain standard deviation
+    let channels = P::CHANNEL_COUNT as usize;
+    let (xs, ys) = (border.sources(width, radius), border.sources(height, radius));
+    let src = img.as_raw();
 
-    for y in 0..height {
-        for x in 0..width {
-            let center_pixel = img.get_pixel(x, y);
+    // Calculate spatial weight, the same for every pixel
+    let spatial_weights: Vec<f32> = (0..=radius*2)
+        .flat_map(|dy| (0..=radius*2).map(move |dx| (dx, dy)))
+        .map(|(dx, dy)| {
+            let x_diff = (dx as f32 - radius as f32).powf(2.0);
+            let y_diff = (dy as f32 - radius as f32).powf(2.0);
+            (-((x_diff + y_diff) / (2.0 * sigma_d * sigma_d))).exp()
+        })
+        .collect();
+    let range_weight = |intensity_diff: f32| (-intensity_diff / (2.0 * sigma_r * sigma_r)).exp();
+
+    // Gray pixels differ by a whole number of steps, so their range weights
+    // are looked up instead of taking an exp for every neighbor
+    let gray_weights: Vec<f32> = match channels {
+        1 => (0..=P::Subpixel::MAX_VALUE as u32).map(|diff| range_weight((diff as f32).powf(2.0))).collect(),
+        _ => Vec::new(),
+    };
+
+    progress.add_total(height as usize);
+    for (y, row) in new_img.chunks_mut(width as usize * channels).enumerate() {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        for (x, pixel) in row.chunks_mut(channels).enumerate() {
+            let center = &src[(y * width as usize + x) * channels..][..channels];
             let mut sums = [0.0f32; 3];
             let mut weight_sum = 0.0;
 
-            for dy in 0..=radius*2 {
-                for dx in 0..=radius*2 {
-                    let nx = x as i32 + dx as i32 - radius as i32;
-                    let ny = y as i32 + dy as i32 - radius as i32;
-                    
-                    if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                        let neighbor_pixel = img.get_pixel(nx as u32, ny as u32);
-                        
-                        // Calculate spatial weight
-                        let x_diff = (dx as f32 - radius as f32).powf(2.0);
-                        let y_diff = (dy as f32 - radius as f32).powf(2.0);
-                        let spatial_weight = (-((x_diff + y_diff) / (2.0 * sigma_d * sigma_d))).exp();
-                        
-                        // Calculate range weight
-                        let mut intensity_diff = 0.0;
-                        for c in 0..3 {
-                            intensity_diff += (center_pixel[c] as f32 - neighbor_pixel[c] as f32).powf(2.0);
-                        }
-                        intensity_diff /= 3.0;
-                        let range_weight = (-intensity_diff / (2.0 * sigma_r * sigma_r)).exp();
-                        
-                        let weight = spatial_weight * range_weight;
-                        for c in 0..3 {
-                            sums[c] += neighbor_pixel[c] as f32 * weight;
+            for (sy, spatial_row) in ys[y..=y + radius * 2].iter().zip(spatial_weights.chunks(radius * 2 + 1)) {
+                let Some(sy) = sy else { continue };
+                let line = *sy as usize * width as usize;
+                for (sx, &spatial_weight) in xs[x..=x + radius * 2].iter().zip(spatial_row) {
+                    let Some(sx) = sx else { continue };
+                    let i = (line + *sx as usize) * channels;
+                    let neighbor = &src[i..i + channels];
+
+                    // Calculate range weight
+                    let range_weight = match channels {
+                        1 => gray_weights[Into::<u32>::into(center[0]).abs_diff(neighbor[0].into()) as usize],
+                        _ => {
+                            let mut intensity_diff = 0.0;
+                            for (c, n) in center.iter().zip(neighbor) {
+                                intensity_diff += (c.to_f32() - n.to_f32()).powf(2.0);
+                            }
+                            range_weight(intensity_diff / channels as f32)
                         }
-                        weight_sum += weight;
+                    };
+
+                    let weight = spatial_weight * range_weight;
+                    for (sum, value) in sums.iter_mut().zip(neighbor) {
+                        *sum += value.to_f32() * weight;
                     }
+                    weight_sum += weight;
                 }
             }
-            
-            let pixel = [
-                (sums[0] / weight_sum) as u8,
-                (sums[1] / weight_sum) as u8,
-                (sums[2] / weight_sum) as u8,
-            ];
-            new_img.put_pixel(x, y, Rgb(pixel));
+
+            for (value, sum) in pixel.iter_mut().zip(sums) {
+                *value = P::Subpixel::from_f32(sum / weight_sum);
+            }
         }
+        progress.advance(1);
     }
 }
 
-fn non_local_means(
-    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    new_img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+// Non-local means works on tiles of this many pixels square, so its
+// scratch buffers stay small however large the image is
+const NLM_TILE_SIZE: u32 = 128;
+/// Half size of the patches non-local means compares.
+pub const NLM_PATCH_RADIUS: i32 = 2;
+/// Half size of the window non-local means searches for similar patches.
+pub const NLM_SEARCH_RADIUS: i32 = 5;
+
+fn non_local_means<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
     width: u32,
     height: u32,
-) {
-    let ds = 2; // Block size for calculating the weight
-    let Ds = 5; // Search window size
-    let h = 10.0; // Decay factor
-
-    // Create padded image
-    let offset = ds + Ds;
-    let offset_u32 = offset as u32;
-    let mut padded_img = ImageBuffer::new(width + 2 * offset_u32, height + 2 * offset_u32);
-
-    // Use symmetric padding
-    for y in 0..height + 2 * offset_u32 {
-        for x in 0..width + 2 * offset_u32 {
-            let src_x = if x < offset_u32 {
-                offset_u32 - x - 1
-            } else if x >= width + offset_u32 {
-                2 * width + offset_u32 - x - 1
-            } else {
-                x - offset_u32
-            };
-            
-            let src_y = if y < offset_u32 {
-                offset_u32 - y - 1
-            } else if y >= height + offset_u32 {
-                (2 * height + offset_u32).checked_sub(y).map_or(0, |val| val - 1)
-            } else {
-                y - offset_u32
-            };
-            
-            padded_img.put_pixel(x, y, *img.get_pixel(src_x.min(width-1), src_y.min(height-1)));
+    border: BorderMode,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    let tiles_x = width.div_ceil(NLM_TILE_SIZE);
+    let tiles_y = height.div_ceil(NLM_TILE_SIZE);
+    progress.add_total((tiles_x * tiles_y) as usize);
+
+    // One row of tiles at a time, so only that row's results are held
+    // before being copied into the output
+    for tile_y in 0..tiles_y {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        let y = tile_y * NLM_TILE_SIZE;
+        let tile_height = NLM_TILE_SIZE.min(height - y);
+        let tiles: Vec<(u32, Vec<P::Subpixel>)> = (0..tiles_x)
+            .into_par_iter()
+            .map_init(NlmScratch::default, |scratch, tile_x| {
+                let x = tile_x * NLM_TILE_SIZE;
+                let tile_width = NLM_TILE_SIZE.min(width - x);
+                let tile = if progress.is_cancelled() {
+                    Vec::new()
+                } else {
+                    nlm_tile(img, scratch, x, y, tile_width, tile_height, border)
+                };
+                progress.advance(1);
+                (x, tile)
+            })
+            .collect();
+
+        if progress.is_cancelled() {
+            return;
+        }
+        let channels = P::CHANNEL_COUNT as usize;
+        let output: &mut [P::Subpixel] = new_img;
+        for (x, tile) in tiles {
+            let tile_width = NLM_TILE_SIZE.min(width - x) as usize;
+            for (row, values) in tile.chunks_exact(tile_width * channels).enumerate() {
+                let start = ((y as usize + row) * width as usize + x as usize) * channels;
+                output[start..start + values.len()].copy_from_slice(values);
+            }
         }
     }
+}
 
-    let mut sum_image = vec![vec![0.0f32; 3]; (width * height) as usize];
-    let mut sum_weight = vec![0.0; (width * height) as usize];
-    let mut max_weight = vec![0.0; (width * height) as usize];
+/// Buffers reused across the tiles one worker thread filters.
+#[derive(Default)]
+struct NlmScratch {
+    /// The tile and the context around it, padded past the image border
+    values: Vec<f32>,
+    /// Distance of each pixel to its counterpart at the current offset
+    diff: Vec<f32>,
+    /// Summed area table of `diff`, one row and column larger
+    integral: Vec<f32>,
+    sums: Vec<f32>,
+    sum_weights: Vec<f32>,
+    max_weights: Vec<f32>,
+}
+
+// Filters one tile, returning its samples row by row
+fn nlm_tile<P: FilterPixel>(
+    img: &Buffer<P>,
+    scratch: &mut NlmScratch,
+    x: u32,
+    y: u32,
+    tile_width: u32,
+    tile_height: u32,
+    border: BorderMode,
+) -> Vec<P::Subpixel>
+where
+    P::Subpixel: Sample,
+{
+    let (width, height) = img.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let h = 10.0 * P::Subpixel::scale(); // Decay factor
+    let window = 2 * NLM_PATCH_RADIUS + 1;
+    let (tile_width, tile_height) = (tile_width as i32, tile_height as i32);
+    let (x, y) = (x as i32, y as i32);
 
-    // Iterate over the search window
-    for r in -Ds..=Ds {
-        for s in -Ds..=Ds {
+    // A pixel's patch distance sums the differences over the window to its
+    // lower right, and its neighbours lie up to the search radius away
+    let before = NLM_SEARCH_RADIUS;
+    let after = window + NLM_SEARCH_RADIUS;
+    let context_width = tile_width + before + after;
+    let context_height = tile_height + before + after;
+    scratch.values.clear();
+    for cy in y - before..y + tile_height + after {
+        for cx in x - before..x + tile_width + after {
+            let pixel = img.get_pixel(border.padded(cx, width), border.padded(cy, height));
+            scratch.values.extend(pixel.channels().iter().map(|value| value.to_f32()));
+        }
+    }
+    let value_range = |cx: i32, cy: i32| {
+        let start = (((cy - y + before) * context_width + cx - x + before) as usize) * channels;
+        start..start + channels
+    };
+
+    let diff_width = tile_width + 2 * NLM_PATCH_RADIUS;
+    let diff_height = tile_height + 2 * NLM_PATCH_RADIUS;
+    let len = (tile_width * tile_height) as usize;
+    scratch.diff.resize((diff_width * diff_height) as usize, 0.0);
+    scratch.integral.resize(((diff_width + 1) * (diff_height + 1)) as usize, 0.0);
+    scratch.sums.clear();
+    scratch.sums.resize(len * channels, 0.0);
+    scratch.sum_weights.clear();
+    scratch.sum_weights.resize(len, 0.0);
+    scratch.max_weights.clear();
+    scratch.max_weights.resize(len, 0.0);
+    debug_assert_eq!(scratch.values.len(), (context_width * context_height) as usize * channels);
+
+    for r in -NLM_SEARCH_RADIUS..=NLM_SEARCH_RADIUS {
+        for s in -NLM_SEARCH_RADIUS..=NLM_SEARCH_RADIUS {
             if r == 0 && s == 0 {
                 continue;
             }
 
-            // Calculate the patch distance integral image
-            let mut diff = vec![0.0; (width + 2 * offset_u32) as usize * (height + 2 * offset_u32) as usize];
-            
-            for y in offset_u32..height + offset_u32 {
-                for x in offset_u32..width + offset_u32 {
-                    let base_y = y as i32;
-                    let base_x = x as i32;
-                    let offset_y = (base_y + r).max(0) as u32;
-                    let offset_x = (base_x + s).max(0) as u32;
-                    
-                    if offset_y < height + 2 * offset_u32 && offset_x < width + 2 * offset_u32 {
-                        let p1 = padded_img.get_pixel(base_x as u32, base_y as u32);
-                        let p2 = padded_img.get_pixel(offset_x, offset_y);
-                        let mut d = 0.0;
-                        for c in 0..3 {
-                            d += (p1[c] as f32 - p2[c] as f32).powf(2.0);
-                        }
-                        d /= 3.0;
-                        let idx = ((y - offset_u32) * (width + 2 * offset_u32) + (x - offset_u32)) as usize;
-                        if idx < diff.len() {
-                            diff[idx] = d;
-                        }
-                    }
+            // Distance of the pixels the tile's patches cover to their
+            // counterparts. Past the image's right or bottom edge there
+            // is nothing to compare and it stays zero.
+            for dy in 0..diff_height {
+                let cy = y + 1 + dy;
+                for dx in 0..diff_width {
+                    let cx = x + 1 + dx;
+                    let i = (dy * diff_width + dx) as usize;
+                    scratch.diff[i] = if cx < width as i32 && cy < height as i32 {
+                        let p1 = &scratch.values[value_range(cx, cy)];
+                        let p2 = &scratch.values[value_range(cx + s, cy + r)];
+                        p1.iter().zip(p2).map(|(a, b)| (a - b).powf(2.0)).sum::<f32>() / channels as f32
+                    } else {
+                        0.0
+                    };
                 }
             }
 
-            // Calculate the integral image
-            let mut integral = vec![0.0; (width + 2 * offset_u32) as usize * (height + 2 * offset_u32) as usize];
-
-            // Horizontal summation
-            for y in 0..height + 2 * offset_u32 {
-                let mut sum = 0.0;
-                for x in 0..width + 2 * offset_u32 {
-                    let idx = (y * (width + 2 * offset_u32) + x) as usize;
-                    if idx < diff.len() {
-                        sum += diff[idx];
-                        integral[idx] = sum;
-                    }
+            let stride = (diff_width + 1) as usize;
+            for dy in 0..diff_height as usize {
+                let mut row_sum = 0.0;
+                for dx in 0..diff_width as usize {
+                    row_sum += scratch.diff[dy * diff_width as usize + dx];
+                    scratch.integral[(dy + 1) * stride + dx + 1] = scratch.integral[dy * stride + dx + 1] + row_sum;
                 }
             }
 
-            // Vertical summation
-            for x in 0..width + 2 * offset_u32 {
-                let mut sum = 0.0;
-                for y in 0..height + 2 * offset_u32 {
-                    let idx = (y * (width + 2 * offset_u32) + x) as usize;
-                    if idx < integral.len() {
-                        sum += integral[idx];
-                        integral[idx] = sum;
+            for ty in 0..tile_height {
+                for tx in 0..tile_width {
+                    // Skipped neighbours are still compared through their
+                    // mirrored patches, but never averaged in
+                    let (nx, ny) = (x + tx + s, y + ty + r);
+                    if border.source(nx, width).is_none() || border.source(ny, height).is_none() {
+                        continue;
                     }
-                }
-            }
+                    let (ix, iy) = (tx as usize, ty as usize);
+                    let (wx, wy) = (ix + window as usize, iy + window as usize);
+                    let distance = scratch.integral[wy * stride + wx] + scratch.integral[iy * stride + ix]
+                        - scratch.integral[wy * stride + ix]
+                        - scratch.integral[iy * stride + wx];
+                    let distance = distance / (window * window) as f32;
+                    let weight = (-distance / (h * h)).exp();
 
-            // Compute pixel weights and update pixel values
-            for y in 0..height {
-                for x in 0..width {
-                    let i = (y * width + x) as usize;
-                    let window_size = (2 * ds + 1) as u32;
-                    let top_right = ((y + window_size) * (width + 2 * offset_u32) + (x + window_size)) as usize;
-                    let top_left = ((y + window_size) * (width + 2 * offset_u32) + x) as usize;
-                    let bottom_right = ((y) * (width + 2 * offset_u32) + (x + window_size)) as usize;
-                    let bottom_left = ((y) * (width + 2 * offset_u32) + x) as usize;
-
-                    if top_right < integral.len() && top_left < integral.len() &&
-                       bottom_right < integral.len() && bottom_left < integral.len() {
-                        let distance = integral[top_right] + integral[bottom_left] 
-                                       - integral[top_left] - integral[bottom_right];
-                        
-                        let distance = distance / ((window_size * window_size) as f32);
-                        let weight = (-distance / (h * h)).exp();
-                        
-                        // Retrieve the offset pixel value
-                        let offset_y = ((y + offset_u32) as i32 + r).max(0) as u32;
-                        let offset_x = ((x + offset_u32) as i32 + s).max(0) as u32;
-                        
-                        if offset_y < height + 2 * offset_u32 && offset_x < width + 2 * offset_u32 {
-                            let pixel = padded_img.get_pixel(offset_x, offset_y);
-                            for c in 0..3 {
-                                sum_image[i][c] += weight * pixel[c] as f32;
-                            }
-                            sum_weight[i] += weight;
-                            max_weight[i] = weight.max(max_weight[i]);
-                        }
+                    let i = (ty * tile_width + tx) as usize;
+                    let neighbour = &scratch.values[value_range(nx, ny)];
+                    for (sum, value) in scratch.sums[i * channels..(i + 1) * channels].iter_mut().zip(neighbour) {
+                        *sum += weight * value;
                     }
+                    scratch.sum_weights[i] += weight;
+                    scratch.max_weights[i] = weight.max(scratch.max_weights[i]);
                 }
             }
         }
     }
 
-    // Update the center pixels
-    for y in 0..height {
-        for x in 0..width {
-            let i = (y * width + x) as usize;
-            if let Some(center_pixel) = padded_img.get_pixel_checked(x + offset_u32, y + offset_u32) {
-                for c in 0..3 {
-                    sum_image[i][c] += max_weight[i] * center_pixel[c] as f32;
-                }
-                sum_weight[i] += max_weight[i];
-            }
-        }
-    }
-
-    // Final image generation
-    for y in 0..height {
-        for x in 0..width {
-            let i = (y * width + x) as usize;
-            if sum_weight[i] > 0.0 {
-                let pixel = [
-                    (sum_image[i][0] / sum_weight[i]).round().max(0.0).min(255.0) as u8,
-                    (sum_image[i][1] / sum_weight[i]).round().max(0.0).min(255.0) as u8,
-                    (sum_image[i][2] / sum_weight[i]).round().max(0.0).min(255.0) as u8,
-                ];
-                new_img.put_pixel(x, y, Rgb(pixel));
-            } else {
-                new_img.put_pixel(x, y, *img.get_pixel(x, y));
+    // The center pixel counts as much as its most similar neighbour
+    let mut result = Vec::with_capacity(len * channels);
+    for ty in 0..tile_height {
+        for tx in 0..tile_width {
+            let i = (ty * tile_width + tx) as usize;
+            let center = &scratch.values[value_range(x + tx, y + ty)];
+            let weight = scratch.max_weights[i];
+            let sum_weight = scratch.sum_weights[i] + weight;
+            for (sum, value) in scratch.sums[i * channels..(i + 1) * channels].iter().zip(center) {
+                result.push(P::Subpixel::from_f32(((sum + weight * value) / sum_weight).round()));
             }
         }
     }
+    result
 }
 
-fn total_variation(
-    img: &ImageBuffer<Rgb<u8>, Vec<u8>>,
-    new_img: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
+/// Iterations of the fixed point `TotalVariation`, which has no settings of
+/// its own: the lambda and iterations of a `Denoise` are `ChambolleTV`'s.
+pub const TV_ITERATIONS: usize = 50;
+// Weight of the input against the smoothing in `total_variation`, in 8-bit units
+const TV_LAMBDA: f64 = 0.1;
+
+fn total_variation<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
     width: u32,
     height: u32,
-    _lambda: f32,
-    _iterations: usize,
-) {
-    let mut u = vec![vec![vec![0.0f64; 3]; width as usize]; height as usize];
-    let mut u0 = vec![vec![vec![0.0f64; 3]; width as usize]; height as usize];
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    // Only the inner pixels are iterated, with the border copied from them
+    if width < 3 || height < 3 {
+        new_img.copy_from_slice(img);
+        return;
+    }
+
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut u = vec![vec![vec![0.0f64; channels]; width as usize]; height as usize];
+    let mut u0 = vec![vec![vec![0.0f64; channels]; width as usize]; height as usize];
+    // The iteration isn't scale invariant, so it always runs on 8-bit units
+    let scale = P::Subpixel::scale() as f64;
     
     for y in 0..height {
         for x in 0..width {
             let pixel = img.get_pixel(x, y);
-            for c in 0..3 {  // Add this loop to iterate over channels
-                u[y as usize][x as usize][c] = pixel[c] as f64;
-                u0[y as usize][x as usize][c] = pixel[c] as f64;
+            for c in 0..channels {  // Add this loop to iterate over channels
+                u[y as usize][x as usize][c] = pixel.channels()[c].to_f32() as f64 / scale;
+                u0[y as usize][x as usize][c] = pixel.channels()[c].to_f32() as f64 / scale;
             }
         }
     }
 
     let h = 1.0; // Discrete spatial step
-    let lambda = 0.1; // Regularization parameter
-    let iter_max = 50; // Maximum iterations
+    let lambda = TV_LAMBDA;
+    let iter_max = TV_ITERATIONS;
     
+    progress.add_total(iter_max);
     for _ in 0..iter_max {
-        for c in 0..3 {  // Add this loop to iterate over channels
+        if progress.is_cancelled() {
+            return;
+        }
+
+        for c in 0..channels {  // Add this loop to iterate over channels
             for i in 1..height as usize - 1 {
                 for j in 1..width as usize - 1 {
                     let mut ux = (u[i+1][j][c] - u[i][j][c]) / h;
@@ -459,37 +828,260 @@ fn total_variation(
         }
         
         for i in 1..height as usize - 1 {
-            for c in 0..3 {  // Add this loop to iterate over channels
+            for c in 0..channels {  // Add this loop to iterate over channels
                 u[i][0][c] = u[i][1][c];
                 u[i][width as usize - 1][c] = u[i][width as usize - 2][c];
             }
         }
         
         for j in 1..width as usize - 1 {
-            for c in 0..3 {  // Add this loop to iterate over channels
+            for c in 0..channels {  // Add this loop to iterate over channels
                 u[0][j][c] = u[1][j][c];
                 u[height as usize - 1][j][c] = u[height as usize - 2][j][c];
             }
         }
         
-        for c in 0..3 {  // Add this loop to iterate over channels
+        for c in 0..channels {  // Add this loop to iterate over channels
             u[0][0][c] = u[1][1][c];
             u[0][width as usize - 1][c] = u[1][width as usize - 2][c];
             u[height as usize - 1][0][c] = u[height as usize - 2][1][c];
             u[height as usize - 1][width as usize - 1][c] = u[height as usize - 2][width as usize - 2][c];
         }
+        progress.advance(1);
     }
 
     // Convert result back to image
     for y in 0..height {
         for x in 0..width {
-            let pixel = [
-                u[y as usize][x as usize][0].max(0.0).min(255.0) as u8,
-                u[y as usize][x as usize][1].max(0.0).min(255.0) as u8,
-                u[y as usize][x as usize][2].max(0.0).min(255.0) as u8,
-            ];
-            new_img.put_pixel(x, y, Rgb(pixel));
+            let pixel: Vec<P::Subpixel> = u[y as usize][x as usize]
+                .iter()
+                .map(|value| P::Subpixel::from_f32((value * scale).trunc() as f32))
+                .collect();
+            new_img.put_pixel(x, y, *P::from_slice(&pixel));
+        }
+    }
+}
+
+// Chambolle's step size, just below the 1/4 that works in practice
+const CHAMBOLLE_STEP: f32 = 0.248;
+
+/// Total variation denoising (the ROF model) with Chambolle's dual
+/// projection algorithm. `lambda` is the smoothing strength for 0-1
+/// sample values, roughly the noise level it removes.
+///
+/// Iterates until `u` changes by less than `tolerance` relative to its
+/// size, or `max_iterations` is reached, and notes how many it took.
+/// Borders are handled by mirroring, so they are filtered like the rest.
+fn chambolle_tv<P: FilterPixel>(
+    img: &Buffer<P>,
+    new_img: &mut Buffer<P>,
+    lambda: f32,
+    max_iterations: usize,
+    tolerance: f32,
+    progress: &Progress,
+)
+where
+    P::Subpixel: Sample,
+{
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let channels = P::CHANNEL_COUNT as usize;
+    let len = width * height;
+    let lambda = lambda.max(1e-6);
+
+    let f: Vec<Vec<f32>> = (0..channels)
+        .map(|c| img.as_raw().iter().skip(c).step_by(channels).map(|value| value.to_f32() / P::Subpixel::MAX_VALUE).collect())
+        .collect();
+    let mut u = f.clone();
+    let mut px = vec![vec![0.0f32; len]; channels];
+    let mut py = vec![vec![0.0f32; len]; channels];
+    let mut divergence = vec![0.0f32; len];
+    let mut target = vec![0.0f32; len];
+
+    progress.add_total(max_iterations);
+    let mut iterations = max_iterations;
+    for iteration in 0..max_iterations {
+        if progress.is_cancelled() {
+            return;
+        }
+
+        let mut change = 0.0f64;
+        let mut size = 0.0f64;
+        for c in 0..channels {
+            divergence_of(&px[c], &py[c], width, height, &mut divergence);
+            for ((target, divergence), f) in target.iter_mut().zip(&divergence).zip(&f[c]) {
+                *target = divergence - f / lambda;
+            }
+
+            for y in 0..height {
+                for x in 0..width {
+                    let i = y * width + x;
+                    // Forward differences, zero across the border
+                    let gx = if x + 1 < width { target[i + 1] - target[i] } else { 0.0 };
+                    let gy = if y + 1 < height { target[i + width] - target[i] } else { 0.0 };
+                    let norm = 1.0 + CHAMBOLLE_STEP * (gx * gx + gy * gy).sqrt();
+                    px[c][i] = (px[c][i] + CHAMBOLLE_STEP * gx) / norm;
+                    py[c][i] = (py[c][i] + CHAMBOLLE_STEP * gy) / norm;
+                }
+            }
+
+            divergence_of(&px[c], &py[c], width, height, &mut divergence);
+            for ((u, divergence), f) in u[c].iter_mut().zip(&divergence).zip(&f[c]) {
+                let updated = f - lambda * divergence;
+                change += ((updated - *u) as f64).powi(2);
+                size += (updated as f64).powi(2);
+                *u = updated;
+            }
+        }
+        progress.advance(1);
+
+        if change.sqrt() <= tolerance as f64 * size.sqrt().max(f64::EPSILON) {
+            iterations = iteration + 1;
+            progress.advance(max_iterations - iterations);
+            break;
+        }
+    }
+    progress.add_note(format!("Total variation stopped after {} of {} iterations", iterations, max_iterations));
+
+    for (i, value) in new_img.iter_mut().enumerate() {
+        *value = P::Subpixel::from_f32((u[i % channels][i / channels] * P::Subpixel::MAX_VALUE).round());
+    }
+}
+
+// Backward difference divergence, the negative adjoint of the forward
+// difference gradient used by `chambolle_tv`
+pub(super) fn divergence_of(px: &[f32], py: &[f32], width: usize, height: usize, divergence: &mut [f32]) {
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let dx = match x {
+                0 => px[i],
+                _ if x + 1 == width => -px[i - 1],
+                _ => px[i] - px[i - 1],
+            };
+            let dy = match y {
+                0 => py[i],
+                _ if y + 1 == height => -py[i - width],
+                _ => py[i] - py[i - width],
+            };
+            divergence[i] = dx + dy;
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use image::{ColorType, GenericImageView, GrayImage, Luma, LumaA, Rgb, Rgb32FImage, RgbImage};
+
+    use super::*;
+
+    fn flat_images(width: u32, height: u32) -> [DynamicImage; 3] {
+        [
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(width, height, Rgb([90, 140, 201]))),
+            DynamicImage::ImageLuma8(GrayImage::from_pixel(width, height, Luma([77]))),
+            DynamicImage::ImageRgb16(ImageBuffer::from_pixel(width, height, Rgb([12_345, 40_000, 65_535]))),
+        ]
+    }
+
+    #[test]
+    fn total_variation_keeps_flat_images() {
+        for denoise_type in [DenoiseType::TotalVariation, DenoiseType::ChambolleTV] {
+            for (width, height) in [(9, 7), (1, 5), (6, 1), (2, 2), (1, 1)] {
+                for img in flat_images(width, height) {
+                    let denoised = denoise_image(&img, denoise_type, 3, 0.1, 50, 1e-4, BorderMode::Mirror);
+                    assert_eq!(denoised, img, "{denoise_type:?} changed a flat {width}x{height} {:?} image", img.color());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn chroma_denoising_keeps_the_luma() {
+        // Shades of one tint: the chroma is flat, all the detail is in the luma
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(24, 16, |x, y| {
+            let shade = (x * 8 + y * 2) as u8;
+            Rgb([shade + 30, shade + 10, shade])
+        }));
+        let denoised = denoise_ycbcr_with_progress(
+            &img,
+            DenoiseType::MedianFilter,
+            5,
+            0.1,
+            50,
+            1e-4,
+            PlaneStrengths::CHROMA_ONLY,
+            BorderMode::Mirror,
+            &Progress::new(),
+        )
+        .unwrap();
+        for (original, result) in img.to_rgb8().pixels().zip(denoised.to_rgb8().pixels()) {
+            assert!(original.0.iter().zip(result.0).all(|(&a, b)| a.abs_diff(b) <= 1), "{:?} became {:?}", original, result);
+        }
+    }
+
+    // Iterations the last Chambolle run took, from its note
+    fn iterations_used(progress: &Progress) -> usize {
+        let note = progress.notes().pop().expect("Chambolle notes its iterations");
+        note.split_whitespace().find_map(|word| word.parse().ok()).unwrap()
+    }
+
+    #[test]
+    fn chambolle_stops_early_on_smooth_images() {
+        let smooth = DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(32, 24, |x, y| {
+            Rgb([x as f32 / 31.0, y as f32 / 23.0, 0.5])
+        }));
+        let smooth = DynamicImage::ImageRgb16(smooth.to_rgb16());
+        let progress = Progress::new();
+        denoise_image_with_progress(&smooth, DenoiseType::ChambolleTV, 3, 0.05, 500, 1e-4, BorderMode::Mirror, &progress).unwrap();
+        let used = iterations_used(&progress);
+        assert!(used < 500, "a smooth image took all {used} iterations");
+
+        let flat = Progress::new();
+        denoise_image_with_progress(&flat_images(8, 8)[0], DenoiseType::ChambolleTV, 3, 0.1, 500, 1e-4, BorderMode::Mirror, &flat).unwrap();
+        assert_eq!(iterations_used(&flat), 1);
+    }
+
+    #[test]
+    fn gray_images_stay_gray() {
+        let gray = GrayImage::from_fn(13, 9, |x, y| Luma([(x * 17 + y * 5 + (x * y) % 7 * 9) as u8]));
+        let with_alpha = DynamicImage::ImageLumaA8(ImageBuffer::from_fn(13, 9, |x, y| LumaA([gray.get_pixel(x, y).0[0], 200])));
+        let deep = DynamicImage::ImageLuma16(DynamicImage::ImageLuma8(gray.clone()).to_luma16());
+        for denoise_type in [
+            DenoiseType::MeanFilter,
+            DenoiseType::GaussianFilter,
+            DenoiseType::MedianFilter,
+            DenoiseType::BilateralFilter,
+            DenoiseType::NonLocalMeans,
+            DenoiseType::TotalVariation,
+            DenoiseType::ChambolleTV,
+            DenoiseType::BlockMatching,
+            DenoiseType::AdaptiveMedian,
+        ] {
+            // Gray with alpha is filtered as plain gray
+            for (img, expected) in [(DynamicImage::ImageLuma8(gray.clone()), ColorType::L8), (with_alpha.clone(), ColorType::L8), (deep.clone(), ColorType::L16)] {
+                let denoised = denoise_image(&img, denoise_type, 3, 0.1, 50, 1e-4, BorderMode::Mirror);
+                assert_eq!(denoised.color(), expected, "{denoise_type:?} on {:?}", img.color());
+                assert_eq!(denoised.dimensions(), (13, 9));
+            }
+        }
+    }
+
+    #[test]
+    fn the_chroma_kernel_only_sizes_the_color() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(24, 16, |x, y| {
+            let grain = ((x * 37 + y * 91) % 23) as u8;
+            Rgb([90 + grain * 3, 120 - grain, 60 + grain * 2])
+        }));
+        let denoise = |strengths| {
+            denoise_ycbcr_with_progress(&img, DenoiseType::MeanFilter, 3, 0.1, 50, 1e-4, strengths, BorderMode::Mirror, &Progress::new())
+                .unwrap()
+                .to_rgb8()
+        };
+
+        let luma_only = PlaneStrengths { luma: 1.0, chroma: 0.0, chroma_kernel_size: None };
+        let wide_chroma = PlaneStrengths { chroma_kernel_size: Some(9), ..luma_only };
+        assert_eq!(denoise(luma_only), denoise(wide_chroma));
+
+        let chroma_only = PlaneStrengths { chroma_kernel_size: Some(9), ..PlaneStrengths::CHROMA_ONLY };
+        assert_ne!(denoise(PlaneStrengths::CHROMA_ONLY), denoise(chroma_only));
+    }
+}
@@ -0,0 +1,52 @@
+use image::DynamicImage;
+
+use super::blur::gaussian_blur;
+use super::sample::{with_pixel_type, Buffer, FilterPixel, Sample};
+
+/// Puts texture the denoiser removed back on top of its result. Both images
+/// are split into a low pass, blurred over `radius` pixels, and the high
+/// frequencies left over; the result keeps the denoised low pass and takes
+/// `amount` (0-1) of its high frequencies from `original` instead. At 0 the
+/// denoised image is returned unchanged, at 1 all fine detail is the
+/// original's, grain included, over the cleaned base.
+pub fn restore_detail(original: &DynamicImage, denoised: &DynamicImage, amount: f32, radius: f32) -> DynamicImage {
+    if amount <= 0.0 {
+        return denoised.clone();
+    }
+    with_pixel_type!(denoised, |P| restore_detail_at::<P>(original, denoised, amount.min(1.0), radius))
+}
+
+fn restore_detail_at<P: FilterPixel>(original: &DynamicImage, denoised: &DynamicImage, amount: f32, radius: f32) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    // Both are read in the denoised pixel type, should the denoiser have changed it
+    let original = P::from_dynamic(original);
+    let denoised = P::from_dynamic(denoised);
+    let (width, height) = denoised.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let sigma = radius.max(1.0) / 3.0;
+
+    let plane = |img: &Buffer<P>, channel: usize| -> Vec<f32> {
+        img.pixels().map(|pixel| pixel.channels()[channel].to_f32()).collect()
+    };
+
+    let mut new_img = denoised.clone();
+    for channel in 0..channels {
+        let original = plane(&original, channel);
+        let denoised = plane(&denoised, channel);
+        let original_low = gaussian_blur(&original, width, height, sigma);
+        let denoised_low = gaussian_blur(&denoised, width, height, sigma);
+
+        for (index, pixel) in new_img.pixels_mut().enumerate() {
+            // d + w * (high(o) - high(d)), the denoised low pass plus the mix
+            // of both high passes
+            let original_high = original[index] - original_low[index];
+            let denoised_high = denoised[index] - denoised_low[index];
+            let value = denoised[index] + amount * (original_high - denoised_high);
+            pixel.channels_mut()[channel] = P::Subpixel::from_f32(value.round());
+        }
+    }
+
+    P::into_dynamic(new_img)
+}
@@ -0,0 +1,102 @@
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+use super::blur::box_blur;
+
+// Dynamic range of the standard deviation in Sauvola's formula, for values in 0-1
+const SAUVOLA_RANGE: f32 = 0.5;
+
+/// How `binarize_document` picks the threshold of each pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ThresholdMethod {
+    /// Sauvola & Pietikäinen, "Adaptive document image binarization": the
+    /// local mean, lowered in flat areas by `k`
+    Sauvola,
+    /// The local mean minus `c`
+    MeanC,
+}
+
+/// Parameters of `binarize_document`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DocumentSettings {
+    /// Divide out uneven lighting before thresholding
+    pub flatten: bool,
+    /// Radius in pixels of the blur estimating the paper's brightness,
+    /// well above the size of the writing
+    pub flatten_radius: u32,
+    pub method: ThresholdMethod,
+    /// Side in pixels of the window the threshold is taken over
+    pub window: u32,
+    /// Sauvola's sensitivity to local contrast, usually 0.2-0.5
+    pub k: f32,
+    /// Mean-C offset below the local mean, 0-1
+    pub c: f32,
+}
+
+impl Default for DocumentSettings {
+    fn default() -> Self {
+        Self {
+            flatten: true,
+            flatten_radius: 40,
+            method: ThresholdMethod::Sauvola,
+            window: 25,
+            k: 0.2,
+            c: 0.04,
+        }
+    }
+}
+
+impl DocumentSettings {
+    /// How far, in pixels, a pixel's result depends on its neighbours.
+    pub fn context_radius(&self) -> u32 {
+        // The background estimate blurs twice
+        let flatten = if self.flatten { 2 * self.flatten_radius } else { 0 };
+        self.window / 2 + flatten
+    }
+}
+
+/// Turns a scanned page into pure black writing on white: the luma is
+/// optionally divided by a heavily blurred copy of itself to even out the
+/// lighting, then thresholded against statistics of each pixel's window.
+/// Windows are cut off at the image edges. Always returns an 8-bit gray
+/// image holding only 0 and 255.
+pub fn binarize_document(img: &DynamicImage, settings: &DocumentSettings) -> DynamicImage {
+    // Rec. 709 luma, 16-bit sources keep their precision until the threshold
+    let luma = img.to_luma16();
+    let (width, height) = luma.dimensions();
+    if width == 0 || height == 0 {
+        return DynamicImage::ImageLuma8(GrayImage::new(width, height));
+    }
+    let mut plane: Vec<f32> = luma.iter().map(|&value| value as f32 / u16::MAX as f32).collect();
+
+    if settings.flatten {
+        // Box blurring twice comes close to a gaussian at a fraction of the cost
+        let radius = settings.flatten_radius.max(1);
+        let background = box_blur(&box_blur(&plane, width, height, radius), width, height, radius);
+        for (value, background) in plane.iter_mut().zip(&background) {
+            *value = (*value / background.max(1e-3)).min(1.0);
+        }
+    }
+
+    let radius = settings.window / 2;
+    let mean = box_blur(&plane, width, height, radius);
+    let squares: Vec<f32> = plane.iter().map(|value| value * value).collect();
+    let mean_square = box_blur(&squares, width, height, radius);
+
+    let data = plane
+        .iter()
+        .zip(mean.iter().zip(&mean_square))
+        .map(|(&value, (&mean, &mean_square))| {
+            let threshold = match settings.method {
+                ThresholdMethod::Sauvola => {
+                    let deviation = (mean_square - mean * mean).max(0.0).sqrt();
+                    mean * (1.0 + settings.k * (deviation / SAUVOLA_RANGE - 1.0))
+                }
+                ThresholdMethod::MeanC => mean - settings.c,
+            };
+            if value > threshold { u8::MAX } else { 0 }
+        })
+        .collect();
+    DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data).expect("one value per pixel"))
+}
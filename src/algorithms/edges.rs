@@ -0,0 +1,51 @@
+use image::{DynamicImage, GrayImage, Rgb};
+use rayon::prelude::*;
+
+// Share of the edge color in a tinted pixel
+const TINT_STRENGTH: f32 = 0.7;
+const TINT_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Sobel gradient magnitude of the luma, scaled so a hard black to white
+/// step reads 255. Values beyond the edges repeat the nearest edge value.
+pub fn sobel_magnitude(img: &DynamicImage) -> GrayImage {
+    let luma = img.to_luma8();
+    let (width, height) = luma.dimensions();
+    let mut magnitude = GrayImage::new(width, height);
+    if width == 0 || height == 0 {
+        return magnitude;
+    }
+
+    let at = |x: i64, y: i64| {
+        luma.get_pixel(x.clamp(0, width as i64 - 1) as u32, y.clamp(0, height as i64 - 1) as u32)[0] as f32
+    };
+    magnitude
+        .par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as i64;
+            for (x, output) in row.iter_mut().enumerate() {
+                let x = x as i64;
+                let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1) - 2.0 * at(x - 1, y) - at(x - 1, y + 1);
+                let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                    - at(x - 1, y - 1) - 2.0 * at(x, y - 1) - at(x + 1, y - 1);
+                // The kernels weigh a step by 4 on each side
+                *output = (gx.hypot(gy) / 4.0).round().min(255.0) as u8;
+            }
+        });
+    magnitude
+}
+
+/// `img` with the pixels whose gradient `magnitude` exceeds `threshold`
+/// tinted red.
+pub fn tint_edges(img: &DynamicImage, magnitude: &GrayImage, threshold: u8) -> DynamicImage {
+    let mut tinted = img.to_rgb8();
+    for (pixel, edge) in tinted.pixels_mut().zip(magnitude.pixels()) {
+        if edge[0] > threshold {
+            for (value, &tint) in pixel.0.iter_mut().zip(&TINT_COLOR.0) {
+                *value = (*value as f32 + TINT_STRENGTH * (tint as f32 - *value as f32)).round() as u8;
+            }
+        }
+    }
+    DynamicImage::ImageRgb8(tinted)
+}
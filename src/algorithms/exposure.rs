@@ -0,0 +1,69 @@
+use super::colorspace::{linear_to_srgb, srgb_to_linear};
+use super::sample::Sample;
+
+// Linear values above this are compressed towards white instead of clipping
+const SHOULDER_START: f32 = 0.8;
+
+/// `value` with its light scaled by 2^`ev`, like changing the exposure time
+/// of a camera by `ev` stops.
+pub fn exposure_value<S: Sample>(value: S, ev: f32) -> S {
+    let max = S::MAX_VALUE;
+    let gain = 2f32.powf(ev);
+    let linear = srgb_to_linear(value.to_f32() / max) * gain;
+    S::from_f32(linear_to_srgb(shoulder(linear, gain)) * max + 0.5)
+}
+
+// Rolls linear values above `SHOULDER_START` off smoothly so that `gain`,
+// what white is brightened to, lands on white instead of clipping. Nothing
+// can go past white when `gain` is at most 1, the values stay as they are.
+fn shoulder(linear: f32, gain: f32) -> f32 {
+    if gain <= 1.0 || linear <= SHOULDER_START {
+        return linear;
+    }
+    // Extended Reinhard on the part above the start: slope 1 where it
+    // begins, white at `gain`, and the identity as `gain` approaches 1
+    let range = 1.0 - SHOULDER_START;
+    let (t, white) = ((linear - SHOULDER_START) / range, (gain - SHOULDER_START) / range);
+    SHOULDER_START + range * (t * (1.0 + t / (white * white)) / (1.0 + t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_ev_is_exact() {
+        for value in 0..=255u8 {
+            assert_eq!(exposure_value(value, 0.0), value);
+        }
+        for value in (0..=u16::MAX).step_by(7).chain([u16::MAX]) {
+            assert_eq!(exposure_value(value, 0.0), value);
+        }
+    }
+
+    #[test]
+    fn white_stays_white_near_zero_ev() {
+        for ev in [0.01, 0.5, 1.0, 3.0] {
+            assert_eq!(exposure_value(255u8, ev), 255, "at {ev} EV");
+            assert_eq!(exposure_value(u16::MAX, ev), u16::MAX, "at {ev} EV");
+        }
+        assert_eq!(exposure_value(255u8, -0.01), 254);
+    }
+
+    #[test]
+    fn one_ev_doubles_mid_gray() {
+        // 18% gray in linear light
+        let mid_gray = (linear_to_srgb(0.18) * 65535.0).round() as u16;
+        let brightened = exposure_value(mid_gray, 1.0);
+        let ratio = srgb_to_linear(brightened as f32 / 65535.0) / srgb_to_linear(mid_gray as f32 / 65535.0);
+        assert!((ratio - 2.0).abs() < 1e-3, "+1 EV scaled linear light by {ratio}");
+    }
+
+    #[test]
+    fn brightening_stays_monotonic() {
+        for ev in [0.01, 0.5, 2.0] {
+            let curve: Vec<u8> = (0..=255u8).map(|value| exposure_value(value, ev)).collect();
+            assert!(curve.windows(2).all(|pair| pair[0] <= pair[1]), "not monotonic at {ev} EV");
+        }
+    }
+}
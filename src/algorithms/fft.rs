@@ -0,0 +1,309 @@
+use std::ops::{Add, Mul, Sub};
+
+use rayon::prelude::*;
+
+/// A complex number, only as much of one as the transforms need.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub const ZERO: Complex = Complex { re: 0.0, im: 0.0 };
+
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn norm(self) -> f32 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn scale(self, factor: f32) -> Self {
+        Self::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re)
+    }
+}
+
+// Radix-2 transform of one power of two length, with its twiddle factors
+// computed once for every row or column of that length
+struct Fft {
+    twiddles: Vec<Complex>,
+}
+
+impl Fft {
+    fn new(len: usize) -> Self {
+        debug_assert!(len.is_power_of_two());
+        // Computed in double precision, the error of every later butterfly builds on them
+        let twiddles = (0..len / 2)
+            .map(|k| {
+                let angle = -2.0 * std::f64::consts::PI * k as f64 / len as f64;
+                Complex::new(angle.cos() as f32, angle.sin() as f32)
+            })
+            .collect();
+        Self { twiddles }
+    }
+
+    fn forward(&self, data: &mut [Complex]) {
+        let len = data.len();
+        if len < 2 {
+            return;
+        }
+        let bits = len.trailing_zeros();
+        for i in 0..len {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= len {
+            let half = size / 2;
+            let stride = len / size;
+            for block in data.chunks_exact_mut(size) {
+                let (low, high) = block.split_at_mut(half);
+                for (k, (a, b)) in low.iter_mut().zip(high).enumerate() {
+                    let t = *b * self.twiddles[k * stride];
+                    *b = *a - t;
+                    *a = *a + t;
+                }
+            }
+            size *= 2;
+        }
+    }
+
+    // Unscaled, the caller divides by the number of samples
+    fn inverse(&self, data: &mut [Complex]) {
+        data.iter_mut().for_each(|value| *value = value.conj());
+        self.forward(data);
+        data.iter_mut().for_each(|value| *value = value.conj());
+    }
+}
+
+/// 2D spectrum of a real plane. Only the non-negative horizontal
+/// frequencies are kept, the others are their complex conjugates.
+#[derive(Debug, Clone)]
+pub struct RealSpectrum {
+    /// Dimensions of the plane that was transformed
+    pub plane_width: usize,
+    pub plane_height: usize,
+    /// Dimensions it was mirrored out to, powers of two
+    pub width: usize,
+    pub height: usize,
+    // `height` bins for each of the `width / 2 + 1` horizontal frequencies,
+    // column after column
+    bins: Vec<Complex>,
+}
+
+impl RealSpectrum {
+    /// Number of horizontal frequencies kept, 0 up to the Nyquist frequency.
+    pub fn columns(&self) -> usize {
+        self.width / 2 + 1
+    }
+
+    /// Frequency of bin (`column`, `row`) in cycles per pixel, horizontally
+    /// 0 to 0.5 and vertically -0.5 to 0.5.
+    pub fn frequency(&self, column: usize, row: usize) -> (f32, f32) {
+        frequency(self.width, self.height, column, row)
+    }
+
+    pub fn bin(&self, column: usize, row: usize) -> Complex {
+        self.bins[column * self.height + row]
+    }
+
+    /// Multiplies every bin by `gain` of its frequency. The hidden half
+    /// mirrors the kept one, so `gain` should be point symmetric for the
+    /// result to mean anything.
+    pub fn apply_gain(&mut self, gain: impl Fn(f32, f32) -> f32 + Sync) {
+        let (width, height) = (self.width, self.height);
+        self.bins.par_chunks_mut(height).enumerate().for_each(|(column, bins)| {
+            for (row, bin) in bins.iter_mut().enumerate() {
+                let (fx, fy) = frequency(width, height, column, row);
+                *bin = bin.scale(gain(fx, fy));
+            }
+        });
+    }
+}
+
+fn frequency(width: usize, height: usize, column: usize, row: usize) -> (f32, f32) {
+    let row = if row < height / 2 { row as f32 } else { row as f32 - height as f32 };
+    (column as f32 / width as f32, row / height as f32)
+}
+
+// Index into a plane of `len` samples mirrored out past its end, for
+// indices up to twice `len`. The edge sample repeats, which keeps the
+// padding free of a jump.
+fn mirror(index: usize, len: usize) -> usize {
+    if index < len {
+        index
+    } else {
+        2 * len - 1 - index
+    }
+}
+
+/// Transforms the `width` x `height` `plane`, mirrored out to the next
+/// powers of two. Rows are transformed two at a time as the real and
+/// imaginary part of one complex transform.
+pub fn forward_real(plane: &[f32], width: usize, height: usize) -> RealSpectrum {
+    assert_eq!(plane.len(), width * height, "plane size doesn't match its dimensions");
+    let padded_width = width.max(1).next_power_of_two();
+    let padded_height = height.max(1).next_power_of_two();
+    let columns = padded_width / 2 + 1;
+    let row_fft = Fft::new(padded_width);
+
+    // Row spectra, row after row
+    let mut rows = vec![Complex::ZERO; columns * padded_height];
+    if width > 0 && height > 0 {
+        rows.par_chunks_mut(2 * columns).enumerate().for_each(|(pair, spectra)| {
+            let source = |row: usize| {
+                let y = mirror(row, height);
+                &plane[y * width..(y + 1) * width]
+            };
+            let first = source(2 * pair);
+            let second = (2 * pair + 1 < padded_height).then(|| source(2 * pair + 1));
+            let mut packed: Vec<Complex> = (0..padded_width)
+                .map(|x| {
+                    let x = mirror(x, width);
+                    Complex::new(first[x], second.map_or(0.0, |second| second[x]))
+                })
+                .collect();
+            row_fft.forward(&mut packed);
+
+            // Both rows being real, their spectra are the conjugate
+            // symmetric and antisymmetric parts of the packed one
+            let (first_spectrum, second_spectrum) = spectra.split_at_mut(columns);
+            for k in 0..columns {
+                let z = packed[k];
+                let mirrored = packed[(padded_width - k) % padded_width].conj();
+                first_spectrum[k] = (z + mirrored).scale(0.5);
+                let difference = z - mirrored;
+                if let Some(bin) = second_spectrum.get_mut(k) {
+                    *bin = Complex::new(difference.im, -difference.re).scale(0.5);
+                }
+            }
+        });
+    }
+
+    // Columns are stored contiguously for their transforms
+    let mut bins = vec![Complex::ZERO; columns * padded_height];
+    let column_fft = Fft::new(padded_height);
+    bins.par_chunks_mut(padded_height).enumerate().for_each(|(column, bins)| {
+        for (row, bin) in bins.iter_mut().enumerate() {
+            *bin = rows[row * columns + column];
+        }
+        column_fft.forward(bins);
+    });
+
+    RealSpectrum {
+        plane_width: width,
+        plane_height: height,
+        width: padded_width,
+        height: padded_height,
+        bins,
+    }
+}
+
+/// Transforms `spectrum` back to a plane, cropped to the size it came from.
+pub fn inverse_real(mut spectrum: RealSpectrum) -> Vec<f32> {
+    let (width, height) = (spectrum.plane_width, spectrum.plane_height);
+    let (padded_width, padded_height) = (spectrum.width, spectrum.height);
+    let columns = spectrum.columns();
+    let column_fft = Fft::new(padded_height);
+    spectrum.bins.par_chunks_mut(padded_height).for_each(|bins| column_fft.inverse(bins));
+
+    let mut plane = vec![0.0f32; width * height];
+    if width == 0 || height == 0 {
+        return plane;
+    }
+    let row_fft = Fft::new(padded_width);
+    let scale = 1.0 / (padded_width * padded_height) as f32;
+    let bins = &spectrum.bins;
+    let row_bin = |row: usize, k: usize| {
+        if k < columns {
+            bins[k * padded_height + row]
+        } else {
+            bins[(padded_width - k) * padded_height + row].conj()
+        }
+    };
+    // Only the rows of the plane are needed, still two at a time
+    plane.par_chunks_mut(2 * width).enumerate().for_each(|(pair, output)| {
+        let (first, second) = (2 * pair, 2 * pair + 1);
+        let mut packed: Vec<Complex> = (0..padded_width)
+            .map(|k| {
+                let b = if second < padded_height { row_bin(second, k) } else { Complex::ZERO };
+                row_bin(first, k) + Complex::new(-b.im, b.re)
+            })
+            .collect();
+        row_fft.inverse(&mut packed);
+
+        let (first_row, second_row) = output.split_at_mut(width);
+        for (x, value) in first_row.iter_mut().enumerate() {
+            *value = packed[x].re * scale;
+        }
+        for (x, value) in second_row.iter_mut().enumerate() {
+            *value = packed[x].im * scale;
+        }
+    });
+    plane
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn planes_survive_the_round_trip() {
+        for (width, height) in [(1, 1), (8, 4), (37, 23), (1, 17), (64, 3)] {
+            let plane: Vec<f32> = (0..width * height).map(|i| ((i * 37) % 101) as f32 * 2.5).collect();
+            let back = inverse_real(forward_real(&plane, width, height));
+            assert_eq!(back.len(), plane.len());
+            for (i, (before, after)) in plane.iter().zip(&back).enumerate() {
+                assert!((before - after).abs() < 1e-3, "{}x{}: sample {} went from {} to {}", width, height, i, before, after);
+            }
+        }
+    }
+
+    #[test]
+    fn a_cosine_is_a_single_bin() {
+        // Period 8 across a plane as wide as the transform, nothing to pad
+        let (width, height) = (32, 16);
+        let plane: Vec<f32> = (0..width * height).map(|i| (2.0 * std::f32::consts::PI * (i % width) as f32 / 8.0).cos()).collect();
+        let spectrum = forward_real(&plane, width, height);
+        for column in 0..spectrum.columns() {
+            for row in 0..spectrum.height {
+                let expected = if (column, row) == (4, 0) { (width * height / 2) as f32 } else { 0.0 };
+                assert!((spectrum.bin(column, row).norm() - expected).abs() < 1e-2, "bin ({}, {})", column, row);
+            }
+        }
+        assert_eq!(spectrum.frequency(4, 0), (0.125, 0.0));
+    }
+}
@@ -0,0 +1,211 @@
+use image::{DynamicImage, Rgb};
+use rayon::prelude::*;
+
+// Darkest mean luma, on a 0-1 scale, the metric is normalized by. Keeps
+// nearly black images from reading as razor sharp.
+const MIN_MEAN: f32 = 1.0 / 255.0;
+// Share of the heat color in a tinted pixel
+const HEAT_STRENGTH: f32 = 0.55;
+const SOFT_COLOR: Rgb<u8> = Rgb([40, 80, 255]);
+const SHARP_COLOR: Rgb<u8> = Rgb([255, 40, 0]);
+
+/// Sharpness of each tile of a grid laid over the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharpnessMap {
+    /// Side of a tile in pixels, the last row and column may be cut short
+    pub tile: u32,
+    pub columns: u32,
+    pub rows: u32,
+    /// `sharpness_metric` of each tile, row by row
+    pub values: Vec<f32>,
+}
+
+// Sums of a region, merged into tiles and the whole image
+#[derive(Debug, Clone, Copy, Default)]
+struct LaplacianSums {
+    count: f64,
+    laplacian: f64,
+    laplacian_square: f64,
+    luma: f64,
+}
+
+impl LaplacianSums {
+    fn add(&mut self, other: &LaplacianSums) {
+        self.count += other.count;
+        self.laplacian += other.laplacian;
+        self.laplacian_square += other.laplacian_square;
+        self.luma += other.luma;
+    }
+
+    // Variance of the Laplacian over the squared mean luma: scaling the
+    // brightness scales both alike, so only the detail is left
+    fn metric(&self) -> f32 {
+        if self.count == 0.0 {
+            return 0.0;
+        }
+        let mean = self.laplacian / self.count;
+        let variance = (self.laplacian_square / self.count - mean * mean).max(0.0);
+        let luma = (self.luma / self.count).max(MIN_MEAN as f64);
+        (variance / (luma * luma)) as f32
+    }
+}
+
+/// Variance of the Laplacian of the luma, divided by the squared mean
+/// luma so it doesn't change with the exposure. Low values mean a blurry
+/// image: photos in focus score around 0.01, a pixel of blur divides that
+/// by about ten. Noise counts as detail too.
+pub fn sharpness_metric(img: &DynamicImage) -> f32 {
+    let rows = row_sums(img, img.width().max(1));
+    let mut total = LaplacianSums::default();
+    rows.iter().flatten().for_each(|sums| total.add(sums));
+    total.metric()
+}
+
+/// `sharpness_metric` of every `tile` x `tile` block of `img`.
+pub fn sharpness_map(img: &DynamicImage, tile: u32) -> SharpnessMap {
+    let tile = tile.max(1);
+    let columns = img.width().div_ceil(tile);
+    let rows = img.height().div_ceil(tile);
+    let row_sums = row_sums(img, tile);
+
+    let mut tiles = vec![LaplacianSums::default(); (columns * rows) as usize];
+    for (y, sums) in row_sums.iter().enumerate() {
+        let row = y as u32 / tile;
+        for (column, sums) in sums.iter().enumerate() {
+            tiles[(row * columns) as usize + column].add(sums);
+        }
+    }
+    SharpnessMap {
+        tile,
+        columns,
+        rows,
+        values: tiles.iter().map(LaplacianSums::metric).collect(),
+    }
+}
+
+/// `img` with each tile of `map` tinted from blue where it is soft to red
+/// where it is sharpest. Tiles are compared with each other, so the
+/// sharpest part of even a blurry image shows red.
+pub fn heat_overlay(img: &DynamicImage, map: &SharpnessMap) -> DynamicImage {
+    let mut tinted = img.to_rgb8();
+    // The metric spans orders of magnitude, its logarithm spreads the colors
+    let heat: Vec<f32> = map.values.iter().map(|value| value.max(f32::EPSILON).ln()).collect();
+    let (low, high) = heat
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), &value| (low.min(value), high.max(value)));
+    let range = (high - low).max(f32::EPSILON);
+
+    let width = tinted.width() as usize;
+    if width == 0 {
+        return DynamicImage::ImageRgb8(tinted);
+    }
+    tinted.par_chunks_mut(width * 3).enumerate().for_each(|(y, row)| {
+        let tile_row = y as u32 / map.tile;
+        for (x, pixel) in row.chunks_mut(3).enumerate() {
+            let t = (heat[(tile_row * map.columns + x as u32 / map.tile) as usize] - low) / range;
+            for ((value, &soft), &sharp) in pixel.iter_mut().zip(&SOFT_COLOR.0).zip(&SHARP_COLOR.0) {
+                let color = soft as f32 + t * (sharp as f32 - soft as f32);
+                *value = (*value as f32 + HEAT_STRENGTH * (color - *value as f32)).round() as u8;
+            }
+        }
+    });
+    DynamicImage::ImageRgb8(tinted)
+}
+
+// Laplacian sums of each image row, split into `tile` wide stretches.
+// Values beyond the edges repeat the nearest edge value.
+fn row_sums(img: &DynamicImage, tile: u32) -> Vec<Vec<LaplacianSums>> {
+    let luma = img.to_luma8();
+    let (width, height) = luma.dimensions();
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+
+    let at = |x: i64, y: i64| luma.get_pixel(x.clamp(0, width as i64 - 1) as u32, y.clamp(0, height as i64 - 1) as u32)[0] as f64;
+    (0..height as i64)
+        .into_par_iter()
+        .map(|y| {
+            let mut sums = vec![LaplacianSums::default(); width.div_ceil(tile) as usize];
+            for x in 0..width as i64 {
+                let center = at(x, y);
+                let laplacian = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * center;
+                let stretch = &mut sums[x as usize / tile as usize];
+                stretch.count += 1.0;
+                stretch.laplacian += laplacian / 255.0;
+                stretch.laplacian_square += (laplacian / 255.0).powi(2);
+                stretch.luma += center / 255.0;
+            }
+            sums
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma};
+
+    use super::*;
+    use crate::algorithms::border::BorderMode;
+    use crate::algorithms::denoise::{denoise_image, DenoiseType};
+
+    // Squares of 6 pixels under fine stripes, between 40 and 160 so a
+    // brighter copy doesn't clip
+    fn sharp() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(96, 64, |x, y| {
+            let square = match (x / 6 + y / 6) % 2 {
+                0 => 40,
+                _ => 120,
+            };
+            Luma([(square + (x + 2 * y) % 5 * 10) as u8])
+        }))
+    }
+
+    fn blurred(img: &DynamicImage, kernel_size: usize) -> DynamicImage {
+        denoise_image(img, DenoiseType::GaussianFilter, kernel_size, 0.0, 0, 0.0, BorderMode::Mirror)
+    }
+
+    // `img` with every value multiplied by `gain`
+    fn scaled(img: &DynamicImage, gain: f32) -> DynamicImage {
+        let mut scaled = img.to_luma8();
+        scaled.pixels_mut().for_each(|pixel| pixel.0[0] = (pixel.0[0] as f32 * gain).round() as u8);
+        DynamicImage::ImageLuma8(scaled)
+    }
+
+    #[test]
+    fn sharp_ranks_above_blurred() {
+        let metrics: Vec<f32> = [sharp(), blurred(&sharp(), 3), blurred(&sharp(), 5), blurred(&blurred(&sharp(), 7), 7)]
+            .iter()
+            .map(sharpness_metric)
+            .collect();
+        assert!(metrics.windows(2).all(|pair| pair[0] > 2.0 * pair[1]), "{:?}", metrics);
+    }
+
+    #[test]
+    fn brightness_leaves_the_metric_alone() {
+        for img in [sharp(), blurred(&sharp(), 3)] {
+            let metric = sharpness_metric(&img);
+            for gain in [0.6, 1.5] {
+                let brighter = sharpness_metric(&scaled(&img, gain));
+                assert!((brighter / metric - 1.0).abs() < 0.05, "{} at {}x, {} at 1x", brighter, gain, metric);
+            }
+        }
+    }
+
+    #[test]
+    fn the_map_finds_the_blurred_half() {
+        let mut img = sharp().to_luma8();
+        let soft = blurred(&sharp(), 7).to_luma8();
+        for (x, y, pixel) in img.enumerate_pixels_mut().filter(|(x, _, _)| *x >= 48) {
+            *pixel = *soft.get_pixel(x, y);
+        }
+        let map = sharpness_map(&DynamicImage::ImageLuma8(img), 16);
+        assert_eq!((map.columns, map.rows), (6, 4));
+        // Tiles clear of the seam, which is an edge of its own
+        for row in map.values.chunks(6) {
+            let (left, right) = (&row[..2], &row[4..]);
+            let softest_left = left.iter().copied().fold(f32::INFINITY, f32::min);
+            let sharpest_right = right.iter().copied().fold(0.0, f32::max);
+            assert!(softest_left > 2.0 * sharpest_right, "{:?}", row);
+        }
+    }
+}
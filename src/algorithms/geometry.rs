@@ -0,0 +1,487 @@
+use image::imageops::FilterType;
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, Pixel, Rgba32FImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::region::Region;
+
+/// Resampling filter used when resizing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ResampleFilter {
+    Nearest,
+    Bilinear,
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    pub const ALL: [ResampleFilter; 3] = [
+        ResampleFilter::Nearest,
+        ResampleFilter::Bilinear,
+        ResampleFilter::Lanczos3,
+    ];
+
+    fn filter_type(self) -> FilterType {
+        match self {
+            ResampleFilter::Nearest => FilterType::Nearest,
+            ResampleFilter::Bilinear => FilterType::Triangle,
+            ResampleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// Target size of a resize operation.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResizeSettings {
+    pub width: u32,
+    pub height: u32,
+    pub filter: ResampleFilter,
+}
+
+/// Resamples `img` to exactly the requested size (zero sizes become one pixel).
+pub fn resize(img: &DynamicImage, settings: &ResizeSettings) -> DynamicImage {
+    let width = settings.width.max(1);
+    let height = settings.height.max(1);
+    if img.dimensions() == (width, height) {
+        return img.clone();
+    }
+    img.resize_exact(width, height, settings.filter.filter_type())
+}
+
+/// Cuts the `w`×`h` rectangle at (`x`, `y`) out of `img`.
+///
+/// A rectangle reaching outside the image is clamped to it, and the result is
+/// always at least one pixel in each direction for a non-empty image.
+pub fn crop(img: &DynamicImage, x: u32, y: u32, w: u32, h: u32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return img.clone();
+    }
+
+    let x = x.min(width - 1);
+    let y = y.min(height - 1);
+    let region = Region::new(x, y, w.max(1), h.max(1)).clamp_to(width, height);
+    img.crop_imm(region.x, region.y, region.width, region.height)
+}
+
+/// Largest angle, either way, `rotate` is meant for. Straightening never
+/// needs more, larger turns are what the 90° rotations are for.
+pub const MAX_ROTATE_ANGLE: f32 = 45.0;
+
+/// Sampling used when rotating by an arbitrary angle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RotateInterpolation {
+    Bilinear,
+    /// Catmull-Rom, sharper than bilinear but may ring slightly at hard edges
+    Bicubic,
+}
+
+impl RotateInterpolation {
+    pub const ALL: [RotateInterpolation; 2] = [RotateInterpolation::Bilinear, RotateInterpolation::Bicubic];
+}
+
+/// A rotation by an arbitrary angle, see `rotate`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RotateSettings {
+    /// In degrees, positive turns clockwise
+    pub angle: f32,
+    pub interpolation: RotateInterpolation,
+    /// Crop to the largest axis-aligned rectangle inside the rotated image
+    /// instead of growing the canvas to hold all of it
+    pub auto_crop: bool,
+    /// RGBA color of the corners a grown canvas adds
+    pub fill: [u8; 4],
+}
+
+impl Default for RotateSettings {
+    fn default() -> Self {
+        Self {
+            angle: 0.0,
+            interpolation: RotateInterpolation::Bicubic,
+            auto_crop: true,
+            fill: [255, 255, 255, 255],
+        }
+    }
+}
+
+/// Size of the image `rotate` makes out of a `width`×`height` one.
+pub fn rotated_size(width: u32, height: u32, settings: &RotateSettings) -> (u32, u32) {
+    if settings.angle == 0.0 || width == 0 || height == 0 {
+        return (width, height);
+    }
+    let (width, height) = (width as f32, height as f32);
+    let (sin, cos) = settings.angle.to_radians().sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    if settings.auto_crop {
+        let (crop_width, crop_height) = inscribed_size(width, height, sin, cos);
+        (crop_width.floor().max(1.0) as u32, crop_height.floor().max(1.0) as u32)
+    } else {
+        // The tolerance keeps float error from adding a row at tiny angles
+        let grown = |a: f32, b: f32| (a * cos + b * sin - 1e-3).ceil() as u32;
+        (grown(width, height), grown(height, width))
+    }
+}
+
+// Largest axis-aligned rectangle inside a `width`×`height` one turned by an
+// angle with the given absolute sine and cosine
+fn inscribed_size(width: f32, height: f32, sin: f32, cos: f32) -> (f32, f32) {
+    let (long, short) = if width >= height { (width, height) } else { (height, width) };
+    if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-6 {
+        // Two corners touch the long sides, the rectangle is limited by the short side
+        let half = 0.5 * short;
+        if width >= height {
+            (half / sin, half / cos)
+        } else {
+            (half / cos, half / sin)
+        }
+    } else {
+        let cos_2a = cos * cos - sin * sin;
+        ((width * cos - height * sin) / cos_2a, (height * cos - width * sin) / cos_2a)
+    }
+}
+
+/// Rotates `img` about its center by `settings.angle`, either growing the
+/// canvas and filling the new corners with `settings.fill`, or cropping to
+/// the largest rectangle without any, see `rotated_size`.
+///
+/// The result keeps the pixel format of `img`. A 0° rotation returns an
+/// exact copy.
+pub fn rotate(img: &DynamicImage, settings: &RotateSettings) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if settings.angle == 0.0 || width == 0 || height == 0 {
+        return img.clone();
+    }
+    let (out_width, out_height) = rotated_size(width, height, settings);
+    let rotated = rotate_rgba(&img.to_rgba32f(), out_width, out_height, settings);
+    convert_to(DynamicImage::ImageRgba32F(rotated), img.color())
+}
+
+fn rotate_rgba(src: &Rgba32FImage, out_width: u32, out_height: u32, settings: &RotateSettings) -> Rgba32FImage {
+    let (width, height) = src.dimensions();
+    let (sin, cos) = settings.angle.to_radians().sin_cos();
+    let fill = settings.fill.map(|value| value as f32 / 255.0);
+    let pixels = src.as_raw();
+
+    // Outside the source a grown canvas shows the fill color, so its edges
+    // blend into it. A cropped result never reaches that far except for
+    // rounding, there the edge is repeated instead.
+    let tap = |x: i64, y: i64| -> [f32; 4] {
+        let inside = x >= 0 && y >= 0 && x < width as i64 && y < height as i64;
+        if !inside && !settings.auto_crop {
+            return fill;
+        }
+        let x = x.clamp(0, width as i64 - 1) as usize;
+        let y = y.clamp(0, height as i64 - 1) as usize;
+        let index = (y * width as usize + x) * 4;
+        [pixels[index], pixels[index + 1], pixels[index + 2], pixels[index + 3]]
+    };
+
+    let mut data = vec![0.0; out_width as usize * out_height as usize * 4];
+    data.par_chunks_mut(out_width as usize * 4).enumerate().for_each(|(y, row)| {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            // Turn the output pixel center back into the source, both
+            // centered on the image center
+            let dx = x as f32 + 0.5 - out_width as f32 / 2.0;
+            let dy = y as f32 + 0.5 - out_height as f32 / 2.0;
+            let u = dx * cos + dy * sin + width as f32 / 2.0 - 0.5;
+            let v = -dx * sin + dy * cos + height as f32 / 2.0 - 0.5;
+            let value = match settings.interpolation {
+                RotateInterpolation::Bilinear => sample_bilinear(u, v, tap),
+                RotateInterpolation::Bicubic => sample_bicubic(u, v, tap),
+            };
+            pixel.copy_from_slice(&value);
+        }
+    });
+    ImageBuffer::from_raw(out_width, out_height, data).expect("rotated buffer has the output size")
+}
+
+fn sample_bilinear(u: f32, v: f32, tap: impl Fn(i64, i64) -> [f32; 4]) -> [f32; 4] {
+    let (x0, y0) = (u.floor(), v.floor());
+    let (fx, fy) = (u - x0, v - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let weights = [(0, 0, (1.0 - fx) * (1.0 - fy)), (1, 0, fx * (1.0 - fy)), (0, 1, (1.0 - fx) * fy), (1, 1, fx * fy)];
+
+    let mut sum = [0.0; 4];
+    for (dx, dy, weight) in weights {
+        let value = tap(x0 + dx, y0 + dy);
+        for c in 0..4 {
+            sum[c] += value[c] * weight;
+        }
+    }
+    sum
+}
+
+fn sample_bicubic(u: f32, v: f32, tap: impl Fn(i64, i64) -> [f32; 4]) -> [f32; 4] {
+    let (x0, y0) = (u.floor(), v.floor());
+    let (fx, fy) = (u - x0, v - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let weights_x = [-1.0, 0.0, 1.0, 2.0].map(|offset: f32| catmull_rom(offset - fx));
+    let weights_y = [-1.0, 0.0, 1.0, 2.0].map(|offset: f32| catmull_rom(offset - fy));
+
+    let mut sum = [0.0; 4];
+    for (dy, weight_y) in (-1..=2).zip(weights_y) {
+        for (dx, weight_x) in (-1..=2).zip(weights_x) {
+            let value = tap(x0 + dx, y0 + dy);
+            for c in 0..4 {
+                sum[c] += value[c] * weight_x * weight_y;
+            }
+        }
+    }
+    sum
+}
+
+fn catmull_rom(t: f32) -> f32 {
+    let t = t.abs();
+    if t < 1.0 {
+        1.5 * t * t * t - 2.5 * t * t + 1.0
+    } else if t < 2.0 {
+        -0.5 * t * t * t + 2.5 * t * t - 4.0 * t + 2.0
+    } else {
+        0.0
+    }
+}
+
+// Converts a working copy back to the format the image came in
+fn convert_to(img: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(img.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(img.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(img.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(img.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(img.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(img.to_rgb32f()),
+        ColorType::Rgba32F => img,
+        _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+    }
+}
+
+// Applies a buffer transform to whatever pixel format the image is stored in,
+// so no conversion (and no precision loss) happens on the way.
+macro_rules! map_buffer {
+    ($img:expr, $buffer:ident => $transform:expr) => {
+        match $img {
+            DynamicImage::ImageLuma8($buffer) => DynamicImage::ImageLuma8($transform),
+            DynamicImage::ImageLumaA8($buffer) => DynamicImage::ImageLumaA8($transform),
+            DynamicImage::ImageRgb8($buffer) => DynamicImage::ImageRgb8($transform),
+            DynamicImage::ImageRgba8($buffer) => DynamicImage::ImageRgba8($transform),
+            DynamicImage::ImageLuma16($buffer) => DynamicImage::ImageLuma16($transform),
+            DynamicImage::ImageLumaA16($buffer) => DynamicImage::ImageLumaA16($transform),
+            DynamicImage::ImageRgb16($buffer) => DynamicImage::ImageRgb16($transform),
+            DynamicImage::ImageRgba16($buffer) => DynamicImage::ImageRgba16($transform),
+            DynamicImage::ImageRgb32F($buffer) => DynamicImage::ImageRgb32F($transform),
+            DynamicImage::ImageRgba32F($buffer) => DynamicImage::ImageRgba32F($transform),
+            other => {
+                let $buffer = &other.to_rgba8();
+                DynamicImage::ImageRgba8($transform)
+            }
+        }
+    };
+}
+
+type Buffer<P> = ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>;
+
+/// Rotates 90° clockwise.
+pub fn rotate_right(img: &DynamicImage) -> DynamicImage {
+    map_buffer!(img, buffer => rotate_buffer_right(buffer))
+}
+
+/// Rotates 90° counter-clockwise.
+pub fn rotate_left(img: &DynamicImage) -> DynamicImage {
+    map_buffer!(img, buffer => rotate_buffer_left(buffer))
+}
+
+pub fn rotate_180(img: &DynamicImage) -> DynamicImage {
+    map_buffer!(img, buffer => rotate_buffer_180(buffer))
+}
+
+/// Mirrors left to right.
+pub fn flip_horizontal(img: &DynamicImage) -> DynamicImage {
+    map_buffer!(img, buffer => flip_buffer_horizontal(buffer))
+}
+
+/// Mirrors top to bottom.
+pub fn flip_vertical(img: &DynamicImage) -> DynamicImage {
+    map_buffer!(img, buffer => flip_buffer_vertical(buffer))
+}
+
+fn rotate_buffer_right<P: Pixel>(buffer: &Buffer<P>) -> Buffer<P> {
+    let (width, height) = buffer.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let src = buffer.as_raw();
+    let mut data = Vec::with_capacity(src.len());
+
+    // Output row y is source column y read from the bottom up
+    for y in 0..width as usize {
+        for x in 0..height as usize {
+            let idx = ((height as usize - 1 - x) * width as usize + y) * channels;
+            data.extend_from_slice(&src[idx..idx + channels]);
+        }
+    }
+
+    ImageBuffer::from_raw(height, width, data).expect("rotated buffer has the source length")
+}
+
+fn rotate_buffer_left<P: Pixel>(buffer: &Buffer<P>) -> Buffer<P> {
+    let (width, height) = buffer.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let src = buffer.as_raw();
+    let mut data = Vec::with_capacity(src.len());
+
+    // Output row y is source column (width - 1 - y) read from the top down
+    for y in 0..width as usize {
+        for x in 0..height as usize {
+            let idx = (x * width as usize + (width as usize - 1 - y)) * channels;
+            data.extend_from_slice(&src[idx..idx + channels]);
+        }
+    }
+
+    ImageBuffer::from_raw(height, width, data).expect("rotated buffer has the source length")
+}
+
+fn rotate_buffer_180<P: Pixel>(buffer: &Buffer<P>) -> Buffer<P> {
+    let (width, height) = buffer.dimensions();
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut data = Vec::with_capacity(buffer.as_raw().len());
+    for pixel in buffer.as_raw().chunks_exact(channels).rev() {
+        data.extend_from_slice(pixel);
+    }
+    ImageBuffer::from_raw(width, height, data).expect("rotated buffer has the source length")
+}
+
+fn flip_buffer_horizontal<P: Pixel>(buffer: &Buffer<P>) -> Buffer<P> {
+    let (width, height) = buffer.dimensions();
+    if width == 0 || height == 0 {
+        return buffer.clone();
+    }
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut data = Vec::with_capacity(buffer.as_raw().len());
+    for row in buffer.as_raw().chunks_exact(width as usize * channels) {
+        for pixel in row.chunks_exact(channels).rev() {
+            data.extend_from_slice(pixel);
+        }
+    }
+    ImageBuffer::from_raw(width, height, data).expect("flipped buffer has the source length")
+}
+
+fn flip_buffer_vertical<P: Pixel>(buffer: &Buffer<P>) -> Buffer<P> {
+    let (width, height) = buffer.dimensions();
+    if width == 0 || height == 0 {
+        return buffer.clone();
+    }
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut data = Vec::with_capacity(buffer.as_raw().len());
+    for row in buffer.as_raw().chunks_exact(width as usize * channels).rev() {
+        data.extend_from_slice(row);
+    }
+    ImageBuffer::from_raw(width, height, data).expect("flipped buffer has the source length")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, ImageBuffer, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn grown(angle: f32, interpolation: RotateInterpolation) -> RotateSettings {
+        RotateSettings { angle, interpolation, auto_crop: false, fill: [0, 0, 0, 255] }
+    }
+
+    // A linear ramp, which bilinear sampling reproduces at any position
+    fn ramp() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(60, 40, |x, y| Rgb([(x * 3 + 20) as u8, (y * 4 + 30) as u8, 100])))
+    }
+
+    #[test]
+    fn zero_degrees_is_pixel_exact() {
+        let images = [
+            ramp(),
+            DynamicImage::ImageLuma8(GrayImage::from_fn(7, 5, |x, y| Luma([(x * 31 + y * 17) as u8]))),
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(9, 4, |x, y| Rgba([x as u8, y as u8, 200, (x * 25) as u8]))),
+            DynamicImage::ImageRgb16(ramp().to_rgb16()),
+        ];
+        for img in images {
+            for interpolation in RotateInterpolation::ALL {
+                for auto_crop in [false, true] {
+                    let settings = RotateSettings { angle: 0.0, interpolation, auto_crop, ..Default::default() };
+                    assert_eq!(rotate(&img, &settings), img, "{:?} with {:?}", img.color(), settings);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn turning_there_and_back_grows_the_canvas_twice() {
+        // 60x40 turned 10° needs ceil(60 cos + 40 sin) x ceil(40 cos + 60 sin)
+        // = 67x50, which turned back needs 75x61
+        for angle in [10.0, -10.0] {
+            for interpolation in RotateInterpolation::ALL {
+                let there = rotate(&ramp(), &grown(angle, interpolation));
+                assert_eq!(there.dimensions(), (67, 50));
+                let back = rotate(&there, &grown(-angle, interpolation));
+                assert_eq!(back.dimensions(), (75, 61));
+
+                // Both turns are about the centers, so the middle of the ramp
+                // ends up in the middle again, between the same four pixels
+                let original = ramp().to_rgb8();
+                let expected: Vec<f32> = (0..3)
+                    .map(|c| [(29, 19), (30, 19), (29, 20), (30, 20)].iter().map(|&(x, y)| original.get_pixel(x, y)[c] as f32).sum::<f32>() / 4.0)
+                    .collect();
+                let center = back.to_rgb8().get_pixel(37, 30).0;
+                for (value, expected) in center.iter().zip(expected) {
+                    assert!((*value as f32 - expected).abs() <= 2.0, "{angle}° with {interpolation:?}: {center:?} in the middle");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_size_matches_the_result() {
+        for angle in [-45.0, -12.5, 0.5, 30.0, 45.0] {
+            for auto_crop in [false, true] {
+                let settings = RotateSettings { angle, auto_crop, ..Default::default() };
+                assert_eq!(rotate(&ramp(), &settings).dimensions(), rotated_size(60, 40, &settings), "{settings:?}");
+            }
+        }
+    }
+
+    // A wide and a tall image where every pixel differs from every other
+    fn quarter_turn_sources() -> [DynamicImage; 2] {
+        [
+            DynamicImage::ImageRgb8(RgbImage::from_fn(7, 4, |x, y| Rgb([(y * 7 + x) as u8, (x * 30) as u8, (y * 60) as u8]))),
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(3, 8, |x, y| Luma([((y * 3 + x) * 997) as u16]))),
+        ]
+    }
+
+    #[test]
+    fn quarter_turns_swap_the_sides_and_move_the_corners() {
+        for img in quarter_turn_sources() {
+            let (width, height) = img.dimensions();
+            let (right, left) = (rotate_right(&img), rotate_left(&img));
+            assert_eq!(right.dimensions(), (height, width));
+            assert_eq!(left.dimensions(), (height, width));
+            assert_eq!(rotate_180(&img).dimensions(), (width, height));
+            // The top left corner turns to the top right, or to the bottom left
+            assert_eq!(right.get_pixel(height - 1, 0), img.get_pixel(0, 0), "{:?}", img.color());
+            assert_eq!(left.get_pixel(0, width - 1), img.get_pixel(0, 0), "{:?}", img.color());
+            assert_eq!(rotate_180(&img).get_pixel(width - 1, height - 1), img.get_pixel(0, 0), "{:?}", img.color());
+        }
+    }
+
+    #[test]
+    fn turns_and_flips_round_trip_exactly() {
+        for img in quarter_turn_sources() {
+            let color = img.color();
+            assert_eq!(rotate_right(&rotate_left(&img)), img, "{:?} left then right", color);
+            assert_eq!(rotate_left(&rotate_right(&img)), img, "{:?} right then left", color);
+            let four_turns = (0..4).fold(img.clone(), |turned, _| rotate_right(&turned));
+            assert_eq!(four_turns, img, "{:?} turned right four times", color);
+            assert_eq!(rotate_180(&rotate_180(&img)), img, "{:?} turned 180° twice", color);
+            assert_eq!(rotate_right(&rotate_right(&img)), rotate_180(&img), "{:?}", color);
+            assert_eq!(flip_horizontal(&flip_horizontal(&img)), img, "{:?} flipped horizontally twice", color);
+            assert_eq!(flip_vertical(&flip_vertical(&img)), img, "{:?} flipped vertically twice", color);
+            assert_ne!(flip_horizontal(&img), img);
+            assert_ne!(flip_vertical(&img), img);
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::sync::OnceLock;
+
+use image::{DynamicImage, ImageBuffer};
+use wgpu::util::DeviceExt;
+
+use super::backend::GpuFilter;
+use super::border::BorderMode;
+use super::denoise::BILATERAL_SIGMA_R;
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+use super::sharpness::SharpenKernel;
+
+const SHADER: &str = include_str!("gpu_filters.wgsl");
+// Must match `@workgroup_size` in the shader
+const WORKGROUP_SIZE: u32 = 8;
+
+impl GpuFilter {
+    // Shader entry points, in the order of `index`
+    const ENTRY_POINTS: [&'static str; 4] = ["mean", "gaussian", "bilateral", "sharpen"];
+
+    fn index(self) -> usize {
+        match self {
+            GpuFilter::Mean { .. } => 0,
+            GpuFilter::Gaussian { .. } => 1,
+            GpuFilter::Bilateral { .. } => 2,
+            GpuFilter::Sharpen { .. } => 3,
+        }
+    }
+
+    // Rows above and below a band the filter reads
+    fn radius(self) -> u32 {
+        match self {
+            GpuFilter::Mean { radius } | GpuFilter::Gaussian { radius } | GpuFilter::Bilateral { radius } => radius,
+            GpuFilter::Sharpen { .. } => 1,
+        }
+    }
+
+    // The filter's `value` parameter for samples of type `S`
+    fn value<S: Sample>(self) -> f32 {
+        match self {
+            GpuFilter::Bilateral { .. } => BILATERAL_SIGMA_R * S::scale(),
+            GpuFilter::Sharpen { amount, .. } => amount,
+            GpuFilter::Mean { .. } | GpuFilter::Gaussian { .. } => 0.0,
+        }
+    }
+
+    // The shader's `kernel` parameter, which only sharpening reads
+    fn kernel(self) -> u32 {
+        match self {
+            GpuFilter::Sharpen { kernel: SharpenKernel::Laplacian4, .. } => 0,
+            GpuFilter::Sharpen { kernel: SharpenKernel::Laplacian8, .. } => 1,
+            GpuFilter::Sharpen { kernel: SharpenKernel::UnsharpMask, .. } => 2,
+            GpuFilter::Mean { .. } | GpuFilter::Gaussian { .. } | GpuFilter::Bilateral { .. } => 0,
+        }
+    }
+}
+
+// Layout of `Params` in the shader
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    width: u32,
+    height: u32,
+    channels: u32,
+    radius: u32,
+    band_top: u32,
+    out_top: u32,
+    out_rows: u32,
+    border: u32,
+    value: f32,
+    kernel: u32,
+    _padding1: [f32; 2],
+}
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    layout: wgpu::BindGroupLayout,
+    /// One per filter, see `GpuFilter::index`
+    pipelines: Vec<wgpu::ComputePipeline>,
+    /// Largest buffer a band of rows may take up
+    max_buffer_size: u64,
+}
+
+impl Gpu {
+    fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))?;
+        let limits = adapter.limits();
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("filters"),
+                required_features: wgpu::Features::empty(),
+                required_limits: limits.clone(),
+            },
+            None,
+        ))
+        .ok()?;
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("filters"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("filters"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("filters"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let pipelines = GpuFilter::ENTRY_POINTS
+            .iter()
+            .map(|&entry_point| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some(entry_point),
+                    layout: Some(&pipeline_layout),
+                    module: &module,
+                    entry_point,
+                })
+            })
+            .collect();
+
+        Some(Self {
+            device,
+            queue,
+            layout,
+            pipelines,
+            max_buffer_size: (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size),
+        })
+    }
+
+    // Filters one band of rows, returning the `output_len` filtered samples
+    fn run(&self, filter: GpuFilter, params: Params, input: &[f32], output_len: usize) -> Vec<f32> {
+        let params_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let input_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("input"),
+            contents: bytemuck::cast_slice(input),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let output_size = (output_len * std::mem::size_of::<f32>()) as u64;
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("output"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("filters"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: input_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: output_buffer.as_entire_binding() },
+            ],
+        });
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("filters") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("filters"), timestamp_writes: None });
+            pass.set_pipeline(&self.pipelines[filter.index()]);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(params.width.div_ceil(WORKGROUP_SIZE), params.out_rows.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&output_buffer, 0, &readback_buffer, 0, output_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+        let output: Vec<f32> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        readback_buffer.unmap();
+        output
+    }
+}
+
+// How the shader's `source` reads past the edges
+fn border_index(border: BorderMode) -> u32 {
+    match border {
+        BorderMode::Clamp => 0,
+        BorderMode::Mirror => 1,
+        BorderMode::Wrap => 2,
+        BorderMode::Skip => 3,
+    }
+}
+
+// Set up on first use, `None` if there is no usable adapter
+fn gpu() -> Option<&'static Gpu> {
+    static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+    GPU.get_or_init(Gpu::new).as_ref()
+}
+
+/// Runs `filter` on the GPU, with the same results as the CPU filter up to
+/// rounding. Images too large for one buffer are filtered in bands of rows.
+/// Returns `None` if there is no usable GPU or not even a single band fits,
+/// in which case the CPU filter has to be used. So does wrapping around an
+/// image split into bands, which only hold the rows next to them.
+pub fn filter_image(img: &DynamicImage, filter: GpuFilter, border: BorderMode) -> Option<DynamicImage> {
+    let gpu = gpu()?;
+    with_pixel_type!(img, |P| filter_at::<P>(gpu, img, filter, border, gpu.max_buffer_size))
+}
+
+// A band takes up at most `max_buffer_size` bytes, the tests pass less to
+// split small images
+fn filter_at<P: FilterPixel>(gpu: &Gpu, img: &DynamicImage, filter: GpuFilter, border: BorderMode, max_buffer_size: u64) -> Option<DynamicImage>
+where
+    P::Subpixel: Sample,
+{
+    let buffer = P::from_dynamic(img);
+    let (width, height) = buffer.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let channels = P::CHANNEL_COUNT as u32;
+    let row_len = (width * channels) as usize;
+
+    let halo = filter.radius();
+    let max_rows = (max_buffer_size / (row_len * std::mem::size_of::<f32>()).max(1) as u64).min(u32::MAX as u64) as u32;
+    if max_rows <= 2 * halo {
+        return None;
+    }
+    let band_rows = max_rows - 2 * halo;
+    if border == BorderMode::Wrap && band_rows < height {
+        return None;
+    }
+
+    let input: Vec<f32> = buffer.iter().map(|value| value.to_f32()).collect();
+    let mut output = Vec::with_capacity(input.len());
+    for out_top in (0..height).step_by(band_rows as usize) {
+        let out_rows = band_rows.min(height - out_top);
+        let band_top = out_top.saturating_sub(halo);
+        let band_bottom = (out_top + out_rows + halo).min(height);
+        let params = Params {
+            width,
+            height,
+            channels,
+            radius: filter.radius(),
+            band_top,
+            out_top,
+            out_rows,
+            border: border_index(border),
+            value: filter.value::<P::Subpixel>(),
+            kernel: filter.kernel(),
+            _padding1: [0.0; 2],
+        };
+        let band = &input[band_top as usize * row_len..band_bottom as usize * row_len];
+        output.extend(gpu.run(filter, params, band, out_rows as usize * row_len));
+    }
+
+    let samples = output.into_iter().map(P::Subpixel::from_f32).collect();
+    Some(P::into_dynamic(ImageBuffer::from_raw(width, height, samples)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    use super::*;
+    use crate::algorithms::backend::{denoise_on_gpu, sharpen_on_gpu, Backend};
+    use crate::algorithms::denoise::{denoise_image, DenoiseType};
+    use crate::algorithms::progress::Progress;
+    use crate::algorithms::sharpness::sharpen_image;
+
+    const BORDERS: [BorderMode; 4] = [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Wrap, BorderMode::Skip];
+
+    // Noisy ramps with odd sides, so the last workgroups hang over the edges
+    fn fixtures() -> [DynamicImage; 2] {
+        let noise = |x: u32, y: u32, c: u32| ((x * 73 + y * 151 + c * 37) % 41) as u8;
+        [
+            DynamicImage::ImageRgb8(RgbImage::from_fn(19, 13, |x, y| {
+                Rgb([(x * 9) as u8 + noise(x, y, 0), (y * 14) as u8 + noise(x, y, 1), 200 - noise(x, y, 2)])
+            })),
+            DynamicImage::ImageLuma8(GrayImage::from_fn(19, 13, |x, y| Luma([(x * 6 + y * 5) as u8 + noise(x, y, 0)]))),
+        ]
+    }
+
+    // Largest difference between two samples of 8-bit images
+    fn max_difference(a: &DynamicImage, b: &DynamicImage) -> u8 {
+        assert_eq!((a.color(), a.width(), a.height()), (b.color(), b.width(), b.height()));
+        a.as_bytes().iter().zip(b.as_bytes()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0)
+    }
+
+    fn assert_denoise_matches(denoise_type: DenoiseType, tolerance: u8) {
+        if gpu().is_none() {
+            // No adapter to compare
+            return;
+        }
+        for img in fixtures() {
+            for (kernel_size, border) in [3, 5].into_iter().flat_map(|size| BORDERS.map(|border| (size, border))) {
+                let on_gpu = denoise_on_gpu(Backend::Gpu, &img, denoise_type, kernel_size, border, &Progress::new()).unwrap();
+                let on_cpu = denoise_image(&img, denoise_type, kernel_size, 0.1, 50, 1e-4, border);
+                let difference = max_difference(&on_gpu, &on_cpu);
+                assert!(
+                    difference <= tolerance,
+                    "{:?} of size {} on {:?} with {:?} borders is off by {}",
+                    denoise_type,
+                    kernel_size,
+                    img.color(),
+                    border,
+                    difference
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mean_matches_the_cpu() {
+        assert_denoise_matches(DenoiseType::MeanFilter, 1);
+    }
+
+    #[test]
+    fn gaussian_matches_the_cpu() {
+        assert_denoise_matches(DenoiseType::GaussianFilter, 1);
+    }
+
+    #[test]
+    fn bilateral_matches_the_cpu() {
+        // The GPU's exp rounds differently, which adds up over the weights
+        assert_denoise_matches(DenoiseType::BilateralFilter, 2);
+    }
+
+    #[test]
+    fn sharpen_matches_the_cpu() {
+        if gpu().is_none() {
+            return;
+        }
+        for img in fixtures() {
+            for kernel in [SharpenKernel::Laplacian4, SharpenKernel::Laplacian8, SharpenKernel::UnsharpMask] {
+                for border in BORDERS {
+                    let on_gpu = sharpen_on_gpu(Backend::Gpu, &img, 0.8, kernel, border, &Progress::new()).unwrap();
+                    let on_cpu = sharpen_image(&img, 0.8, kernel, border);
+                    let difference = max_difference(&on_gpu, &on_cpu);
+                    assert!(difference <= 1, "{:?} on {:?} with {:?} borders is off by {}", kernel, img.color(), border, difference);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn images_over_the_buffer_limit_are_filtered_in_bands() {
+        let Some(gpu) = gpu() else {
+            return;
+        };
+        let filters = [
+            GpuFilter::Mean { radius: 2 },
+            GpuFilter::Gaussian { radius: 2 },
+            GpuFilter::Bilateral { radius: 2 },
+            GpuFilter::Sharpen { amount: 0.8, kernel: SharpenKernel::UnsharpMask },
+        ];
+        for img in fixtures() {
+            let row_bytes = img.width() as u64 * img.color().channel_count() as u64 * std::mem::size_of::<f32>() as u64;
+            for filter in filters {
+                // Room for 7 rows, the output rows between the halos: bands
+                // of 3 rows with a short last one for a radius of 2
+                let limit = 7 * row_bytes;
+                for border in [BorderMode::Clamp, BorderMode::Mirror, BorderMode::Skip] {
+                    let banded = with_pixel_type!(&img, |P| filter_at::<P>(gpu, &img, filter, border, limit)).unwrap();
+                    let whole = filter_image(&img, filter, border).unwrap();
+                    assert_eq!(banded, whole, "{:?} on {:?} with {:?} borders", filter, img.color(), border);
+                }
+
+                // Wrapping reads the rows at the far side, which no band holds
+                assert!(with_pixel_type!(&img, |P| filter_at::<P>(gpu, &img, filter, BorderMode::Wrap, limit)).is_none());
+                // Not even one output row fits between the halos
+                let halos = 2 * filter.radius() as u64 * row_bytes;
+                assert!(with_pixel_type!(&img, |P| filter_at::<P>(gpu, &img, filter, BorderMode::Mirror, halos)).is_none());
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Compute shader versions of the convolution filters in denoise.rs and
+// sharpness.rs. Each invocation filters one pixel of a band of rows, all
+// channels at once. Samples are floats in the units of the source image,
+// the host converts the results back the same way the CPU filters do.
+
+struct Params {
+    width: u32,
+    // Of the whole image, filters treat its edges specially
+    height: u32,
+    channels: u32,
+    radius: u32,
+    // Image row of the first row in `input`
+    band_top: u32,
+    // Image row of the first row in `output`
+    out_top: u32,
+    out_rows: u32,
+    // See `border_index`
+    border: u32,
+    // Bilateral: range standard deviation, sharpen: amount
+    value: f32,
+    // Sharpen: the `SharpenKernel`, see `GpuFilter::kernel`
+    kernel: u32,
+    _padding2: f32,
+    _padding3: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> input: array<f32>;
+@group(0) @binding(2) var<storage, read_write> output: array<f32>;
+
+fn load(x: i32, y: i32, c: u32) -> f32 {
+    let row = u32(y) - params.band_top;
+    return input[(row * params.width + u32(x)) * params.channels + c];
+}
+
+fn store(id: vec3<u32>, c: u32, value: f32) {
+    output[(id.y * params.width + id.x) * params.channels + c] = value;
+}
+
+// `value.rem_euclid(len)`. The GL backends turn `%` into GLSL's, which is
+// undefined for negative operands, so only positive numbers are divided
+fn rem_euclid(value: i32, len: i32) -> i32 {
+    if value >= 0 {
+        return value % len;
+    }
+    return (len - (-value) % len) % len;
+}
+
+// Coordinate read for `value` on an axis `len` pixels long, the same as
+// `BorderMode::source`, -1 when it is skipped
+fn source(value: i32, len: i32) -> i32 {
+    if value >= 0 && value < len {
+        return value;
+    }
+    if params.border == 0u {
+        return clamp(value, 0, len - 1);
+    }
+    if params.border == 1u {
+        let period = 2 * len;
+        let folded = rem_euclid(value, period);
+        if folded < len {
+            return folded;
+        }
+        return period - 1 - folded;
+    }
+    if params.border == 2u {
+        return rem_euclid(value, len);
+    }
+    return -1;
+}
+
+fn source_x(x: i32) -> i32 {
+    return source(x, i32(params.width));
+}
+
+fn source_y(y: i32) -> i32 {
+    return source(y, i32(params.height));
+}
+
+@compute @workgroup_size(8, 8)
+fn mean(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.out_rows {
+        return;
+    }
+    let x = i32(id.x);
+    let y = i32(params.out_top + id.y);
+    let r = i32(params.radius);
+
+    var sums = array<f32, 3>(0.0, 0.0, 0.0);
+    var count = 0.0;
+    for (var dy = -r; dy <= r; dy += 1) {
+        for (var dx = -r; dx <= r; dx += 1) {
+            let nx = source_x(x + dx);
+            let ny = source_y(y + dy);
+            if nx >= 0 && ny >= 0 {
+                for (var c = 0u; c < params.channels; c += 1u) {
+                    sums[c] += load(nx, ny, c);
+                }
+                count += 1.0;
+            }
+        }
+    }
+    // The CPU filter averages with integer division
+    for (var c = 0u; c < params.channels; c += 1u) {
+        store(id, c, floor(sums[c] / count));
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn gaussian(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.out_rows {
+        return;
+    }
+    let x = i32(id.x);
+    let y = i32(params.out_top + id.y);
+    let r = i32(params.radius);
+    let sigma = f32(params.radius) / 2.0;
+
+    // Normalized over the pixels read, skipped ones leave part of the kernel unused
+    var sums = array<f32, 3>(0.0, 0.0, 0.0);
+    var weight_sum = 0.0;
+    for (var dy = -r; dy <= r; dy += 1) {
+        for (var dx = -r; dx <= r; dx += 1) {
+            let nx = source_x(x + dx);
+            let ny = source_y(y + dy);
+            if nx >= 0 && ny >= 0 {
+                let weight = exp(-f32(dx * dx + dy * dy) / (2.0 * sigma * sigma));
+                for (var c = 0u; c < params.channels; c += 1u) {
+                    sums[c] += load(nx, ny, c) * weight;
+                }
+                weight_sum += weight;
+            }
+        }
+    }
+    for (var c = 0u; c < params.channels; c += 1u) {
+        store(id, c, sums[c] / weight_sum);
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn bilateral(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.out_rows {
+        return;
+    }
+    let x = i32(id.x);
+    let y = i32(params.out_top + id.y);
+    let r = i32(params.radius);
+    let sigma_d = f32(params.radius);
+    let sigma_r = params.value;
+
+    var sums = array<f32, 3>(0.0, 0.0, 0.0);
+    var weight_sum = 0.0;
+    for (var dy = -r; dy <= r; dy += 1) {
+        for (var dx = -r; dx <= r; dx += 1) {
+            let nx = source_x(x + dx);
+            let ny = source_y(y + dy);
+            if nx >= 0 && ny >= 0 {
+                let spatial_weight = exp(-f32(dx * dx + dy * dy) / (2.0 * sigma_d * sigma_d));
+                var intensity_diff = 0.0;
+                for (var c = 0u; c < params.channels; c += 1u) {
+                    let diff = load(x, y, c) - load(nx, ny, c);
+                    intensity_diff += diff * diff;
+                }
+                intensity_diff /= f32(params.channels);
+                let range_weight = exp(-intensity_diff / (2.0 * sigma_r * sigma_r));
+
+                let weight = spatial_weight * range_weight;
+                for (var c = 0u; c < params.channels; c += 1u) {
+                    sums[c] += load(nx, ny, c) * weight;
+                }
+                weight_sum += weight;
+            }
+        }
+    }
+    for (var c = 0u; c < params.channels; c += 1u) {
+        store(id, c, sums[c] / weight_sum);
+    }
+}
+
+// Weight of the neighbour at (`dx`, `dy`) in the mean sharpening measures
+// detail against, the same as `SharpenKernel::weight`
+fn sharpen_weight(dx: i32, dy: i32) -> f32 {
+    if params.kernel == 0u {
+        return select(0.0, 1.0, (dx == 0) != (dy == 0));
+    } else if params.kernel == 1u {
+        return select(0.0, 1.0, dx != 0 || dy != 0);
+    }
+    return f32((2 - abs(dx)) * (2 - abs(dy)));
+}
+
+@compute @workgroup_size(8, 8)
+fn sharpen(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.out_rows {
+        return;
+    }
+    let x = i32(id.x);
+    let y = i32(params.out_top + id.y);
+    let amount = params.value;
+
+    var sums = array<f32, 3>(0.0, 0.0, 0.0);
+    var weight_sum = 0.0;
+    for (var ky = -1; ky <= 1; ky += 1) {
+        for (var kx = -1; kx <= 1; kx += 1) {
+            let weight = sharpen_weight(kx, ky);
+            let nx = source_x(x + kx);
+            let ny = source_y(y + ky);
+            if weight > 0.0 && nx >= 0 && ny >= 0 {
+                for (var c = 0u; c < params.channels; c += 1u) {
+                    sums[c] += load(nx, ny, c) * weight;
+                }
+                weight_sum += weight;
+            }
+        }
+    }
+
+    for (var c = 0u; c < params.channels; c += 1u) {
+        let value = load(x, y, c);
+        // Without any neighbour there is no detail to measure
+        var detail = 0.0;
+        if weight_sum > 0.0 {
+            detail = value - sums[c] / weight_sum;
+        }
+        store(id, c, value + amount * detail);
+    }
+}
@@ -0,0 +1,146 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// Number of bins per channel. 16-bit images put 256 values into each.
+pub const BINS: usize = 256;
+
+/// A channel `Histograms` counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistogramChannel {
+    Red,
+    Green,
+    Blue,
+    Luma,
+}
+
+impl HistogramChannel {
+    pub const ALL: [HistogramChannel; 4] = [
+        HistogramChannel::Red,
+        HistogramChannel::Green,
+        HistogramChannel::Blue,
+        HistogramChannel::Luma,
+    ];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Per channel histograms of an image, with the number of pixels clipped to
+/// black or to white in each channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histograms {
+    bins: [[u64; BINS]; 4],
+    /// Per channel, pixels at exactly 0
+    black: [u64; 4],
+    /// Per channel, pixels at exactly the largest value
+    white: [u64; 4],
+    pixels: u64,
+    /// Largest sample value, 255 or 65535
+    max_value: u32,
+    /// Gray images only have the luma histogram worth showing
+    pub is_gray: bool,
+}
+
+impl Default for Histograms {
+    fn default() -> Self {
+        Self {
+            bins: [[0; BINS]; 4],
+            black: [0; 4],
+            white: [0; 4],
+            pixels: 0,
+            max_value: u8::MAX as u32,
+            is_gray: false,
+        }
+    }
+}
+
+impl Histograms {
+    pub fn of(img: &DynamicImage) -> Self {
+        let mut histograms = Self::default();
+        histograms.recompute(img);
+        histograms
+    }
+
+    /// Counts `img` in place of what was counted before, reusing the bins.
+    pub fn recompute(&mut self, img: &DynamicImage) {
+        with_pixel_type!(img, |P| self.recompute_at::<P>(img))
+    }
+
+    fn recompute_at<P: FilterPixel>(&mut self, img: &DynamicImage)
+    where
+        P::Subpixel: Sample,
+    {
+        let buffer = P::from_dynamic(img);
+        let max_value = P::Subpixel::MAX_VALUE as u32;
+        let shift = if max_value > u8::MAX as u32 { 8 } else { 0 };
+        let row_len = (buffer.width() as usize * P::CHANNEL_COUNT as usize).max(1);
+
+        // Rows are counted into one set of bins per worker, merged at the end
+        let counted = buffer
+            .par_chunks(row_len)
+            .fold(Self::default, |mut counts, row| {
+                for pixel in row.chunks_exact(P::CHANNEL_COUNT as usize) {
+                    let pixel = P::from_slice(pixel);
+                    let luma = pixel.to_luma()[0];
+                    let values = match pixel.channels() {
+                        &[r, g, b] => [r, g, b, luma],
+                        _ => [luma; 4],
+                    };
+                    for (channel, value) in values.into_iter().enumerate() {
+                        let value: u32 = value.into();
+                        counts.bins[channel][(value >> shift) as usize] += 1;
+                        counts.black[channel] += (value == 0) as u64;
+                        counts.white[channel] += (value == max_value) as u64;
+                    }
+                }
+                counts
+            })
+            .reduce(Self::default, |mut total, counts| {
+                total.add(&counts);
+                total
+            });
+
+        self.bins = counted.bins;
+        self.black = counted.black;
+        self.white = counted.white;
+        self.pixels = buffer.width() as u64 * buffer.height() as u64;
+        self.max_value = max_value;
+        self.is_gray = P::CHANNEL_COUNT == 1;
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (bins, other_bins) in self.bins.iter_mut().zip(&other.bins) {
+            for (bin, other_bin) in bins.iter_mut().zip(other_bins) {
+                *bin += other_bin;
+            }
+        }
+        for channel in 0..4 {
+            self.black[channel] += other.black[channel];
+            self.white[channel] += other.white[channel];
+        }
+    }
+
+    pub fn bins(&self, channel: HistogramChannel) -> &[u64; BINS] {
+        &self.bins[channel.index()]
+    }
+
+    /// Sample values counted in `bin`, both ends included.
+    pub fn bin_range(&self, bin: usize) -> (u32, u32) {
+        let width = (self.max_value + 1) / BINS as u32;
+        (bin as u32 * width, (bin as u32 + 1) * width - 1)
+    }
+
+    /// Largest sample value, 255 or 65535.
+    pub fn max_value(&self) -> u32 {
+        self.max_value
+    }
+
+    /// Percentages of pixels clipped to black and to white in `channel`.
+    pub fn clipped(&self, channel: HistogramChannel) -> (f32, f32) {
+        let percent = |count: u64| (count as f64 * 100.0 / self.pixels.max(1) as f64) as f32;
+        (percent(self.black[channel.index()]), percent(self.white[channel.index()]))
+    }
+}
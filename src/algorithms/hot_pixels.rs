@@ -0,0 +1,165 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// Deviation from the neighbourhood median, as a share of the full range,
+/// beyond which a pixel counts as stuck unless told otherwise.
+pub const DEFAULT_HOT_PIXEL_THRESHOLD: f32 = 0.25;
+
+/// Replaces hot and dead pixels with the median of their `window` ×
+/// `window` neighbourhood (3 or 5) and returns how many were replaced.
+///
+/// A pixel counts as stuck when, in any channel, it lies beyond all of its
+/// neighbours and more than `threshold` (a share of the full range) away
+/// from their median. Lying beyond all neighbours keeps one pixel wide lines
+/// and other detail a neighbour shares, which a median alone would erase.
+/// Every other pixel is left exactly as it was.
+pub fn repair_hot_pixels(img: &DynamicImage, window: usize, threshold: f32) -> (DynamicImage, usize) {
+    with_pixel_type!(img, |P| repair_hot_pixels_at::<P>(img, window, threshold))
+}
+
+fn repair_hot_pixels_at<P: FilterPixel>(img: &DynamicImage, window: usize, threshold: f32) -> (DynamicImage, usize)
+where
+    P::Subpixel: Sample,
+{
+    let source = P::from_dynamic(img);
+    let (width, height) = (source.width() as usize, source.height() as usize);
+    if width * height < 2 {
+        return (P::into_dynamic(source), 0);
+    }
+    let channels = P::CHANNEL_COUNT as usize;
+    let radius = (window / 2).max(1);
+    let limit = threshold * P::Subpixel::MAX_VALUE;
+    let samples: &[P::Subpixel] = &source;
+
+    let mut result = source.clone();
+    let repaired = result
+        .par_chunks_mut(width * channels)
+        .enumerate()
+        .map(|(y, row)| {
+            let rows = y.saturating_sub(radius)..(y + radius + 1).min(height);
+            let mut neighbours = Vec::with_capacity((2 * radius + 1) * (2 * radius + 1));
+            let mut repaired = 0;
+            for x in 0..width {
+                let columns = x.saturating_sub(radius)..(x + radius + 1).min(width);
+                // Most pixels have a neighbour on either side within the
+                // first few looked at, so this is checked before any median
+                let beyond_all = |channel: usize, value: P::Subpixel| {
+                    let (mut above, mut below) = (true, true);
+                    for ny in rows.clone() {
+                        for nx in columns.clone() {
+                            if (nx, ny) == (x, y) {
+                                continue;
+                            }
+                            let neighbour = samples[(ny * width + nx) * channels + channel];
+                            above &= neighbour < value;
+                            below &= neighbour > value;
+                            if !above && !below {
+                                return false;
+                            }
+                        }
+                    }
+                    true
+                };
+                // Median of the neighbours, the upper one of an even count
+                let median = |neighbours: &mut Vec<P::Subpixel>, channel: usize| {
+                    neighbours.clear();
+                    for ny in rows.clone() {
+                        for nx in columns.clone() {
+                            if (nx, ny) != (x, y) {
+                                neighbours.push(samples[(ny * width + nx) * channels + channel]);
+                            }
+                        }
+                    }
+                    let middle = neighbours.len() / 2;
+                    *neighbours.select_nth_unstable(middle).1
+                };
+
+                let stuck = (0..channels).any(|channel| {
+                    let value = row[x * channels + channel];
+                    beyond_all(channel, value) && (value.to_f32() - median(&mut neighbours, channel).to_f32()).abs() > limit
+                });
+                if !stuck {
+                    continue;
+                }
+                for channel in 0..channels {
+                    row[x * channels + channel] = median(&mut neighbours, channel);
+                }
+                repaired += 1;
+            }
+            repaired
+        })
+        .sum();
+    (P::into_dynamic(result), repaired)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(40, 30, |x, y| Rgb([(40 + x * 4) as u8, (30 + y * 5) as u8, 128])))
+    }
+
+    // Stuck pixels at least three apart, so none is in another's 5x5
+    // window: white, black, single stuck channels, on the edges and corners
+    fn planted() -> Vec<(u32, u32, Rgb<u8>)> {
+        let mut pixels = vec![
+            (0, 0, Rgb([255, 255, 255])),
+            (39, 0, Rgb([0, 0, 0])),
+            (0, 29, Rgb([255, 30, 128])),
+            (39, 29, Rgb([100, 100, 0])),
+            (20, 0, Rgb([40, 255, 128])),
+            (0, 15, Rgb([40, 200, 128])),
+        ];
+        for y in (4..27).step_by(5) {
+            for x in (4..37).step_by(6) {
+                let stuck = match (x + y) % 3 {
+                    0 => Rgb([255, 255, 255]),
+                    1 => Rgb([0, 0, 0]),
+                    _ => Rgb([255, 0, 255]),
+                };
+                pixels.push((x, y, stuck));
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn repairs_every_planted_pixel_and_nothing_else() {
+        let clean = gradient().to_rgb8();
+        let mut damaged = clean.clone();
+        for &(x, y, stuck) in &planted() {
+            damaged.put_pixel(x, y, stuck);
+        }
+        let damaged = DynamicImage::ImageRgb8(damaged);
+
+        for img in [damaged.clone(), DynamicImage::ImageRgb16(damaged.to_rgb16())] {
+            for window in [3, 5] {
+                let (repaired, count) = repair_hot_pixels(&img, window, DEFAULT_HOT_PIXEL_THRESHOLD);
+                assert_eq!(count, planted().len(), "{window}x{window} on {:?}", img.color());
+                let repaired = repaired.to_rgb8();
+                for (x, y, pixel) in repaired.enumerate_pixels() {
+                    let original = clean.get_pixel(x, y);
+                    if planted().iter().any(|&(px, py, _)| (px, py) == (x, y)) {
+                        // The neighbourhood median, at most two steps of the gradient off
+                        let close = pixel.0.iter().zip(original.0).all(|(&a, b)| a.abs_diff(b) <= 10);
+                        assert!(close, "{window}x{window}: ({x}, {y}) repaired to {pixel:?}, was {original:?}");
+                    } else {
+                        assert_eq!(pixel, original, "{window}x{window}: ({x}, {y}) changed");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clean_images_are_left_alone() {
+        let (repaired, count) = repair_hot_pixels(&gradient(), 5, DEFAULT_HOT_PIXEL_THRESHOLD);
+        assert_eq!(count, 0);
+        assert_eq!(repaired, gradient());
+    }
+}
@@ -0,0 +1,194 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// Largest hue shift of a band, in degrees either way.
+pub const MAX_HUE_SHIFT: f32 = 45.0;
+
+/// A range of hues `adjust_hsl` can target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HslRange {
+    Reds,
+    Oranges,
+    Yellows,
+    Greens,
+    Cyans,
+    Blues,
+    Magentas,
+}
+
+impl HslRange {
+    /// In order of their hues.
+    pub const ALL: [HslRange; 7] = [
+        HslRange::Reds,
+        HslRange::Oranges,
+        HslRange::Yellows,
+        HslRange::Greens,
+        HslRange::Cyans,
+        HslRange::Blues,
+        HslRange::Magentas,
+    ];
+
+    /// Hue, in degrees, the range has its full effect at.
+    pub fn center(self) -> f32 {
+        match self {
+            HslRange::Reds => 0.0,
+            HslRange::Oranges => 30.0,
+            HslRange::Yellows => 60.0,
+            HslRange::Greens => 120.0,
+            HslRange::Cyans => 180.0,
+            HslRange::Blues => 240.0,
+            HslRange::Magentas => 300.0,
+        }
+    }
+}
+
+/// Changes to the colors of one hue range.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HslBand {
+    pub range: HslRange,
+    /// In degrees, up to `MAX_HUE_SHIFT` either way
+    pub hue: f32,
+    /// -1 (gray) to 1 (double)
+    pub saturation: f32,
+    /// -1 to 1, towards black or white
+    pub lightness: f32,
+}
+
+impl HslBand {
+    pub fn neutral(range: HslRange) -> Self {
+        Self {
+            range,
+            hue: 0.0,
+            saturation: 0.0,
+            lightness: 0.0,
+        }
+    }
+
+    pub fn is_neutral(&self) -> bool {
+        self.hue == 0.0 && self.saturation == 0.0 && self.lightness == 0.0
+    }
+}
+
+/// Converts 0-1 RGB to hue in degrees (0-360), saturation and lightness (0-1).
+pub fn rgb_to_hsl([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta <= 0.0 {
+        return [0.0, 0.0, lightness];
+    }
+
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    [hue * 60.0, saturation.min(1.0), lightness]
+}
+
+/// Inverse of `rgb_to_hsl`, any hue is accepted and wrapped around.
+pub fn hsl_to_rgb([hue, saturation, lightness]: [f32; 3]) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [r + m, g + m, b + m]
+}
+
+// Share of each range's adjustment a color of `hue` gets. Only the two
+// ranges the hue lies between take part, blended smoothly so neighbouring
+// colors never jump apart. The shares always add up to one.
+fn range_weights(hue: f32) -> [f32; 7] {
+    let mut weights = [0.0; 7];
+    let hue = hue.rem_euclid(360.0);
+    for (index, range) in HslRange::ALL.iter().enumerate() {
+        let next = (index + 1) % HslRange::ALL.len();
+        let start = range.center();
+        let end = if next == 0 { 360.0 } else { HslRange::ALL[next].center() };
+        if hue >= start && hue < end {
+            let t = (hue - start) / (end - start);
+            let smooth = t * t * (3.0 - 2.0 * t);
+            weights[index] = 1.0 - smooth;
+            weights[next] = smooth;
+            break;
+        }
+    }
+    weights
+}
+
+// The band deltas blended by `weights`
+fn blended_deltas(bands: &[HslBand], weights: &[f32; 7]) -> (f32, f32, f32) {
+    bands.iter().fold((0.0, 0.0, 0.0), |(hue, saturation, lightness), band| {
+        let index = HslRange::ALL.iter().position(|&range| range == band.range).expect("every range is listed");
+        let weight = weights[index];
+        (hue + band.hue * weight, saturation + band.saturation * weight, lightness + band.lightness * weight)
+    })
+}
+
+fn adjust_pixel(rgb: [f32; 3], bands: &[HslBand]) -> [f32; 3] {
+    let [hue, saturation, lightness] = rgb_to_hsl(rgb);
+    if saturation == 0.0 {
+        return rgb;
+    }
+    let (hue_shift, saturation_delta, lightness_delta) = blended_deltas(bands, &range_weights(hue));
+    if (hue_shift, saturation_delta, lightness_delta) == (0.0, 0.0, 0.0) {
+        return rgb;
+    }
+
+    let new_saturation = (saturation * (1.0 + saturation_delta)).clamp(0.0, 1.0);
+    // Scaled by the saturation, so the barely colored pixels whose hue is
+    // mostly noise keep their brightness
+    let lightness_change = lightness_delta * saturation;
+    let new_lightness = if lightness_change > 0.0 {
+        lightness + lightness_change * (1.0 - lightness)
+    } else {
+        lightness + lightness_change * lightness
+    };
+    hsl_to_rgb([hue + hue_shift, new_saturation, new_lightness])
+}
+
+/// Shifts the hue and changes the saturation and lightness of the colors in
+/// each band's hue range. Colors between two ranges get a blend of both
+/// adjustments. Gray pixels and gray images are left alone, as is
+/// everything when all bands are neutral.
+pub fn adjust_hsl(img: &DynamicImage, bands: &[HslBand]) -> DynamicImage {
+    if bands.iter().all(HslBand::is_neutral) {
+        return img.clone();
+    }
+    with_pixel_type!(img, |P| adjust_hsl_at::<P>(img, bands))
+}
+
+fn adjust_hsl_at<P: FilterPixel>(img: &DynamicImage, bands: &[HslBand]) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let mut buffer = P::from_dynamic(img);
+    if P::CHANNEL_COUNT != 3 {
+        return P::into_dynamic(buffer);
+    }
+
+    let max = P::Subpixel::MAX_VALUE;
+    buffer.par_chunks_mut(3).for_each(|pixel| {
+        let rgb = [pixel[0].to_f32() / max, pixel[1].to_f32() / max, pixel[2].to_f32() / max];
+        let adjusted = adjust_pixel(rgb, bands);
+        for (sample, value) in pixel.iter_mut().zip(adjusted) {
+            *sample = P::Subpixel::from_f32((value * max).round());
+        }
+    });
+    P::into_dynamic(buffer)
+}
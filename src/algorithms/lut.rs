@@ -0,0 +1,280 @@
+use std::fmt;
+
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::sample::{is_high_depth, FilterPixel, Sample};
+
+/// Largest `LUT_3D_SIZE` accepted, the largest in common use. The format
+/// allows up to 256, which would take hundreds of megabytes.
+pub const MAX_CUBE_SIZE: usize = 65;
+/// Largest `LUT_1D_SIZE` accepted.
+pub const MAX_CURVE_SIZE: usize = 65536;
+
+/// A color lookup table read from an Adobe / Resolve `.cube` file: per
+/// channel curves, a 3D cube or the curves as a shaper in front of a cube.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lut3d {
+    /// From the `TITLE` line, if there is one
+    pub title: Option<String>,
+    /// Input values mapped to the first and last table entries
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    /// `LUT_1D_SIZE` entries
+    curves: Option<Vec<[f32; 3]>>,
+    /// `LUT_3D_SIZE`, 0 without a cube
+    size: usize,
+    /// `size`³ entries, red changing fastest
+    cube: Vec<[f32; 3]>,
+}
+
+/// Why a `.cube` file could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeError {
+    /// 1-based, `None` for problems with the file as a whole
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for CubeError {}
+
+impl Lut3d {
+    /// Parses the text of a `.cube` file. Unknown keywords are skipped.
+    pub fn parse_cube(text: &str) -> Result<Self, CubeError> {
+        let error = |line: usize, message: String| CubeError { line: Some(line + 1), message };
+        let mut title = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut curve_size = None;
+        let mut cube_size = None;
+        let mut entries = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            let is_data = keyword.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '+' | '.'));
+            if is_data {
+                entries.push(parse_triple(line).map_err(|message| error(index, message))?);
+                continue;
+            }
+            if !entries.is_empty() {
+                return Err(error(index, format!("{} after the table data", keyword)));
+            }
+
+            match keyword {
+                "TITLE" => title = Some(rest.trim_matches('"').to_string()),
+                "DOMAIN_MIN" => domain_min = parse_triple(rest).map_err(|message| error(index, message))?,
+                "DOMAIN_MAX" => domain_max = parse_triple(rest).map_err(|message| error(index, message))?,
+                "LUT_1D_SIZE" => curve_size = Some(parse_size(rest, MAX_CURVE_SIZE).map_err(|message| error(index, message))?),
+                "LUT_3D_SIZE" => cube_size = Some(parse_size(rest, MAX_CUBE_SIZE).map_err(|message| error(index, message))?),
+                _ => {}
+            }
+        }
+
+        let whole_file = |message: String| CubeError { line: None, message };
+        if curve_size.is_none() && cube_size.is_none() {
+            return Err(whole_file("Neither LUT_1D_SIZE nor LUT_3D_SIZE is given".to_string()));
+        }
+        if (0..3).any(|channel| domain_min[channel] >= domain_max[channel]) {
+            return Err(whole_file("DOMAIN_MIN has to be below DOMAIN_MAX for every channel".to_string()));
+        }
+        let curve_len = curve_size.unwrap_or(0);
+        let cube_len = cube_size.map_or(0, |size| size * size * size);
+        if entries.len() != curve_len + cube_len {
+            return Err(whole_file(format!(
+                "Expected {} table entries, found {}",
+                curve_len + cube_len,
+                entries.len()
+            )));
+        }
+
+        // Files with both tables list the shaper curves first
+        let cube = entries.split_off(curve_len);
+        Ok(Self {
+            title,
+            domain_min,
+            domain_max,
+            curves: curve_size.map(|_| entries),
+            size: cube_size.unwrap_or(0),
+            cube,
+        })
+    }
+
+    /// Entries per side of the cube, 0 for curves only.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // What each channel value up to `max` becomes before the cube lookup,
+    // so the domain and the curves cost nothing per pixel
+    fn channel_tables(&self, max: usize) -> [Vec<f32>; 3] {
+        std::array::from_fn(|channel| {
+            let (min, range) = (self.domain_min[channel], self.domain_max[channel] - self.domain_min[channel]);
+            (0..=max)
+                .map(|value| {
+                    let value = ((value as f32 / max as f32 - min) / range).clamp(0.0, 1.0);
+                    match &self.curves {
+                        Some(curves) => interpolate_curve(curves, channel, value),
+                        None => value,
+                    }
+                })
+                .collect()
+        })
+    }
+
+    fn interpolate_cube(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let size = self.size;
+        let last = (size - 1) as f32;
+        // Cell index and position within it, the top entry interpolates towards itself
+        let split = |value: f32| {
+            let position = value.clamp(0.0, 1.0) * last;
+            let index = (position as usize).min(size - 2);
+            (index, position - index as f32)
+        };
+        let (r, fr) = split(rgb[0]);
+        let (g, fg) = split(rgb[1]);
+        let (b, fb) = split(rgb[2]);
+
+        // The eight corners of the cell, relative to the first
+        let (green, blue) = (size, size * size);
+        let base = (b * size + g) * size + r;
+        let corners = &self.cube[base..=base + blue + green + 1];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| -> [f32; 3] { std::array::from_fn(|i| a[i] + (b[i] - a[i]) * t) };
+        let near = lerp(lerp(corners[0], corners[1], fr), lerp(corners[green], corners[green + 1], fr), fg);
+        let far = lerp(
+            lerp(corners[blue], corners[blue + 1], fr),
+            lerp(corners[blue + green], corners[blue + green + 1], fr),
+            fg,
+        );
+        lerp(near, far, fb)
+    }
+}
+
+fn interpolate_curve(curve: &[[f32; 3]], channel: usize, value: f32) -> f32 {
+    let position = value * (curve.len() - 1) as f32;
+    let index = (position as usize).min(curve.len() - 2);
+    let t = position - index as f32;
+    let (a, b) = (curve[index][channel], curve[index + 1][channel]);
+    a + (b - a) * t
+}
+
+fn parse_triple(text: &str) -> Result<[f32; 3], String> {
+    let values: Vec<f32> = text
+        .split_whitespace()
+        .map(|value| value.parse::<f32>().ok().filter(|value| value.is_finite()))
+        .collect::<Option<_>>()
+        .ok_or_else(|| format!("expected three numbers, found \"{}\"", text))?;
+    values.try_into().map_err(|_| format!("expected three numbers, found \"{}\"", text))
+}
+
+fn parse_size(text: &str, max: usize) -> Result<usize, String> {
+    match text.parse::<usize>() {
+        Ok(size) if (2..=max).contains(&size) => Ok(size),
+        _ => Err(format!("size has to be a number from 2 to {}, found \"{}\"", max, text)),
+    }
+}
+
+/// Maps every pixel through `lut`, blended with the unmapped image by
+/// `intensity` (0-1). Gray images come out as RGB, since LUTs may tint.
+pub fn apply_lut(img: &DynamicImage, lut: &Lut3d, intensity: f32) -> DynamicImage {
+    if intensity <= 0.0 {
+        return img.clone();
+    }
+    if is_high_depth(img) {
+        apply_lut_at::<image::Rgb<u16>>(img, lut, intensity.min(1.0))
+    } else {
+        apply_lut_at::<image::Rgb<u8>>(img, lut, intensity.min(1.0))
+    }
+}
+
+fn apply_lut_at<P: FilterPixel>(img: &DynamicImage, lut: &Lut3d, intensity: f32) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let mut buffer = P::from_dynamic(img);
+    let max = P::Subpixel::MAX_VALUE;
+    let tables = lut.channel_tables(max as usize);
+    let row_len = buffer.width() as usize * 3;
+    buffer.par_chunks_mut(row_len.max(3)).for_each(|row| {
+        for pixel in row.chunks_exact_mut(3) {
+            let index = |channel: usize| Into::<u32>::into(pixel[channel]) as usize;
+            let shaped = [tables[0][index(0)], tables[1][index(1)], tables[2][index(2)]];
+            let mapped = if lut.size > 0 { lut.interpolate_cube(shaped) } else { shaped };
+            for (sample, after) in pixel.iter_mut().zip(mapped) {
+                let before = sample.to_f32();
+                let value = before + (after * max - before) * intensity;
+                *sample = P::Subpixel::from_f32(value.round());
+            }
+        }
+    });
+    P::into_dynamic(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgb, RgbImage};
+
+    use super::*;
+
+    // A `.cube` file mapping every color to itself, with a cube of `size`
+    // and optionally identity curves in front of it
+    fn identity_cube(size: usize, curve_size: Option<usize>) -> String {
+        let mut text = String::from("TITLE \"identity\"\n");
+        if let Some(curve_size) = curve_size {
+            text += &format!("LUT_1D_SIZE {}\n", curve_size);
+        }
+        text += &format!("LUT_3D_SIZE {}\n", size);
+        for i in 0..curve_size.unwrap_or(0) {
+            let value = i as f32 / (curve_size.unwrap() - 1) as f32;
+            text += &format!("{} {} {}\n", value, value, value);
+        }
+        let last = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    text += &format!("{} {} {}\n", r as f32 / last, g as f32 / last, b as f32 / last);
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn identity_luts_change_no_pixel() {
+        // Every 8-bit value in each channel, mixed with the others
+        let rgb8 = DynamicImage::ImageRgb8(RgbImage::from_fn(256, 16, |x, y| Rgb([x as u8, (x * 7 + y) as u8, (255 - x) as u8])));
+        let rgb16 = DynamicImage::ImageRgb16(ImageBuffer::from_fn(256, 16, |x, y| {
+            Rgb([(x * 256 + y * 3) as u16, (65535 - x * 200) as u16, (x * 97 + y * 4000) as u16])
+        }));
+        for (size, curve_size) in [(2, None), (17, None), (33, None), (33, Some(1024))] {
+            let lut = Lut3d::parse_cube(&identity_cube(size, curve_size)).unwrap();
+            for img in [&rgb8, &rgb16] {
+                assert_eq!(&apply_lut(img, &lut, 1.0), img, "{}³ cube, curves {:?}, {:?}", size, curve_size, img.color());
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_files_say_where() {
+        let error = Lut3d::parse_cube("LUT_3D_SIZE 2\n0 0 0\n1 0 zero\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: expected three numbers, found \"1 0 zero\"");
+        let error = Lut3d::parse_cube("LUT_3D_SIZE 2\n0 0 0\n").unwrap_err();
+        assert_eq!(error.to_string(), "Expected 8 table entries, found 1");
+        let error = Lut3d::parse_cube("LUT_3D_SIZE 99\n").unwrap_err();
+        assert_eq!(error.line, Some(1));
+    }
+}
@@ -0,0 +1,75 @@
+use std::borrow::Cow;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage};
+
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// Mixes `processed` into `original` pixel by pixel, taking all of the
+/// processed value where `mask` is 255 and keeping the original where it is
+/// 0. A mask of a different size, like the full resolution mask for a
+/// downscaled preview, is scaled to fit. The images must have the same size.
+pub fn blend_with_mask(original: &DynamicImage, processed: &DynamicImage, mask: &GrayImage) -> DynamicImage {
+    with_pixel_type!(processed, |P| blend::<P>(original, processed, mask))
+}
+
+fn blend<P: FilterPixel>(original: &DynamicImage, processed: &DynamicImage, mask: &GrayImage) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let (width, height) = (processed.width(), processed.height());
+    let mask = if mask.dimensions() == (width, height) {
+        Cow::Borrowed(mask)
+    } else {
+        Cow::Owned(imageops::resize(mask, width, height, FilterType::Triangle))
+    };
+
+    // Converted the same way, a grayscale result blends with the original's luma
+    let original = P::from_dynamic(original);
+    let mut result = P::from_dynamic(processed);
+    let channels = P::CHANNEL_COUNT as usize;
+    for ((pixel, original), weight) in result
+        .chunks_exact_mut(channels)
+        .zip(original.chunks_exact(channels))
+        .zip(mask.as_raw())
+    {
+        let weight = *weight as f32 / 255.0;
+        for (value, original) in pixel.iter_mut().zip(original) {
+            let original = original.to_f32();
+            *value = P::Subpixel::from_f32((original + weight * (value.to_f32() - original)).round());
+        }
+    }
+    P::into_dynamic(result)
+}
+
+/// Stamps a round brush of `radius` pixels centered on `(x, y)` into `mask`,
+/// raising it towards 255, or lowering it towards 0 when `erase` is set.
+/// The brush is solid out to `hardness` (0-1) of its radius and fades out
+/// smoothly beyond.
+pub fn paint_dab(mask: &mut GrayImage, x: f32, y: f32, radius: f32, hardness: f32, erase: bool) {
+    let (width, height) = mask.dimensions();
+    let solid = radius * hardness.clamp(0.0, 1.0);
+    let left = (x - radius).floor().max(0.0) as u32;
+    let top = (y - radius).floor().max(0.0) as u32;
+    let right = ((x + radius).ceil().max(0.0) as u32).min(width);
+    let bottom = ((y + radius).ceil().max(0.0) as u32).min(height);
+
+    for py in top..bottom {
+        for px in left..right {
+            // Distance from the brush center to the pixel center
+            let distance = (px as f32 + 0.5 - x).hypot(py as f32 + 0.5 - y);
+            if distance >= radius {
+                continue;
+            }
+            let strength = if distance <= solid {
+                1.0
+            } else {
+                let t = (radius - distance) / (radius - solid);
+                t * t * (3.0 - 2.0 * t)
+            };
+            let value = (strength * 255.0).round() as u8;
+            let pixel = &mut mask.get_pixel_mut(px, py)[0];
+            *pixel = if erase { (*pixel).min(255 - value) } else { (*pixel).max(value) };
+        }
+    }
+}
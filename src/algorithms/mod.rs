@@ -1,6 +1,41 @@
 pub mod denoise;
 pub mod contrast;
 pub mod brightness;
+pub mod exposure;
 pub mod sharpness;
 pub mod auto_adjust;
-pub mod parallel;
\ No newline at end of file
+pub mod parallel;
+pub mod pipeline;
+pub mod progress;
+pub mod region;
+pub mod geometry;
+pub mod sample;
+pub mod blur;
+pub mod tone;
+pub mod dehaze;
+pub mod colorspace;
+pub mod point_ops;
+pub mod stack;
+pub mod block_matching;
+pub mod benchmark;
+pub mod residual;
+pub mod edges;
+pub mod mask;
+pub mod backend;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+pub mod hsl;
+pub mod lut;
+pub mod hot_pixels;
+pub mod histogram;
+pub mod document;
+pub mod quantize;
+pub mod detail;
+pub mod border;
+pub mod deconvolution;
+pub mod white_balance;
+pub mod focus;
+pub mod fft;
+pub mod notch;
+pub mod streaming;
+pub mod channels;
\ No newline at end of file
@@ -0,0 +1,355 @@
+use image::{DynamicImage, GrayImage, Luma};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::fft::{forward_real, inverse_real};
+use super::sample::{with_pixel_type, Buffer, FilterPixel, Sample};
+
+/// Notch radius new notches start with, in cycles per pixel.
+pub const DEFAULT_NOTCH_RADIUS: f32 = 0.006;
+/// Most notches `detect_notches` proposes.
+pub const MAX_DETECTED_NOTCHES: usize = 8;
+// Rec. 601 weights, the luma `rgb_to_ycbcr` separates from the chroma
+const LUMA_WEIGHTS: [f32; 3] = [0.299, 0.587, 0.114];
+// Frequencies below this, in cycles per pixel, are the image itself and
+// never proposed as periodic noise
+const MIN_PEAK_FREQUENCY: f32 = 0.04;
+// Natural logs of how far a spike has to stand above its surroundings,
+// about 30x in magnitude, and how much less than the strongest spike it
+// may stand out. Fine natural texture like fur stays below the first,
+// faint echoes of a screen below the second.
+const PEAK_PROMINENCE: f32 = 3.5;
+const MAX_PEAK_SPREAD: f32 = 3.5;
+// Chebyshev distances, in spectrum cells, of the ring a spike is compared
+// with. The inner cells may still belong to the spike.
+const RING: (usize, usize) = (3, 6);
+
+/// A gaussian notch at a frequency and its mirror image, which together
+/// remove one periodic pattern from a real image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Notch {
+    /// Horizontal frequency in cycles per pixel, -0.5 to 0.5
+    pub fx: f32,
+    /// Vertical frequency in cycles per pixel, -0.5 to 0.5
+    pub fy: f32,
+    /// Standard deviation of the gaussian in cycles per pixel
+    pub radius: f32,
+}
+
+impl Notch {
+    /// Gain at frequency (`fx`, `fy`), 0 at the centers and 1 far from them.
+    pub fn gain(&self, fx: f32, fy: f32) -> f32 {
+        let two_variance = 2.0 * self.radius.max(f32::EPSILON).powi(2);
+        let notch = |dx: f32, dy: f32| 1.0 - (-(dx * dx + dy * dy) / two_variance).exp();
+        notch(fx - self.fx, fy - self.fy) * notch(fx + self.fx, fy + self.fy)
+    }
+}
+
+/// Pixels a notch filtered pixel depends on in each direction, about three
+/// times the spread of the narrowest notch's ripple.
+pub fn notch_context_radius(notches: &[Notch]) -> u32 {
+    notches
+        .iter()
+        .map(|notch| (3.0 / (2.0 * std::f32::consts::PI * notch.radius.max(1e-3))).ceil() as u32)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Removes periodic patterns like halftone screens and moiré by zeroing
+/// the frequencies of `notches` in the spectrum of the luma. The color is
+/// left alone: every channel moves by the same amount, which keeps Cb and
+/// Cr as they were. Without notches the round trip through the transform
+/// gives back the input exactly.
+pub fn notch_filter(img: &DynamicImage, notches: &[Notch]) -> DynamicImage {
+    with_pixel_type!(img, |P| notch_filter_at::<P>(img, notches))
+}
+
+fn notch_filter_at<P: FilterPixel>(img: &DynamicImage, notches: &[Notch]) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let mut buffer = P::from_dynamic(img);
+    let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+    let luma = luma_plane::<P>(&buffer);
+
+    let mut spectrum = forward_real(&luma, width, height);
+    spectrum.apply_gain(|fx, fy| notches.iter().map(|notch| notch.gain(fx, fy)).product());
+    let filtered = inverse_real(spectrum);
+
+    let channels = P::CHANNEL_COUNT as usize;
+    buffer
+        .par_chunks_mut(channels)
+        .zip(luma.par_iter().zip(&filtered))
+        .for_each(|(pixel, (&before, &after))| {
+            let change = after - before;
+            for sample in pixel {
+                *sample = P::Subpixel::from_f32((sample.to_f32() + change).round());
+            }
+        });
+    P::into_dynamic(buffer)
+}
+
+fn luma_plane<P: FilterPixel>(buffer: &Buffer<P>) -> Vec<f32>
+where
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    buffer
+        .par_chunks(channels)
+        .map(|pixel| match pixel {
+            [value] => value.to_f32(),
+            _ => pixel.iter().zip(LUMA_WEIGHTS).map(|(value, weight)| value.to_f32() * weight).sum(),
+        })
+        .collect()
+}
+
+/// Log magnitude spectrum of the luma, the zero frequency in the middle
+/// and pooled down to at most a given size for display. Each cell keeps the
+/// strongest frequency inside it, so narrow spikes survive the pooling.
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    pub width: u32,
+    pub height: u32,
+    // Per cell, row by row: the log magnitude and the frequency it was found at
+    cells: Vec<(f32, (f32, f32))>,
+}
+
+impl Spectrum {
+    /// Spectrum of `img` pooled to at most `max_side` cells a side.
+    pub fn of(img: &DynamicImage, max_side: u32) -> Self {
+        let luma = with_pixel_type!(img, |P| luma_plane::<P>(&P::from_dynamic(img)));
+        let (plane, width, height) = windowed(&luma, img.width() as usize, img.height() as usize);
+        let spectrum = forward_real(&plane, width, height);
+        let width = (spectrum.width as u32).min(max_side.max(1));
+        let height = (spectrum.height as u32).min(max_side.max(1));
+
+        let mut cells = vec![(f32::NEG_INFINITY, (0.0, 0.0)); (width * height) as usize];
+        // Nyquist frequencies are their own mirror images, 0.5 wraps around to -0.5
+        let cell_of = |fx: f32, fy: f32| {
+            let x = ((fx + 0.5) * width as f32) as u32 % width;
+            let y = ((fy + 0.5) * height as f32) as u32 % height;
+            (y * width + x) as usize
+        };
+        for column in 0..spectrum.columns() {
+            for row in 0..spectrum.height {
+                let magnitude = spectrum.bin(column, row).norm().ln_1p();
+                let (fx, fy) = spectrum.frequency(column, row);
+                // Each kept bin stands for its mirror image too
+                for (fx, fy) in [(fx, fy), (-fx, -fy)] {
+                    let cell = &mut cells[cell_of(fx, fy)];
+                    if magnitude > cell.0 {
+                        *cell = (magnitude, (fx, fy));
+                    }
+                }
+            }
+        }
+        Self { width, height, cells }
+    }
+
+    fn value(&self, x: u32, y: u32) -> f32 {
+        self.cells[(y * self.width + x) as usize].0
+    }
+
+    /// The spectrum as an image, from the weakest frequency in black to the
+    /// strongest one apart from the zero frequency in white.
+    pub fn to_image(&self) -> GrayImage {
+        let center = self.position_of(0.0, 0.0);
+        let center = (center.1 as u32 * self.width + center.0 as u32) as usize;
+        let low = self.cells.iter().map(|cell| cell.0).fold(f32::INFINITY, f32::min);
+        let high = self
+            .cells
+            .iter()
+            .enumerate()
+            .filter(|&(index, cell)| index != center && cell.0.is_finite())
+            .fold(f32::NEG_INFINITY, |high, (_, cell)| high.max(cell.0));
+        let range = (high - low).max(f32::EPSILON);
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            let value = self.value(x, y);
+            let shade = if value.is_finite() { (value - low) / range * 255.0 } else { 0.0 };
+            Luma([shade.round().clamp(0.0, 255.0) as u8])
+        })
+    }
+
+    /// Frequency at (`x`, `y`) in the spectrum image, in cycles per pixel.
+    pub fn frequency_at(&self, x: f32, y: f32) -> (f32, f32) {
+        (x / self.width as f32 - 0.5, y / self.height as f32 - 0.5)
+    }
+
+    /// Where frequency (`fx`, `fy`) lies in the spectrum image.
+    pub fn position_of(&self, fx: f32, fy: f32) -> (f32, f32) {
+        ((fx + 0.5) * self.width as f32, (fy + 0.5) * self.height as f32)
+    }
+}
+
+/// Proposes notches of `radius` for the strongest spikes away from the
+/// center of `spectrum`, the mark of a halftone screen or other periodic
+/// noise. Natural image content falls off smoothly instead.
+pub fn detect_notches(spectrum: &Spectrum, radius: f32) -> Vec<Notch> {
+    let (width, height) = (spectrum.width as usize, spectrum.height as usize);
+    let (center_x, center_y) = (width / 2, height / 2);
+    let median = |values: &mut Vec<f32>| {
+        values.sort_by(f32::total_cmp);
+        values.get(values.len() / 2).copied().unwrap_or(f32::NEG_INFINITY)
+    };
+
+    let mut peaks: Vec<(f32, Notch)> = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| (0..width).map(move |x| (x, y)))
+        // One of each mirrored pair is enough
+        .filter(|&(x, y)| y < center_y || (y == center_y && x > center_x))
+        .filter_map(|(x, y)| {
+            let (value, (fx, fy)) = spectrum.cells[y * width + x];
+            if !value.is_finite() || fx.hypot(fy) < MIN_PEAK_FREQUENCY {
+                return None;
+            }
+            let at = |dx: isize, dy: isize| {
+                let (x, y) = (x.checked_add_signed(dx)?, y.checked_add_signed(dy)?);
+                (x < width && y < height).then(|| spectrum.value(x as u32, y as u32))
+            };
+            let local_maximum = (-2..=2)
+                .flat_map(|dy| (-2..=2).map(move |dx| (dx, dy)))
+                .all(|(dx, dy)| at(dx, dy).is_none_or(|other| other <= value));
+            if !local_maximum {
+                return None;
+            }
+
+            let (inner, outer) = (RING.0 as isize, RING.1 as isize);
+            let mut ring: Vec<f32> = (-outer..=outer)
+                .flat_map(|dy| (-outer..=outer).map(move |dx| (dx, dy)))
+                .filter(|&(dx, dy)| dx.abs().max(dy.abs()) >= inner)
+                .filter_map(|(dx, dy)| at(dx, dy))
+                .filter(|value| value.is_finite())
+                .collect();
+            let mut background = median(&mut ring);
+            // The image borders leave streaks along the axes, a spike on
+            // one has to stand out from the streak too
+            let along = |offsets: &dyn Fn(isize) -> (isize, isize)| {
+                let mut values: Vec<f32> = (inner..=outer)
+                    .flat_map(|d| [offsets(d), offsets(-d)])
+                    .filter_map(|(dx, dy)| at(dx, dy))
+                    .collect();
+                median(&mut values)
+            };
+            if y.abs_diff(center_y) <= 1 {
+                background = background.max(along(&|d| (d, 0)));
+            }
+            if x.abs_diff(center_x) <= 1 {
+                background = background.max(along(&|d| (0, d)));
+            }
+
+            let prominence = value - background;
+            (prominence > PEAK_PROMINENCE).then_some((prominence, Notch { fx, fy, radius }))
+        })
+        .collect();
+
+    // The strongest spike of a cluster stands for it, a notch takes out its
+    // neighbours along with it
+    peaks.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut notches: Vec<Notch> = Vec::new();
+    let strongest = peaks.first().map_or(0.0, |peak| peak.0);
+    for (prominence, peak) in peaks {
+        if prominence < strongest - MAX_PEAK_SPREAD {
+            break;
+        }
+        let covered = notches.iter().any(|notch| {
+            let distance = (peak.fx - notch.fx).hypot(peak.fy - notch.fy).min((peak.fx + notch.fx).hypot(peak.fy + notch.fy));
+            distance < 3.0 * radius
+        });
+        if !covered {
+            notches.push(peak);
+        }
+        if notches.len() == MAX_DETECTED_NOTCHES {
+            break;
+        }
+    }
+    notches
+}
+
+// The `width` x `height` plane with its mean taken out, faded to zero at
+// the edges with a Hann window and zero-padded to powers of two. The
+// filter pads by mirroring, but for looking at the spectrum that would
+// add mirrored copies of every pattern, and the hard edges would smear
+// each spike into a cross.
+fn windowed(plane: &[f32], width: usize, height: usize) -> (Vec<f32>, usize, usize) {
+    let (padded_width, padded_height) = (width.max(1).next_power_of_two(), height.max(1).next_power_of_two());
+    let mean = plane.iter().sum::<f32>() / plane.len().max(1) as f32;
+    let hann = |len: usize| -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * (i as f32 + 0.5) / len as f32).cos())
+            .collect()
+    };
+    let (across, down) = (hann(width), hann(height));
+    let mut padded = vec![0.0f32; padded_width * padded_height];
+    for (y, (row, weight)) in plane.chunks_exact(width.max(1)).zip(&down).enumerate() {
+        for (x, (&value, across)) in row.iter().zip(&across).enumerate() {
+            padded[y * padded_width + x] = (value - mean) * weight * across;
+        }
+    }
+    (padded, padded_width, padded_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, ImageBuffer, Luma, Rgb, RgbImage};
+
+    use super::*;
+
+    // Sizes that aren't powers of two, so the transform pads and crops
+    fn sources() -> Vec<DynamicImage> {
+        let pattern = |x: u32, y: u32| (x * 7 + y * 13) % 11;
+        vec![
+            DynamicImage::ImageLuma8(GrayImage::from_fn(37, 23, |x, y| Luma([(x * 6 + pattern(x, y)) as u8]))),
+            DynamicImage::ImageRgb8(RgbImage::from_fn(37, 23, |x, y| Rgb([(x * 6) as u8, (y * 11) as u8, pattern(x, y) as u8 * 20]))),
+            DynamicImage::ImageRgb8(RgbImage::from_fn(5, 1, |x, _| Rgb([x as u8 * 60, 0, 255]))),
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(1, 9, |_, y| Luma([y as u16 * 8191]))),
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(33, 17, |x, y| Rgb([(x * 1900) as u16, 65535, pattern(x, y) as u16 * 6000]))),
+        ]
+    }
+
+    #[test]
+    fn no_notches_give_back_the_input_bit_exactly() {
+        for img in sources() {
+            assert_eq!(notch_filter(&img, &[]), img, "{:?} {:?}", img.color(), img.dimensions());
+        }
+    }
+
+    // A color gradient, and the same with vertical stripes of period 4 in the luma
+    fn striped() -> (DynamicImage, DynamicImage) {
+        let clean = RgbImage::from_fn(64, 48, |x, y| Rgb([(60 + x * 2) as u8, (50 + y * 3) as u8, 110]));
+        let mut striped = clean.clone();
+        for (x, _, pixel) in striped.enumerate_pixels_mut() {
+            let stripe = 30.0 * (std::f32::consts::FRAC_PI_2 * x as f32).cos();
+            pixel.0.iter_mut().for_each(|value| *value = (*value as f32 + stripe).round() as u8);
+        }
+        (DynamicImage::ImageRgb8(clean), DynamicImage::ImageRgb8(striped))
+    }
+
+    // Largest difference between two images in any sample
+    fn largest_difference(a: &DynamicImage, b: &DynamicImage) -> u8 {
+        a.to_rgb8().as_raw().iter().zip(b.to_rgb8().as_raw()).map(|(a, b)| a.abs_diff(*b)).max().unwrap()
+    }
+
+    #[test]
+    fn notches_remove_a_periodic_pattern_and_keep_the_color() {
+        let (clean, striped) = striped();
+        let notch = Notch { fx: 0.25, fy: 0.0, radius: DEFAULT_NOTCH_RADIUS };
+        let filtered = notch_filter(&striped, &[notch]);
+        assert!(largest_difference(&filtered, &clean) <= 3, "{} left of 30", largest_difference(&filtered, &clean));
+
+        // Every channel moved alike
+        for (before, after) in striped.to_rgb8().pixels().zip(filtered.to_rgb8().pixels()) {
+            let [r, g, b] = before.0.map(i16::from);
+            let [r2, g2, b2] = after.0.map(i16::from);
+            assert!((r - g - (r2 - g2)).abs() <= 1 && (b - g - (b2 - g2)).abs() <= 1, "{:?} became {:?}", before, after);
+        }
+    }
+
+    #[test]
+    fn detection_proposes_the_pattern() {
+        let (clean, striped) = striped();
+        let notches = detect_notches(&Spectrum::of(&striped, 256), DEFAULT_NOTCH_RADIUS);
+        assert_eq!(notches.len(), 1, "{:?}", notches);
+        assert!((notches[0].fx - 0.25).abs() < 0.01 && notches[0].fy.abs() < 0.01, "{:?}", notches);
+        assert!(detect_notches(&Spectrum::of(&clean, 256), DEFAULT_NOTCH_RADIUS).is_empty());
+    }
+}
@@ -1,173 +1,211 @@
-use image::{DynamicImage, Rgb, ImageBuffer};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use image::{ImageBuffer, Pixel, Primitive, Rgb};
 use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use super::progress::Progress;
+use super::sample::{Buffer, FilterPixel, Sample};
 
+/// Stage names `process_image_parallel` times splitting and merging under.
+pub const SPLIT_STAGE: &str = "Split";
+pub const MERGE_STAGE: &str = "Merge";
+
+/// A tile of gray or RGB samples, 8 or 16 bits per channel.
 #[derive(Clone)]
-pub struct ImageBlock {
+pub struct ImageBlock<P: Pixel = Rgb<u8>> {
     pub x: u32,
     pub y: u32,
     pub width: u32,
     pub height: u32,
-    pub data: Vec<u8>,
+    pub data: Vec<P::Subpixel>,
     pub overlap: u32,
 }
 
-impl ImageBlock {
+impl<P: FilterPixel> ImageBlock<P> {
     pub fn new(x: u32, y: u32, width: u32, height: u32, overlap: u32) -> Self {
         Self {
             x,
             y,
             width,
             height,
-            data: vec![0; (width * height * 3) as usize],
+            data: vec![P::Subpixel::DEFAULT_MIN_VALUE; (width * height * P::CHANNEL_COUNT as u32) as usize],
             overlap,
         }
     }
 }
 
-pub fn split_image_into_blocks(img: &DynamicImage, block_size: u32) -> Vec<ImageBlock> {
-    let img = img.to_rgb8();
+/// Splits `img` into tiles of `block_size` that overlap their neighbours by
+/// `overlap` pixels, for filters reaching up to `overlap / 2` pixels away.
+///
+/// Panics unless `block_size > 2 * overlap`, smaller blocks would be all overlap.
+pub fn split_image_into_blocks<P: FilterPixel>(img: &Buffer<P>, block_size: u32, overlap: u32) -> Vec<ImageBlock<P>> {
+    assert!(
+        block_size > 2 * overlap,
+        "block size {} must exceed twice the overlap of {}",
+        block_size,
+        overlap
+    );
+
+    let channels = P::CHANNEL_COUNT as usize;
     let (width, height) = img.dimensions();
     let mut blocks = Vec::new();
-    let overlap = block_size / 4;
 
-    for y in (0..height).step_by((block_size - overlap) as usize) {
-        for x in (0..width).step_by((block_size - overlap) as usize) {
+    for y in block_positions(height, block_size, overlap) {
+        for x in block_positions(width, block_size, overlap) {
             let block_width = (width - x).min(block_size);
             let block_height = (height - y).min(block_size);
-            
+
             let mut block = ImageBlock::new(x, y, block_width, block_height, overlap);
-            
-            for by in 0..block_height {
-                for bx in 0..block_width {
-                    let src_x = x + bx;
-                    let src_y = y + by;
-                    if src_x < width && src_y < height {
-                        let pixel = img.get_pixel(src_x, src_y);
-                        let idx = ((by * block_width + bx) * 3) as usize;
-                        block.data[idx] = pixel[0];
-                        block.data[idx + 1] = pixel[1];
-                        block.data[idx + 2] = pixel[2];
-                    }
-                }
+            let row_len = block_width as usize * channels;
+            for (by, row) in block.data.chunks_exact_mut(row_len).enumerate() {
+                let start = ((y as usize + by) * width as usize + x as usize) * channels;
+                row.copy_from_slice(&img.as_raw()[start..start + row_len]);
             }
-            
+
             blocks.push(block);
         }
     }
-    
+
     blocks
 }
 
-pub fn merge_blocks_into_image(blocks: Vec<ImageBlock>, width: u32, height: u32) -> DynamicImage {
-    let mut img = ImageBuffer::new(width, height);
-    let mut weights = vec![vec![0.0f32; width as usize]; height as usize];
-    
-    // Initialize image with zeros
-    for y in 0..height {
-        for x in 0..width {
-            img.put_pixel(x, y, Rgb([0, 0, 0]));
-        }
+// Start of each block along one axis. The last block is moved back to end
+// flush with the image, so no block is cut short
+fn block_positions(len: u32, block_size: u32, overlap: u32) -> Vec<u32> {
+    if len <= block_size {
+        return vec![0];
     }
-    
+    let mut positions: Vec<u32> = (0..len - block_size).step_by((block_size - overlap) as usize).collect();
+    positions.push(len - block_size);
+    positions
+}
+
+/// Blends processed blocks back together, weighting each pixel by a
+/// raised cosine over the overlap so seams fade out. Every pixel is covered
+/// by at least one block that had full context around it.
+pub fn merge_blocks_into_image<P: FilterPixel>(blocks: Vec<ImageBlock<P>>, width: u32, height: u32) -> Buffer<P>
+where
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut sums = vec![0.0f32; width as usize * height as usize * channels];
+    let mut weights = vec![0.0f32; width as usize * height as usize];
+
     for block in blocks {
-        for y in 0..block.height {
-            for x in 0..block.width {
-                let src_x = block.x + x;
-                let src_y = block.y + y;
-                if src_x < width && src_y < height {
-                    let idx = ((y * block.width + x) * 3) as usize;
-                    let pixel = Rgb([
-                        block.data[idx],
-                        block.data[idx + 1],
-                        block.data[idx + 2],
-                    ]);
-                    
-                    // Calculate weight
-                    let weight = calculate_weight(x, y, block.width, block.height, block.overlap);
-                    
-                    // Accumulate weighted pixel values
-                    let current_pixel: &Rgb<u8> = img.get_pixel(src_x, src_y);
-                    let new_pixel = Rgb([
-                        ((current_pixel[0] as f32 * weights[src_y as usize][src_x as usize] + 
-                          pixel[0] as f32 * weight) / 
-                         (weights[src_y as usize][src_x as usize] + weight)) as u8,
-                        ((current_pixel[1] as f32 * weights[src_y as usize][src_x as usize] + 
-                          pixel[1] as f32 * weight) / 
-                         (weights[src_y as usize][src_x as usize] + weight)) as u8,
-                        ((current_pixel[2] as f32 * weights[src_y as usize][src_x as usize] + 
-                          pixel[2] as f32 * weight) / 
-                         (weights[src_y as usize][src_x as usize] + weight)) as u8,
-                    ]);
-                    
-                    img.put_pixel(src_x, src_y, new_pixel);
-                    weights[src_y as usize][src_x as usize] += weight;
+        let x_weights = edge_weights(block.x, block.width, width, block.overlap);
+        let y_weights = edge_weights(block.y, block.height, height, block.overlap);
+        for (by, y_weight) in y_weights.iter().enumerate() {
+            for (bx, x_weight) in x_weights.iter().enumerate() {
+                let weight = x_weight * y_weight;
+                let i = (block.y as usize + by) * width as usize + block.x as usize + bx;
+                let block_index = (by * block.width as usize + bx) * channels;
+                for (sum, value) in sums[i * channels..(i + 1) * channels]
+                    .iter_mut()
+                    .zip(&block.data[block_index..block_index + channels])
+                {
+                    *sum += weight * value.to_f32();
                 }
+                weights[i] += weight;
             }
         }
     }
-    
-    // Handle zero-weight regions (boundaries)
-    for y in 0..height {
-        for x in 0..width {
-            if weights[y as usize][x as usize] == 0.0 {
-                // Use nearest valid pixel value
-                let mut nearest_pixel = Rgb([0, 0, 0]);
-                let mut min_dist = f32::MAX;
-                
-                for dy in -1..=1 {
-                    for dx in -1..=1 {
-                        let nx = x as i32 + dx;
-                        let ny = y as i32 + dy;
-                        if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                            let dist = (dx * dx + dy * dy) as f32;
-                            if dist < min_dist && weights[ny as usize][nx as usize] > 0.0 {
-                                min_dist = dist;
-                                nearest_pixel = *img.get_pixel(nx as u32, ny as u32);
-                            }
-                        }
-                    }
-                }
-                
-                img.put_pixel(x, y, nearest_pixel);
-            }
+
+    let data = sums
+        .chunks_exact(channels)
+        .zip(&weights)
+        .flat_map(|(sums, &weight)| sums.iter().map(move |sum| P::Subpixel::from_f32((sum / weight).round())))
+        .collect();
+    ImageBuffer::from_raw(width, height, data).unwrap()
+}
+
+// Weights along one axis of a block starting at `start`. Edges inside the
+// image get no weight for the first half of the overlap, where the filters
+// lacked context, then ramp up with a Hann window over the second half.
+// Edges on the image border have no neighbour to blend with.
+fn edge_weights(start: u32, len: u32, image_len: u32, overlap: u32) -> Vec<f32> {
+    let unreliable = overlap / 2;
+    let ramp = |distance: u32| {
+        if distance >= overlap {
+            1.0
+        } else if distance < unreliable {
+            0.0
+        } else {
+            let phase = std::f32::consts::FRAC_PI_2 * ((distance - unreliable) as f32 + 0.5) / (overlap - unreliable) as f32;
+            phase.sin().powi(2)
+        }
+    };
+
+    (0..len)
+        .map(|i| {
+            let before = if start > 0 { ramp(i) } else { 1.0 };
+            let after = if start + len < image_len { ramp(len - 1 - i) } else { 1.0 };
+            before * after
+        })
+        .collect()
+}
+
+/// A pool of `threads` worker threads, `None` for 0 which means rayon's
+/// global pool sized to the machine. The last pool built is reused as long
+/// as the count stays the same.
+pub fn thread_pool(threads: usize) -> Option<Arc<ThreadPool>> {
+    static POOL: Mutex<Option<(usize, Arc<ThreadPool>)>> = Mutex::new(None);
+
+    if threads == 0 {
+        return None;
+    }
+    let mut cached = POOL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    match &*cached {
+        Some((count, pool)) if *count == threads => Some(Arc::clone(pool)),
+        _ => {
+            let pool = Arc::new(ThreadPoolBuilder::new().num_threads(threads).build().ok()?);
+            *cached = Some((threads, Arc::clone(&pool)));
+            Some(pool)
         }
     }
-    
-    DynamicImage::ImageRgb8(img)
 }
 
-fn calculate_weight(x: u32, y: u32, width: u32, height: u32, overlap: u32) -> f32 {
-    let x_weight = if x < overlap {
-        (x as f32 / overlap as f32).powf(1.5) // Use 1.5 power for smoother transition
-    } else if x >= width - overlap {
-        ((width - x - 1) as f32 / overlap as f32).powf(1.5)
-    } else {
-        1.0
-    };
-    
-    let y_weight = if y < overlap {
-        (y as f32 / overlap as f32).powf(1.5)
-    } else if y >= height - overlap {
-        ((height - y - 1) as f32 / overlap as f32).powf(1.5)
-    } else {
-        1.0
-    };
-    
-    x_weight * y_weight
+/// Runs `op` with every parallel iterator inside it on `pool`, or on the
+/// global pool without one.
+pub fn in_pool<R: Send>(pool: Option<&ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
 }
 
-pub fn process_blocks_parallel<F>(blocks: Vec<ImageBlock>, process_fn: F) -> Vec<ImageBlock>
+pub fn process_blocks_parallel<P: FilterPixel, F>(blocks: Vec<ImageBlock<P>>, pool: Option<&ThreadPool>, process_fn: F) -> Vec<ImageBlock<P>>
 where
-    F: Fn(&ImageBlock) -> ImageBlock + Send + Sync,
+    P::Subpixel: Sample,
+    F: Fn(&ImageBlock<P>) -> ImageBlock<P> + Send + Sync,
 {
-    blocks.par_iter().map(|block| process_fn(block)).collect()
+    in_pool(pool, || blocks.par_iter().map(|block| process_fn(block)).collect())
 }
 
-pub fn process_image_parallel<F>(img: &DynamicImage, block_size: u32, process_fn: F) -> DynamicImage
+/// Processes `img` block by block, see `split_image_into_blocks`, on `pool`
+/// or the global pool without one. Splitting and merging are timed in
+/// `progress`.
+pub fn process_image_parallel<P: FilterPixel, F>(
+    img: &Buffer<P>,
+    block_size: u32,
+    overlap: u32,
+    pool: Option<&ThreadPool>,
+    progress: &Progress,
+    process_fn: F,
+) -> Buffer<P>
 where
-    F: Fn(&ImageBlock) -> ImageBlock + Send + Sync,
+    P::Subpixel: Sample,
+    F: Fn(&ImageBlock<P>) -> ImageBlock<P> + Send + Sync,
 {
-    let blocks = split_image_into_blocks(img, block_size);
-    let processed_blocks = process_blocks_parallel(blocks, process_fn);
-    merge_blocks_into_image(processed_blocks, img.width(), img.height())
+    let start_time = Instant::now();
+    let blocks = split_image_into_blocks(img, block_size, overlap);
+    progress.add_timing(SPLIT_STAGE, start_time.elapsed());
+
+    let processed_blocks = process_blocks_parallel(blocks, pool, process_fn);
+
+    let start_time = Instant::now();
+    let merged = merge_blocks_into_image(processed_blocks, img.width(), img.height());
+    progress.add_timing(MERGE_STAGE, start_time.elapsed());
+    merged
 } 
\ No newline at end of file
@@ -0,0 +1,425 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use image::DynamicImage;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use super::backend::{denoise_on_gpu, sharpen_on_gpu, Backend};
+use super::block_matching::{PATCH_SIZE, SEARCH_RADIUS};
+use super::border::BorderMode;
+use super::colorspace::{in_linear_light, to_grayscale};
+use super::deconvolution::{deconvolve, DeconvolutionSettings};
+use super::dehaze::{dehaze, GUIDED_RADIUS, PATCH_RADIUS};
+use super::detail::restore_detail;
+use super::document::{binarize_document, DocumentSettings};
+use super::denoise::{denoise_image_with_progress, denoise_ycbcr_with_progress, DenoiseType, PlaneStrengths, NLM_PATCH_RADIUS, NLM_SEARCH_RADIUS, TV_ITERATIONS};
+use super::geometry::{resize, ResizeSettings};
+use super::hot_pixels::repair_hot_pixels;
+use super::hsl::{adjust_hsl, HslBand};
+use super::lut::{apply_lut, Lut3d};
+use super::notch::{notch_context_radius, notch_filter, Notch};
+use super::point_ops::{apply_point_ops, PointOp, PointOps};
+use super::progress::Progress;
+use super::quantize::{posterize, Dither};
+use super::sharpness::{sharpen_image, SharpenKernel};
+use super::tone::shadows_highlights;
+use super::white_balance::{apply_white_balance, WhiteBalance};
+
+/// A single processing step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    /// Periodic noise removal in the frequency domain, see `notch_filter`
+    NotchFilter(Vec<Notch>),
+    /// Stuck pixel repair, see `repair_hot_pixels`
+    HotPixels {
+        /// Side of the neighbourhood, 3 or 5
+        window: usize,
+        /// Share of the full range
+        threshold: f32,
+    },
+    Denoise {
+        denoise_type: DenoiseType,
+        kernel_size: usize,
+        tv_lambda: f32,
+        tv_iterations: usize,
+        /// Relative change at which `ChambolleTV` stops iterating early
+        #[serde(default = "default_tv_tolerance")]
+        tv_tolerance: f32,
+        /// Filter the Y and the Cb/Cr planes separately, with these strengths
+        #[serde(default, alias = "chroma_only", deserialize_with = "deserialize_planes")]
+        planes: Option<PlaneStrengths>,
+        /// Average light rather than gamma encoded values. Only affects the
+        /// filters that take weighted averages, see `filters_linear_light`
+        #[serde(default)]
+        linear_light: bool,
+        /// Only mean, gaussian and bilateral have a GPU version
+        #[serde(default)]
+        backend: Backend,
+        /// How the neighbourhood filters read past the image edges
+        #[serde(default)]
+        border: BorderMode,
+        /// 0-1, share of the high frequencies taken back from the input, see `restore_detail`
+        #[serde(default)]
+        detail: f32,
+        /// Radius in pixels separating detail from the base, see `restore_detail`
+        #[serde(default = "default_detail_radius")]
+        detail_radius: f32,
+    },
+    /// Deblurring, see `deconvolve`
+    Deconvolve(DeconvolutionSettings),
+    /// Removes a color cast, see `apply_white_balance`
+    WhiteBalance(WhiteBalance),
+    /// In stops, see `exposure_value`
+    Exposure(f32),
+    /// See `shadows_highlights`
+    ShadowsHighlights {
+        shadows: f32,
+        highlights: f32,
+        radius: f32,
+    },
+    /// Strength 0-1, see `dehaze`
+    Dehaze(f32),
+    Brightness(f32),
+    Contrast(f32),
+    /// Per hue range changes, see `adjust_hsl`
+    Hsl(Vec<HslBand>),
+    Sharpen {
+        /// 0-3, see `sharpen_image`
+        amount: f32,
+        #[serde(default)]
+        kernel: SharpenKernel,
+        /// See `Operation::Denoise::linear_light`
+        #[serde(default)]
+        linear_light: bool,
+        #[serde(default)]
+        backend: Backend,
+        #[serde(default)]
+        border: BorderMode,
+    },
+    Resize(ResizeSettings),
+    /// A color grade, see `apply_lut`
+    Lut {
+        lut: Arc<Lut3d>,
+        /// 0-1, blend with the ungraded image
+        intensity: f32,
+    },
+    /// Converts to BT.601 luma, after which the remaining steps run on one channel
+    Grayscale,
+    /// Black and white page, see `binarize_document`
+    Document(DocumentSettings),
+    /// Few levels per channel, see `posterize`
+    Posterize {
+        /// 2-32
+        levels: u32,
+        dither: Dither,
+    },
+}
+
+fn default_tv_tolerance() -> f32 {
+    1e-4
+}
+
+pub fn default_detail_radius() -> f32 {
+    3.0
+}
+
+// Pipelines stored before the planes got their own strengths have a
+// `chroma_only` flag instead
+fn deserialize_planes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<PlaneStrengths>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        ChromaOnly(bool),
+        Planes(Option<PlaneStrengths>),
+    }
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::ChromaOnly(chroma_only) => chroma_only.then_some(PlaneStrengths::CHROMA_ONLY),
+        Stored::Planes(planes) => planes,
+    })
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::NotchFilter(_) => "Notch Filter",
+            Operation::HotPixels { .. } => "Hot Pixels",
+            Operation::Denoise { .. } => "Denoise",
+            Operation::Deconvolve(_) => "Deconvolve",
+            Operation::WhiteBalance(_) => "White Balance",
+            Operation::Exposure(_) => "Exposure",
+            Operation::ShadowsHighlights { .. } => "Shadows/Highlights",
+            Operation::Dehaze(_) => "Dehaze",
+            Operation::Brightness(_) => "Brightness",
+            Operation::Contrast(_) => "Contrast",
+            Operation::Hsl(_) => "HSL",
+            Operation::Sharpen { .. } => "Sharpen",
+            Operation::Resize(_) => "Resize",
+            Operation::Lut { .. } => "LUT",
+            Operation::Grayscale => "Grayscale",
+            Operation::Document(_) => "Document",
+            Operation::Posterize { .. } => "Posterize",
+        }
+    }
+
+    /// Denoisers, deconvolution and the notch filter, too slow to re-run on
+    /// every slider movement. The notches are also placed on the full
+    /// resolution spectrum, a downscaled preview would need others.
+    pub fn is_expensive(&self) -> bool {
+        matches!(
+            self,
+            Operation::Denoise { denoise_type, .. }
+                if !matches!(denoise_type, DenoiseType::MeanFilter | DenoiseType::GaussianFilter)
+        ) || matches!(self, Operation::Deconvolve(_) | Operation::NotchFilter(_))
+    }
+
+    /// Notch filter, hot pixel repair, denoising and deconvolution, which
+    /// clean up the image rather than change how it looks.
+    pub fn is_restoration(&self) -> bool {
+        matches!(
+            self,
+            Operation::NotchFilter(_) | Operation::HotPixels { .. } | Operation::Denoise { .. } | Operation::Deconvolve(_)
+        )
+    }
+
+    /// Whether the output has different dimensions than the input, which
+    /// rules out block-wise or region processing.
+    pub fn changes_dimensions(&self) -> bool {
+        matches!(self, Operation::Resize(_))
+    }
+
+    /// The operation as a point operation, if it is one. Runs of these are
+    /// applied through a single lookup table.
+    pub fn point_op(&self) -> Option<PointOp> {
+        match *self {
+            Operation::Exposure(ev) => Some(PointOp::Exposure(ev)),
+            Operation::Brightness(amount) => Some(PointOp::Brightness(amount)),
+            Operation::Contrast(amount) => Some(PointOp::Contrast(amount)),
+            _ => None,
+        }
+    }
+
+    /// Whether the result depends on statistics of the whole image, so
+    /// processing it in independent blocks would give each a different look.
+    /// Dithering counts too, its pattern follows the position in the whole image,
+    /// and so do filters wrapping around, which read the opposite edge.
+    /// The notch filter transforms the whole image at once.
+    pub fn is_global(&self) -> bool {
+        matches!(self, Operation::Dehaze(_) | Operation::NotchFilter(_))
+            || matches!(self, Operation::Posterize { dither, .. } if *dither != Dither::None)
+            || matches!(
+                self,
+                Operation::Denoise { border: BorderMode::Wrap, .. } | Operation::Sharpen { border: BorderMode::Wrap, .. }
+            )
+    }
+
+    /// Whether the operation can run band by band on an image too large to
+    /// hold, see `StreamedBands`. Global operations, resizing and some of
+    /// the denoisers (see `DenoiseType::streamable`) need the whole image.
+    pub fn streamable(&self) -> bool {
+        let denoiser = match self {
+            Operation::Denoise { denoise_type, .. } => denoise_type.streamable(),
+            _ => true,
+        };
+        denoiser && !self.is_global() && !self.changes_dimensions()
+    }
+
+    /// How far, in pixels, the value of an output pixel can depend on its
+    /// neighbours. Used to give partial-image processing enough context.
+    pub fn context_radius(&self) -> u32 {
+        match *self {
+            Operation::NotchFilter(ref notches) => notch_context_radius(notches),
+            Operation::Denoise { denoise_type, kernel_size, tv_iterations, detail, detail_radius, .. } => {
+                let denoise = match denoise_type {
+                    // Search window radius plus the patch window, which lies to
+                    // the lower right of its pixel
+                    DenoiseType::NonLocalMeans => (NLM_SEARCH_RADIUS + 2 * NLM_PATCH_RADIUS + 1) as u32,
+                    // Each iteration spreads information by one pixel
+                    DenoiseType::TotalVariation => TV_ITERATIONS as u32,
+                    DenoiseType::ChambolleTV => tv_iterations as u32,
+                    // Patches up to the search radius away, plus their extent
+                    DenoiseType::BlockMatching => (SEARCH_RADIUS + PATCH_SIZE) as u32,
+                    _ => (kernel_size / 2) as u32,
+                };
+                // The detail low pass blurs the denoised result once more
+                let detail = if detail > 0.0 { detail_radius.ceil() as u32 } else { 0 };
+                denoise + detail
+            }
+            Operation::WhiteBalance(_) | Operation::Exposure(_) | Operation::Brightness(_) | Operation::Contrast(_) | Operation::Hsl(_) | Operation::Lut { .. } | Operation::Grayscale => 0,
+            // Dithered runs are never split, see `is_global`
+            Operation::Posterize { .. } => 0,
+            Operation::Sharpen { .. } => 1,
+            Operation::HotPixels { window, .. } => (window / 2).max(1) as u32,
+            Operation::Document(settings) => settings.context_radius(),
+            Operation::Deconvolve(settings) => settings.context_radius(),
+            // The guided filter averages twice over its window
+            Operation::Dehaze(_) => PATCH_RADIUS + 2 * GUIDED_RADIUS,
+            Operation::ShadowsHighlights { radius, .. } => radius.ceil() as u32,
+            // Never processed by region, see `changes_dimensions`
+            Operation::Resize(_) => 0,
+        }
+    }
+
+    /// Applies the operation, returning `None` if `progress` was cancelled.
+    pub fn apply_with_progress(&self, img: &DynamicImage, progress: &Progress) -> Option<DynamicImage> {
+        match *self {
+            Operation::NotchFilter(ref notches) => Some(single_step(progress, || notch_filter(img, notches))),
+            Operation::HotPixels { window, threshold } => Some(single_step(progress, || {
+                let (repaired, count) = repair_hot_pixels(img, window, threshold);
+                progress.add_repaired_pixels(count);
+                repaired
+            })),
+            Operation::Denoise { denoise_type, kernel_size, tv_lambda, tv_iterations, tv_tolerance, planes, linear_light, backend, border, detail, detail_radius } => {
+                let denoise = |img: &DynamicImage| match denoise_on_gpu(backend, img, denoise_type, kernel_size, border, progress) {
+                    Some(denoised) => Some(denoised),
+                    None => denoise_image_with_progress(img, denoise_type, kernel_size, tv_lambda, tv_iterations, tv_tolerance, border, progress),
+                };
+                // The YCbCr planes are always filtered on the CPU
+                let denoised = if let Some(strengths) = planes {
+                    denoise_ycbcr_with_progress(img, denoise_type, kernel_size, tv_lambda, tv_iterations, tv_tolerance, strengths, border, progress)
+                } else if linear_light && denoise_type.filters_linear_light() {
+                    in_linear_light(img, denoise)
+                } else {
+                    denoise(img)
+                }?;
+                if detail > 0.0 {
+                    Some(restore_detail(img, &denoised, detail, detail_radius))
+                } else {
+                    Some(denoised)
+                }
+            }
+            Operation::Deconvolve(settings) => deconvolve(img, &settings, progress),
+            Operation::WhiteBalance(white_balance) => Some(single_step(progress, || apply_white_balance(img, white_balance))),
+            Operation::ShadowsHighlights { shadows, highlights, radius } => {
+                Some(single_step(progress, || shadows_highlights(img, shadows, highlights, radius)))
+            }
+            Operation::Dehaze(strength) => Some(single_step(progress, || dehaze(img, strength))),
+            Operation::Exposure(_) | Operation::Brightness(_) | Operation::Contrast(_) => {
+                let ops = PointOps(self.point_op().into_iter().collect());
+                Some(single_step(progress, || apply_point_ops(img.clone(), &ops)))
+            }
+            Operation::Hsl(ref bands) => Some(single_step(progress, || adjust_hsl(img, bands))),
+            Operation::Sharpen { amount, kernel, linear_light, backend, border } => Some(single_step(progress, || {
+                let sharpen = |img: &DynamicImage| {
+                    sharpen_on_gpu(backend, img, amount, kernel, border, progress).unwrap_or_else(|| sharpen_image(img, amount, kernel, border))
+                };
+                if linear_light {
+                    in_linear_light(img, |img| Some(sharpen(img))).expect("sharpening always completes")
+                } else {
+                    sharpen(img)
+                }
+            })),
+            Operation::Resize(settings) => Some(single_step(progress, || resize(img, &settings))),
+            Operation::Lut { ref lut, intensity } => Some(single_step(progress, || apply_lut(img, lut, intensity))),
+            Operation::Grayscale => Some(single_step(progress, || to_grayscale(img))),
+            Operation::Document(settings) => Some(single_step(progress, || binarize_document(img, &settings))),
+            Operation::Posterize { levels, dither } => Some(single_step(progress, || posterize(img, levels, dither))),
+        }
+    }
+}
+
+// Point operations and sharpening are quick, count them as one unit each
+fn single_step(progress: &Progress, step: impl FnOnce() -> DynamicImage) -> DynamicImage {
+    progress.add_total(1);
+    let result = step();
+    progress.advance(1);
+    result
+}
+
+// Applies the pending point operations, if any, as one step timed under
+// their names joined, e.g. "Brightness + Contrast"
+fn flush_point_ops(img: DynamicImage, point_ops: &mut PointOps, progress: &Progress) -> DynamicImage {
+    if point_ops.0.is_empty() {
+        return img;
+    }
+    let ops = std::mem::take(point_ops);
+    let start_time = Instant::now();
+    let result = single_step(progress, || apply_point_ops(img, &ops));
+    let stage = ops.0.iter().map(|op| op.name()).collect::<Vec<_>>().join(" + ");
+    progress.add_timing(&stage, start_time.elapsed());
+    result
+}
+
+/// An ordered list of operations applied one after another.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Pipeline(pub Vec<Operation>);
+
+impl Pipeline {
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        self.apply_with_progress(img, &Progress::new())
+            .expect("pipelines without a cancel request always complete")
+    }
+
+    /// Applies every operation in order, recording the time each took in
+    /// `progress` under its name.
+    pub fn apply_with_progress(&self, img: &DynamicImage, progress: &Progress) -> Option<DynamicImage> {
+        let mut current_img = img.clone();
+        let mut point_ops = PointOps::default();
+        for operation in &self.0 {
+            if progress.is_cancelled() {
+                return None;
+            }
+            if let Some(op) = operation.point_op() {
+                point_ops.0.push(op);
+                continue;
+            }
+            current_img = flush_point_ops(current_img, &mut point_ops, progress);
+            let start_time = Instant::now();
+            current_img = operation.apply_with_progress(&current_img, progress)?;
+            progress.add_timing(operation.name(), start_time.elapsed());
+        }
+        Some(flush_point_ops(current_img, &mut point_ops, progress))
+    }
+
+    pub fn context_radius(&self) -> u32 {
+        self.0.iter().map(Operation::context_radius).sum()
+    }
+
+    /// Splits the pipeline after its last restoration step, see
+    /// `Operation::is_restoration`. Applying the two parts one after the
+    /// other gives the same result as the whole, and the adjustments in the
+    /// second are quick to re-run on the output of the first.
+    pub fn split_restoration(&self) -> (Pipeline, Pipeline) {
+        let split = self.0.iter().rposition(Operation::is_restoration).map_or(0, |index| index + 1);
+        (Pipeline(self.0[..split].to_vec()), Pipeline(self.0[split..].to_vec()))
+    }
+
+    /// The pipeline with every operation that has a GPU version set to run on `backend`.
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        for operation in &mut self.0 {
+            if let Operation::Denoise { backend: op_backend, .. } | Operation::Sharpen { backend: op_backend, .. } = operation {
+                *op_backend = backend;
+            }
+        }
+        self
+    }
+
+    /// The pipeline with every filter reading past the image edges as `border` says.
+    pub fn with_border(mut self, border: BorderMode) -> Self {
+        for operation in &mut self.0 {
+            if let Operation::Denoise { border: op_border, .. } | Operation::Sharpen { border: op_border, .. } = operation {
+                *op_border = border;
+            }
+        }
+        self
+    }
+
+    pub fn changes_dimensions(&self) -> bool {
+        self.0.iter().any(Operation::changes_dimensions)
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.0.iter().any(Operation::is_global)
+    }
+
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.0.len() {
+            self.0.swap(index - 1, index);
+        }
+    }
+
+    pub fn move_down(&mut self, index: usize) {
+        if index + 1 < self.0.len() {
+            self.0.swap(index, index + 1);
+        }
+    }
+}
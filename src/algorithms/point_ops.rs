@@ -0,0 +1,120 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use super::brightness::brightness_value;
+use super::contrast::contrast_value;
+use super::exposure::exposure_value;
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// An adjustment where each output sample depends only on the input sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointOp {
+    Exposure(f32),
+    Brightness(f32),
+    Contrast(f32),
+}
+
+impl PointOp {
+    pub fn name(self) -> &'static str {
+        match self {
+            PointOp::Exposure(_) => "Exposure",
+            PointOp::Brightness(_) => "Brightness",
+            PointOp::Contrast(_) => "Contrast",
+        }
+    }
+
+    fn apply<S: Sample>(self, value: S) -> S {
+        match self {
+            PointOp::Exposure(ev) => exposure_value(value, ev),
+            PointOp::Brightness(brightness) => brightness_value(value, brightness),
+            PointOp::Contrast(contrast) => contrast_value(value, contrast),
+        }
+    }
+}
+
+/// A chain of point operations, applied in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PointOps(pub Vec<PointOp>);
+
+impl PointOps {
+    /// The whole chain as one table indexed by sample value, each step
+    /// rounding exactly like it would applied on its own.
+    fn lut<S: Sample>(&self) -> Vec<S> {
+        (0..=S::MAX_VALUE as u32)
+            .map(|value| self.0.iter().fold(S::from_f32(value as f32), |value, op| op.apply(value)))
+            .collect()
+    }
+}
+
+/// Applies every operation of `ops` to `img` in a single pass. Works in
+/// place when `img` is already in its working format.
+pub fn apply_point_ops(img: DynamicImage, ops: &PointOps) -> DynamicImage {
+    with_pixel_type!(&img, |P| apply_point_ops_at::<P>(img, ops))
+}
+
+fn apply_point_ops_at<P: FilterPixel>(img: DynamicImage, ops: &PointOps) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let lut = ops.lut::<P::Subpixel>();
+    let mut img = P::into_buffer(img);
+    img.par_chunks_mut(1 << 16).for_each(|samples| {
+        for sample in samples {
+            *sample = lut[Into::<u32>::into(*sample) as usize];
+        }
+    });
+    P::into_dynamic(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Luma, Rgb};
+
+    use super::*;
+
+    fn chains() -> Vec<PointOps> {
+        vec![
+            PointOps(vec![PointOp::Exposure(0.7)]),
+            PointOps(vec![PointOp::Brightness(-0.3)]),
+            PointOps(vec![PointOp::Contrast(0.45)]),
+            PointOps(vec![PointOp::Exposure(-0.4), PointOp::Brightness(0.2), PointOp::Contrast(-0.6)]),
+            PointOps(vec![PointOp::Contrast(0.8), PointOp::Exposure(1.5), PointOp::Brightness(-0.05)]),
+        ]
+    }
+
+    // The chain worked out sample by sample, the way it ran before the table
+    fn per_sample<S: Sample>(ops: &PointOps, value: S) -> S {
+        ops.0.iter().fold(value, |value, op| op.apply(value))
+    }
+
+    #[test]
+    fn table_matches_per_sample_math_at_8_bit() {
+        let img = ImageBuffer::from_fn(256, 3, |x, y| Rgb([x as u8, 255 - x as u8, (x * 7 + y) as u8]));
+        for ops in chains() {
+            let fused = apply_point_ops(DynamicImage::ImageRgb8(img.clone()), &ops).into_rgb8();
+            for (before, after) in img.as_raw().iter().zip(fused.as_raw()) {
+                assert_eq!(*after, per_sample(&ops, *before), "{:?} on {}", ops, before);
+            }
+        }
+    }
+
+    #[test]
+    fn table_matches_per_sample_math_at_16_bit() {
+        let img = ImageBuffer::from_fn(256, 256, |x, y| Luma([(y * 256 + x) as u16]));
+        for ops in chains() {
+            let fused = apply_point_ops(DynamicImage::ImageLuma16(img.clone()), &ops).into_luma16();
+            for (before, after) in img.as_raw().iter().zip(fused.as_raw()) {
+                assert_eq!(*after, per_sample(&ops, *before), "{:?} on {}", ops, before);
+            }
+        }
+    }
+
+    #[test]
+    fn one_pass_matches_one_pass_per_operation() {
+        let img = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 32, |x, y| Rgb([(x * 4) as u8, (y * 8) as u8, 100])));
+        for ops in chains() {
+            let separate = ops.0.iter().fold(img.clone(), |img, &op| apply_point_ops(img, &PointOps(vec![op])));
+            assert_eq!(apply_point_ops(img.clone(), &ops), separate, "{:?}", ops);
+        }
+    }
+}
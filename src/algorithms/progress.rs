@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Shared progress counter and cancel flag for long-running filters.
+///
+/// Filters add the number of work units they are about to process with
+/// `add_total`, then `advance` after each row / iteration and bail out as
+/// soon as `is_cancelled` returns true. Filters can also leave notes about
+/// how the run went, like the number of iterations an iterative solver needed,
+/// and count the pixels they repaired. Pipelines record the time each stage took.
+#[derive(Default)]
+pub struct Progress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+    notes: Mutex<Vec<String>>,
+    repaired_pixels: AtomicUsize,
+    timings: Mutex<Vec<(String, Duration)>>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_total(&self, units: usize) {
+        self.total.fetch_add(units, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, units: usize) {
+        self.done.fetch_add(units, Ordering::Relaxed);
+    }
+
+    pub fn fraction(&self) -> f32 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            return 0.0;
+        }
+        (self.done.load(Ordering::Relaxed) as f32 / total as f32).min(1.0)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn add_note(&self, note: String) {
+        self.notes.lock().unwrap().push(note);
+    }
+
+    /// The notes left so far, oldest first.
+    pub fn notes(&self) -> Vec<String> {
+        self.notes.lock().unwrap().clone()
+    }
+
+    pub fn add_repaired_pixels(&self, count: usize) {
+        self.repaired_pixels.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Pixels repaired so far, see `repair_hot_pixels`.
+    pub fn repaired_pixels(&self) -> usize {
+        self.repaired_pixels.load(Ordering::Relaxed)
+    }
+
+    /// Adds `duration` to the time spent in `stage`. Stages running more
+    /// than once, like an operation in every block, add up.
+    pub fn add_timing(&self, stage: &str, duration: Duration) {
+        let mut timings = self.timings.lock().unwrap();
+        match timings.iter_mut().find(|(name, _)| name == stage) {
+            Some((_, total)) => *total += duration,
+            None => timings.push((stage.to_string(), duration)),
+        }
+    }
+
+    /// Time spent in each stage so far, in the order they first ran.
+    pub fn timings(&self) -> Vec<(String, Duration)> {
+        self.timings.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,417 @@
+use image::{DynamicImage, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::sample::{with_pixel_type, Buffer, FilterPixel, Sample};
+
+/// Fewest and most levels per channel `posterize` takes.
+pub const MIN_LEVELS: u32 = 2;
+pub const MAX_LEVELS: u32 = 32;
+
+/// Largest palette an indexed image can have.
+pub const MAX_COLORS: usize = 256;
+
+// Alpha below which a pixel maps to the transparent palette entry
+const TRANSPARENT_BELOW: u8 = 128;
+
+// Bits per channel of the color histogram median cut splits, and of the
+// nearest color cache
+const HISTOGRAM_BITS: u32 = 6;
+const HISTOGRAM_SIDE: usize = 1 << HISTOGRAM_BITS;
+
+// Bayer's 8×8 threshold matrix, every value 0-63 once
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+// Bayer threshold of a pixel, evenly spread over 0-1
+fn bayer_threshold(x: usize, y: usize) -> f32 {
+    (BAYER[y % 8][x % 8] as f32 + 0.5) / 64.0
+}
+
+/// How the rounding error of a reduced color is hidden.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Dither {
+    /// Plain rounding, flat areas band
+    None,
+    /// A repeating 8×8 Bayer pattern, stable between frames and tiles
+    Ordered,
+    /// Floyd–Steinberg error diffusion, finer but position dependent
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub const ALL: [Dither; 3] = [Dither::None, Dither::Ordered, Dither::FloydSteinberg];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Dither::None => "None",
+            Dither::Ordered => "Ordered",
+            Dither::FloydSteinberg => "Floyd–Steinberg",
+        }
+    }
+}
+
+/// Reduces every channel to `levels` evenly spaced values, 2-32, dithered
+/// as asked. Keeps the working format of `img`.
+pub fn posterize(img: &DynamicImage, levels: u32, dither: Dither) -> DynamicImage {
+    with_pixel_type!(img, |P| P::into_dynamic(posterize_buffer::<P>(
+        P::from_dynamic(img),
+        levels.clamp(MIN_LEVELS, MAX_LEVELS),
+        dither
+    )))
+}
+
+fn posterize_buffer<P: FilterPixel>(mut buffer: Buffer<P>, levels: u32, dither: Dither) -> Buffer<P>
+where
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let width = buffer.width() as usize;
+    if width == 0 || buffer.height() == 0 {
+        return buffer;
+    }
+    // Values are worked on in steps, one step between neighbouring levels
+    let steps = (levels - 1) as f32;
+    let to_steps = steps / P::Subpixel::MAX_VALUE;
+    let from_steps = |level: f32| P::Subpixel::from_f32((level.clamp(0.0, steps) / to_steps).round());
+
+    match dither {
+        Dither::None | Dither::Ordered => {
+            buffer.par_chunks_mut(width * channels).enumerate().for_each(|(y, row)| {
+                for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+                    for value in pixel {
+                        let position = value.to_f32() * to_steps;
+                        let level = match dither {
+                            Dither::Ordered => (position + bayer_threshold(x, y)).floor(),
+                            _ => position.round(),
+                        };
+                        *value = from_steps(level);
+                    }
+                }
+            });
+        }
+        Dither::FloydSteinberg => {
+            // Errors carried to the current and the next row, one pixel of
+            // padding on either side
+            let mut current = vec![0.0f32; (width + 2) * channels];
+            let mut next = current.clone();
+            for row in buffer.chunks_exact_mut(width * channels) {
+                for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+                    for (channel, value) in pixel.iter_mut().enumerate() {
+                        let at = |offset: usize| (x + offset) * channels + channel;
+                        let wanted = value.to_f32() * to_steps + current[at(1)];
+                        let level = wanted.round().clamp(0.0, steps);
+                        *value = from_steps(level);
+                        let error = wanted - level;
+                        current[at(2)] += error * 7.0 / 16.0;
+                        next[at(0)] += error * 3.0 / 16.0;
+                        next[at(1)] += error * 5.0 / 16.0;
+                        next[at(2)] += error / 16.0;
+                    }
+                }
+                std::mem::swap(&mut current, &mut next);
+                next.fill(0.0);
+            }
+        }
+    }
+    buffer
+}
+
+/// An image stored as one palette index per pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA entries, at most `MAX_COLORS`. When there is a transparent
+    /// entry it comes first.
+    pub palette: Vec<[u8; 4]>,
+    /// Row by row
+    pub indices: Vec<u8>,
+}
+
+/// Reduces `img` to at most `colors` colors, 2-256, with a palette chosen
+/// by median cut. Pixels that are mostly transparent share one fully
+/// transparent entry, which counts towards `colors`. High bit depth
+/// sources are reduced to 8 bits first.
+pub fn quantize(img: &DynamicImage, colors: usize, dither: Dither) -> IndexedImage {
+    let rgba = img.to_rgba8();
+    let colors = colors.clamp(2, MAX_COLORS);
+    let has_transparency = rgba.pixels().any(|pixel| pixel[3] < TRANSPARENT_BELOW);
+
+    let opaque = median_cut(&ColorHistogram::of(&rgba), colors - has_transparency as usize);
+    let mut palette: Vec<[u8; 4]> = Vec::with_capacity(colors);
+    if has_transparency {
+        palette.push([0, 0, 0, 0]);
+    }
+    let first_opaque = palette.len();
+    palette.extend(opaque.iter().map(|&[r, g, b]| [r, g, b, u8::MAX]));
+
+    let indices = if opaque.is_empty() {
+        vec![0; rgba.len() / 4]
+    } else {
+        map_to_palette(&rgba, &opaque, dither)
+            .into_iter()
+            .zip(rgba.pixels())
+            .map(|(index, pixel)| if pixel[3] < TRANSPARENT_BELOW { 0 } else { (first_opaque + index as usize) as u8 })
+            .collect()
+    };
+    IndexedImage {
+        width: rgba.width(),
+        height: rgba.height(),
+        palette,
+        indices,
+    }
+}
+
+// Count and channel sums of the opaque pixels falling into each cell of a
+// coarse RGB grid
+struct ColorHistogram {
+    counts: Vec<u64>,
+    sums: Vec<[u64; 3]>,
+}
+
+fn histogram_cell(r: u8, g: u8, b: u8) -> usize {
+    let shift = 8 - HISTOGRAM_BITS;
+    ((r as usize >> shift) * HISTOGRAM_SIDE + (g as usize >> shift)) * HISTOGRAM_SIDE + (b as usize >> shift)
+}
+
+impl ColorHistogram {
+    fn of(img: &RgbaImage) -> Self {
+        let cells = HISTOGRAM_SIDE.pow(3);
+        let mut histogram = Self {
+            counts: vec![0; cells],
+            sums: vec![[0; 3]; cells],
+        };
+        for pixel in img.pixels().filter(|pixel| pixel[3] >= TRANSPARENT_BELOW) {
+            let [r, g, b, _] = pixel.0;
+            let cell = histogram_cell(r, g, b);
+            histogram.counts[cell] += 1;
+            for (sum, value) in histogram.sums[cell].iter_mut().zip([r, g, b]) {
+                *sum += value as u64;
+            }
+        }
+        histogram
+    }
+}
+
+// Occupied histogram cells, with the extent of the box they span
+struct ColorBox {
+    cells: Vec<usize>,
+    pixels: u64,
+    min: [usize; 3],
+    max: [usize; 3],
+}
+
+impl ColorBox {
+    fn new(cells: Vec<usize>, histogram: &ColorHistogram) -> Self {
+        let mut color_box = Self {
+            pixels: cells.iter().map(|&cell| histogram.counts[cell]).sum(),
+            min: [HISTOGRAM_SIDE; 3],
+            max: [0; 3],
+            cells,
+        };
+        for &cell in &color_box.cells {
+            for (channel, coordinate) in cell_coordinates(cell).into_iter().enumerate() {
+                color_box.min[channel] = color_box.min[channel].min(coordinate);
+                color_box.max[channel] = color_box.max[channel].max(coordinate);
+            }
+        }
+        color_box
+    }
+
+    fn longest_channel(&self) -> usize {
+        (0..3).max_by_key(|&channel| self.max[channel] - self.min[channel]).expect("three channels")
+    }
+
+    // Boxes with many pixels spread far are split first
+    fn priority(&self) -> u64 {
+        let channel = self.longest_channel();
+        self.pixels * (self.max[channel] - self.min[channel]) as u64
+    }
+
+    // Pixel weighted mean of the colors inside
+    fn mean(&self, histogram: &ColorHistogram) -> [u8; 3] {
+        let mut sums = [0u64; 3];
+        for &cell in &self.cells {
+            for (sum, cell_sum) in sums.iter_mut().zip(histogram.sums[cell]) {
+                *sum += cell_sum;
+            }
+        }
+        sums.map(|sum| ((sum + self.pixels / 2) / self.pixels.max(1)) as u8)
+    }
+}
+
+fn cell_coordinates(cell: usize) -> [usize; 3] {
+    [cell / (HISTOGRAM_SIDE * HISTOGRAM_SIDE), cell / HISTOGRAM_SIDE % HISTOGRAM_SIDE, cell % HISTOGRAM_SIDE]
+}
+
+// Up to `colors` palette entries: the box of occupied cells is repeatedly
+// split at the pixel median of its longest side
+fn median_cut(histogram: &ColorHistogram, colors: usize) -> Vec<[u8; 3]> {
+    let occupied: Vec<usize> = (0..histogram.counts.len()).filter(|&cell| histogram.counts[cell] > 0).collect();
+    if occupied.is_empty() || colors == 0 {
+        return Vec::new();
+    }
+    let mut boxes = vec![ColorBox::new(occupied, histogram)];
+
+    while boxes.len() < colors {
+        let Some((index, _)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.cells.len() > 1)
+            .max_by_key(|(_, color_box)| color_box.priority())
+        else {
+            break;
+        };
+        let mut color_box = boxes.swap_remove(index);
+        let channel = color_box.longest_channel();
+        color_box.cells.sort_unstable_by_key(|&cell| cell_coordinates(cell)[channel]);
+
+        // First cell past half the pixels, leaving at least one on each side
+        let mut seen = 0;
+        let split = color_box
+            .cells
+            .iter()
+            .position(|&cell| {
+                seen += histogram.counts[cell];
+                2 * seen >= color_box.pixels
+            })
+            .map_or(1, |position| position + 1)
+            .clamp(1, color_box.cells.len() - 1);
+        let upper = color_box.cells.split_off(split);
+        boxes.push(ColorBox::new(color_box.cells, histogram));
+        boxes.push(ColorBox::new(upper, histogram));
+    }
+
+    boxes.iter().map(|color_box| color_box.mean(histogram)).collect()
+}
+
+fn nearest(palette: &[[u8; 3]], color: [f32; 3]) -> usize {
+    let distance = |entry: &[u8; 3]| -> f32 {
+        entry.iter().zip(color).map(|(&value, wanted)| (value as f32 - wanted).powi(2)).sum()
+    };
+    (0..palette.len())
+        .min_by(|&a, &b| distance(&palette[a]).total_cmp(&distance(&palette[b])))
+        .expect("the palette is not empty")
+}
+
+// Index into `palette` of every pixel. Nearest entries are looked up once
+// per histogram cell, from its center.
+fn map_to_palette(img: &RgbaImage, palette: &[[u8; 3]], dither: Dither) -> Vec<u8> {
+    let shift = 8 - HISTOGRAM_BITS;
+    let mut cache = vec![u16::MAX; HISTOGRAM_SIDE.pow(3)];
+    let mut lookup = |color: [f32; 3]| {
+        let [r, g, b] = color.map(|value| value.round().clamp(0.0, 255.0) as u8);
+        let cell = histogram_cell(r, g, b);
+        if cache[cell] == u16::MAX {
+            let center = cell_coordinates(cell).map(|coordinate| ((coordinate << shift) + (1 << shift) / 2) as f32);
+            cache[cell] = nearest(palette, center) as u16;
+        }
+        cache[cell] as usize
+    };
+
+    let width = img.width() as usize;
+    let mut indices = Vec::with_capacity(img.len() / 4);
+    match dither {
+        Dither::None | Dither::Ordered => {
+            // A pattern about as strong as the spacing of a regular palette this size
+            let spread = 255.0 / (palette.len() as f32).cbrt();
+            for (x, y, pixel) in img.enumerate_pixels() {
+                let offset = match dither {
+                    Dither::Ordered => (bayer_threshold(x as usize, y as usize) - 0.5) * spread,
+                    _ => 0.0,
+                };
+                let color = [pixel[0], pixel[1], pixel[2]].map(|value| value as f32 + offset);
+                indices.push(lookup(color) as u8);
+            }
+        }
+        Dither::FloydSteinberg => {
+            let mut current = vec![[0.0f32; 3]; width + 2];
+            let mut next = current.clone();
+            for row in img.rows() {
+                for (x, pixel) in row.enumerate() {
+                    // Transparent pixels neither take nor pass on error
+                    if pixel[3] < TRANSPARENT_BELOW {
+                        indices.push(0);
+                        continue;
+                    }
+                    let wanted: [f32; 3] = std::array::from_fn(|channel| pixel[channel] as f32 + current[x + 1][channel]);
+                    let index = lookup(wanted);
+                    indices.push(index as u8);
+                    for channel in 0..3 {
+                        let error = wanted[channel].clamp(0.0, 255.0) - palette[index][channel] as f32;
+                        current[x + 2][channel] += error * 7.0 / 16.0;
+                        next[x][channel] += error * 3.0 / 16.0;
+                        next[x + 1][channel] += error * 5.0 / 16.0;
+                        next[x + 2][channel] += error / 16.0;
+                    }
+                }
+                std::mem::swap(&mut current, &mut next);
+                next.fill([0.0; 3]);
+            }
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage, Rgba};
+
+    use super::*;
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, 100])))
+    }
+
+    fn channel_means(img: &DynamicImage) -> [f64; 3] {
+        let rgb = img.to_rgb8();
+        let count = (rgb.width() * rgb.height()) as f64;
+        std::array::from_fn(|c| rgb.pixels().map(|pixel| pixel[c] as f64).sum::<f64>() / count)
+    }
+
+    #[test]
+    fn posterize_uses_only_the_levels() {
+        for dither in Dither::ALL {
+            let posterized = posterize(&gradient(), 4, dither).to_rgb8();
+            assert!(posterized.as_raw().iter().all(|value| [0, 85, 170, 255].contains(value)), "{:?}", dither);
+        }
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        let source = channel_means(&gradient());
+        for dither in [Dither::Ordered, Dither::FloydSteinberg] {
+            let means = channel_means(&posterize(&gradient(), 4, dither));
+            for (posterized, wanted) in means.iter().zip(source) {
+                assert!((posterized - wanted).abs() < 2.0, "{:?}: mean {:.1}, source {:.1}", dither, posterized, wanted);
+            }
+        }
+        // Plain rounding snaps the constant blue of 100 to the nearest level
+        let plain = channel_means(&posterize(&gradient(), 4, Dither::None));
+        assert_eq!(plain[2], 85.0);
+    }
+
+    #[test]
+    fn quantize_respects_the_color_count() {
+        let mut img = gradient().to_rgba8();
+        img.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        for colors in [2, 7, 64, 256] {
+            for dither in Dither::ALL {
+                let indexed = quantize(&DynamicImage::ImageRgba8(img.clone()), colors, dither);
+                assert!(indexed.palette.len() <= colors, "{} entries for {} colors", indexed.palette.len(), colors);
+                // The transparent entry comes first and takes one of them
+                assert_eq!(indexed.palette[0], [0, 0, 0, 0]);
+                assert!(indexed.indices.iter().all(|&index| (index as usize) < indexed.palette.len()));
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use image::{DynamicImage, GenericImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+use super::sample::{with_pixel_type, FilterPixel};
+
+/// Axis-aligned rectangle in image pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Clips the region to an image of the given size.
+    pub fn clamp_to(&self, image_width: u32, image_height: u32) -> Self {
+        let x = self.x.min(image_width);
+        let y = self.y.min(image_height);
+        Self {
+            x,
+            y,
+            width: self.width.min(image_width - x),
+            height: self.height.min(image_height - y),
+        }
+    }
+
+    /// Grows the region by `margin` pixels on every side, staying inside the image.
+    pub fn expand(&self, margin: u32, image_width: u32, image_height: u32) -> Self {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+        let right = (self.x + self.width).saturating_add(margin).min(image_width);
+        let bottom = (self.y + self.height).saturating_add(margin).min(image_height);
+        Self {
+            x,
+            y,
+            width: right.saturating_sub(x),
+            height: bottom.saturating_sub(y),
+        }
+    }
+
+    /// Grows the region around its center until it is at least `min_size`
+    /// pixels on each side, as far as the image allows.
+    pub fn ensure_min_size(&self, min_size: u32, image_width: u32, image_height: u32) -> Self {
+        let grow = |start: u32, length: u32, limit: u32| -> (u32, u32) {
+            let target = min_size.min(limit);
+            if length >= target {
+                return (start, length);
+            }
+            let before = (target - length) / 2;
+            let start = start.saturating_sub(before).min(limit - target);
+            (start, target)
+        };
+
+        let (x, width) = grow(self.x, self.width, image_width);
+        let (y, height) = grow(self.y, self.height, image_height);
+        Self { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// Processes only `region` of `img`.
+///
+/// The region is expanded by `margin` pixels of context so filters see real
+/// neighbours at the selection edge, processed with `process`, and only the
+/// pixels inside the original region are copied back into the full image.
+/// Returns `None` if `process` does.
+pub fn process_region<F>(img: &DynamicImage, region: Region, margin: u32, process: F) -> Option<DynamicImage>
+where
+    F: FnOnce(&DynamicImage) -> Option<DynamicImage>,
+{
+    let (width, height) = img.dimensions();
+    let region = region.clamp_to(width, height);
+    if region.is_empty() {
+        return Some(img.clone());
+    }
+
+    // Filters need a few pixels to work with, even for tiny selections
+    let context = region
+        .expand(margin, width, height)
+        .ensure_min_size(3, width, height);
+    let patch = img.crop_imm(context.x, context.y, context.width, context.height);
+    let processed = process(&patch)?;
+
+    let inner = processed.crop_imm(
+        region.x - context.x,
+        region.y - context.y,
+        region.width,
+        region.height,
+    );
+    Some(with_pixel_type!(img, |P| paste::<P>(img, &inner, region.x, region.y)))
+}
+
+// `patch` copied over `img` at (`x`, `y`) in the working format `P`, so
+// neither gray images nor 16-bit samples are widened or truncated
+fn paste<P: FilterPixel>(img: &DynamicImage, patch: &DynamicImage, x: u32, y: u32) -> DynamicImage {
+    let mut result = P::from_dynamic(img);
+    result
+        .copy_from(&P::from_dynamic(patch), x, y)
+        .expect("region lies inside the image");
+    P::into_dynamic(result)
+}
@@ -0,0 +1,33 @@
+use image::{DynamicImage, GenericImageView, GrayImage, RgbImage};
+
+/// Mid-gray, where the residual of an unchanged pixel lands.
+const NEUTRAL: f32 = 128.0;
+
+/// The difference `original - processed` amplified by `gain` and centered on
+/// mid-gray, showing what a filter removed. With `luma_only` the difference
+/// of the luminance is shown in gray, so color loss doesn't show up.
+///
+/// Works on the 8-bit display values. Returns `None` if the images have
+/// different dimensions.
+pub fn residual_image(original: &DynamicImage, processed: &DynamicImage, gain: f32, luma_only: bool) -> Option<DynamicImage> {
+    if original.dimensions() != processed.dimensions() {
+        return None;
+    }
+
+    let (width, height) = original.dimensions();
+    let amplify = |(&a, &b): (&u8, &u8)| {
+        // Differences span -255..=255, which only fits once widened
+        let difference = a as i16 - b as i16;
+        (NEUTRAL + difference as f32 * gain).round().clamp(0.0, 255.0) as u8
+    };
+
+    Some(if luma_only {
+        let (a, b) = (original.to_luma8(), processed.to_luma8());
+        let data = a.as_raw().iter().zip(b.as_raw()).map(amplify).collect();
+        DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, data).unwrap())
+    } else {
+        let (a, b) = (original.to_rgb8(), processed.to_rgb8());
+        let data = a.as_raw().iter().zip(b.as_raw()).map(amplify).collect();
+        DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data).unwrap())
+    })
+}
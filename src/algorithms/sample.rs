@@ -0,0 +1,149 @@
+use image::{DynamicImage, ImageBuffer, Luma, Pixel, Primitive, Rgb};
+
+pub type Buffer<P> = ImageBuffer<P, Vec<<P as Pixel>::Subpixel>>;
+
+/// Channel type the filters run on: `u8` for ordinary images, `u16` for
+/// high bit depth sources so no precision is lost before the final export.
+pub trait Sample: Primitive + Ord + Into<u32> + Send + Sync + 'static {
+    /// Largest channel value as a float (255 or 65535).
+    const MAX_VALUE: f32;
+
+    /// Converts back from a filter result, clamping to the valid range and
+    /// truncating like an `as` cast.
+    fn from_f32(value: f32) -> Self;
+
+    fn to_f32(self) -> f32 {
+        Into::<u32>::into(self) as f32
+    }
+
+    /// Factor from 8-bit units to this type's units, for filter parameters
+    /// that were tuned on 8-bit values.
+    fn scale() -> f32 {
+        Self::MAX_VALUE / 255.0
+    }
+}
+
+impl Sample for u8 {
+    const MAX_VALUE: f32 = 255.0;
+
+    fn from_f32(value: f32) -> Self {
+        value.clamp(0.0, Self::MAX_VALUE) as u8
+    }
+}
+
+impl Sample for u16 {
+    const MAX_VALUE: f32 = 65535.0;
+
+    fn from_f32(value: f32) -> Self {
+        value.clamp(0.0, Self::MAX_VALUE) as u16
+    }
+}
+
+/// Pixel types the filters run on: gray or RGB, 8 or 16 bits per channel.
+pub trait FilterPixel: Pixel + Send + Sync + 'static {
+    fn from_dynamic(img: &DynamicImage) -> Buffer<Self>;
+
+    /// Like `from_dynamic`, but reuses the buffer of `img` when it already
+    /// has this pixel type.
+    fn into_buffer(img: DynamicImage) -> Buffer<Self>;
+
+    fn into_dynamic(buffer: Buffer<Self>) -> DynamicImage;
+}
+
+macro_rules! impl_filter_pixel {
+    ($pixel:ty, $variant:ident, $convert:ident) => {
+        impl FilterPixel for $pixel {
+            fn from_dynamic(img: &DynamicImage) -> Buffer<Self> {
+                match img {
+                    DynamicImage::$variant(buffer) => buffer.clone(),
+                    _ => img.$convert(),
+                }
+            }
+
+            fn into_buffer(img: DynamicImage) -> Buffer<Self> {
+                match img {
+                    DynamicImage::$variant(buffer) => buffer,
+                    _ => img.$convert(),
+                }
+            }
+
+            fn into_dynamic(buffer: Buffer<Self>) -> DynamicImage {
+                DynamicImage::$variant(buffer)
+            }
+        }
+    };
+}
+
+impl_filter_pixel!(Luma<u8>, ImageLuma8, to_luma8);
+impl_filter_pixel!(Luma<u16>, ImageLuma16, to_luma16);
+impl_filter_pixel!(Rgb<u8>, ImageRgb8, to_rgb8);
+impl_filter_pixel!(Rgb<u16>, ImageRgb16, to_rgb16);
+
+/// The working format an image is processed in. Alpha is dropped, gray
+/// images stay single-channel and high bit depth sources keep 16 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Luma8,
+    Luma16,
+    Rgb8,
+    Rgb16,
+}
+
+impl PixelFormat {
+    pub fn of(img: &DynamicImage) -> Self {
+        let gray = matches!(
+            img,
+            DynamicImage::ImageLuma8(_)
+                | DynamicImage::ImageLumaA8(_)
+                | DynamicImage::ImageLuma16(_)
+                | DynamicImage::ImageLumaA16(_)
+        );
+        match (gray, is_high_depth(img)) {
+            (true, false) => PixelFormat::Luma8,
+            (true, true) => PixelFormat::Luma16,
+            (false, false) => PixelFormat::Rgb8,
+            (false, true) => PixelFormat::Rgb16,
+        }
+    }
+}
+
+/// Whether `img` stores more than 8 bits per channel and should be
+/// processed with `u16` samples.
+pub fn is_high_depth(img: &DynamicImage) -> bool {
+    !matches!(
+        img,
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)
+    )
+}
+
+/// Evaluates `$body` with `$pixel` naming the `FilterPixel` type matching the
+/// working format of `$img`, so generic filters can be called with it.
+/// `with_pixel_type!(format $format, |P| ...)` takes the `PixelFormat` itself.
+#[macro_export]
+macro_rules! with_pixel_type {
+    (format $format:expr, |$pixel:ident| $body:expr) => {
+        match $format {
+            $crate::algorithms::sample::PixelFormat::Luma8 => {
+                type $pixel = image::Luma<u8>;
+                $body
+            }
+            $crate::algorithms::sample::PixelFormat::Luma16 => {
+                type $pixel = image::Luma<u16>;
+                $body
+            }
+            $crate::algorithms::sample::PixelFormat::Rgb8 => {
+                type $pixel = image::Rgb<u8>;
+                $body
+            }
+            $crate::algorithms::sample::PixelFormat::Rgb16 => {
+                type $pixel = image::Rgb<u16>;
+                $body
+            }
+        }
+    };
+    ($img:expr, |$pixel:ident| $body:expr) => {
+        $crate::with_pixel_type!(format $crate::algorithms::sample::PixelFormat::of($img), |$pixel| $body)
+    };
+}
+
+pub use crate::with_pixel_type;
@@ -1,80 +1,138 @@
-use image::{DynamicImage, Rgb, ImageBuffer};
+use image::{DynamicImage, ImageBuffer, Primitive};
+use serde::{Deserialize, Serialize};
 
-pub fn sharpen_image(img: &DynamicImage, amount: f32) -> DynamicImage {
-    let img = img.to_rgb8();
+use super::border::BorderMode;
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// The neighbourhood `sharpen_image` measures detail against. Every kernel
+/// reaches one pixel around, the detail is the pixel less a weighted mean of
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SharpenKernel {
+    /// The four direct neighbours, the classic Laplacian. Rings in a plus
+    /// shape and leaves diagonal detail weaker
+    Laplacian4,
+    /// All eight neighbours, sharpens diagonal edges as much as straight ones
+    Laplacian8,
+    /// The pixel less a 3x3 gaussian blur of itself, the gentlest of the three
+    #[default]
+    UnsharpMask,
+}
+
+impl SharpenKernel {
+    pub const ALL: [SharpenKernel; 3] = [SharpenKernel::Laplacian4, SharpenKernel::Laplacian8, SharpenKernel::UnsharpMask];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SharpenKernel::Laplacian4 => "Laplacian (4 neighbours)",
+            SharpenKernel::Laplacian8 => "Laplacian (8 neighbours)",
+            SharpenKernel::UnsharpMask => "Unsharp mask",
+        }
+    }
+
+    /// Weight of the pixel at (`dx`, `dy`), each -1 to 1, in the mean the
+    /// detail is measured against.
+    pub fn weight(self, dx: i32, dy: i32) -> f32 {
+        match self {
+            SharpenKernel::Laplacian4 => ((dx == 0) != (dy == 0)) as u8 as f32,
+            SharpenKernel::Laplacian8 => (dx != 0 || dy != 0) as u8 as f32,
+            SharpenKernel::UnsharpMask => ((2 - dx.abs()) * (2 - dy.abs())) as f32,
+        }
+    }
+}
+
+/// Sharpens by adding `amount` times the detail `kernel` finds, reading past
+/// the image edges as `border` says. 1 adds the detail once, which doubles
+/// the contrast of the finest features, 0 leaves the image as it is.
+pub fn sharpen_image(img: &DynamicImage, amount: f32, kernel: SharpenKernel, border: BorderMode) -> DynamicImage {
+    with_pixel_type!(img, |P| sharpen_image_at::<P>(img, amount, kernel, border))
+}
+
+fn sharpen_image_at<P: FilterPixel>(img: &DynamicImage, amount: f32, kernel: SharpenKernel, border: BorderMode) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let img = P::from_dynamic(img);
+    let channels = P::CHANNEL_COUNT as usize;
     let (width, height) = img.dimensions();
     let mut new_img = ImageBuffer::new(width, height);
 
-    // Laplacian kernel for sharpening
-    let kernel = [
-        [0.0, -1.0, 0.0],
-        [-1.0, 5.0, -1.0],
-        [0.0, -1.0, 0.0],
-    ];
-
-    // Apply sharpening
     for y in 0..height {
         for x in 0..width {
-            let mut sum_r = 0.0;
-            let mut sum_g = 0.0;
-            let mut sum_b = 0.0;
+            let mut sums = [0.0f32; 3];
             let mut weight_sum = 0.0;
 
-            // Apply convolution kernel
             for ky in -1..=1 {
                 for kx in -1..=1 {
-                    let nx = x as i32 + kx;
-                    let ny = y as i32 + ky;
-                    
-                    // Boundary handling: mirror padding
-                    let (nx, ny) = if nx < 0 {
-                        (-nx, ny)
-                    } else if nx >= width as i32 {
-                        (2 * width as i32 - nx - 1, ny)
-                    } else {
-                        (nx, ny)
-                    };
-                    
-                    let (nx, ny) = if ny < 0 {
-                        (nx, -ny)
-                    } else if ny >= height as i32 {
-                        (nx, 2 * height as i32 - ny - 1)
-                    } else {
-                        (nx, ny)
-                    };
-
-                    if nx >= 0 && nx < width as i32 && ny >= 0 && ny < height as i32 {
-                        let pixel = img.get_pixel(nx as u32, ny as u32);
-                        let weight = kernel[(ky + 1) as usize][(kx + 1) as usize];
-                        
-                        let edge_factor = if x < 2 || x >= width - 2 || y < 2 || y >= height - 2 {
-                            0.5
-                        } else {
-                            1.0
-                        };
-                        
-                        let adjusted_weight = weight * edge_factor;
-                        
-                        sum_r += pixel[0] as f32 * adjusted_weight;
-                        sum_g += pixel[1] as f32 * adjusted_weight;
-                        sum_b += pixel[2] as f32 * adjusted_weight;
-                        weight_sum += adjusted_weight;
+                    let weight = kernel.weight(kx, ky);
+                    if weight == 0.0 {
+                        continue;
+                    }
+                    // Skipped neighbours leave their weight out of the mean
+                    if let Some(pixel) = border.pixel(&img, x as i32 + kx, y as i32 + ky) {
+                        for (sum, value) in sums.iter_mut().zip(pixel.channels()) {
+                            *sum += value.to_f32() * weight;
+                        }
+                        weight_sum += weight;
                     }
                 }
             }
 
-            // 归一化并应用锐化强度
-            let scale = 1.0 / weight_sum;
-            let r = ((sum_r * scale * amount + img.get_pixel(x, y)[0] as f32 * (1.0 - amount))
-                .clamp(0.0, 255.0)) as u8;
-            let g = ((sum_g * scale * amount + img.get_pixel(x, y)[1] as f32 * (1.0 - amount))
-                .clamp(0.0, 255.0)) as u8;
-            let b = ((sum_b * scale * amount + img.get_pixel(x, y)[2] as f32 * (1.0 - amount))
-                .clamp(0.0, 255.0)) as u8;
+            let original = img.get_pixel(x, y).channels();
+            let mut values = [P::Subpixel::DEFAULT_MIN_VALUE; 3];
+            for c in 0..channels {
+                let value = original[c].to_f32();
+                // Without any neighbour there is no detail to measure
+                let detail = if weight_sum > 0.0 { value - sums[c] / weight_sum } else { 0.0 };
+                values[c] = P::Subpixel::from_f32(value + amount * detail);
+            }
+
+            new_img.put_pixel(x, y, *P::from_slice(&values[..channels]));
+        }
+    }
+
+    P::into_dynamic(new_img)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgb, RgbImage};
 
-            new_img.put_pixel(x, y, Rgb([r, g, b]));
+    use super::*;
+
+    #[test]
+    fn no_amount_is_an_exact_no_op() {
+        let rgb = RgbImage::from_fn(13, 9, |x, y| Rgb([(x * 19 + y * 7) as u8, (x * y * 5) as u8, ((x ^ y) * 17) as u8]));
+        for img in [DynamicImage::ImageRgb8(rgb.clone()), DynamicImage::ImageRgb16(DynamicImage::ImageRgb8(rgb).to_rgb16())] {
+            for kernel in SharpenKernel::ALL {
+                for border in BorderMode::ALL {
+                    assert_eq!(sharpen_image(&img, 0.0, kernel, border), img, "{:?} with {:?} borders", kernel, border);
+                }
+            }
         }
     }
 
-    DynamicImage::ImageRgb8(new_img)
-} 
\ No newline at end of file
+    #[test]
+    fn a_bright_pixel_rings_in_the_kernel_shape() {
+        // 164 on 100 at half strength: the pixel gains half its detail, its
+        // neighbours lose half the share of it their means take in
+        for (kernel, [corner, side, center]) in [
+            (SharpenKernel::Laplacian4, [100, 92, 196]),
+            (SharpenKernel::Laplacian8, [96, 96, 196]),
+            (SharpenKernel::UnsharpMask, [98, 96, 188]),
+        ] {
+            let mut img = GrayImage::from_pixel(9, 9, Luma([100]));
+            img.put_pixel(4, 4, Luma([164]));
+            let sharpened = sharpen_image(&DynamicImage::ImageLuma8(img), 0.5, kernel, BorderMode::Clamp).to_luma8();
+            for (x, y, pixel) in sharpened.enumerate_pixels() {
+                let expected = match (x.abs_diff(4), y.abs_diff(4)) {
+                    (0, 0) => center,
+                    (0, 1) | (1, 0) => side,
+                    (1, 1) => corner,
+                    _ => 100,
+                };
+                assert_eq!(pixel.0[0], expected, "{:?} at ({}, {})", kernel, x, y);
+            }
+        }
+    }
+}
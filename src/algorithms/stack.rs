@@ -0,0 +1,69 @@
+use image::{DynamicImage, ImageBuffer, Primitive};
+use rayon::prelude::*;
+
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// How the frames of a stack are combined per sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackMethod {
+    /// Best noise reduction
+    Mean,
+    /// Ignores values that show up in only a few frames, like hot pixels or
+    /// passing cars
+    Median,
+}
+
+impl StackMethod {
+    pub const ALL: [StackMethod; 2] = [StackMethod::Mean, StackMethod::Median];
+}
+
+/// Combines several exposures of the same scene into one. The frames must
+/// all have the same size, the result has the working format of the first.
+pub fn stack_images(frames: &[DynamicImage], method: StackMethod) -> Result<DynamicImage, String> {
+    let Some(first) = frames.first() else {
+        return Err("No frames to stack".to_string());
+    };
+    if frames.iter().any(|frame| frame.width() != first.width() || frame.height() != first.height()) {
+        return Err("Frames must all have the same size".to_string());
+    }
+    Ok(with_pixel_type!(first, |P| stack_at::<P>(frames, method)))
+}
+
+fn stack_at<P: FilterPixel>(frames: &[DynamicImage], method: StackMethod) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    const CHUNK: usize = 1 << 14;
+
+    let (width, height) = (frames[0].width(), frames[0].height());
+    let buffers: Vec<Vec<P::Subpixel>> = frames.iter().map(|frame| P::from_dynamic(frame).into_raw()).collect();
+    let count = buffers.len() as f32;
+
+    let mut samples = vec![P::Subpixel::DEFAULT_MIN_VALUE; buffers[0].len()];
+    samples.par_chunks_mut(CHUNK).enumerate().for_each(|(chunk_index, chunk)| {
+        let mut values = Vec::with_capacity(buffers.len());
+        for (offset, sample) in chunk.iter_mut().enumerate() {
+            let index = chunk_index * CHUNK + offset;
+            *sample = match method {
+                // Summed in f32, 8-bit or 16-bit sums would overflow
+                StackMethod::Mean => {
+                    let sum: f32 = buffers.iter().map(|buffer| buffer[index].to_f32()).sum();
+                    P::Subpixel::from_f32(sum / count + 0.5)
+                }
+                StackMethod::Median => {
+                    values.clear();
+                    values.extend(buffers.iter().map(|buffer| buffer[index]));
+                    values.sort_unstable();
+                    let middle = values.len() / 2;
+                    if values.len() % 2 == 0 {
+                        P::Subpixel::from_f32((values[middle - 1].to_f32() + values[middle].to_f32()) / 2.0 + 0.5)
+                    } else {
+                        values[middle]
+                    }
+                }
+            };
+        }
+    });
+
+    P::into_dynamic(ImageBuffer::from_raw(width, height, samples).expect("one sample per channel and pixel"))
+}
@@ -0,0 +1,105 @@
+use image::{DynamicImage, ImageBuffer};
+
+use super::sample::{with_pixel_type, FilterPixel};
+
+/// An image handed out a few rows at a time, from the top down, for
+/// images too large to hold whole.
+pub trait RowSource {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    /// The next `rows` rows, in the working format of the image (see
+    /// `PixelFormat`). Every call returns the same format.
+    fn read_rows(&mut self, rows: u32) -> Result<DynamicImage, String>;
+}
+
+/// Processes the rows of `source` band after band, yielding the processed
+/// bands from the top down. Each band of `band_rows` rows is processed with
+/// up to `margin` rows of context above and below it, so operations
+/// reaching no further than `margin` give the same result as on the whole
+/// image, up to rounding. Only one band and its margins are held at a time.
+pub struct StreamedBands<S, F> {
+    source: S,
+    band_rows: u32,
+    margin: u32,
+    process: F,
+    // Rows read so far that are still needed, starting at row `window_top`
+    window: Option<DynamicImage>,
+    window_top: u32,
+    // First row of the next band
+    next_row: u32,
+}
+
+impl<S, F> StreamedBands<S, F>
+where
+    S: RowSource,
+    F: FnMut(&DynamicImage) -> Result<DynamicImage, String>,
+{
+    pub fn new(source: S, band_rows: u32, margin: u32, process: F) -> Self {
+        Self {
+            source,
+            band_rows: band_rows.max(1),
+            margin,
+            process,
+            window: None,
+            window_top: 0,
+            next_row: 0,
+        }
+    }
+
+    fn next_band(&mut self) -> Result<DynamicImage, String> {
+        let (width, height) = (self.source.width(), self.source.height());
+        let top = self.next_row;
+        let bottom = (top + self.band_rows).min(height);
+        let context_bottom = (bottom + self.margin).min(height);
+
+        let read = self.window_top + self.window.as_ref().map_or(0, DynamicImage::height);
+        if context_bottom > read {
+            let rows = self.source.read_rows(context_bottom - read)?;
+            self.window = Some(match self.window.take() {
+                Some(window) => stack_rows(window, rows),
+                None => rows,
+            });
+        }
+        let window = self.window.take().expect("rows were read for the band");
+
+        let processed = (self.process)(&window)?;
+        let band = processed.crop_imm(0, top - self.window_top, width, bottom - top);
+
+        // The rows above the next band's margin are done with
+        let keep_top = bottom.saturating_sub(self.margin).max(self.window_top);
+        self.window = Some(window.crop_imm(0, keep_top - self.window_top, width, context_bottom - keep_top));
+        self.window_top = keep_top;
+        self.next_row = bottom;
+        Ok(band)
+    }
+}
+
+impl<S, F> Iterator for StreamedBands<S, F>
+where
+    S: RowSource,
+    F: FnMut(&DynamicImage) -> Result<DynamicImage, String>,
+{
+    type Item = Result<DynamicImage, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_row >= self.source.height() {
+            return None;
+        }
+        let band = self.next_band();
+        if band.is_err() {
+            // Nothing sensible follows a band that failed
+            self.next_row = self.source.height();
+        }
+        Some(band)
+    }
+}
+
+// `top` with the rows of `bottom` below it, both in the same working format
+fn stack_rows(top: DynamicImage, bottom: DynamicImage) -> DynamicImage {
+    let (width, height) = (top.width(), top.height() + bottom.height());
+    with_pixel_type!(&top, |P| {
+        let mut data = P::into_buffer(top).into_raw();
+        data.extend(P::into_buffer(bottom).into_raw());
+        P::into_dynamic(ImageBuffer::from_raw(width, height, data).unwrap())
+    })
+}
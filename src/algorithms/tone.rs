@@ -0,0 +1,50 @@
+use image::{DynamicImage, ImageBuffer};
+
+use super::blur::gaussian_blur;
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+// Largest gain the shadows slider applies to the darkest regions
+const MAX_SHADOW_GAIN: f32 = 3.0;
+// Share of their light the highlights slider takes from the brightest regions
+const MAX_HIGHLIGHT_CUT: f32 = 0.5;
+// How far the mask may differ from a pixel's own luminance, see below
+const MASK_SPREAD: f32 = 0.25;
+
+/// Brightens dark regions by up to `shadows` and darkens bright ones by up
+/// to `highlights` (both 0-1). Whether a region counts as dark or bright is
+/// decided on the luminance blurred over `radius` pixels, so local contrast
+/// is kept. Colors are scaled proportionally to keep their saturation.
+pub fn shadows_highlights(img: &DynamicImage, shadows: f32, highlights: f32, radius: f32) -> DynamicImage {
+    if shadows == 0.0 && highlights == 0.0 {
+        return img.clone();
+    }
+    with_pixel_type!(img, |P| shadows_highlights_at::<P>(img, shadows, highlights, radius))
+}
+
+fn shadows_highlights_at<P: FilterPixel>(img: &DynamicImage, shadows: f32, highlights: f32, radius: f32) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let img = P::from_dynamic(img);
+    let (width, height) = img.dimensions();
+    let max = P::Subpixel::MAX_VALUE;
+
+    let luma: Vec<f32> = img.pixels().map(|pixel| pixel.to_luma()[0].to_f32() / max).collect();
+    let mask = gaussian_blur(&luma, width, height, radius / 3.0);
+
+    let mut new_img = ImageBuffer::new(width, height);
+    for ((x, y, pixel), (&luma, &mask)) in img.enumerate_pixels().zip(luma.iter().zip(&mask)) {
+        // Right next to a strong edge the blurred mask mostly reflects the
+        // other side, which shows up as a halo. Keeping it near the pixel's
+        // own luminance stops a dark edge against the sky from being lifted
+        // as if it were a shadow.
+        let mask = mask.clamp(luma - MASK_SPREAD, luma + MASK_SPREAD);
+        let shadow_weight = (1.0 - mask / 0.5).clamp(0.0, 1.0).powi(2);
+        let highlight_weight = ((mask - 0.5) / 0.5).clamp(0.0, 1.0).powi(2);
+        let gain = (1.0 + shadows * shadow_weight * (MAX_SHADOW_GAIN - 1.0))
+            * (1.0 - highlights * highlight_weight * MAX_HIGHLIGHT_CUT);
+        new_img.put_pixel(x, y, pixel.map(|value| P::Subpixel::from_f32(value.to_f32() * gain)));
+    }
+
+    P::into_dynamic(new_img)
+}
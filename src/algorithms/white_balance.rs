@@ -0,0 +1,89 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::colorspace::{linear_to_srgb, srgb_to_linear};
+use super::sample::{with_pixel_type, FilterPixel, Sample};
+
+/// Largest temperature or tint shift in stops, the slider range.
+pub const MAX_SHIFT: f32 = 1.0;
+// Rec. 709 luminance of linear RGB, kept unchanged by the gains
+const LUMINANCE: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// A white balance shift as the two axes photographers know, both in stops.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhiteBalance {
+    /// Red over blue, positive warms the image
+    pub temperature: f32,
+    /// Red and blue over green, positive pulls towards magenta and removes a green cast
+    pub tint: f32,
+}
+
+impl WhiteBalance {
+    pub fn is_neutral(&self) -> bool {
+        self.temperature == 0.0 && self.tint == 0.0
+    }
+
+    /// Multipliers of linear red, green and blue, scaled so a neutral gray
+    /// keeps its luminance.
+    pub fn gains(&self) -> [f32; 3] {
+        let stops = [
+            self.temperature / 2.0 + self.tint / 3.0,
+            -2.0 * self.tint / 3.0,
+            -self.temperature / 2.0 + self.tint / 3.0,
+        ];
+        let gains = stops.map(f32::exp2);
+        let luminance: f32 = gains.iter().zip(LUMINANCE).map(|(gain, weight)| gain * weight).sum();
+        gains.map(|gain| gain / luminance)
+    }
+
+    /// The shift closest to multiplying linear red, green and blue by
+    /// `gains`. Their overall scale doesn't matter, both axes are ratios.
+    pub fn from_gains(gains: [f32; 3]) -> Self {
+        let [red, green, blue] = gains.map(|gain| gain.max(f32::EPSILON).log2());
+        Self {
+            temperature: (red - blue).clamp(-MAX_SHIFT, MAX_SHIFT),
+            tint: ((red + blue) / 2.0 - green).clamp(-MAX_SHIFT, MAX_SHIFT),
+        }
+    }
+}
+
+/// Multiplies the channels of `img` in linear light by the gains of
+/// `white_balance`. Gray images have no color to balance and are returned as they are.
+pub fn apply_white_balance(img: &DynamicImage, white_balance: WhiteBalance) -> DynamicImage {
+    if white_balance.is_neutral() {
+        return img.clone();
+    }
+    with_pixel_type!(img, |P| apply_white_balance_at::<P>(img, white_balance))
+}
+
+fn apply_white_balance_at<P: FilterPixel>(img: &DynamicImage, white_balance: WhiteBalance) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let mut img = P::from_dynamic(img);
+    let channels = P::CHANNEL_COUNT as usize;
+    if channels < 3 {
+        return P::into_dynamic(img);
+    }
+
+    let max = P::Subpixel::MAX_VALUE;
+    let luts: Vec<Vec<P::Subpixel>> = white_balance
+        .gains()
+        .iter()
+        .map(|gain| {
+            (0..=max as u32)
+                .map(|value| P::Subpixel::from_f32((linear_to_srgb((srgb_to_linear(value as f32 / max) * gain).min(1.0)) * max).round()))
+                .collect()
+        })
+        .collect();
+    img.par_chunks_mut(channels << 16).for_each(|samples| {
+        for pixel in samples.chunks_mut(channels) {
+            for (sample, lut) in pixel.iter_mut().zip(&luts) {
+                *sample = lut[Into::<u32>::into(*sample) as usize];
+            }
+        }
+    });
+    P::into_dynamic(img)
+}
@@ -0,0 +1,50 @@
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::image_loader::Animation;
+use crate::processing::FramesJob;
+
+/// An animated GIF opened for editing. The panes show one frame at a time,
+/// processing all of them is a separate run.
+pub struct AnimationState {
+    pub source: Animation,
+    /// Index of the frame in the panes
+    pub frame: usize,
+    /// Every frame run through the pipeline, in order
+    pub processed: Option<Vec<DynamicImage>>,
+    pub job: Option<FramesJob>,
+}
+
+impl AnimationState {
+    pub fn new(source: Animation) -> Self {
+        Self {
+            source,
+            frame: 0,
+            processed: None,
+            job: None,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.source.frames.len()
+    }
+
+    pub fn current(&self) -> &DynamicImage {
+        &self.source.frames[self.frame]
+    }
+
+    /// The processed version of the current frame, once all were processed.
+    pub fn current_processed(&self) -> Option<&DynamicImage> {
+        self.processed.as_ref().and_then(|frames| frames.get(self.frame))
+    }
+
+    /// Edits every frame the same way, the processed frames no longer match
+    /// and are dropped.
+    pub fn edit_frames(&mut self, edit: impl Fn(&DynamicImage) -> DynamicImage + Sync) {
+        if let Some(job) = self.job.take() {
+            job.cancel();
+        }
+        self.source.frames = self.source.frames.par_iter().map(&edit).collect();
+        self.processed = None;
+    }
+}
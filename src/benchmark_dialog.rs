@@ -0,0 +1,303 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use eframe::egui;
+use image::DynamicImage;
+
+use crate::algorithms::benchmark::{
+    add_gaussian_noise, channel_scores, psnr, results_to_csv, run_benchmark, slow_copy, ssim, BenchResult, DenoiseSpec, SLOW_MAX_SIDE,
+};
+use crate::algorithms::channels::ChannelView;
+use crate::algorithms::denoise::DenoiseType;
+use crate::algorithms::progress::Progress;
+use crate::settings::ProcessingSettings;
+
+// Fixed so repeated comparisons see the same noise
+const NOISE_SEED: u64 = 1;
+
+struct BenchmarkOutcome {
+    results: Vec<BenchResult>,
+    /// PSNR and SSIM of the noisy input, what the denoisers have to beat
+    noisy_scores: Option<(f64, f64)>,
+    /// The clean image and its noisy copy, kept to score other channels
+    images: Option<(DynamicImage, DynamicImage)>,
+}
+
+impl BenchmarkOutcome {
+    // Scores in `channel` alone, `None` without a clean reference
+    fn score_channel(&self, channel: ChannelView) -> Option<ChannelScores> {
+        let (clean, noisy) = self.images.as_ref()?;
+        let small = (clean.width().max(clean.height()) > SLOW_MAX_SIDE).then(|| slow_copy(clean));
+        let results = self
+            .results
+            .iter()
+            .map(|result| {
+                let reference = match (&small, result.downscaled) {
+                    (Some(small), true) => small,
+                    _ => clean,
+                };
+                channel_scores(&result.image, reference, channel)
+            })
+            .collect();
+        Some(ChannelScores {
+            channel,
+            noisy: channel_scores(noisy, clean, channel),
+            results,
+        })
+    }
+}
+
+// PSNR and SSIM of the noisy input and of each result in one channel
+struct ChannelScores {
+    channel: ChannelView,
+    noisy: (f64, f64),
+    results: Vec<(f64, f64)>,
+}
+
+/// A comparison running on a background thread.
+struct BenchmarkJob {
+    progress: Arc<Progress>,
+    receiver: Receiver<Option<BenchmarkOutcome>>,
+}
+
+impl BenchmarkJob {
+    fn spawn(img: DynamicImage, noise_sigma: Option<f32>, specs: Vec<DenoiseSpec>) -> Self {
+        let progress = Arc::new(Progress::new());
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = Arc::clone(&progress);
+        thread::spawn(move || {
+            // With synthetic noise the loaded image is the clean reference
+            let outcome = match noise_sigma {
+                Some(sigma) => {
+                    let noisy = add_gaussian_noise(&img, sigma, NOISE_SEED);
+                    let noisy_scores = (psnr(&noisy, &img), ssim(&noisy, &img));
+                    run_benchmark(&noisy, Some(&img), &specs, &thread_progress).map(|results| BenchmarkOutcome {
+                        results,
+                        noisy_scores: Some(noisy_scores),
+                        images: Some((img, noisy)),
+                    })
+                }
+                None => run_benchmark(&img, None, &specs, &thread_progress)
+                    .map(|results| BenchmarkOutcome { results, noisy_scores: None, images: None }),
+            };
+            let _ = sender.send(outcome);
+        });
+
+        Self { progress, receiver }
+    }
+}
+
+/// Window running every denoiser with the current parameters and listing
+/// how long each took and, on synthetic noise, how close it got to the
+/// clean image.
+pub struct BenchmarkDialog {
+    pub open: bool,
+    add_noise: bool,
+    noise_sigma: f32,
+    job: Option<BenchmarkJob>,
+    outcome: Option<Arc<BenchmarkOutcome>>,
+    selected: Option<usize>,
+    /// Scores in the channel the panes show, when that isn't RGB
+    channel_scores: Option<ChannelScores>,
+    // Another channel being scored on a background thread
+    scoring: Option<(ChannelView, Receiver<Option<ChannelScores>>)>,
+}
+
+impl Default for BenchmarkDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            add_noise: true,
+            noise_sigma: 20.0,
+            job: None,
+            outcome: None,
+            selected: None,
+            channel_scores: None,
+            scoring: None,
+        }
+    }
+}
+
+impl BenchmarkDialog {
+    /// Drops the results of a previous image, cancelling a running comparison.
+    pub fn clear(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.progress.cancel();
+        }
+        self.outcome = None;
+        self.selected = None;
+        self.channel_scores = None;
+        self.scoring = None;
+    }
+
+    fn poll(&mut self, ctx: &egui::Context) {
+        if let Some(job) = &self.job {
+            match job.receiver.try_recv() {
+                Ok(outcome) => {
+                    self.outcome = outcome.map(Arc::new);
+                    self.selected = None;
+                    self.channel_scores = None;
+                    self.scoring = None;
+                    self.job = None;
+                }
+                Err(TryRecvError::Empty) => ctx.request_repaint_after(Duration::from_millis(100)),
+                Err(TryRecvError::Disconnected) => self.job = None,
+            }
+        }
+        if let Some((_, receiver)) = &self.scoring {
+            match receiver.try_recv() {
+                Ok(scores) => {
+                    self.channel_scores = scores;
+                    self.scoring = None;
+                }
+                Err(TryRecvError::Empty) => ctx.request_repaint_after(Duration::from_millis(100)),
+                Err(TryRecvError::Disconnected) => self.scoring = None,
+            }
+        }
+    }
+
+    // Starts scoring `channel` unless its scores are there or on their way
+    fn score(&mut self, channel: ChannelView) {
+        let Some(outcome) = &self.outcome else {
+            return;
+        };
+        let known = self.channel_scores.as_ref().map(|scores| scores.channel);
+        let pending = self.scoring.as_ref().map(|(channel, _)| *channel);
+        if channel == ChannelView::Rgb || outcome.images.is_none() || known == Some(channel) || pending == Some(channel) {
+            return;
+        }
+        let (sender, receiver) = mpsc::channel();
+        let outcome = Arc::clone(outcome);
+        thread::spawn(move || {
+            let _ = sender.send(outcome.score_channel(channel));
+        });
+        self.scoring = Some((channel, receiver));
+    }
+
+    /// Shows the window, returning the result whose row was clicked.
+    /// Scores are given in `channel`, the one the image panes show.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        img: Option<&DynamicImage>,
+        settings: &ProcessingSettings,
+        denoise_types: &[DenoiseType],
+        channel: ChannelView,
+    ) -> Option<BenchResult> {
+        self.poll(ctx);
+        if self.open {
+            self.score(channel);
+        }
+
+        let mut open = self.open;
+        let mut clicked = None;
+        egui::Window::new("Compare Algorithms")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.add_noise, "Add synthetic noise")
+                        .on_hover_text("Denoise a noisy copy and score the results against the loaded image");
+                    ui.add_enabled(self.add_noise, egui::Slider::new(&mut self.noise_sigma, 1.0..=50.0).text("σ"));
+                });
+                ui.label(egui::RichText::new(format!(
+                    "Slow algorithms run on a copy downscaled to {} pixels",
+                    SLOW_MAX_SIDE
+                )).weak());
+
+                match &self.job {
+                    Some(job) => {
+                        ui.horizontal(|ui| {
+                            let text = if job.progress.is_cancelled() { "Cancelling..." } else { "Comparing..." };
+                            ui.add(egui::ProgressBar::new(job.progress.fraction()).desired_width(300.0).show_percentage().text(text));
+                            if ui.add_enabled(!job.progress.is_cancelled(), egui::Button::new("Cancel")).clicked() {
+                                job.progress.cancel();
+                            }
+                        });
+                    }
+                    None => {
+                        if ui.add_enabled(img.is_some(), egui::Button::new("Run")).clicked() {
+                            if let Some(img) = img {
+                                let specs = denoise_types
+                                    .iter()
+                                    .map(|&denoise_type| DenoiseSpec {
+                                        denoise_type,
+                                        kernel_size: settings.kernel_size,
+                                        tv_lambda: settings.tv_lambda,
+                                        tv_iterations: settings.tv_iterations,
+                                        tv_tolerance: settings.tv_tolerance,
+                                    })
+                                    .collect();
+                                let noise_sigma = self.add_noise.then_some(self.noise_sigma);
+                                self.job = Some(BenchmarkJob::spawn(img.clone(), noise_sigma, specs));
+                            }
+                        }
+                    }
+                }
+
+                let Some(outcome) = &self.outcome else {
+                    return;
+                };
+                // Scores in a single channel are missing until they are computed
+                let channel_scores = self.channel_scores.as_ref().filter(|scores| scores.channel == channel);
+                let noisy_scores = match channel {
+                    ChannelView::Rgb => outcome.noisy_scores,
+                    _ => channel_scores.map(|scores| scores.noisy),
+                };
+                let result_scores = |index: usize| match channel {
+                    ChannelView::Rgb => (outcome.results[index].psnr, outcome.results[index].ssim),
+                    _ => channel_scores.map_or((None, None), |scores| (Some(scores.results[index].0), Some(scores.results[index].1))),
+                };
+                let suffix = match channel {
+                    ChannelView::Rgb => String::new(),
+                    _ => format!(" ({})", channel.label()),
+                };
+                if let Some((psnr, ssim)) = noisy_scores {
+                    ui.label(format!("Noisy input{}: PSNR {:.2} dB, SSIM {:.4}", suffix, psnr, ssim));
+                }
+                if self.scoring.is_some() {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(format!("Scoring the {} channel...", channel.label()));
+                    });
+                }
+
+                egui::Grid::new("benchmark_results").striped(true).show(ui, |ui| {
+                    ui.strong("Algorithm");
+                    ui.strong("Time");
+                    ui.strong("Size");
+                    ui.strong(format!("PSNR{}", suffix));
+                    ui.strong(format!("SSIM{}", suffix));
+                    ui.end_row();
+
+                    for (index, result) in outcome.results.iter().enumerate() {
+                        let name = format!("{:?}", result.spec.denoise_type);
+                        if ui.selectable_label(self.selected == Some(index), name)
+                            .on_hover_text("Show this result in the processed image pane")
+                            .clicked()
+                        {
+                            self.selected = Some(index);
+                            clicked = Some(result.clone());
+                        }
+                        ui.label(format!("{:.3} s", result.duration.as_secs_f64()));
+                        let size = format!("{}x{}", result.image.width(), result.image.height());
+                        ui.label(if result.downscaled { format!("{} (downscaled)", size) } else { size });
+                        let (psnr, ssim) = result_scores(index);
+                        ui.label(psnr.map(|psnr| format!("{:.2} dB", psnr)).unwrap_or_else(|| "-".to_string()));
+                        ui.label(ssim.map(|ssim| format!("{:.4}", ssim)).unwrap_or_else(|| "-".to_string()));
+                        ui.end_row();
+                    }
+                });
+
+                if ui.button("Copy as CSV").on_hover_text("Scores in the CSV are over the whole image, whichever channel is shown").clicked() {
+                    let csv = results_to_csv(&outcome.results);
+                    ui.output_mut(|output| output.copied_text = csv);
+                }
+            });
+        self.open = open;
+        clicked
+    }
+}
@@ -0,0 +1,34 @@
+use std::borrow::Cow;
+
+use arboard::{Clipboard, ImageData};
+use image::{DynamicImage, RgbaImage};
+
+/// Puts `img` on the system clipboard as RGBA.
+pub fn copy_image(img: &DynamicImage) -> Result<(), String> {
+    let rgba = img.to_rgba8();
+    let data = ImageData {
+        width: rgba.width() as usize,
+        height: rgba.height() as usize,
+        bytes: Cow::Owned(rgba.into_raw()),
+    };
+    Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_image(data))
+        .map_err(|err| format!("Could not copy to the clipboard: {}", err))
+}
+
+/// Reads an image from the system clipboard.
+pub fn paste_image() -> Result<DynamicImage, String> {
+    let data = Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_image())
+        .map_err(|err| match err {
+            arboard::Error::ContentNotAvailable => "The clipboard does not contain an image".to_string(),
+            err => format!("Could not read the clipboard: {}", err),
+        })?;
+
+    if data.width == 0 || data.height == 0 {
+        return Err("The clipboard image is empty".to_string());
+    }
+    RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "The clipboard image data is malformed".to_string())
+}
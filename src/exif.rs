@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use image::DynamicImage;
+
+use crate::algorithms::geometry::{flip_horizontal, flip_vertical, rotate_180, rotate_left, rotate_right};
+
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Reads the EXIF orientation (1-8) of a JPEG file.
+///
+/// Returns `None` for other formats, files without EXIF data and
+/// anything that can't be parsed, which callers treat as upright.
+pub fn read_orientation(path: &Path) -> Option<u16> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let tiff = find_exif_segment(&mut reader).ok()??;
+    parse_orientation(&tiff).filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Rotates and flips `img` so an image stored with `orientation` comes out upright.
+///
+/// The result is meant to be treated as orientation 1. Exports never write
+/// the EXIF block back, so saved files are upright in every viewer.
+pub fn apply_orientation(img: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => flip_horizontal(&img),
+        3 => rotate_180(&img),
+        4 => flip_vertical(&img),
+        // Transpose: mirrored along the top-left to bottom-right diagonal
+        5 => flip_horizontal(&rotate_right(&img)),
+        6 => rotate_right(&img),
+        // Transverse: mirrored along the other diagonal
+        7 => flip_horizontal(&rotate_left(&img)),
+        8 => rotate_left(&img),
+        _ => img,
+    }
+}
+
+// Walks the JPEG marker segments up to the image data and returns the TIFF
+// payload of the first APP1 segment holding EXIF data.
+fn find_exif_segment<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut soi = [0u8; 2];
+    reader.read_exact(&mut soi)?;
+    if soi != [0xFF, 0xD8] {
+        return Ok(None);
+    }
+
+    loop {
+        let mut marker = [0u8; 2];
+        reader.read_exact(&mut marker)?;
+        if marker[0] != 0xFF {
+            return Ok(None);
+        }
+        // Fill bytes before a marker
+        if marker[1] == 0xFF {
+            reader.seek(SeekFrom::Current(-1))?;
+            continue;
+        }
+        // Start of scan or end of image, no EXIF before the image data
+        if marker[1] == 0xDA || marker[1] == 0xD9 {
+            return Ok(None);
+        }
+
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as usize;
+        if length < 2 {
+            return Ok(None);
+        }
+
+        if marker[1] == 0xE1 {
+            let mut segment = vec![0u8; length - 2];
+            reader.read_exact(&mut segment)?;
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return Ok(Some(tiff.to_vec()));
+            }
+        } else {
+            reader.seek(SeekFrom::Current(length as i64 - 2))?;
+        }
+    }
+}
+
+// Looks up the orientation tag in IFD0 of a TIFF structure.
+fn parse_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(0..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+
+    if read_u16(2)? != 42 {
+        return None;
+    }
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| read_u16(entry) == Some(ORIENTATION_TAG))
+        // SHORT values are stored in the first two bytes of the value field
+        .and_then(|entry| read_u16(entry + 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::image_loader::load_image_from_path;
+
+    // Each fixture holds the same 32x16 picture, stored so that only its
+    // orientation tag turns it upright: red, green on top of blue, yellow.
+    // Odd orientations are written big-endian, even ones little-endian.
+    fn fixture(orientation: u16) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join(format!("tests/fixtures/exif/orientation_{}.jpg", orientation))
+    }
+
+    fn assert_upright(orientation: u16) {
+        let path = fixture(orientation);
+        assert_eq!(read_orientation(&path), Some(orientation));
+
+        let img = load_image_from_path(&path).unwrap().to_rgb8();
+        assert_eq!(img.dimensions(), (32, 16), "orientation {}", orientation);
+        let quadrants = [((8, 4), [255, 0, 0]), ((24, 4), [0, 255, 0]), ((8, 12), [0, 0, 255]), ((24, 12), [255, 255, 0])];
+        for ((x, y), expected) in quadrants {
+            let pixel = img.get_pixel(x, y).0;
+            // JPEG only keeps the colors approximately
+            let close = pixel.iter().zip(expected).all(|(&got, want)| got.abs_diff(want) <= 40);
+            assert!(close, "orientation {}: {:?} at ({}, {}), expected {:?}", orientation, pixel, x, y, expected);
+        }
+    }
+
+    #[test]
+    fn orientation_1_is_left_alone() {
+        assert_upright(1);
+    }
+
+    #[test]
+    fn orientation_2_is_mirrored() {
+        assert_upright(2);
+    }
+
+    #[test]
+    fn orientation_3_is_turned_around() {
+        assert_upright(3);
+    }
+
+    #[test]
+    fn orientation_4_is_flipped() {
+        assert_upright(4);
+    }
+
+    #[test]
+    fn orientation_5_is_transposed() {
+        assert_upright(5);
+    }
+
+    #[test]
+    fn orientation_6_is_turned_right() {
+        assert_upright(6);
+    }
+
+    #[test]
+    fn orientation_7_is_transversed() {
+        assert_upright(7);
+    }
+
+    #[test]
+    fn orientation_8_is_turned_left() {
+        assert_upright(8);
+    }
+
+    #[test]
+    fn files_without_exif_have_no_orientation() {
+        let png = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/exposure.png");
+        assert_eq!(read_orientation(&png), None);
+        assert_eq!(read_orientation(&fixture(0)), None);
+    }
+}
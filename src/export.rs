@@ -0,0 +1,821 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::jpeg::{JpegEncoder, PixelDensity, PixelDensityUnit};
+use image::codecs::webp::WebPEncoder;
+use image::{Delay, DynamicImage, Frame};
+use serde::{Deserialize, Serialize};
+use tiff::encoder::colortype::{self, ColorType};
+use tiff::encoder::compression::{Compression, Deflate, Lzw, Packbits, Uncompressed};
+use tiff::encoder::{Rational, TiffEncoder, TiffValue};
+use tiff::tags::ResolutionUnit;
+
+use crate::algorithms::geometry::{resize, ResampleFilter, ResizeSettings};
+use crate::algorithms::quantize::{quantize, Dither, IndexedImage, MAX_COLORS};
+use crate::print_size::PrintOptions;
+use crate::sidecar::{sidecar_path, ProcessingRecord};
+use crate::watermark::{apply_watermark, Watermark};
+
+/// File format an image is exported as.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Png,
+    /// PNG with a palette, see `quantize`
+    IndexedPng,
+    Jpeg,
+    WebP,
+    Tiff,
+    Gif,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 6] = [
+        ExportFormat::Png,
+        ExportFormat::IndexedPng,
+        ExportFormat::Jpeg,
+        ExportFormat::WebP,
+        ExportFormat::Tiff,
+        ExportFormat::Gif,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ExportFormat::Png => "PNG Image",
+            ExportFormat::IndexedPng => "Indexed PNG Image",
+            ExportFormat::Jpeg => "JPEG Image",
+            ExportFormat::WebP => "WebP Image",
+            ExportFormat::Tiff => "TIFF Image",
+            ExportFormat::Gif => "GIF Image",
+        }
+    }
+
+    /// Accepted file extensions, the first one is appended when missing.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            ExportFormat::Png | ExportFormat::IndexedPng => &["png"],
+            ExportFormat::Jpeg => &["jpg", "jpeg"],
+            ExportFormat::WebP => &["webp"],
+            ExportFormat::Tiff => &["tif", "tiff"],
+            ExportFormat::Gif => &["gif"],
+        }
+    }
+}
+
+/// zlib effort used for PNG files.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    pub const ALL: [PngCompression; 3] = [PngCompression::Fast, PngCompression::Default, PngCompression::Best];
+
+    // Written with the png crate directly, image's encoder can't store a resolution
+    fn compression(self) -> ::png::Compression {
+        match self {
+            PngCompression::Fast => ::png::Compression::Fast,
+            PngCompression::Default => ::png::Compression::Default,
+            PngCompression::Best => ::png::Compression::Best,
+        }
+    }
+}
+
+/// Compression scheme used for TIFF files, all of them lossless.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+impl TiffCompression {
+    pub const ALL: [TiffCompression; 4] = [
+        TiffCompression::None,
+        TiffCompression::Lzw,
+        TiffCompression::Deflate,
+        TiffCompression::PackBits,
+    ];
+}
+
+// Trades quantization quality for speed, 1 is the slowest and best, 30 the fastest
+const GIF_SPEED: i32 = 10;
+
+/// Whether this build can write lossy WebP files, see the `webp-lossy` feature.
+pub const LOSSY_WEBP_AVAILABLE: bool = cfg!(feature = "webp-lossy");
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// 1-100
+    pub jpeg_quality: u8,
+    pub png_compression: PngCompression,
+    /// Palette size of indexed PNG files, 2-256
+    pub indexed_colors: usize,
+    pub indexed_dither: Dither,
+    /// Lossy WebP is only written when `LOSSY_WEBP_AVAILABLE`
+    pub webp_lossless: bool,
+    /// 0-100, used for lossy WebP
+    pub webp_quality: u8,
+    pub tiff_compression: TiffCompression,
+    /// Resolution and physical size, see `PrintOptions::layout`
+    pub print: PrintOptions,
+    /// Write a `ProcessingRecord` next to single exported images
+    pub sidecar: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Png,
+            jpeg_quality: 90,
+            png_compression: PngCompression::Default,
+            indexed_colors: MAX_COLORS,
+            indexed_dither: Dither::FloydSteinberg,
+            webp_lossless: true,
+            webp_quality: 80,
+            tiff_compression: TiffCompression::Lzw,
+            print: PrintOptions::default(),
+            sidecar: false,
+        }
+    }
+}
+
+/// `path` with the format's extension appended unless it already has one of them.
+pub fn with_extension(path: PathBuf, format: ExportFormat) -> PathBuf {
+    let has_extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            format.extensions().iter().any(|known| known.eq_ignore_ascii_case(extension))
+        });
+    if has_extension {
+        return path;
+    }
+
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(format.extensions()[0]);
+    path.with_file_name(file_name)
+}
+
+/// Encodes `img` into `path` with the given options. With a print size it
+/// is resampled with Lanczos first if that asks for other pixel dimensions,
+/// and PNG, JPEG and TIFF files get the resolution written into them.
+pub fn save_image(img: &DynamicImage, path: &Path, options: &ExportOptions) -> Result<(), String> {
+    let layout = options.print.layout(img.width(), img.height());
+    let resampled;
+    let (img, dpi) = match layout {
+        Some(layout) if layout.pixels != (img.width(), img.height()) => {
+            let (width, height) = layout.pixels;
+            resampled = resize(img, &ResizeSettings { width, height, filter: ResampleFilter::Lanczos3 });
+            (&resampled, Some(layout.dpi))
+        }
+        layout => (img, layout.map(|layout| layout.dpi)),
+    };
+
+    let file = File::create(path).map_err(|err| format!("Could not create {}: {}", path.display(), err))?;
+    let writer = BufWriter::new(file);
+    let error = |err: &dyn std::fmt::Display| format!("Could not export {}: {}", path.display(), err);
+
+    let result = match options.format {
+        ExportFormat::Png => return write_png(img, writer, options.png_compression, dpi).map_err(|err| error(&err)),
+        ExportFormat::IndexedPng => {
+            let indexed = quantize(img, options.indexed_colors, options.indexed_dither);
+            return write_indexed_png(&indexed, writer, options.png_compression, dpi).map_err(|err| error(&err));
+        }
+        ExportFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(writer, options.jpeg_quality.clamp(1, 100));
+            if let Some(dpi) = dpi {
+                // JFIF stores whole dots per inch
+                let density = dpi.round().clamp(1.0, u16::MAX as f32) as u16;
+                encoder.set_pixel_density(PixelDensity { density: (density, density), unit: PixelDensityUnit::Inches });
+            }
+            jpeg_compatible(img).write_with_encoder(encoder)
+        }
+        ExportFormat::WebP => {
+            let encoder = webp_encoder(writer, options);
+            webp_compatible(img).write_with_encoder(encoder)
+        }
+        ExportFormat::Tiff => return write_tiff(img, writer, options.tiff_compression, dpi).map_err(|err| error(&err)),
+        ExportFormat::Gif => {
+            let mut encoder = GifEncoder::new_with_speed(writer, GIF_SPEED);
+            encoder.encode_frame(Frame::new(img.to_rgba8()))
+        }
+    };
+    result.map_err(|err| error(&err))
+}
+
+/// Encodes a `width` x `height` image into `path` band by band as `bands`
+/// yields its rows from the top down, so it is never held whole. Only PNG
+/// and TIFF files can be written that way, and TIFF strips go uncompressed:
+/// the TIFF encoder only compresses images handed over whole. A print size
+/// is written as the resolution, but can't resample the image.
+pub fn save_bands(
+    mut bands: impl Iterator<Item = Result<DynamicImage, String>>,
+    (width, height): (u32, u32),
+    path: &Path,
+    options: &ExportOptions,
+) -> Result<(), String> {
+    let dpi = match options.print.layout(width, height) {
+        Some(layout) if layout.pixels != (width, height) => {
+            return Err("Resampling to a print size needs the whole image, export large images at their own size".to_string());
+        }
+        layout => layout.map(|layout| layout.dpi),
+    };
+    let Some(first) = bands.next().transpose()? else {
+        return Err(format!("Could not export {}: the image is empty", path.display()));
+    };
+    let bands = std::iter::once(Ok(first.clone())).chain(bands);
+
+    let file = File::create(path).map_err(|err| format!("Could not create {}: {}", path.display(), err))?;
+    let writer = BufWriter::new(file);
+    let size = (width, height, dpi);
+    match options.format {
+        ExportFormat::Png => write_png_bands(&first, bands, writer, options.png_compression, size),
+        ExportFormat::Tiff => match tiff_compatible(&first) {
+            DynamicImage::ImageLuma8(_) => write_tiff_strips::<colortype::Gray8, _>(writer, size, bands.map(|band| Ok(band?.into_luma8().into_raw()))),
+            DynamicImage::ImageLuma16(_) => write_tiff_strips::<colortype::Gray16, _>(writer, size, bands.map(|band| Ok(band?.into_luma16().into_raw()))),
+            DynamicImage::ImageRgb8(_) => write_tiff_strips::<colortype::RGB8, _>(writer, size, bands.map(|band| Ok(band?.into_rgb8().into_raw()))),
+            DynamicImage::ImageRgb16(_) => write_tiff_strips::<colortype::RGB16, _>(writer, size, bands.map(|band| Ok(band?.into_rgb16().into_raw()))),
+            DynamicImage::ImageRgba16(_) => write_tiff_strips::<colortype::RGBA16, _>(writer, size, bands.map(|band| Ok(band?.into_rgba16().into_raw()))),
+            _ => write_tiff_strips::<colortype::RGBA8, _>(writer, size, bands.map(|band| Ok(band?.into_rgba8().into_raw()))),
+        },
+        format => Err(format!("{} files can't be written a few rows at a time, export large images as PNG or TIFF", format.label())),
+    }
+    .map_err(|err| format!("Could not export {}: {}", path.display(), err))
+}
+
+/// Encodes the frames of an animation into the GIF at `path`, each with its
+/// own palette, shown for its delay and looping as `repeat` says.
+pub fn save_animation(frames: &[DynamicImage], delays: &[Duration], repeat: Repeat, path: &Path) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("Could not create {}: {}", path.display(), err))?;
+    let mut encoder = GifEncoder::new_with_speed(BufWriter::new(file), GIF_SPEED);
+    let result = encoder.set_repeat(repeat).and_then(|()| {
+        frames.iter().zip(delays).try_for_each(|(frame, &delay)| {
+            let delay = Delay::from_saturating_duration(delay);
+            encoder.encode_frame(Frame::from_parts(frame.to_rgba8(), 0, 0, delay))
+        })
+    });
+    result.map_err(|err| format!("Could not export {}: {}", path.display(), err))
+}
+
+/// An export running on a worker thread, so encoding a large image doesn't
+/// freeze the UI.
+pub struct ExportJob {
+    receiver: Receiver<Result<(), String>>,
+    pub path: PathBuf,
+}
+
+impl ExportJob {
+    /// Exports `img`, with `watermark` drawn onto the exported copy only.
+    /// `record` is written next to the file once it saved, see `sidecar_path`.
+    pub fn spawn(
+        img: DynamicImage,
+        path: PathBuf,
+        options: ExportOptions,
+        watermark: Option<Watermark>,
+        record: Option<ProcessingRecord>,
+    ) -> Self {
+        Self::spawn_with(path, move |path| {
+            match &watermark {
+                Some(watermark) => save_image(&apply_watermark(&img, watermark)?, path, &options)?,
+                None => save_image(&img, path, &options)?,
+            }
+            match record {
+                Some(record) => record.write(&sidecar_path(path)),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Exports an animated GIF, see `save_animation`, with `watermark` drawn
+    /// onto every frame.
+    pub fn spawn_animation(
+        frames: Vec<DynamicImage>,
+        delays: Vec<Duration>,
+        repeat: Repeat,
+        path: PathBuf,
+        watermark: Option<Watermark>,
+    ) -> Self {
+        Self::spawn_with(path, move |path| match &watermark {
+            Some(watermark) => {
+                let frames = frames
+                    .iter()
+                    .map(|frame| apply_watermark(frame, watermark))
+                    .collect::<Result<Vec<_>, _>>()?;
+                save_animation(&frames, &delays, repeat, path)
+            }
+            None => save_animation(&frames, &delays, repeat, path),
+        })
+    }
+
+    /// Exports several images one after another, each to its own path,
+    /// stopping at the first failure. `folder` is what the export reports.
+    pub fn spawn_all(images: Vec<(Arc<DynamicImage>, PathBuf)>, folder: PathBuf, options: ExportOptions, watermark: Option<Watermark>) -> Self {
+        Self::spawn_with(folder, move |_| {
+            images.iter().try_for_each(|(img, path)| match &watermark {
+                Some(watermark) => save_image(&apply_watermark(img, watermark)?, path, &options),
+                None => save_image(img, path, &options),
+            })
+        })
+    }
+
+    fn spawn_with(path: PathBuf, save: impl FnOnce(&Path) -> Result<(), String> + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let thread_path = path.clone();
+        thread::spawn(move || {
+            let _ = sender.send(save(&thread_path));
+        });
+        Self { receiver, path }
+    }
+
+    /// Returns `None` while the export is still running, otherwise whether
+    /// it succeeded.
+    pub fn poll(&self) -> Option<Result<(), String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(format!("Exporting {} failed unexpectedly", self.path.display()))),
+        }
+    }
+}
+
+#[cfg(feature = "webp-lossy")]
+fn webp_encoder<W: std::io::Write>(writer: W, options: &ExportOptions) -> WebPEncoder<W> {
+    if options.webp_lossless {
+        WebPEncoder::new_lossless(writer)
+    } else {
+        // Deprecated upstream in favour of lossless only, but still the only lossy encoder at hand
+        #[allow(deprecated)]
+        WebPEncoder::new_with_quality(writer, image::codecs::webp::WebPQuality::lossy(options.webp_quality))
+    }
+}
+
+#[cfg(not(feature = "webp-lossy"))]
+fn webp_encoder<W: std::io::Write>(writer: W, _options: &ExportOptions) -> WebPEncoder<W> {
+    WebPEncoder::new_lossless(writer)
+}
+
+// PNG stores the resolution as pixels per meter
+fn pixel_dims(dpi: f32) -> ::png::PixelDimensions {
+    let per_meter = (dpi / 0.0254).round() as u32;
+    ::png::PixelDimensions { xppu: per_meter, yppu: per_meter, unit: ::png::Unit::Meter }
+}
+
+// Adaptive filtering like image's PNG encoder
+fn png_encoder<W: std::io::Write>(writer: W, (width, height, dpi): (u32, u32, Option<f32>), img: &DynamicImage, compression: PngCompression) -> ::png::Encoder<'static, W> {
+    let (color, depth) = png_color(img);
+    let mut encoder = ::png::Encoder::new(writer, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    encoder.set_compression(compression.compression());
+    encoder.set_filter(::png::FilterType::Sub);
+    encoder.set_adaptive_filter(::png::AdaptiveFilterType::Adaptive);
+    encoder.set_pixel_dims(dpi.map(pixel_dims));
+    encoder
+}
+
+fn png_color(img: &DynamicImage) -> (::png::ColorType, ::png::BitDepth) {
+    match img {
+        DynamicImage::ImageLuma8(_) => (::png::ColorType::Grayscale, ::png::BitDepth::Eight),
+        DynamicImage::ImageLumaA8(_) => (::png::ColorType::GrayscaleAlpha, ::png::BitDepth::Eight),
+        DynamicImage::ImageRgb8(_) => (::png::ColorType::Rgb, ::png::BitDepth::Eight),
+        DynamicImage::ImageRgba8(_) => (::png::ColorType::Rgba, ::png::BitDepth::Eight),
+        DynamicImage::ImageLuma16(_) => (::png::ColorType::Grayscale, ::png::BitDepth::Sixteen),
+        DynamicImage::ImageLumaA16(_) => (::png::ColorType::GrayscaleAlpha, ::png::BitDepth::Sixteen),
+        DynamicImage::ImageRgb16(_) => (::png::ColorType::Rgb, ::png::BitDepth::Sixteen),
+        _ => (::png::ColorType::Rgba, ::png::BitDepth::Sixteen),
+    }
+}
+
+// 16-bit samples big-endian as PNG wants them
+fn png_data(img: &DynamicImage) -> Vec<u8> {
+    match png_color(img).1 {
+        ::png::BitDepth::Eight => img.as_bytes().to_vec(),
+        _ => img.as_bytes().chunks_exact(2).flat_map(|sample| u16::from_ne_bytes([sample[0], sample[1]]).to_be_bytes()).collect(),
+    }
+}
+
+fn write_png<W: std::io::Write>(img: &DynamicImage, writer: W, compression: PngCompression, dpi: Option<f32>) -> Result<(), ::png::EncodingError> {
+    let img = png_compatible(img);
+    let encoder = png_encoder(writer, (img.width(), img.height(), dpi), &img, compression);
+    encoder.write_header()?.write_image_data(&png_data(&img))
+}
+
+// Every band has the format of `first`
+fn write_png_bands<W: std::io::Write>(
+    first: &DynamicImage,
+    bands: impl Iterator<Item = Result<DynamicImage, String>>,
+    writer: W,
+    compression: PngCompression,
+    size: (u32, u32, Option<f32>),
+) -> Result<(), String> {
+    let first = png_compatible(first);
+    let mut writer = png_encoder(writer, size, &first, compression).write_header().map_err(|err| err.to_string())?;
+    let mut stream = writer.stream_writer().map_err(|err| err.to_string())?;
+    for band in bands {
+        stream.write_all(&png_data(&png_compatible(&band?))).map_err(|err| err.to_string())?;
+    }
+    stream.finish().map_err(|err| err.to_string())
+}
+
+// Palette entries are packed as tightly as PNG allows, down to one bit per
+// pixel for two colors
+fn write_indexed_png<W: std::io::Write>(
+    indexed: &IndexedImage,
+    writer: W,
+    compression: PngCompression,
+    dpi: Option<f32>,
+) -> Result<(), ::png::EncodingError> {
+    let (bits, depth) = match indexed.palette.len() {
+        0..=2 => (1, ::png::BitDepth::One),
+        3..=4 => (2, ::png::BitDepth::Two),
+        5..=16 => (4, ::png::BitDepth::Four),
+        _ => (8, ::png::BitDepth::Eight),
+    };
+    let mut encoder = ::png::Encoder::new(writer, indexed.width, indexed.height);
+    encoder.set_color(::png::ColorType::Indexed);
+    encoder.set_depth(depth);
+    encoder.set_compression(compression.compression());
+    encoder.set_pixel_dims(dpi.map(pixel_dims));
+    encoder.set_palette(indexed.palette.iter().flat_map(|entry| [entry[0], entry[1], entry[2]]).collect::<Vec<_>>());
+    // Only the leading transparent entry, if any, needs an alpha value
+    let transparent = indexed.palette.iter().take_while(|entry| entry[3] < u8::MAX).count();
+    if transparent > 0 {
+        encoder.set_trns(indexed.palette[..transparent].iter().map(|entry| entry[3]).collect::<Vec<_>>());
+    }
+
+    let per_byte = 8 / bits;
+    let mut data = Vec::with_capacity(indexed.indices.len() / per_byte + indexed.height as usize);
+    for row in indexed.indices.chunks(indexed.width.max(1) as usize) {
+        for pixels in row.chunks(per_byte) {
+            // Leftmost pixel in the highest bits, a short last byte is padded with zeros
+            let byte = pixels.iter().enumerate().fold(0u8, |byte, (position, &index)| {
+                byte | index << (8 - bits * (position + 1))
+            });
+            data.push(byte);
+        }
+    }
+    encoder.write_header()?.write_image_data(&data)
+}
+
+fn write_tiff<W: std::io::Write + std::io::Seek>(
+    img: &DynamicImage,
+    writer: W,
+    compression: TiffCompression,
+    dpi: Option<f32>,
+) -> tiff::TiffResult<()> {
+    let (width, height) = (img.width(), img.height());
+    let size = (width, height, dpi);
+    match tiff_compatible(img) {
+        DynamicImage::ImageLuma8(buffer) => write_tiff_data::<colortype::Gray8, _>(writer, size, &buffer, compression),
+        DynamicImage::ImageLuma16(buffer) => write_tiff_data::<colortype::Gray16, _>(writer, size, &buffer, compression),
+        DynamicImage::ImageRgb8(buffer) => write_tiff_data::<colortype::RGB8, _>(writer, size, &buffer, compression),
+        DynamicImage::ImageRgb16(buffer) => write_tiff_data::<colortype::RGB16, _>(writer, size, &buffer, compression),
+        DynamicImage::ImageRgba16(buffer) => write_tiff_data::<colortype::RGBA16, _>(writer, size, &buffer, compression),
+        other => write_tiff_data::<colortype::RGBA8, _>(writer, size, &other.to_rgba8(), compression),
+    }
+}
+
+// `size` is the width, height and resolution to write, if any
+fn write_tiff_data<C: ColorType, W: std::io::Write + std::io::Seek>(
+    writer: W,
+    size: (u32, u32, Option<f32>),
+    data: &[C::Inner],
+    compression: TiffCompression,
+) -> tiff::TiffResult<()>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut encoder = TiffEncoder::new(writer)?;
+    match compression {
+        TiffCompression::None => write_tiff_image::<C, _, _>(&mut encoder, size, Uncompressed, data),
+        TiffCompression::Lzw => write_tiff_image::<C, _, _>(&mut encoder, size, Lzw, data),
+        TiffCompression::Deflate => write_tiff_image::<C, _, _>(&mut encoder, size, Deflate::default(), data),
+        TiffCompression::PackBits => write_tiff_image::<C, _, _>(&mut encoder, size, Packbits, data),
+    }
+}
+
+fn write_tiff_image<C: ColorType, W: std::io::Write + std::io::Seek, D: Compression>(
+    encoder: &mut TiffEncoder<W>,
+    (width, height, dpi): (u32, u32, Option<f32>),
+    compression: D,
+    data: &[C::Inner],
+) -> tiff::TiffResult<()>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut image = encoder.new_image_with_compression::<C, D>(width, height, compression)?;
+    if let Some(dpi) = dpi {
+        // Hundredths of a dot per inch
+        image.resolution(ResolutionUnit::Inch, Rational { n: (dpi * 100.0).round() as u32, d: 100 });
+    }
+    image.write_data(data)
+}
+
+// Writes the samples of `bands` strip by strip as they come
+fn write_tiff_strips<C: ColorType, W: std::io::Write + std::io::Seek>(
+    writer: W,
+    (width, height, dpi): (u32, u32, Option<f32>),
+    bands: impl Iterator<Item = Result<Vec<C::Inner>, String>>,
+) -> Result<(), String>
+where
+    [C::Inner]: TiffValue,
+    C::Inner: Clone,
+{
+    let error = |err: tiff::TiffError| err.to_string();
+    let mut encoder = TiffEncoder::new(writer).map_err(error)?;
+    let mut image = encoder.new_image::<C>(width, height).map_err(error)?;
+    if let Some(dpi) = dpi {
+        image.resolution(ResolutionUnit::Inch, Rational { n: (dpi * 100.0).round() as u32, d: 100 });
+    }
+    // Bands and strips don't line up, samples wait here for a full strip
+    let mut pending: Vec<C::Inner> = Vec::new();
+    for band in bands {
+        pending.extend(band?);
+        loop {
+            let samples = image.next_strip_sample_count() as usize;
+            if samples == 0 || pending.len() < samples {
+                break;
+            }
+            image.write_strip(&pending[..samples]).map_err(error)?;
+            pending.drain(..samples);
+        }
+    }
+    image.finish().map_err(error)
+}
+
+// PNG stores up to 16 bits per channel but no floating point
+fn png_compatible(img: &DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgba16(img.to_rgba16()),
+        _ => img.clone(),
+    }
+}
+
+// The TIFF encoder takes gray, RGB and RGBA in 8 or 16 bits
+fn tiff_compatible(img: &DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLumaA16(_) | DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgba16(img.to_rgba16()),
+        DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        _ => img.clone(),
+    }
+}
+
+// WebP is 8-bit only, keep alpha when there is some
+fn webp_compatible(img: &DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => {
+            img.clone()
+        }
+        _ if img.color().has_alpha() => DynamicImage::ImageRgba8(img.to_rgba8()),
+        _ => DynamicImage::ImageRgb8(img.to_rgb8()),
+    }
+}
+
+// JPEG is 8-bit gray or RGB only, alpha is dropped
+fn jpeg_compatible(img: &DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => img.clone(),
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) => {
+            DynamicImage::ImageLuma8(img.to_luma8())
+        }
+        _ => DynamicImage::ImageRgb8(img.to_rgb8()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, GrayImage, ImageBuffer, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::image_loader::load_image_from_path;
+
+    fn test_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("export-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    // Gradients with a pattern on top, so every sample differs from its
+    // neighbours and the alpha isn't constant
+    fn sources() -> Vec<DynamicImage> {
+        let pattern = |x: u32, y: u32| (x * 7 + y * 13) % 11;
+        vec![
+            DynamicImage::ImageLuma8(GrayImage::from_fn(37, 23, |x, y| Luma([(x * 6 + pattern(x, y)) as u8]))),
+            DynamicImage::ImageRgb8(RgbImage::from_fn(37, 23, |x, y| Rgb([(x * 6) as u8, (y * 11) as u8, pattern(x, y) as u8 * 20]))),
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(37, 23, |x, y| Rgba([(x * 6) as u8, (y * 11) as u8, 90, (pattern(x, y) * 23) as u8]))),
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(37, 23, |x, y| Luma([(x * 1700 + y * 3 + pattern(x, y)) as u16]))),
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(37, 23, |x, y| Rgb([(x * 1700) as u16, (y * 2800 + 1) as u16, pattern(x, y) as u16 * 5000]))),
+            DynamicImage::ImageRgba16(ImageBuffer::from_fn(37, 23, |x, y| Rgba([(x * 1700) as u16, (y * 2800) as u16, 12345, (pattern(x, y) * 5900) as u16]))),
+        ]
+    }
+
+    fn round_trip(img: &DynamicImage, path: &Path, options: &ExportOptions) -> DynamicImage {
+        save_image(img, path, options).unwrap();
+        load_image_from_path(path).unwrap()
+    }
+
+    #[test]
+    fn png_round_trips_exactly() {
+        let folder = test_folder("png");
+        for (index, img) in sources().iter().enumerate() {
+            for png_compression in PngCompression::ALL {
+                let options = ExportOptions { format: ExportFormat::Png, png_compression, ..Default::default() };
+                let decoded = round_trip(img, &folder.join(format!("{}.png", index)), &options);
+                assert_eq!(&decoded, img, "{:?} with {:?} compression", img.color(), png_compression);
+            }
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn tiff_round_trips_exactly() {
+        let folder = test_folder("tiff");
+        for (index, img) in sources().iter().enumerate() {
+            for tiff_compression in TiffCompression::ALL {
+                let options = ExportOptions { format: ExportFormat::Tiff, tiff_compression, ..Default::default() };
+                let decoded = round_trip(img, &folder.join(format!("{}.tif", index)), &options);
+                assert_eq!(&decoded, img, "{:?} with {:?} compression", img.color(), tiff_compression);
+            }
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn lossless_webp_round_trips_8_bit_images() {
+        let folder = test_folder("webp");
+        let options = ExportOptions { format: ExportFormat::WebP, webp_lossless: true, ..Default::default() };
+        for (index, img) in sources().iter().take(3).enumerate() {
+            let decoded = round_trip(img, &folder.join(format!("{}.webp", index)), &options);
+            assert_eq!(decoded.dimensions(), img.dimensions());
+            // The decoder hands every lossless file back as RGBA
+            assert_eq!(decoded.to_rgba8(), img.to_rgba8(), "{:?}", img.color());
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn banded_exports_round_trip_exactly() {
+        let folder = test_folder("bands");
+        for (index, img) in sources().iter().enumerate() {
+            for format in [ExportFormat::Png, ExportFormat::Tiff] {
+                let path = folder.join(format!("{}.{}", index, format.extensions()[0]));
+                let options = ExportOptions { format, ..Default::default() };
+                let bands = (0..img.height()).step_by(5).map(|y| Ok(img.crop_imm(0, y, img.width(), 5.min(img.height() - y))));
+                save_bands(bands, img.dimensions(), &path, &options).unwrap();
+                assert_eq!(&load_image_from_path(&path).unwrap(), img, "{:?} as {}", img.color(), format.label());
+            }
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    // Mean of each RGB channel, in 8-bit units
+    fn channel_means(img: &DynamicImage) -> [f64; 3] {
+        let rgb = img.to_rgb8();
+        let count = (rgb.width() * rgb.height()) as f64;
+        std::array::from_fn(|c| rgb.pixels().map(|pixel| pixel[c] as f64).sum::<f64>() / count)
+    }
+
+    #[test]
+    fn indexed_png_keeps_the_palette_size_and_the_average() {
+        let folder = test_folder("indexed");
+        let source = DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])));
+        for colors in [2, 16, 256] {
+            for indexed_dither in Dither::ALL {
+                let path = folder.join(format!("{}-{:?}.png", colors, indexed_dither));
+                let options = ExportOptions { format: ExportFormat::IndexedPng, indexed_colors: colors, indexed_dither, ..Default::default() };
+                save_image(&source, &path, &options).unwrap();
+
+                let reader = ::png::Decoder::new(File::open(&path).unwrap()).read_info().unwrap();
+                let palette = reader.info().palette.as_ref().expect("indexed PNGs have a palette").len() / 3;
+                assert!(palette <= colors, "{} palette entries for {} colors", palette, colors);
+
+                if indexed_dither != Dither::None {
+                    // Dithering trades the banding for noise that averages out
+                    let means = channel_means(&load_image_from_path(&path).unwrap());
+                    for (exported, wanted) in means.iter().zip(channel_means(&source)) {
+                        assert!((exported - wanted).abs() < 3.0, "{} colors {:?}: mean {:.1}, source {:.1}", colors, indexed_dither, exported, wanted);
+                    }
+                }
+            }
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    fn print_options(dpi: f32, fit: bool, resample: bool) -> PrintOptions {
+        PrintOptions { enabled: true, dpi, fit, resample, width: 15.0, height: 10.0, ..Default::default() }
+    }
+
+    // Pixel dimensions of the PNG at `path` and the pixels per meter of its pHYs chunk
+    fn png_resolution(path: &Path) -> ((u32, u32), Option<(u32, u32)>) {
+        let reader = ::png::Decoder::new(File::open(path).unwrap()).read_info().unwrap();
+        let per_meter = reader.info().pixel_dims.map(|dims| {
+            assert_eq!(dims.unit, ::png::Unit::Meter);
+            (dims.xppu, dims.yppu)
+        });
+        (reader.info().size(), per_meter)
+    }
+
+    // Units and horizontal and vertical density of the JFIF header
+    fn jfif_density(path: &Path) -> (u8, u16, u16) {
+        let bytes = std::fs::read(path).unwrap();
+        let start = bytes.windows(5).position(|window| window == b"JFIF\0").expect("JPEG without a JFIF header");
+        let field = |offset: usize| u16::from_be_bytes([bytes[start + offset], bytes[start + offset + 1]]);
+        (bytes[start + 7], field(8), field(10))
+    }
+
+    #[test]
+    fn png_carries_the_resolution() {
+        let folder = test_folder("png-dpi");
+        let source = DynamicImage::ImageRgb8(RgbImage::from_fn(60, 40, |x, y| Rgb([(x * 4) as u8, (y * 6) as u8, 90])));
+        // 300 dpi are 11811 pixels per meter and 50 dpi 1969, while 60
+        // pixels fitted into 15 cm without resampling are 400
+        for (print, size, per_meter) in [
+            (print_options(300.0, false, false), (60, 40), 11811),
+            (print_options(50.0, true, true), (295, 197), 1969),
+            (print_options(300.0, true, false), (60, 40), 400),
+        ] {
+            for format in [ExportFormat::Png, ExportFormat::IndexedPng] {
+                let path = folder.join("print.png");
+                save_image(&source, &path, &ExportOptions { format, print, ..Default::default() }).unwrap();
+                assert_eq!(png_resolution(&path), (size, Some((per_meter, per_meter))), "{} with {:?}", format.label(), print);
+            }
+        }
+
+        save_image(&source, &folder.join("plain.png"), &ExportOptions::default()).unwrap();
+        assert_eq!(png_resolution(&folder.join("plain.png")), ((60, 40), None));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn jpeg_carries_the_resolution() {
+        let folder = test_folder("jpeg-dpi");
+        let source = DynamicImage::ImageRgb8(RgbImage::from_fn(60, 40, |x, y| Rgb([(x * 4) as u8, (y * 6) as u8, 90])));
+        // 60 pixels fitted into 15 cm are 10.16 dpi, rounded to whole dots
+        for (print, size, dpi) in [
+            (print_options(240.0, false, false), (60, 40), 240),
+            (print_options(50.0, true, true), (295, 197), 50),
+            (print_options(300.0, true, false), (60, 40), 10),
+        ] {
+            let path = folder.join("print.jpg");
+            save_image(&source, &path, &ExportOptions { format: ExportFormat::Jpeg, print, ..Default::default() }).unwrap();
+            // Unit 1 is dots per inch
+            assert_eq!(jfif_density(&path), (1, dpi, dpi), "{:?}", print);
+            assert_eq!(load_image_from_path(&path).unwrap().dimensions(), size, "{:?}", print);
+        }
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn tiff_carries_the_resolution() {
+        let folder = test_folder("tiff-dpi");
+        let path = folder.join("print.tif");
+        let options = ExportOptions { format: ExportFormat::Tiff, print: print_options(254.5, false, false), ..Default::default() };
+        save_image(&sources()[1], &path, &options).unwrap();
+
+        let mut decoder = tiff::decoder::Decoder::new(File::open(&path).unwrap()).unwrap();
+        let mut resolution = |tag| match decoder.get_tag(tag).unwrap() {
+            tiff::decoder::ifd::Value::Rational(n, d) => n as f32 / d as f32,
+            value => panic!("{:?} is {:?}", tag, value),
+        };
+        assert_eq!(resolution(tiff::tags::Tag::XResolution), 254.5);
+        assert_eq!(resolution(tiff::tags::Tag::YResolution), 254.5);
+        assert_eq!(decoder.get_tag_u32(tiff::tags::Tag::ResolutionUnit).unwrap(), ResolutionUnit::Inch.to_u16() as u32);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn unwritable_paths_are_reported() {
+        let path = std::env::temp_dir().join(format!("export-missing-{}", std::process::id())).join("result.png");
+        let err = save_image(&sources()[1], &path, &ExportOptions::default()).unwrap_err();
+        assert!(err.starts_with("Could not create"), "{}", err);
+    }
+
+    #[test]
+    fn multi_page_tiffs_load_their_first_page() {
+        let folder = test_folder("pages");
+        let path = folder.join("pages.tif");
+        let first = RgbImage::from_fn(12, 8, |x, y| Rgb([(x * 20) as u8, (y * 30) as u8, 7]));
+        let second = GrayImage::from_pixel(5, 3, Luma([200]));
+        let mut encoder = TiffEncoder::new(BufWriter::new(File::create(&path).unwrap())).unwrap();
+        encoder.write_image::<colortype::RGB8>(12, 8, first.as_raw()).unwrap();
+        encoder.write_image::<colortype::Gray8>(5, 3, second.as_raw()).unwrap();
+        drop(encoder);
+
+        let loaded = load_image_from_path(&path);
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(loaded.unwrap(), DynamicImage::ImageRgb8(first));
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::PathBuf;
+
+use eframe::egui;
+
+use crate::algorithms::quantize::{Dither, MAX_COLORS};
+use crate::export::{ExportFormat, ExportOptions, PngCompression, TiffCompression, LOSSY_WEBP_AVAILABLE};
+use crate::image_loader::pick_png_file;
+use crate::print_size::{LengthUnit, LOW_DPI};
+use crate::watermark::{Anchor, Watermark, WatermarkKind};
+
+/// Which images an export writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportTarget {
+    /// The result in the panes, to a file the user picks
+    Active,
+    /// Every result of the session, to a folder the user picks
+    All,
+}
+
+/// Window with the encoder options used by "Export Image".
+pub struct ExportDialog {
+    pub open: bool,
+    pub options: ExportOptions,
+    pub watermark: Watermark,
+    /// Appended to the original file names by "Export All"
+    pub suffix: String,
+}
+
+impl Default for ExportDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            options: ExportOptions::default(),
+            watermark: Watermark::default(),
+            suffix: "_processed".to_string(),
+        }
+    }
+}
+
+impl ExportDialog {
+    /// Returns what the user asked to export, if anything. `directory` is
+    /// where the logo file dialog starts. `active_size` is the size of the
+    /// result in the panes if there is one, `session_results` how many images
+    /// of a session have one, "Export All" is only offered in sessions.
+    pub fn show(
+        &mut self,
+        ctx: &egui::Context,
+        directory: &mut Option<PathBuf>,
+        active_size: Option<(u32, u32)>,
+        session_results: Option<usize>,
+    ) -> Option<ExportTarget> {
+        let mut open = self.open;
+        let mut export = None;
+
+        egui::Window::new("Export")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                egui::ComboBox::from_label("Format")
+                    .selected_text(self.options.format.label())
+                    .show_ui(ui, |ui| {
+                        for format in ExportFormat::ALL {
+                            ui.selectable_value(&mut self.options.format, format, format.label());
+                        }
+                    });
+
+                match self.options.format {
+                    ExportFormat::Png | ExportFormat::IndexedPng => {
+                        egui::ComboBox::from_label("Compression")
+                            .selected_text(format!("{:?}", self.options.png_compression))
+                            .show_ui(ui, |ui| {
+                                for compression in PngCompression::ALL {
+                                    ui.selectable_value(&mut self.options.png_compression, compression, format!("{:?}", compression));
+                                }
+                            });
+                        if self.options.format == ExportFormat::IndexedPng {
+                            ui.add(egui::Slider::new(&mut self.options.indexed_colors, 2..=MAX_COLORS).text("Colors"))
+                                .on_hover_text("Palette size, picked by median cut. Transparent pixels take one entry");
+                            egui::ComboBox::from_label("Dithering")
+                                .selected_text(self.options.indexed_dither.label())
+                                .show_ui(ui, |ui| {
+                                    for dither in Dither::ALL {
+                                        ui.selectable_value(&mut self.options.indexed_dither, dither, dither.label());
+                                    }
+                                });
+                            ui.label(egui::RichText::new("8-bit colors, transparency is either full or none").weak());
+                        }
+                    }
+                    ExportFormat::Jpeg => {
+                        ui.add(egui::Slider::new(&mut self.options.jpeg_quality, 1..=100).text("Quality"));
+                        ui.label(egui::RichText::new("Transparency is dropped and 16-bit images are reduced to 8-bit").weak());
+                    }
+                    ExportFormat::WebP => {
+                        if LOSSY_WEBP_AVAILABLE {
+                            ui.checkbox(&mut self.options.webp_lossless, "Lossless");
+                            if !self.options.webp_lossless {
+                                ui.add(egui::Slider::new(&mut self.options.webp_quality, 0..=100).text("Quality"));
+                            }
+                        } else {
+                            ui.label(egui::RichText::new("Lossless (lossy WebP needs the webp-lossy build feature)").weak());
+                        }
+                    }
+                    ExportFormat::Tiff => {
+                        egui::ComboBox::from_label("Compression")
+                            .selected_text(format!("{:?}", self.options.tiff_compression))
+                            .show_ui(ui, |ui| {
+                                for compression in TiffCompression::ALL {
+                                    ui.selectable_value(&mut self.options.tiff_compression, compression, format!("{:?}", compression));
+                                }
+                            });
+                    }
+                    ExportFormat::Gif => {
+                        ui.label(egui::RichText::new("Up to 256 colors per frame, animations export every processed frame").weak());
+                    }
+                }
+
+                ui.separator();
+                self.print_options(ui, active_size);
+
+                ui.separator();
+                self.watermark_options(ui, directory);
+
+                ui.separator();
+                ui.checkbox(&mut self.options.sidecar, "Write processing record")
+                    .on_hover_text("A JSON file next to the exported image with the source, every operation and its settings, which Load Record reads back. Single images only");
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(active_size.is_some(), egui::Button::new("Export...")).clicked() {
+                        export = Some(ExportTarget::Active);
+                    }
+                    if let Some(results) = session_results {
+                        ui.separator();
+                        ui.label("Suffix");
+                        ui.add(egui::TextEdit::singleline(&mut self.suffix).desired_width(90.0));
+                        if ui.add_enabled(results > 0, egui::Button::new(format!("Export All ({})...", results)))
+                            .on_hover_text("Every processed image of the session into one folder, named after its original file plus the suffix")
+                            .clicked()
+                        {
+                            export = Some(ExportTarget::All);
+                        }
+                    }
+                });
+            });
+
+        self.open = open && export.is_none();
+        export
+    }
+
+    // Resolution and print size, with the pixels the active result comes out at
+    fn print_options(&mut self, ui: &mut egui::Ui, active_size: Option<(u32, u32)>) {
+        let print = &mut self.options.print;
+        ui.checkbox(&mut print.enabled, "Print size")
+            .on_hover_text("Write a resolution into the file so it prints at a known size");
+        if !print.enabled {
+            return;
+        }
+
+        ui.add(egui::DragValue::new(&mut print.dpi).clamp_range(1.0..=4800.0).speed(1.0).suffix(" dpi"));
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut print.fit, "Fit into");
+            ui.add_enabled_ui(print.fit, |ui| {
+                let suffix = print.unit.suffix();
+                ui.add(egui::DragValue::new(&mut print.width).clamp_range(0.1..=1000.0).speed(0.1).suffix(suffix));
+                ui.label("×");
+                ui.add(egui::DragValue::new(&mut print.height).clamp_range(0.1..=1000.0).speed(0.1).suffix(suffix));
+                egui::ComboBox::from_id_source("print_unit")
+                    .selected_text(format!("{:?}", print.unit))
+                    .show_ui(ui, |ui| {
+                        for unit in LengthUnit::ALL {
+                            ui.selectable_value(&mut print.unit, unit, format!("{:?}", unit));
+                        }
+                    });
+            });
+        });
+        if print.fit {
+            ui.checkbox(&mut print.resample, "Resample to the resolution")
+                .on_hover_text("Resize with Lanczos to the pixels the print size needs, otherwise the resolution written is lowered or raised to fit");
+        }
+        if matches!(self.options.format, ExportFormat::WebP | ExportFormat::Gif) {
+            ui.label(egui::RichText::new("WebP and GIF files carry no resolution").weak());
+        }
+
+        let Some(layout) = active_size.and_then(|(width, height)| print.layout(width, height)) else {
+            return;
+        };
+        let suffix = print.unit.suffix();
+        ui.label(format!(
+            "{} × {} px, {:.1} × {:.1}{} at {:.0} dpi",
+            layout.pixels.0, layout.pixels.1, layout.size.0, layout.size.1, suffix, layout.dpi,
+        ));
+        if layout.is_low_resolution() {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!("Only {:.0} source pixels per inch, prints below {:.0} dpi look soft", layout.source_dpi, LOW_DPI),
+            );
+        }
+    }
+
+    fn watermark_options(&mut self, ui: &mut egui::Ui, directory: &mut Option<PathBuf>) {
+        let watermark = &mut self.watermark;
+        ui.checkbox(&mut watermark.enabled, "Watermark")
+            .on_hover_text("Drawn onto the exported file only, the processed image stays as it is");
+        if !watermark.enabled {
+            return;
+        }
+
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut watermark.kind, WatermarkKind::Text, "Text");
+            ui.radio_value(&mut watermark.kind, WatermarkKind::Logo, "Logo");
+        });
+        match watermark.kind {
+            WatermarkKind::Text => {
+                ui.text_edit_singleline(&mut watermark.text);
+                ui.horizontal(|ui| {
+                    ui.add(egui::Slider::new(&mut watermark.font_size, 8.0..=400.0).logarithmic(true).suffix(" px").text("Size"));
+                    ui.color_edit_button_srgb(&mut watermark.color);
+                });
+            }
+            WatermarkKind::Logo => {
+                ui.horizontal(|ui| {
+                    if ui.button("Choose PNG...").clicked() {
+                        if let Some(path) = pick_png_file(directory) {
+                            watermark.logo_path = Some(path);
+                        }
+                    }
+                    let name = watermark.logo_path.as_ref().and_then(|path| path.file_name());
+                    match name {
+                        Some(name) => ui.label(name.to_string_lossy()),
+                        None => ui.label(egui::RichText::new("No logo chosen").weak()),
+                    };
+                });
+                ui.add(egui::Slider::new(&mut watermark.logo_width, 1.0..=100.0).suffix("%").text("Width"));
+            }
+        }
+        ui.add(egui::Slider::new(&mut watermark.opacity, 0.0..=1.0).step_by(0.01).text("Opacity"));
+        ui.add(egui::Slider::new(&mut watermark.margin, 0..=500).suffix(" px").text("Margin"));
+
+        ui.horizontal(|ui| {
+            ui.label("Position");
+            egui::Grid::new("watermark_anchor").spacing([0.0, 0.0]).show(ui, |ui| {
+                for (index, anchor) in Anchor::ALL.into_iter().enumerate() {
+                    ui.radio_value(&mut watermark.anchor, anchor, "").on_hover_text(format!("{:?}", anchor));
+                    if index % 3 == 2 {
+                        ui.end_row();
+                    }
+                }
+            });
+        });
+    }
+}
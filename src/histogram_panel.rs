@@ -0,0 +1,146 @@
+use std::borrow::Cow;
+
+use eframe::egui::{self, Color32, Pos2, Rect, Stroke};
+use image::DynamicImage;
+
+use crate::algorithms::channels::ChannelView;
+use crate::algorithms::histogram::{HistogramChannel, Histograms, BINS};
+
+const PLOT_HEIGHT: f32 = 110.0;
+
+fn channel_color(channel: HistogramChannel) -> Color32 {
+    match channel {
+        HistogramChannel::Red => Color32::from_rgba_unmultiplied(255, 60, 60, 110),
+        HistogramChannel::Green => Color32::from_rgba_unmultiplied(60, 220, 60, 110),
+        HistogramChannel::Blue => Color32::from_rgba_unmultiplied(70, 110, 255, 110),
+        HistogramChannel::Luma => Color32::from_gray(220),
+    }
+}
+
+// A single channel shown in gray is counted as luma, but named after itself
+fn channel_label(channel: HistogramChannel, view: ChannelView) -> &'static str {
+    match (channel, view) {
+        (HistogramChannel::Red, _) => "R",
+        (HistogramChannel::Green, _) => "G",
+        (HistogramChannel::Blue, _) => "B",
+        (HistogramChannel::Luma, ChannelView::Rgb) => "L",
+        (HistogramChannel::Luma, view) => view.label(),
+    }
+}
+
+/// Overlaid R, G, B and luma histograms of the image in the result pane,
+/// with how much of each channel is clipped.
+#[derive(Default)]
+pub struct HistogramPanel {
+    // Histograms of the last image shown, with the texture it was shown through
+    cached: Option<(egui::TextureId, Histograms)>,
+}
+
+impl HistogramPanel {
+    /// Shows the histograms of the image behind `texture`: `known` when
+    /// they were computed already, otherwise `image` is counted. Counts are
+    /// kept until the texture changes. `view` is the channel `image` was
+    /// taken from, if it is a single one.
+    pub fn show<'a>(
+        &mut self,
+        ui: &mut egui::Ui,
+        texture: egui::TextureId,
+        known: Option<&Histograms>,
+        view: ChannelView,
+        image: impl FnOnce() -> Cow<'a, DynamicImage>,
+    ) {
+        egui::CollapsingHeader::new(egui::RichText::new("Histogram").size(16.0))
+            .id_source("histogram")
+            .show(ui, |ui| {
+                let histograms = match known {
+                    Some(histograms) => histograms,
+                    None => {
+                        if self.cached.as_ref().map(|(id, _)| *id) != Some(texture) {
+                            let mut histograms = self.cached.take().map(|(_, histograms)| histograms).unwrap_or_default();
+                            histograms.recompute(&image());
+                            self.cached = Some((texture, histograms));
+                        }
+                        &self.cached.as_ref().expect("counted above").1
+                    }
+                };
+                plot(ui, histograms, view);
+                clipping_badges(ui, histograms, view);
+            });
+    }
+}
+
+fn shown_channels(histograms: &Histograms) -> &'static [HistogramChannel] {
+    if histograms.is_gray {
+        &[HistogramChannel::Luma]
+    } else {
+        &HistogramChannel::ALL
+    }
+}
+
+fn plot(ui: &mut egui::Ui, histograms: &Histograms, view: ChannelView) {
+    let width = ui.available_width().min(2.0 * BINS as f32);
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(width, PLOT_HEIGHT), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, Color32::from_gray(20));
+
+    // Scaled to the tallest bin away from the ends, clipping spikes would
+    // flatten everything else
+    let channels = shown_channels(histograms);
+    let tallest = channels
+        .iter()
+        .flat_map(|&channel| histograms.bins(channel)[1..BINS - 1].iter())
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max(1) as f32;
+    let bin_width = rect.width() / BINS as f32;
+    let height = |count: u64| (count as f32 / tallest).min(1.0) * rect.height();
+
+    for &channel in channels {
+        let bins = histograms.bins(channel);
+        if channel == HistogramChannel::Luma {
+            let points = bins
+                .iter()
+                .enumerate()
+                .map(|(bin, &count)| Pos2::new(rect.left() + (bin as f32 + 0.5) * bin_width, rect.bottom() - height(count)))
+                .collect();
+            painter.add(egui::Shape::line(points, Stroke::new(1.0, channel_color(channel))));
+        } else {
+            for (bin, &count) in bins.iter().enumerate() {
+                let left = rect.left() + bin as f32 * bin_width;
+                let bar = Rect::from_min_max(Pos2::new(left, rect.bottom() - height(count)), Pos2::new(left + bin_width, rect.bottom()));
+                painter.rect_filled(bar, 0.0, channel_color(channel));
+            }
+        }
+    }
+
+    if let Some(pointer) = response.hover_pos() {
+        let bin = (((pointer.x - rect.left()) / bin_width) as usize).min(BINS - 1);
+        let left = rect.left() + bin as f32 * bin_width;
+        painter.rect_stroke(Rect::from_min_max(Pos2::new(left, rect.top()), Pos2::new(left + bin_width, rect.bottom())), 0.0, Stroke::new(1.0, Color32::WHITE));
+        response.on_hover_ui_at_pointer(|ui| {
+            let (low, high) = histograms.bin_range(bin);
+            let range = if low == high { format!("Value {}", low) } else { format!("Values {}-{}", low, high) };
+            ui.label(range);
+            for &channel in channels {
+                ui.label(format!("{}: {} pixels", channel_label(channel, view), histograms.bins(channel)[bin]));
+            }
+        });
+    }
+}
+
+fn clipping_badges(ui: &mut egui::Ui, histograms: &Histograms, view: ChannelView) {
+    ui.horizontal(|ui| {
+        for &channel in shown_channels(histograms) {
+            let (black, white) = histograms.clipped(channel);
+            let color = if black > 0.0 || white > 0.0 { ui.visuals().warn_fg_color } else { ui.visuals().weak_text_color() };
+            let label = channel_label(channel, view);
+            let text = egui::RichText::new(format!("{} ⏷{:.2}% ⏶{:.2}%", label, black, white)).small().color(color);
+            ui.add(egui::Label::new(text).wrap(false)).on_hover_text(format!(
+                "Pixels clipped to 0 and to {} in the {} channel",
+                histograms.max_value(),
+                label
+            ));
+        }
+    });
+}
@@ -0,0 +1,98 @@
+use image::DynamicImage;
+
+use crate::algorithms::region::Region;
+use crate::settings::ProcessingSettings;
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 10;
+
+// Results larger than this are stored as settings only and recomputed on restore
+const MAX_CACHED_PIXELS: u64 = 12_000_000;
+
+pub struct HistoryEntry {
+    pub settings: ProcessingSettings,
+    /// Selection the run was limited to, if any
+    pub region: Option<Region>,
+    pub image: Option<DynamicImage>,
+}
+
+/// Bounded undo/redo stack of processing runs.
+///
+/// `position` counts how many entries are currently applied, so 0 means
+/// the original image is shown and `entries.len()` means nothing can be redone.
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    position: usize,
+    max_depth: usize,
+}
+
+impl History {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            position: 0,
+            max_depth: max_depth.max(1),
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth.max(1);
+        while self.entries.len() > self.max_depth {
+            self.entries.remove(0);
+            self.position = self.position.saturating_sub(1);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.position = 0;
+    }
+
+    pub fn push(&mut self, settings: ProcessingSettings, region: Option<Region>, image: &DynamicImage) {
+        self.entries.truncate(self.position);
+
+        let pixels = image.width() as u64 * image.height() as u64;
+        let image = if pixels <= MAX_CACHED_PIXELS {
+            Some(image.clone())
+        } else {
+            None
+        };
+        self.entries.push(HistoryEntry { settings, region, image });
+
+        if self.entries.len() > self.max_depth {
+            self.entries.remove(0);
+        }
+        self.position = self.entries.len();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position < self.entries.len()
+    }
+
+    /// Steps back one run. Returns the entry now current, or `None` when
+    /// the history is back at the original image.
+    pub fn undo(&mut self) -> Option<&HistoryEntry> {
+        if self.position > 0 {
+            self.position -= 1;
+        }
+        self.current()
+    }
+
+    pub fn redo(&mut self) -> Option<&HistoryEntry> {
+        if self.position < self.entries.len() {
+            self.position += 1;
+        }
+        self.current()
+    }
+
+    pub fn current(&self) -> Option<&HistoryEntry> {
+        self.position.checked_sub(1).map(|i| &self.entries[i])
+    }
+}
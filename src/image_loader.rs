@@ -1,11 +1,395 @@
-use image::DynamicImage;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use image::codecs::gif::{GifDecoder, Repeat};
+use image::error::{DecodingError, ImageFormatHint};
+use image::io::Reader;
+use image::{AnimationDecoder, DynamicImage, ImageBuffer, ImageError, ImageFormat};
 use rfd::FileDialog;
 
-pub fn load_image() -> Option<DynamicImage> {
-    if let Some(path) = FileDialog::new().pick_file() {
-        if let Ok(img) = image::open(path) {
-            return Some(img);
+use crate::algorithms::lut::Lut3d;
+use crate::algorithms::sample::{with_pixel_type, FilterPixel};
+use crate::algorithms::streaming::RowSource;
+
+use crate::exif::{apply_orientation, read_orientation};
+
+/// Why an image file could not be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read
+    Io { path: PathBuf, source: std::io::Error },
+    /// The file was read but is not an image format we can decode
+    Decode { path: PathBuf, source: ImageError },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => write!(f, "Could not read {}: {}", path.display(), source),
+            LoadError::Decode { path, source } => write!(f, "Could not decode {}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::Decode { source, .. } => Some(source),
+        }
+    }
+}
+
+/// The frames of an animated GIF, each composited onto the full canvas.
+#[derive(Clone)]
+pub struct Animation {
+    pub frames: Vec<DynamicImage>,
+    /// How long each frame is shown
+    pub delays: Vec<Duration>,
+    pub repeat: Repeat,
+}
+
+/// An image file too large to hold whole. It is shown and previewed
+/// through a downscaled copy, full runs stream it from the file, see
+/// `RowReader`.
+#[derive(Debug, Clone)]
+pub struct LargeImage {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl LargeImage {
+    pub fn megapixels(&self) -> f64 {
+        self.width as f64 * self.height as f64 / 1e6
+    }
+}
+
+/// Lets the user pick an image file, empty when the dialog was cancelled.
+///
+/// The dialog starts in `directory`, which is updated to the folder of the
+/// picked file.
+pub fn pick_image_file(directory: &mut Option<PathBuf>) -> Option<PathBuf> {
+    let path = image_dialog(directory).pick_file()?;
+    remember_directory(directory, &path);
+    Some(path)
+}
+
+/// Dimensions of the image at `path`, read from its header without
+/// decoding it.
+pub fn image_dimensions(path: &Path) -> Result<(u32, u32), LoadError> {
+    let reader = Reader::open(path)
+        .and_then(Reader::with_guessed_format)
+        .map_err(|source| LoadError::Io { path: path.to_path_buf(), source })?;
+    reader.into_dimensions().map_err(|err| load_error(path, err))
+}
+
+/// Loads the image at `path`, along with all its frames when it is an
+/// animated GIF.
+pub fn load_image_or_animation(path: &Path) -> Result<(DynamicImage, Option<Animation>), LoadError> {
+    if let Some(animation) = load_animation(path)? {
+        return Ok((animation.frames[0].clone(), Some(animation)));
+    }
+    load_image_from_path(path).map(|img| (img, None))
+}
+
+/// Lets the user pick several image files, empty when the dialog was cancelled.
+/// `directory` is handled like in `pick_image_file`, with the folder of the
+/// first file remembered.
+pub fn pick_image_files(directory: &mut Option<PathBuf>) -> Vec<PathBuf> {
+    let paths = image_dialog(directory).pick_files().unwrap_or_default();
+    if let Some(path) = paths.first() {
+        remember_directory(directory, path);
+    }
+    paths
+}
+
+/// Lets the user pick a PNG file, for images with transparency like logos.
+/// `directory` is handled like in `pick_image_file`.
+pub fn pick_png_file(directory: &mut Option<PathBuf>) -> Option<PathBuf> {
+    let path = FileDialog::new()
+        .add_filter("PNG Image", &["png"])
+        .set_directory(directory.as_deref().unwrap_or(Path::new(".")))
+        .pick_file()?;
+    remember_directory(directory, &path);
+    Some(path)
+}
+
+/// Lets the user pick a `.cube` LUT file and reads it, naming it after the
+/// file unless it has a title. Returns `Ok(None)` when the dialog was
+/// cancelled. `directory` is handled like in `pick_image_file`.
+pub fn load_lut(directory: &mut Option<PathBuf>) -> Result<Option<Lut3d>, String> {
+    let Some(path) = FileDialog::new()
+        .add_filter("Cube LUT", &["cube"])
+        .add_filter("All Files", &["*"])
+        .set_directory(directory.as_deref().unwrap_or(Path::new(".")))
+        .pick_file()
+    else {
+        return Ok(None);
+    };
+    remember_directory(directory, &path);
+
+    let text = fs::read_to_string(&path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+    let mut lut = Lut3d::parse_cube(&text).map_err(|err| format!("Could not load the LUT {}: {}", path.display(), err))?;
+    if lut.title.is_none() {
+        lut.title = path.file_stem().map(|stem| stem.to_string_lossy().into_owned());
+    }
+    Ok(Some(lut))
+}
+
+fn image_dialog(directory: &Option<PathBuf>) -> FileDialog {
+    FileDialog::new()
+        .add_filter("Images", &["png", "jpg", "jpeg", "webp", "tif", "tiff", "bmp", "gif"])
+        .add_filter("All Files", &["*"])
+        .set_directory(directory.as_deref().unwrap_or(Path::new(".")))
+}
+
+/// Sets `directory` to the folder containing `path`.
+pub fn remember_directory(directory: &mut Option<PathBuf>, path: &Path) {
+    if let Some(parent) = path.parent() {
+        *directory = Some(parent.to_path_buf());
+    }
+}
+
+fn load_error(path: &Path, err: ImageError) -> LoadError {
+    match err {
+        ImageError::IoError(source) => LoadError::Io { path: path.to_path_buf(), source },
+        source => LoadError::Decode { path: path.to_path_buf(), source },
+    }
+}
+
+/// Loads the image at `path` upright according to its EXIF orientation,
+/// if it has one.
+pub fn load_image_from_path(path: &Path) -> Result<DynamicImage, LoadError> {
+    let mut reader = Reader::open(path)
+        .and_then(Reader::with_guessed_format)
+        .map_err(|source| LoadError::Io { path: path.to_path_buf(), source })?;
+    // Large images are caught by the low-memory threshold before they get
+    // here, image's own allocation limit would refuse them outright
+    reader.no_limits();
+    // Multi-page TIFFs decode their first page, GIFs their first frame
+    let img = reader.decode().map_err(|err| load_error(path, err))?;
+    Ok(match read_orientation(path) {
+        Some(orientation) => apply_orientation(img, orientation),
+        None => img,
+    })
+}
+
+/// Loads every frame of the GIF at `path` with its delay. Returns `Ok(None)`
+/// for other formats and for GIFs with a single frame, which load like any
+/// other image.
+pub fn load_animation(path: &Path) -> Result<Option<Animation>, LoadError> {
+    let io_error = |source| LoadError::Io { path: path.to_path_buf(), source };
+    let format = Reader::open(path).and_then(Reader::with_guessed_format).map_err(io_error)?.format();
+    if format != Some(ImageFormat::Gif) {
+        return Ok(None);
+    }
+
+    let file = File::open(path).map_err(io_error)?;
+    let decoder = GifDecoder::new(BufReader::new(file)).map_err(|err| load_error(path, err))?;
+    let frames = decoder.into_frames().collect_frames().map_err(|err| load_error(path, err))?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+
+    let (delays, frames) = frames
+        .into_iter()
+        .map(|frame| (Duration::from(frame.delay()), DynamicImage::ImageRgba8(frame.into_buffer())))
+        .unzip();
+    Ok(Some(Animation {
+        frames,
+        delays,
+        repeat: read_repeat(path).unwrap_or(Repeat::Infinite),
+    }))
+}
+
+// The loop count is stored in an extension block ahead of the first frame
+fn read_repeat(path: &Path) -> Option<Repeat> {
+    let file = File::open(path).ok()?;
+    let decoder = gif::DecodeOptions::new().read_info(BufReader::new(file)).ok()?;
+    Some(match decoder.repeat() {
+        gif::Repeat::Finite(count) => Repeat::Finite(count),
+        gif::Repeat::Infinite => Repeat::Infinite,
+    })
+}
+
+/// An image file read a few rows at a time. Non-interlaced PNG files are
+/// decoded row by row, anything else is decoded whole once and handed out
+/// from memory.
+pub struct RowReader {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    rows: Rows,
+}
+
+enum Rows {
+    Png {
+        reader: Box<png::Reader<BufReader<File>>>,
+        color: png::ColorType,
+        sixteen_bit: bool,
+    },
+    Decoded {
+        image: DynamicImage,
+        next_row: u32,
+    },
+}
+
+impl RowReader {
+    pub fn open(path: &Path) -> Result<Self, LoadError> {
+        let io_error = |source| LoadError::Io { path: path.to_path_buf(), source };
+        let format = Reader::open(path).and_then(Reader::with_guessed_format).map_err(io_error)?.format();
+        if format == Some(ImageFormat::Png) {
+            let mut decoder = png::Decoder::new(BufReader::new(File::open(path).map_err(io_error)?));
+            // Palettes and low bit depths come out as 8-bit gray or RGB
+            decoder.set_transformations(png::Transformations::EXPAND);
+            let reader = decoder.read_info().map_err(|err| png_error(path, err))?;
+            // Interlaced rows arrive in seven passes over the whole image
+            if !reader.info().interlaced {
+                let (width, height) = (reader.info().width, reader.info().height);
+                let (color, depth) = reader.output_color_type();
+                return Ok(Self {
+                    path: path.to_path_buf(),
+                    width,
+                    height,
+                    rows: Rows::Png { reader: Box::new(reader), color, sixteen_bit: depth == png::BitDepth::Sixteen },
+                });
+            }
+        }
+
+        let img = load_image_from_path(path)?;
+        let image = with_pixel_type!(&img, |P| P::into_dynamic(P::into_buffer(img)));
+        Ok(Self {
+            path: path.to_path_buf(),
+            width: image.width(),
+            height: image.height(),
+            rows: Rows::Decoded { image, next_row: 0 },
+        })
+    }
+
+    /// Reads the whole file into a copy whose longer side is at most
+    /// `max_side`, each of its pixels the average of the ones it covers.
+    pub fn read_preview(mut self, max_side: u32) -> Result<DynamicImage, LoadError> {
+        let factor = self.width.max(self.height).div_ceil(max_side.max(1)).max(1);
+        let (width, height) = (self.width.div_ceil(factor), self.height.div_ceil(factor));
+        let mut preview: Option<DynamicImage> = None;
+        for y in 0..height {
+            let rows = self.read(factor.min(self.height - y * factor))?;
+            let line = rows.thumbnail_exact(width, 1);
+            let preview = preview.get_or_insert_with(|| with_pixel_type!(&line, |P| P::into_dynamic(ImageBuffer::new(width, height))));
+            image::imageops::replace(preview, &line, 0, y as i64);
+        }
+        Ok(preview.unwrap_or_else(|| DynamicImage::new_rgb8(0, 0)))
+    }
+
+    fn read(&mut self, rows: u32) -> Result<DynamicImage, LoadError> {
+        let width = self.width;
+        match &mut self.rows {
+            Rows::Png { reader, color, sixteen_bit } => {
+                // Alpha is dropped like everywhere else in processing
+                let (channels, kept) = match color {
+                    png::ColorType::Grayscale => (1, 1),
+                    png::ColorType::GrayscaleAlpha => (2, 1),
+                    png::ColorType::Rgba => (4, 3),
+                    _ => (3, 3),
+                };
+                let mut data = Vec::with_capacity((width * rows) as usize * kept * if *sixteen_bit { 2 } else { 1 });
+                for _ in 0..rows {
+                    let row = reader.next_row().map_err(|err| png_error(&self.path, err))?.ok_or_else(|| LoadError::Decode {
+                        path: self.path.clone(),
+                        source: decoding_error("the image data ends early"),
+                    })?;
+                    let sample_bytes = if *sixteen_bit { 2 } else { 1 };
+                    for pixel in row.data().chunks_exact(channels * sample_bytes) {
+                        data.extend_from_slice(&pixel[..kept * sample_bytes]);
+                    }
+                }
+                Ok(match (kept, *sixteen_bit) {
+                    (1, false) => DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, rows, data).unwrap()),
+                    (_, false) => DynamicImage::ImageRgb8(ImageBuffer::from_raw(width, rows, data).unwrap()),
+                    (kept, true) => {
+                        // PNG stores 16-bit samples big-endian
+                        let data: Vec<u16> = data.chunks_exact(2).map(|sample| u16::from_be_bytes([sample[0], sample[1]])).collect();
+                        match kept {
+                            1 => DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, rows, data).unwrap()),
+                            _ => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, rows, data).unwrap()),
+                        }
+                    }
+                })
+            }
+            Rows::Decoded { image, next_row } => {
+                let rows = rows.min(image.height() - *next_row);
+                let band = image.crop_imm(0, *next_row, width, rows);
+                *next_row += rows;
+                Ok(band)
+            }
+        }
+    }
+}
+
+impl RowSource for RowReader {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn read_rows(&mut self, rows: u32) -> Result<DynamicImage, String> {
+        self.read(rows).map_err(|err| err.to_string())
+    }
+}
+
+fn png_error(path: &Path, err: png::DecodingError) -> LoadError {
+    match err {
+        png::DecodingError::IoError(source) => LoadError::Io { path: path.to_path_buf(), source },
+        err => LoadError::Decode { path: path.to_path_buf(), source: decoding_error(err) },
+    }
+}
+
+fn decoding_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Png), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("loader-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn garbage_files_are_decode_errors() {
+        let path = temp_file("garbage.png", b"this is not an image at all");
+        let result = load_image_from_path(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(err @ LoadError::Decode { .. }) => assert!(err.to_string().contains("garbage.png"), "{}", err),
+            other => panic!("expected a decode error, got {:?}", other.map(|img| img.color())),
         }
     }
-    None
-} 
\ No newline at end of file
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(64, 64).write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png).unwrap();
+        let path = temp_file("truncated.png", &png[..png.len() / 2]);
+        let result = load_image_from_path(&path);
+        fs::remove_file(&path).unwrap();
+        // Reported as a read or a decode error depending on where the data stops
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn missing_files_are_io_errors() {
+        let path = std::env::temp_dir().join(format!("loader-{}-missing.png", std::process::id()));
+        assert!(matches!(load_image_from_path(&path), Err(LoadError::Io { .. })));
+    }
+}
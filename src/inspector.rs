@@ -0,0 +1,116 @@
+use eframe::egui::{self, Color32, Pos2, Stroke};
+use image::{DynamicImage, GenericImageView, Pixel, Rgb};
+
+use crate::selection::ScreenMapping;
+
+const MARKER_SIZE: f32 = 6.0;
+
+/// Pixel value readout for the point under the cursor and an optional pinned point.
+///
+/// Points are stored in 0..1 image coordinates so the same spot can be looked
+/// up in the original, a downscaled preview and a resized result alike.
+#[derive(Default)]
+pub struct PixelInspector {
+    hovered: Option<Pos2>,
+    pinned: Option<Pos2>,
+}
+
+/// A pixel picked from one image.
+pub struct Sample {
+    pub x: u32,
+    pub y: u32,
+    pub rgb: Rgb<u8>,
+}
+
+impl Sample {
+    pub fn luma(&self) -> u8 {
+        self.rgb.to_luma()[0]
+    }
+}
+
+impl PixelInspector {
+    /// Forgets the hovered point, call once per frame before the panes are shown.
+    pub fn begin_frame(&mut self) {
+        self.hovered = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.hovered = None;
+        self.pinned = None;
+    }
+
+    pub fn hovered(&self) -> Option<Pos2> {
+        self.hovered
+    }
+
+    pub fn pinned(&self) -> Option<Pos2> {
+        self.pinned
+    }
+
+    pub fn unpin(&mut self) {
+        self.pinned = None;
+    }
+
+    /// Tracks the cursor over an image pane; a right click pins the point under it.
+    pub fn interact(&mut self, response: &egui::Response, mapping: &ScreenMapping) {
+        let Some(pointer) = response.hover_pos() else {
+            return;
+        };
+        let point = (mapping.to_image(pointer).to_vec2() / mapping.image_size).to_pos2();
+        if !(0.0..1.0).contains(&point.x) || !(0.0..1.0).contains(&point.y) {
+            return;
+        }
+
+        self.hovered = Some(point);
+        if response.secondary_clicked() {
+            self.pinned = Some(point);
+        }
+    }
+
+    /// Marks the pinned point on a pane.
+    pub fn paint(&self, painter: &egui::Painter, mapping: &ScreenMapping) {
+        let Some(point) = self.pinned else {
+            return;
+        };
+        let center = mapping.to_screen((point.to_vec2() * mapping.image_size).to_pos2());
+        let stroke = Stroke::new(1.5, Color32::from_rgb(255, 64, 64));
+        painter.line_segment([center - egui::vec2(MARKER_SIZE, 0.0), center + egui::vec2(MARKER_SIZE, 0.0)], stroke);
+        painter.line_segment([center - egui::vec2(0.0, MARKER_SIZE), center + egui::vec2(0.0, MARKER_SIZE)], stroke);
+    }
+}
+
+/// The pixel of `img` at `point` in 0..1 image coordinates.
+pub fn sample(img: &DynamicImage, point: Pos2) -> Option<Sample> {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let x = ((point.x * width as f32) as u32).min(width - 1);
+    let y = ((point.y * height as f32) as u32).min(height - 1);
+    Some(Sample {
+        x,
+        y,
+        rgb: img.get_pixel(x, y).to_rgb(),
+    })
+}
+
+/// One line describing `point` in the original and processed images.
+pub fn readout(original: &DynamicImage, processed: Option<&DynamicImage>, point: Pos2) -> Option<String> {
+    let before = sample(original, point)?;
+    let [r, g, b] = before.rgb.0;
+    let mut text = format!(
+        "({}, {})  Original RGB({}, {}, {}) L={}",
+        before.x, before.y, r, g, b, before.luma()
+    );
+
+    if let Some(after) = processed.and_then(|img| sample(img, point)) {
+        let [pr, pg, pb] = after.rgb.0;
+        let delta = |a: u8, b: u8| b as i16 - a as i16;
+        text += &format!(
+            "  Result RGB({}, {}, {}) L={}  Diff({:+}, {:+}, {:+}) L{:+}",
+            pr, pg, pb, after.luma(),
+            delta(r, pr), delta(g, pg), delta(b, pb), delta(before.luma(), after.luma())
+        );
+    }
+    Some(text)
+}
@@ -0,0 +1,4 @@
+//! The image processing behind the editor, without any of its UI, so the
+//! filters can be tested and reused on their own.
+
+pub mod algorithms;
@@ -1,167 +1,1809 @@
 use eframe::egui;
 use eframe::egui::ViewportBuilder;
-use image::{DynamicImage, ImageBuffer};
-use rfd::FileDialog;
+use image::DynamicImage;
+use image::imageops::FilterType;
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-mod algorithms;
+use image_denoising::algorithms;
+
+mod animation;
+mod benchmark_dialog;
+mod clipboard;
+mod exif;
+mod export;
+mod export_dialog;
+mod history;
+mod histogram_panel;
 mod image_loader;
+mod inspector;
+mod mask_painter;
+mod print_size;
+mod processing;
+mod recovery;
+mod resize_dialog;
+mod stack_dialog;
+mod selection;
+mod session;
+mod settings;
+mod sidecar;
+mod spectrum_dialog;
+mod straighten;
+mod toast;
+mod viewer;
+mod watermark;
+
+use algorithms::{denoise::*, auto_adjust::*, geometry::{ResampleFilter, ResizeSettings}, pipeline::Operation};
+use animation::AnimationState;
+use benchmark_dialog::BenchmarkDialog;
+use histogram_panel::HistogramPanel;
+use history::History;
+use export::{ExportFormat, ExportJob};
+use export_dialog::{ExportDialog, ExportTarget};
+use image_loader::{image_dimensions, load_image_from_path, load_image_or_animation, load_lut, pick_image_file, pick_image_files, remember_directory, Animation, LargeImage, RowReader};
+use inspector::PixelInspector;
+use mask_painter::MaskPainter;
+use straighten::Straighten;
+use algorithms::geometry::{crop, flip_horizontal, flip_vertical, rotate, rotate_180, rotate_left, rotate_right, RotateInterpolation, RotateSettings, MAX_ROTATE_ANGLE};
+use algorithms::backend::Backend;
+use algorithms::border::BorderMode;
+use algorithms::channels::ChannelView;
+use algorithms::deconvolution::{DeconvolutionSettings, PsfShape, MAX_REGULARIZATION};
+use algorithms::region::Region;
+use algorithms::document::{DocumentSettings, ThresholdMethod};
+use algorithms::edges::{sobel_magnitude, tint_edges};
+use algorithms::focus::{heat_overlay, sharpness_map};
+use algorithms::histogram::Histograms;
+use algorithms::hot_pixels::DEFAULT_HOT_PIXEL_THRESHOLD;
+use algorithms::hsl::{HslBand, HslRange, MAX_HUE_SHIFT};
+use algorithms::lut::Lut3d;
+use algorithms::mask::blend_with_mask;
+use algorithms::parallel::SPLIT_STAGE;
+use algorithms::quantize::{Dither, MAX_LEVELS, MIN_LEVELS};
+use algorithms::residual::residual_image;
+use algorithms::sample::is_high_depth;
+use algorithms::sharpness::SharpenKernel;
+use algorithms::streaming::RowSource;
+use algorithms::white_balance::{WhiteBalance, MAX_SHIFT};
+use processing::{FramesJob, Intermediate, ProcessingJob, StreamJob};
+use recovery::{Autosave, RecoveredSession, Recovery, RecoveryChoice, RecoveryDialog};
+use resize_dialog::ResizeDialog;
+use stack_dialog::StackDialog;
+use selection::{AspectRatio, RectSelection};
+use session::{Session, SessionAction};
+use settings::{ProcessingSettings, SavedState};
+use sidecar::{load_record, ProcessingRecord, ProcessingRun};
+use spectrum_dialog::SpectrumDialog;
+use toast::Toast;
+use viewer::ImageViewer;
+
+const DENOISE_TYPES: [DenoiseType; 9] = [
+    DenoiseType::MeanFilter,
+    DenoiseType::GaussianFilter,
+    DenoiseType::MedianFilter,
+    DenoiseType::BilateralFilter,
+    DenoiseType::NonLocalMeans,
+    DenoiseType::TotalVariation,
+    DenoiseType::ChambolleTV,
+    DenoiseType::BlockMatching,
+    DenoiseType::AdaptiveMedian,
+];
+
+// Longest side of the downscaled copy used for live previews
+const PREVIEW_MAX_SIDE: u32 = 800;
+// Wait this long after the last slider change before recomputing the preview
+const PREVIEW_DEBOUNCE: Duration = Duration::from_millis(150);
+// Side in pixels of the tiles the sharpness map is measured on
+const SHARPNESS_TILE: u32 = 48;
+// Longest side of the copy a low-memory image is shown and worked on as
+const LARGE_IMAGE_SIDE: u32 = 2048;
+
+/// How detected edges are shown in both image panes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EdgeDisplay {
+    Off,
+    /// Edges above the threshold tinted red over the image
+    Overlay,
+    /// The gradient magnitude as a grayscale image
+    Map,
+}
+
+impl EdgeDisplay {
+    const ALL: [EdgeDisplay; 3] = [EdgeDisplay::Off, EdgeDisplay::Overlay, EdgeDisplay::Map];
+
+    fn render(self, img: &DynamicImage, threshold: u8) -> DynamicImage {
+        match self {
+            EdgeDisplay::Off => img.clone(),
+            EdgeDisplay::Overlay => tint_edges(img, &sobel_magnitude(img), threshold),
+            EdgeDisplay::Map => DynamicImage::ImageLuma8(sobel_magnitude(img)),
+        }
+    }
+}
+
+/// What dragging on the original image does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ImageTool {
+    None,
+    Select,
+    Crop,
+    /// Paint where processing takes effect
+    Mask,
+    /// Rotate by an arbitrary angle
+    Straighten,
+}
 
-use algorithms::{denoise::*, brightness::*, contrast::*, sharpness::*, auto_adjust::*, parallel::*};
-use image_loader::load_image;
+// Window title, also names the folder eframe and the autosave store their files in
+const APP_NAME: &str = "Image Processing";
 
 fn main() {
+    // Escape hatch for stored settings that make the app misbehave
+    let reset_settings = std::env::args().any(|arg| arg == "--reset-settings");
     let options = eframe::NativeOptions {
         viewport: ViewportBuilder::default()
             .with_inner_size([1000.0, 800.0]),
         ..Default::default()
     };
     let _ = eframe::run_native(
-        "Image Processing",
+        APP_NAME,
         options,
-        Box::new(|cc| Box::new(MyApp::new(cc))),
+        Box::new(move |cc| Box::new(MyApp::new(cc, reset_settings))),
     );
 }
 
 struct MyApp {
     original_image: Option<DynamicImage>,
+    /// File `original_image` was loaded from, `None` for pasted or stacked images
+    image_path: Option<PathBuf>,
+    /// `original_image` was cropped or rotated since it was loaded
+    image_edited: bool,
+    /// Frames of an opened animated GIF, `original_image` is the one shown
+    animation: Option<AnimationState>,
+    /// Images opened together, `original_image` is the active one
+    session: Option<Session>,
+    /// Set in low-memory mode, when the opened file is too large to hold:
+    /// `original_image` is a downscaled copy and exports stream the file
+    large_image: Option<LargeImage>,
+    /// Megapixels above which opening an image offers low-memory mode
+    low_memory_megapixels: u32,
     denoised_image: Option<DynamicImage>,
-    denoise_type: DenoiseType,
-    kernel_size: usize,
-    brightness: f32,
-    contrast: f32,
-    sharpness: f32,
-    tv_lambda: f32,
-    tv_iterations: usize,
+    settings: ProcessingSettings,
     processing_time: Option<std::time::Duration>,
-    use_parallel: bool,
-    block_size: u32,
+    /// Per stage breakdown of `processing_time`, empty when there is none
+    stage_timings: Vec<(String, std::time::Duration)>,
+    /// The last run only redid the adjustments, see `intermediate`
+    processing_resumed: bool,
+    /// Result of the restoration steps of the last run on the whole of
+    /// `original_image`, runs changing only the adjustments start from it
+    intermediate: Option<Intermediate>,
+    /// How `denoised_image` was made, what an export's processing record
+    /// describes. `None` when there is no result or it isn't known.
+    last_run: Option<ProcessingRun>,
+    /// Hot pixels the last run repaired, `None` if it didn't look for any
+    repaired_pixels: Option<usize>,
+    /// Histograms of `denoised_image` when the run that made it counted them
+    result_histograms: Option<Histograms>,
+    histogram_panel: HistogramPanel,
+    history: History,
+    job: Option<ProcessingJob>,
+    live_preview: bool,
+    preview_source: Option<DynamicImage>,
+    preview_image: Option<DynamicImage>,
+    // Settings of the last preview or full run, to detect slider changes
+    previewed_settings: ProcessingSettings,
+    preview_requested_at: Option<Instant>,
+    tool: ImageTool,
+    selection: RectSelection,
+    crop: RectSelection,
+    mask_painter: MaskPainter,
+    straighten: Straighten,
+    apply_to_selection: bool,
+    // Uploaded once per image change instead of every frame
+    original_texture: Option<egui::TextureHandle>,
+    result_texture: Option<egui::TextureHandle>,
+    /// What the panes show instead of the plain images, see `displayed_texture`
+    original_overlay_texture: Option<egui::TextureHandle>,
+    result_overlay_texture: Option<egui::TextureHandle>,
+    /// Show `original - result` in the right pane instead of the result
+    show_residual: bool,
+    residual_gain: f32,
+    residual_luma_only: bool,
+    /// Channel both panes show, see `ChannelView`
+    channel_view: ChannelView,
+    edge_display: EdgeDisplay,
+    edge_threshold: u8,
+    /// Tint both panes by how sharp each region is
+    sharpness_overlay: bool,
+    resize_dialog: ResizeDialog,
+    stack_dialog: StackDialog,
+    spectrum_dialog: SpectrumDialog,
+    benchmark_dialog: BenchmarkDialog,
+    viewer: ImageViewer,
+    /// View of the result pane while `link_views` is off
+    result_viewer: ImageViewer,
+    /// Zoom and pan both panes together
+    link_views: bool,
+    inspector: PixelInspector,
+    export_dialog: ExportDialog,
+    // Where the file dialogs start, the folders last picked from
+    open_directory: Option<PathBuf>,
+    export_directory: Option<PathBuf>,
+    /// Export running in the background, the export button is disabled meanwhile
+    export_job: Option<ExportJob>,
+    /// Low-memory export running in the background, likewise
+    stream_job: Option<StreamJob>,
+    /// Notification about a finished export
+    toast: Option<Toast>,
+    /// Outcome of the last clipboard or auto optimize action, shown below the toolbar
+    status_message: Option<String>,
+    /// Why the last load, paste or copy failed, shown in red below the toolbar
+    last_error: Option<String>,
+    autosave: Autosave,
+    /// Offer to restore what the previous run left behind, shown on startup
+    recovery_dialog: Option<RecoveryDialog>,
 }
 
 impl MyApp {
-    fn new(_cc: &eframe::CreationContext<'_>) -> Self {
+    /// Restores the state of the previous session unless `reset_settings`
+    /// is set. Stored state that can't be read is ignored. An autosaved
+    /// session is offered for restoring.
+    fn new(cc: &eframe::CreationContext<'_>, reset_settings: bool) -> Self {
+        let saved: SavedState = cc
+            .storage
+            .filter(|_| !reset_settings)
+            .and_then(|storage| eframe::get_value(storage, SavedState::KEY))
+            .unwrap_or_default();
+        let autosave = Autosave::new(eframe::storage_dir(APP_NAME));
+        let recovery_dialog = if reset_settings { None } else { autosave.load().map(RecoveryDialog::new) };
+
         Self {
             original_image: None,
+            image_path: None,
+            image_edited: false,
+            animation: None,
+            session: None,
+            large_image: None,
+            low_memory_megapixels: saved.low_memory_megapixels,
             denoised_image: None,
-            denoise_type: DenoiseType::MeanFilter,
-            kernel_size: 3,
-            brightness: 0.0,
-            contrast: 0.0,
-            sharpness: 0.0,
-            tv_lambda: 0.1,
-            tv_iterations: 50,
+            settings: saved.settings,
             processing_time: None,
-            use_parallel: false,
-            block_size: 64,
+            stage_timings: Vec::new(),
+            processing_resumed: false,
+            intermediate: None,
+            last_run: None,
+            repaired_pixels: None,
+            result_histograms: None,
+            histogram_panel: HistogramPanel::default(),
+            history: History::new(saved.history_depth),
+            job: None,
+            live_preview: saved.live_preview,
+            preview_source: None,
+            preview_image: None,
+            previewed_settings: ProcessingSettings::default(),
+            preview_requested_at: None,
+            tool: ImageTool::None,
+            selection: RectSelection::default(),
+            crop: RectSelection::new(egui::Color32::LIGHT_BLUE),
+            mask_painter: MaskPainter::default(),
+            straighten: Straighten::default(),
+            apply_to_selection: false,
+            original_texture: None,
+            result_texture: None,
+            original_overlay_texture: None,
+            result_overlay_texture: None,
+            show_residual: false,
+            residual_gain: 5.0,
+            residual_luma_only: false,
+            channel_view: ChannelView::Rgb,
+            edge_display: EdgeDisplay::Off,
+            edge_threshold: 40,
+            sharpness_overlay: false,
+            resize_dialog: ResizeDialog::default(),
+            stack_dialog: StackDialog::default(),
+            spectrum_dialog: SpectrumDialog::default(),
+            benchmark_dialog: BenchmarkDialog::default(),
+            viewer: ImageViewer::default(),
+            result_viewer: ImageViewer::default(),
+            link_views: true,
+            inspector: PixelInspector::default(),
+            export_dialog: ExportDialog { options: saved.export_options, watermark: saved.watermark, ..Default::default() },
+            open_directory: saved.open_directory,
+            export_directory: saved.export_directory,
+            export_job: None,
+            stream_job: None,
+            toast: None,
+            status_message: None,
+            last_error: None,
+            autosave,
+            recovery_dialog,
+        }
+    }
+
+    fn set_original_image(&mut self, img: Option<DynamicImage>) {
+        if let Some(job) = self.job.take() {
+            job.cancel();
+        }
+        if let Some(job) = self.animation.take().and_then(|animation| animation.job) {
+            job.cancel();
+        }
+        self.preview_source = img.as_ref().map(preview_copy);
+        self.original_image = img;
+        self.intermediate = None;
+        self.last_run = None;
+        self.large_image = None;
+        self.denoised_image = None;
+        self.result_histograms = None;
+        self.processing_time = None;
+        self.preview_image = None;
+        self.original_texture = None;
+        self.result_texture = None;
+        self.previewed_settings = self.settings.clone();
+        self.history.clear();
+        self.selection.clear();
+        self.crop.clear();
+        self.mask_painter.clear();
+        self.straighten.reset();
+        self.viewer.fit();
+        self.result_viewer.fit();
+        self.inspector.clear();
+        self.benchmark_dialog.clear();
+        self.spectrum_dialog.clear();
+    }
+
+    fn set_animation(&mut self, animation: Animation) {
+        self.set_original_image(Some(animation.frames[0].clone()));
+        self.animation = Some(AnimationState::new(animation));
+    }
+
+    // Edits the original, or every frame of an animation alike
+    fn edit_original(&mut self, edit: impl Fn(&DynamicImage) -> DynamicImage + Sync) {
+        let Some(img) = &self.original_image else {
+            return;
+        };
+        // Exports stream the file as it is, the edit would only reach the copy
+        if self.large_image.is_some() {
+            self.report(Err("Cropping, rotating and resizing need the whole image, they are unavailable in low-memory mode".to_string()));
+            return;
+        }
+        // The file no longer matches, switching away has to keep the edited copy
+        if let Some(session) = &mut self.session {
+            session.active_mut().modified = true;
+        }
+        self.image_edited = true;
+        match self.animation.take() {
+            Some(mut animation) => {
+                animation.edit_frames(edit);
+                self.set_original_image(Some(animation.current().clone()));
+                self.animation = Some(animation);
+            }
+            None => {
+                let edited = edit(img);
+                self.set_original_image(Some(edited));
+            }
+        }
+    }
+
+    fn transform_original(&mut self, transform: fn(&DynamicImage) -> DynamicImage) {
+        self.edit_original(transform);
+    }
+
+    fn apply_crop(&mut self, region: Region) {
+        self.edit_original(|img| crop(img, region.x, region.y, region.width, region.height));
+        self.tool = ImageTool::None;
+    }
+
+    fn apply_rotation(&mut self, settings: RotateSettings) {
+        self.edit_original(|img| rotate(img, &settings));
+        self.tool = ImageTool::None;
+    }
+
+    // Shows another frame of the animation, along with its processed
+    // version once all frames were processed. Tools and the view stay as
+    // they are, the frames all have the same size.
+    fn show_frame(&mut self, index: usize) {
+        let Some(animation) = &mut self.animation else {
+            return;
+        };
+        animation.frame = index;
+        let frame = animation.current().clone();
+        self.denoised_image = animation.current_processed().cloned();
+        self.result_histograms = None;
+        if let Some(job) = self.job.take() {
+            job.cancel();
+        }
+        self.preview_source = Some(preview_copy(&frame));
+        self.original_image = Some(frame);
+        self.intermediate = None;
+        self.last_run = None;
+        self.processing_time = None;
+        self.preview_image = None;
+        self.original_texture = None;
+        self.result_texture = None;
+        self.history.clear();
+        // Bring the live preview up to date with the new frame
+        self.preview_requested_at = Some(Instant::now());
+    }
+
+    // Opens several images as a session, a single one the usual way
+    fn open_session(&mut self) {
+        let mut paths = pick_image_files(&mut self.open_directory);
+        if paths.len() == 1 {
+            let path = paths.remove(0);
+            match load_image_from_path(&path) {
+                Ok(img) => {
+                    self.end_session();
+                    self.set_original_image(Some(img));
+                    self.set_image_path(Some(path));
+                    self.status_message = None;
+                    self.last_error = None;
+                }
+                Err(err) => self.report(Err(err.to_string())),
+            }
+            return;
+        }
+        if paths.is_empty() {
+            return;
+        }
+
+        match Session::open(paths) {
+            Ok((session, first, errors)) => {
+                self.end_session();
+                self.set_original_image(Some(first));
+                self.set_image_path(Some(session.images[0].path.clone()));
+                let count = session.images.len();
+                self.session = Some(session);
+                match errors.first() {
+                    Some(first) => self.report(Err(format!("{} images loaded, {} failed: {}", count, errors.len(), first))),
+                    None => self.report(Ok(format!("{} images loaded, click a thumbnail to switch", count))),
+                }
+            }
+            Err(errors) => self.report(Err(errors.join("\n"))),
+        }
+    }
+
+    // Leaves the session, the active image stays open on its own
+    fn end_session(&mut self) {
+        if let Some(job) = self.session.take().and_then(|session| session.job) {
+            job.cancel();
+        }
+    }
+
+    // Makes another image of the session the active one. The one shown so
+    // far keeps its result, and its edited original if it was edited.
+    fn select_session_image(&mut self, index: usize) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        let img = match session.images[index].load() {
+            Ok(img) => img,
+            Err(err) => {
+                self.report(Err(err));
+                return;
+            }
+        };
+
+        let previous = session.active_mut();
+        previous.processed = self.denoised_image.take().map(Arc::new);
+        if previous.modified {
+            previous.original = self.original_image.take();
+        }
+        session.active = index;
+        let next = session.active_mut();
+        let (settings, processed) = (next.settings.clone(), next.processed.take());
+        let (path, modified) = (next.path.clone(), next.modified);
+
+        self.set_original_image(Some(img));
+        self.image_path = Some(path);
+        self.image_edited = modified;
+        if let Some(settings) = settings {
+            self.settings = settings;
+        }
+        if let Some(processed) = processed {
+            // Nothing else holds on to it unless an export is still running
+            self.denoised_image = Some(Arc::try_unwrap(processed).unwrap_or_else(|processed| (*processed).clone()));
+            self.previewed_settings = self.settings.clone();
+            self.last_run = Some(ProcessingRun::untimed(self.settings.clone(), None, false));
+        }
+    }
+
+    fn process_session(&mut self) {
+        if let (Some(session), Some(original)) = (&mut self.session, &self.original_image) {
+            session.process_all(original, &self.settings);
+        }
+    }
+
+    fn poll_session_job(&mut self, ctx: &egui::Context) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        let Some(job) = &session.job else {
+            return;
+        };
+        let (finished, done) = job.poll();
+        let settings = job.settings.clone();
+
+        let mut active_result = None;
+        for (index, result) in finished {
+            let image = &mut session.images[index];
+            image.pending = false;
+            match result {
+                Ok(processed) => {
+                    image.settings = Some(settings.clone());
+                    if index == session.active {
+                        active_result = Some(processed);
+                    } else {
+                        image.processed = Some(Arc::new(processed));
+                    }
+                }
+                Err(err) => image.error = Some(err),
+            }
+        }
+
+        if done {
+            session.job = None;
+            let failed = session.images.iter().filter(|image| image.error.is_some()).count();
+            let skipped = session.images.iter().filter(|image| image.pending).count();
+            for image in &mut session.images {
+                image.pending = false;
+            }
+            let message = match (failed, skipped) {
+                (0, 0) => format!("Processed all {} images", session.images.len()),
+                (failed, 0) => format!("Processed the images, {} failed", failed),
+                (_, skipped) => format!("Cancelled, {} images left unprocessed", skipped),
+            };
+            self.report(Ok(message));
+        } else {
+            // Keep repainting so the thumbnails' progress moves
+            ctx.request_repaint();
+        }
+
+        if let Some(processed) = active_result {
+            self.denoised_image = Some(processed);
+            self.last_run = Some(ProcessingRun::untimed(settings, None, false));
+            self.result_histograms = None;
+            self.processing_time = None;
+            self.preview_image = None;
+            self.result_texture = None;
+        }
+    }
+
+    fn process_all_frames(&mut self) {
+        let region = self.active_region();
+        let mask = self.mask_painter.mask().cloned();
+        if let Some(animation) = &mut self.animation {
+            let frames = animation.source.frames.clone();
+            animation.job = Some(FramesJob::spawn(frames, self.settings.clone(), region, mask));
+        }
+    }
+
+    fn poll_frames_job(&mut self, ctx: &egui::Context) {
+        let Some(animation) = &mut self.animation else {
+            return;
+        };
+        let Some(job) = &animation.job else {
+            return;
+        };
+        let Some(result) = job.poll() else {
+            // Keep repainting so the progress bar moves
+            ctx.request_repaint();
+            return;
+        };
+
+        animation.job = None;
+        // A cancelled run leaves the previously processed frames intact
+        if let Some(frames) = result {
+            let count = frames.len();
+            animation.processed = Some(frames);
+            let index = animation.frame;
+            self.status_message = Some(format!("Processed all {} frames, export as GIF to save the animation", count));
+            self.last_error = None;
+            self.show_frame(index);
+        }
+    }
+
+    fn update_preview(&mut self, ctx: &egui::Context) {
+        if !self.live_preview || self.preview_source.is_none() {
+            if self.preview_image.take().is_some() {
+                self.result_texture = None;
+            }
+            return;
+        }
+
+        // Restart the debounce timer on every change while a slider is dragged
+        if self.settings != self.previewed_settings {
+            self.previewed_settings = self.settings.clone();
+            self.preview_requested_at = Some(Instant::now());
+        }
+
+        let Some(requested_at) = self.preview_requested_at else {
+            return;
+        };
+        let elapsed = requested_at.elapsed();
+        if elapsed < PREVIEW_DEBOUNCE {
+            ctx.request_repaint_after(PREVIEW_DEBOUNCE - elapsed);
+            return;
+        }
+
+        self.preview_requested_at = None;
+        if let Some(source) = &self.preview_source {
+            let preview = self.settings.preview_pipeline().apply(source);
+            self.preview_image = Some(match self.mask_painter.mask() {
+                Some(mask) => blend_with_mask(source, &preview, mask),
+                None => preview,
+            });
+            self.result_texture = None;
         }
     }
 
     fn auto_optimize(&mut self) {
         if let Some(img) = &self.original_image {
             // Analyze image and get auto adjustments
-            let (auto_brightness, auto_contrast) = analyze_image(img);
+            let auto = analyze_image(img);
             
-            // Apply auto adjustments
-            self.brightness = auto_brightness;
-            self.contrast = auto_contrast;
-            self.sharpness = 1.0; // Default sharpness value
-            self.kernel_size = 6; // Larger kernel size for better denoising
+            // Apply auto adjustments, the controls show what was picked
+            self.settings.brightness = auto.brightness;
+            self.settings.contrast = auto.contrast;
+            self.settings.denoise_type = auto.denoise_type;
+            self.settings.kernel_size = auto.kernel_size;
+            self.settings.sharpness = auto.sharpness;
+            self.settings.white_balance = auto.color.white_balance;
+            self.settings.use_custom_pipeline = false;
+            let [red, green, blue] = auto.color.means;
+            let cast = if auto.color.cast {
+                format!(
+                    "cast corrected (temperature {:+.2}, tint {:+.2})",
+                    auto.color.white_balance.temperature, auto.color.white_balance.tint,
+                )
+            } else {
+                "no cast".to_string()
+            };
+            self.status_message = Some(format!(
+                "Auto Optimize: levels {}-{} stretched, {:?} (noise σ≈{:.1}, impulses {:.1}%), sharpness {:.2} (blur metric {:.4}), RGB means {:.0}/{:.0}/{:.0}, {}",
+                auto.black_point,
+                auto.white_point,
+                auto.denoise_type,
+                auto.noise_sigma,
+                auto.impulse_fraction * 100.0,
+                auto.sharpness,
+                auto.blur_metric,
+                red,
+                green,
+                blue,
+                cast,
+            ));
+            self.last_error = None;
             
             // Apply denoising and adjustments using the same method as manual optimization
-            let (denoised, duration) = self.apply_denoising(img, self.denoise_type, self.kernel_size);
-            self.denoised_image = Some(denoised);
-            self.processing_time = Some(duration);
+            self.apply_denoising(true);
+        }
+    }
+
+    fn is_processing(&self) -> bool {
+        self.job.is_some()
+            || self.animation.as_ref().is_some_and(|animation| animation.job.is_some())
+            || self.session.as_ref().is_some_and(|session| session.job.is_some())
+    }
+
+    fn undo(&mut self) {
+        if self.history.can_undo() && !self.is_processing() {
+            self.history.undo();
+            self.restore_history_entry();
         }
     }
 
-    fn export_image(&self) {
+    fn redo(&mut self) {
+        if self.history.can_redo() && !self.is_processing() {
+            self.history.redo();
+            self.restore_history_entry();
+        }
+    }
+
+    fn restore_history_entry(&mut self) {
+        let Some(entry) = self.history.current() else {
+            // Undone past the first run: back to the original image
+            self.denoised_image = None;
+            self.result_histograms = None;
+            self.preview_image = None;
+            self.result_texture = None;
+            self.processing_time = None;
+            self.last_run = None;
+            return;
+        };
+
+        self.settings = entry.settings.clone();
+        self.previewed_settings = self.settings.clone();
+        self.preview_image = None;
+        let region = entry.region;
+        match &entry.image {
+            Some(image) => {
+                self.denoised_image = Some(image.clone());
+                self.result_histograms = None;
+                self.result_texture = None;
+                self.processing_time = None;
+                // The history keeps no masks, the one painted now is the best guess
+                let masked = self.mask_painter.mask().is_some();
+                self.last_run = Some(ProcessingRun::untimed(self.settings.clone(), region, masked));
+            }
+            None => {
+                // Too large to keep in memory, recompute from the stored settings
+                self.start_job(region, false);
+            }
+        }
+    }
+
+    fn export_image(&mut self) {
+        if self.export_job.is_some() || self.stream_job.is_some() {
+            return;
+        }
+        if let Some(large) = self.large_image.clone() {
+            self.export_large_image(&large);
+            return;
+        }
+        let options = self.export_dialog.options;
+        // Animations keep their frames as GIF, other formats get the current frame
+        let animation = match &self.animation {
+            Some(animation) if options.format == ExportFormat::Gif => match &animation.processed {
+                Some(frames) => Some((frames.clone(), animation.source.delays.clone(), animation.source.repeat)),
+                None => {
+                    self.report(Err("Process all frames before exporting the animation".to_string()));
+                    return;
+                }
+            },
+            _ => None,
+        };
+        let Some(img) = self.denoised_image.clone() else {
+            return;
+        };
+        let Some(path) = self.pick_export_path(options.format) else {
+            return;
+        };
+
+        let watermark = &self.export_dialog.watermark;
+        let watermark = watermark.enabled.then(|| watermark.clone());
+        let record = match (&self.original_image, &self.last_run) {
+            _ if !options.sidecar || animation.is_some() => None,
+            (Some(original), Some(run)) => {
+                Some(ProcessingRecord::new(original, self.image_path.clone(), self.image_edited, run, options, watermark.is_some()))
+            }
+            _ => {
+                self.report(Ok("How this result was made isn't known, it was exported without a processing record".to_string()));
+                None
+            }
+        };
+        self.export_job = Some(match animation {
+            Some((frames, delays, repeat)) => ExportJob::spawn_animation(frames, delays, repeat, path, watermark),
+            None => ExportJob::spawn(img, path, options, watermark, record),
+        });
+    }
+
+    // Asks where to export to, `None` when cancelled or declined to overwrite
+    fn pick_export_path(&mut self, format: ExportFormat) -> Option<PathBuf> {
+        let chosen = FileDialog::new()
+            .add_filter(format.label(), format.extensions())
+            .set_directory(self.export_directory.as_deref().unwrap_or(Path::new(".")))
+            .save_file()?;
+        remember_directory(&mut self.export_directory, &chosen);
+
+        // Not every platform's save dialog confirms overwriting, and none of
+        // them know about the extension appended here
+        let path = export::with_extension(chosen, format);
+        if path.exists() {
+            let overwrite = MessageDialog::new()
+                .set_level(MessageLevel::Warning)
+                .set_title("Export Image")
+                .set_description(format!("{} already exists. Overwrite it?", path.display()))
+                .set_buttons(MessageButtons::YesNo)
+                .show();
+            if overwrite != MessageDialogResult::Yes {
+                return None;
+            }
+        }
+        Some(path)
+    }
+
+    // Processes the full image of low-memory mode from its file straight
+    // into the exported one, see `StreamJob`
+    fn export_large_image(&mut self, large: &LargeImage) {
+        let options = self.export_dialog.options;
+        if !matches!(options.format, ExportFormat::Png | ExportFormat::Tiff) {
+            self.report(Err("Low-memory mode exports PNG or TIFF files, the other formats need the whole image".to_string()));
+            return;
+        }
+        let Some(path) = self.pick_export_path(options.format) else {
+            return;
+        };
+        match StreamJob::spawn(large, path, self.settings.clone(), options) {
+            Ok(job) => {
+                self.stream_job = Some(job);
+                let left_out = self.active_region().is_some() || self.mask_painter.mask().is_some() || self.export_dialog.watermark.enabled;
+                if left_out {
+                    self.report(Ok("Selections, masks and watermarks are left out of low-memory exports".to_string()));
+                }
+            }
+            Err(err) => self.report(Err(err)),
+        }
+    }
+
+    // Writes every result of the session into a folder the user picks,
+    // named after the original files plus the suffix
+    fn export_all(&mut self) {
+        if self.export_job.is_some() {
+            return;
+        }
+        let Some(session) = &self.session else {
+            return;
+        };
+        let options = self.export_dialog.options;
+        let suffix = self.export_dialog.suffix.clone();
+
+        let results: Vec<(Arc<DynamicImage>, &Path)> = session
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| {
+                let processed = match &image.processed {
+                    _ if index == session.active => self.denoised_image.clone().map(Arc::new),
+                    processed => processed.clone(),
+                };
+                processed.map(|processed| (processed, image.path.as_path()))
+            })
+            .collect();
+        let skipped = session.images.len() - results.len();
+        if results.is_empty() {
+            self.report(Err("Process the images before exporting them".to_string()));
+            return;
+        }
+
+        let Some(folder) = FileDialog::new()
+            .set_directory(self.export_directory.as_deref().unwrap_or(Path::new(".")))
+            .pick_folder()
+        else {
+            return;
+        };
+        let images: Vec<(Arc<DynamicImage>, PathBuf)> = results
+            .into_iter()
+            .map(|(processed, source)| {
+                let mut name = source.file_stem().unwrap_or_default().to_os_string();
+                name.push(&suffix);
+                (processed, export::with_extension(folder.join(name), options.format))
+            })
+            .collect();
+
+        let existing = images.iter().filter(|(_, path)| path.exists()).count();
+        if existing > 0 {
+            let overwrite = MessageDialog::new()
+                .set_level(MessageLevel::Warning)
+                .set_title("Export All")
+                .set_description(format!("{} of the files already exist in {}. Overwrite them?", existing, folder.display()))
+                .set_buttons(MessageButtons::YesNo)
+                .show();
+            if overwrite != MessageDialogResult::Yes {
+                return;
+            }
+        }
+        self.export_directory = Some(folder.clone());
+        if skipped > 0 {
+            self.report(Ok(format!("Exporting {} images, {} without a result are left out", images.len(), skipped)));
+        }
+
+        let watermark = &self.export_dialog.watermark;
+        let watermark = watermark.enabled.then(|| watermark.clone());
+        self.export_job = Some(ExportJob::spawn_all(images, folder, options, watermark));
+    }
+
+    fn poll_export(&mut self, ctx: &egui::Context) {
+        let Some(job) = &self.export_job else {
+            return;
+        };
+        let Some(result) = job.poll() else {
+            // Check again shortly, nothing else may trigger a repaint
+            ctx.request_repaint_after(Duration::from_millis(100));
+            return;
+        };
+
+        let job = self.export_job.take().unwrap();
+        self.toast = Some(Toast::new(result.map(|()| format!("Exported {}", job.path.display()))));
+    }
+
+    fn poll_stream_job(&mut self, ctx: &egui::Context) {
+        let Some(job) = &self.stream_job else {
+            return;
+        };
+        let Some(result) = job.poll() else {
+            // Keep repainting so the progress bar moves
+            ctx.request_repaint_after(Duration::from_millis(100));
+            return;
+        };
+
+        let job = self.stream_job.take().unwrap();
+        if job.is_cancelled() {
+            self.report(Ok("Export cancelled".to_string()));
+        } else {
+            self.toast = Some(Toast::new(result.map(|()| format!("Exported {}", job.path.display()))));
+        }
+    }
+
+    fn copy_result(&mut self) {
         if let Some(img) = &self.denoised_image {
-            if let Some(path) = FileDialog::new()
-                .add_filter("PNG Image", &["png"])
-                .add_filter("JPEG Image", &["jpg", "jpeg"])
-                .set_directory(".")
-                .save_file()
-            {
-                let _ = img.save(path);
-            }
-        }
-    }
-
-    fn apply_denoising(
-        &self,
-        img: &DynamicImage,
-        denoise_type: DenoiseType,
-        kernel_size: usize,
-    ) -> (DynamicImage, std::time::Duration) {
-        let start_time = std::time::Instant::now();
-        let mut current_img = img.clone();
-
-        if self.use_parallel {
-            current_img = process_image_parallel(&current_img, self.block_size, |block| {
-                let mut block_img = DynamicImage::ImageRgb8(ImageBuffer::from_raw(
-                    block.width,
-                    block.height,
-                    block.data.clone(),
-                ).unwrap());
-                
-                block_img = denoise_image(
-                    &block_img,
-                    denoise_type,
-                    kernel_size,
-                    self.tv_lambda,
-                    self.tv_iterations
-                );
-
-                if self.brightness != 0.0 {
-                    block_img = adjust_brightness(&block_img, self.brightness);
+            let result = clipboard::copy_image(img).map(|()| "Result copied to the clipboard".to_string());
+            self.report(result);
+        }
+    }
+
+    fn open_image(&mut self) {
+        let Some(path) = pick_image_file(&mut self.open_directory) else {
+            // Cancelled, keep the current image
+            return;
+        };
+        let threshold = self.low_memory_megapixels as u64 * 1_000_000;
+        match image_dimensions(&path) {
+            Ok((width, height)) if width as u64 * height as u64 > threshold => {
+                let answer = MessageDialog::new()
+                    .set_level(MessageLevel::Warning)
+                    .set_title("Large Image")
+                    .set_description(format!(
+                        "{} is {}x{}, more than {} megapixels. Open it in low-memory mode? You then work on a downscaled copy, \
+                         exports process the full image from the file band by band, and a few of the denoisers are unavailable. \
+                         No loads it whole.",
+                        path.display(),
+                        width,
+                        height,
+                        self.low_memory_megapixels,
+                    ))
+                    .set_buttons(MessageButtons::YesNoCancel)
+                    .show();
+                match answer {
+                    MessageDialogResult::Yes => return self.open_large_image(path),
+                    MessageDialogResult::No => {}
+                    _ => return,
                 }
+            }
+            // Formats whose size isn't in a header are left to the decoder
+            _ => {}
+        }
 
-                if self.contrast != 1.0 {
-                    block_img = adjust_contrast(&block_img, self.contrast);
+        match load_image_or_animation(&path) {
+            Ok((img, animation)) => {
+                self.end_session();
+                match animation {
+                    Some(animation) => self.set_animation(animation),
+                    None => self.set_original_image(Some(img)),
                 }
+                self.set_image_path(Some(path));
+                self.status_message = None;
+                self.last_error = None;
+            }
+            Err(err) => self.report(Err(err.to_string())),
+        }
+    }
+
+    // Reads the file once for the downscaled copy, see `large_image`
+    fn open_large_image(&mut self, path: PathBuf) {
+        let opened = RowReader::open(&path).and_then(|reader| {
+            let large = LargeImage { path: path.clone(), width: reader.width(), height: reader.height() };
+            Ok((large, reader.read_preview(LARGE_IMAGE_SIDE)?))
+        });
+        match opened {
+            Ok((large, copy)) => {
+                self.end_session();
+                self.status_message = Some(format!(
+                    "Low-memory mode: {}x{} ({:.0} MP) is shown and processed as a {}x{} copy, Export Image runs the full image",
+                    large.width,
+                    large.height,
+                    large.megapixels(),
+                    copy.width(),
+                    copy.height(),
+                ));
+                self.last_error = None;
+                self.set_original_image(Some(copy));
+                self.set_image_path(Some(path));
+                self.large_image = Some(large);
+            }
+            Err(err) => self.report(Err(err.to_string())),
+        }
+    }
+
+    fn paste_image(&mut self) {
+        match clipboard::paste_image() {
+            Ok(img) => {
+                self.end_session();
+                self.set_original_image(Some(img));
+                self.set_image_path(None);
+                self.status_message = None;
+                self.last_error = None;
+            }
+            Err(err) => self.report(Err(err)),
+        }
+    }
+
+    // Where a newly loaded original came from, unedited
+    fn set_image_path(&mut self, path: Option<PathBuf>) {
+        self.image_path = path;
+        self.image_edited = false;
+    }
+
+    // What the autosave needs to restore the current work, `None` when
+    // there is no image loaded from a file
+    fn recovery(&self) -> Option<Recovery> {
+        // Restoring would load a low-memory image whole
+        let image_path = self.image_path.clone().filter(|_| self.original_image.is_some() && self.large_image.is_none())?;
+        Some(Recovery {
+            image_path,
+            image_edited: self.image_edited,
+            settings: self.settings.clone(),
+            selection: self.selection.region(),
+            apply_to_selection: self.apply_to_selection,
+            has_mask: self.mask_painter.mask().is_some(),
+            processed: self.denoised_image.is_some(),
+        })
+    }
+
+    fn write_recovery(&mut self) {
+        // Keep what the previous run left until the user decided about it
+        if self.recovery_dialog.is_none() {
+            self.autosave.write(self.recovery(), self.mask_painter.mask(), self.mask_painter.revision());
+        }
+    }
+
+    // Writes the session every `AUTOSAVE_INTERVAL`, waking up for it when idle
+    fn autosave(&mut self, ctx: &egui::Context) {
+        let due_in = self.autosave.due_in();
+        if due_in.is_zero() {
+            self.write_recovery();
+            ctx.request_repaint_after(recovery::AUTOSAVE_INTERVAL);
+        } else {
+            ctx.request_repaint_after(due_in);
+        }
+    }
+
+    // Loads the image of a recovered session again with everything that was
+    // set up for it, processing it again if `rerun`
+    fn restore_session(&mut self, session: RecoveredSession, rerun: bool) {
+        let recovery = session.recovery;
+        let (img, animation) = match load_image_or_animation(&recovery.image_path) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                self.report(Err(err.to_string()));
+                return;
+            }
+        };
+        self.end_session();
+        let size = (img.width(), img.height());
+        match animation {
+            Some(animation) => self.set_animation(animation),
+            None => self.set_original_image(Some(img)),
+        }
+        self.report(Ok(format!("Restored the session of {}", recovery.image_path.display())));
+        self.set_image_path(Some(recovery.image_path));
+        self.settings = recovery.settings;
+        self.apply_to_selection = recovery.apply_to_selection;
+        // Both may not fit any more if the image was cropped or rotated
+        if let Some(region) = recovery.selection.map(|region| region.clamp_to(size.0, size.1)).filter(|region| !region.is_empty()) {
+            self.selection.set_region(region);
+        }
+        if let Some(mask) = session.mask.filter(|mask| mask.dimensions() == size) {
+            self.mask_painter.set_mask(mask);
+        }
+        if rerun {
+            self.apply_denoising(true);
+        }
+    }
+
+    // Reads a processing record written next to an export and sets up what
+    // it describes, opening its source file when another image is open. The
+    // run is repeated when the image is the one the record was made from.
+    fn load_processing_record(&mut self) {
+        if self.is_processing() {
+            self.report(Err("Wait for the processing to finish before loading a record".to_string()));
+            return;
+        }
+        let record = match load_record(&mut self.open_directory) {
+            Ok(Some(record)) => record,
+            Ok(None) => return,
+            Err(err) => {
+                self.report(Err(err));
+                return;
+            }
+        };
+
+        let mut matches = self.original_image.as_ref().is_some_and(|img| record.matches_source(img));
+        if let (false, Some(path)) = (matches, record.source_path.as_ref().filter(|path| path.is_file())) {
+            match load_image_or_animation(path) {
+                Ok((img, animation)) => {
+                    matches = record.matches_source(&img);
+                    self.end_session();
+                    match animation {
+                        Some(animation) => self.set_animation(animation),
+                        None => self.set_original_image(Some(img)),
+                    }
+                    self.set_image_path(Some(path.clone()));
+                }
+                Err(err) => {
+                    self.report(Err(err.to_string()));
+                    return;
+                }
+            }
+        }
+
+        self.settings = record.settings.clone();
+        self.export_dialog.options = record.export;
+        self.apply_to_selection = record.selection.is_some();
+        if let (Some(region), Some(img)) = (record.selection, &self.original_image) {
+            let region = region.clamp_to(img.width(), img.height());
+            if !region.is_empty() {
+                self.selection.set_region(region);
+            }
+        }
+
+        if !matches {
+            let reason = if record.source_edited {
+                "its source was cropped, rotated or resized before processing"
+            } else {
+                "the image open is not the one it was made from"
+            };
+            self.report(Err(format!("Loaded the settings of the record, but the result can't be reproduced: {}", reason)));
+            return;
+        }
+        let mask_note = if record.masked { ", without the painted mask it used" } else { "" };
+        self.report(Ok(format!("Reproducing the result of the record{}", mask_note)));
+        self.apply_denoising(true);
+    }
+
+    // Keyboard shortcuts, unless a text field is being edited. Each one is
+    // ignored whenever its button would be disabled.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let command = egui::Modifiers::COMMAND;
+        let command_shift = egui::Modifiers::COMMAND | egui::Modifiers::SHIFT;
+        // Check the more specific shortcuts first, Ctrl+Z also matches Ctrl+Shift+Z
+        if ctx.input_mut(|i| i.consume_key(command_shift, egui::Key::Z)) {
+            self.redo();
+        } else if ctx.input_mut(|i| i.consume_key(command, egui::Key::Z)) {
+            self.undo();
+        }
+
+        if ctx.input_mut(|i| i.consume_key(command, egui::Key::O)) {
+            self.open_image();
+        }
+
+        let can_export = (self.denoised_image.is_some() || self.large_image.is_some()) && self.export_job.is_none() && self.stream_job.is_none();
+        if ctx.input_mut(|i| i.consume_key(command_shift, egui::Key::S)) {
+            self.export_dialog.open = can_export;
+        } else if ctx.input_mut(|i| i.consume_key(command, egui::Key::S)) && can_export {
+            // Straight to the file dialog with the last used options
+            self.export_image();
+        }
+
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Enter)) && !self.is_processing() {
+            self.apply_denoising(true);
+        }
+
+        // egui turns Ctrl+C into a copy event, while Ctrl+V only produces an
+        // event for text contents, so pasting reacts to the V key being
+        // released instead.
+        let (copy, paste) = ctx.input(|i| {
+            let copy = i.events.iter().any(|event| matches!(event, egui::Event::Copy));
+            let paste = i.events.iter().any(|event| matches!(
+                event,
+                egui::Event::Key { key: egui::Key::V, pressed: false, modifiers, .. } if modifiers.command
+            ));
+            (copy, paste)
+        });
+        if copy {
+            self.copy_result();
+        }
+        if paste {
+            self.paste_image();
+        }
+    }
+
+    // Shows the outcome of a user action, replacing the previous one
+    fn report(&mut self, result: Result<String, String>) {
+        match result {
+            Ok(message) => {
+                self.status_message = Some(message);
+                self.last_error = None;
+            }
+            Err(err) => {
+                self.status_message = None;
+                self.last_error = Some(err);
+            }
+        }
+    }
+
+    fn active_region(&self) -> Option<Region> {
+        if self.apply_to_selection {
+            self.selection.region()
+        } else {
+            None
+        }
+    }
+
+    fn apply_denoising(&mut self, record_history: bool) {
+        let region = self.active_region();
+        self.start_job(region, record_history);
+    }
+
+    fn start_job(&mut self, region: Option<Region>, record_history: bool) {
+        if let Some(img) = &self.original_image {
+            let mask = self.mask_painter.mask().cloned();
+            let intermediate = self
+                .intermediate
+                .clone()
+                .filter(|intermediate| region.is_none() && intermediate.matches(&self.settings));
+            self.job = Some(ProcessingJob::spawn(img.clone(), self.settings.clone(), region, mask, intermediate, record_history));
+        }
+    }
+
+    fn poll_job(&mut self, ctx: &egui::Context) {
+        let Some(job) = &self.job else {
+            return;
+        };
+
+        let Some(result) = job.poll() else {
+            // Keep repainting so the progress bar moves
+            ctx.request_repaint();
+            return;
+        };
+
+        let job = self.job.take().unwrap();
+        // A cancelled run leaves the previous result intact
+        if let Some(output) = result {
+            // Block-wise runs leave a note per block, the first stands for all
+            let notes = job.notes();
+            if let Some(first) = notes.first() {
+                self.status_message = Some(match notes.len() {
+                    1 => first.clone(),
+                    count => format!("{} (first of {} blocks)", first, count),
+                });
+            }
+            self.repaired_pixels = job
+                .settings
+                .pipeline()
+                .0
+                .iter()
+                .any(|operation| matches!(operation, Operation::HotPixels { .. }))
+                .then(|| job.repaired_pixels());
+            if let Some(session) = &mut self.session {
+                session.active_mut().settings = Some(job.settings.clone());
+            }
+            self.last_run = Some(ProcessingRun {
+                settings: job.settings.clone(),
+                selection: job.region,
+                masked: job.masked,
+                duration: Some(output.duration),
+                timings: output.timings.clone(),
+                resumed: output.resumed,
+            });
+            if job.record_history {
+                self.history.push(job.settings, job.region, &output.image);
+            }
+            self.denoised_image = Some(output.image);
+            self.result_histograms = Some(output.histograms);
+            self.processing_time = Some(output.duration);
+            self.stage_timings = output.timings;
+            self.processing_resumed = output.resumed;
+            if output.intermediate.is_some() {
+                self.intermediate = output.intermediate;
+            }
+            self.preview_image = None;
+            self.result_texture = None;
+            self.preview_requested_at = None;
+            self.previewed_settings = self.settings.clone();
+        }
+    }
+
+    fn pipeline_editor(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.settings.use_custom_pipeline, egui::RichText::new("Use custom pipeline").size(16.0));
+        if !self.settings.use_custom_pipeline {
+            return;
+        }
+
+        let low_memory = self.large_image.is_some();
+        let mut move_up = None;
+        let mut move_down = None;
+        let mut remove = None;
+        let count = self.settings.custom_pipeline.0.len();
+
+        for (index, operation) in self.settings.custom_pipeline.0.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(format!("{}. {}", index + 1, operation.name())).size(16.0));
+                match operation {
+                    Operation::NotchFilter(notches) => {
+                        ui.label(format!("{} notches", notches.len()));
+                    }
+                    Operation::HotPixels { window, threshold } => {
+                        hot_pixel_window(ui, ("pipeline_hot_pixels", index), window);
+                        ui.add(egui::Slider::new(threshold, 0.05..=1.0).step_by(0.01).text("threshold"));
+                    }
+                    Operation::Denoise { denoise_type, kernel_size, tv_lambda, tv_iterations, tv_tolerance, planes, linear_light, detail, detail_radius, .. } => {
+                        egui::ComboBox::from_id_source(("pipeline_denoise", index))
+                            .selected_text(format!("{:?}", denoise_type))
+                            .show_ui(ui, |ui| denoise_type_options(ui, denoise_type, low_memory));
+                        if *denoise_type == DenoiseType::ChambolleTV {
+                            ui.add(egui::Slider::new(tv_lambda, 0.01..=0.5).logarithmic(true).text("lambda"));
+                            ui.add(egui::Slider::new(tv_tolerance, 1e-5..=1e-2).logarithmic(true).text("tolerance"));
+                            ui.add(egui::Slider::new(tv_iterations, 10..=500).text("max iterations"));
+                        } else if *denoise_type != DenoiseType::TotalVariation {
+                            ui.add(egui::Slider::new(kernel_size, 3..=15).text("size"));
+                        }
+                        let mut separate = planes.is_some();
+                        if ui.checkbox(&mut separate, "luma/color").changed() {
+                            *planes = separate.then_some(PlaneStrengths { chroma_kernel_size: Some(*kernel_size), ..PlaneStrengths::CHROMA_ONLY });
+                        }
+                        if let Some(strengths) = planes {
+                            ui.add(egui::Slider::new(&mut strengths.luma, 0.0..=1.0).step_by(0.01).text("luma"));
+                            ui.add(egui::Slider::new(&mut strengths.chroma, 0.0..=1.0).step_by(0.01).text("color"));
+                            if let Some(chroma_kernel_size) = &mut strengths.chroma_kernel_size {
+                                ui.add(egui::Slider::new(chroma_kernel_size, 3..=15).text("color size"));
+                            }
+                        }
+                        ui.checkbox(linear_light, "linear light");
+                        ui.add(egui::Slider::new(detail, 0.0..=1.0).step_by(0.01).text("detail"));
+                        if *detail > 0.0 {
+                            ui.add(egui::Slider::new(detail_radius, 1.0..=20.0).step_by(0.5).text("detail radius"));
+                        }
+                    }
+                    Operation::Deconvolve(deconvolution) => {
+                        egui::CollapsingHeader::new("parameters")
+                            .id_source(("pipeline_deconvolve", index))
+                            .show(ui, |ui| deconvolution_controls(ui, ("pipeline_psf", index), deconvolution));
+                    }
+                    Operation::Sharpen { amount, kernel, linear_light, .. } => {
+                        ui.add(egui::Slider::new(amount, 0.0..=3.0).step_by(0.01));
+                        sharpen_kernel_combo(ui, ("pipeline_sharpen_kernel", index), kernel);
+                        ui.checkbox(linear_light, "linear light");
+                    }
+                    Operation::WhiteBalance(white_balance) => {
+                        ui.add(egui::Slider::new(&mut white_balance.temperature, -MAX_SHIFT..=MAX_SHIFT).step_by(0.01).text("temperature"));
+                        ui.add(egui::Slider::new(&mut white_balance.tint, -MAX_SHIFT..=MAX_SHIFT).step_by(0.01).text("tint"));
+                    }
+                    Operation::Exposure(ev) => {
+                        ui.add(egui::Slider::new(ev, -3.0..=3.0).step_by(0.1).suffix(" EV"));
+                    }
+                    Operation::ShadowsHighlights { shadows, highlights, radius } => {
+                        ui.add(egui::Slider::new(shadows, 0.0..=1.0).step_by(0.01).text("shadows"));
+                        ui.add(egui::Slider::new(highlights, 0.0..=1.0).step_by(0.01).text("highlights"));
+                        ui.add(egui::Slider::new(radius, 5.0..=100.0).step_by(1.0).text("radius"));
+                    }
+                    Operation::Dehaze(strength) => {
+                        ui.add(egui::Slider::new(strength, 0.0..=1.0).step_by(0.01));
+                    }
+                    Operation::Brightness(amount) | Operation::Contrast(amount) => {
+                        ui.add(egui::Slider::new(amount, -1.0..=1.0).step_by(0.01));
+                    }
+                    Operation::Hsl(bands) => {
+                        egui::CollapsingHeader::new("bands")
+                            .id_source(("pipeline_hsl", index))
+                            .show(ui, |ui| hsl_sliders(ui, ("pipeline_hsl_grid", index), bands));
+                    }
+                    Operation::Resize(resize) => {
+                        ui.add(egui::DragValue::new(&mut resize.width).clamp_range(1..=65535));
+                        ui.label("x");
+                        ui.add(egui::DragValue::new(&mut resize.height).clamp_range(1..=65535));
+                        egui::ComboBox::from_id_source(("pipeline_resize", index))
+                            .selected_text(format!("{:?}", resize.filter))
+                            .show_ui(ui, |ui| {
+                                for filter in ResampleFilter::ALL {
+                                    ui.selectable_value(&mut resize.filter, filter, format!("{:?}", filter));
+                                }
+                            });
+                    }
+                    Operation::Lut { lut, intensity } => {
+                        ui.label(lut_name(lut));
+                        ui.add(egui::Slider::new(intensity, 0.0..=1.0).step_by(0.01).text("intensity"));
+                    }
+                    Operation::Grayscale => {}
+                    Operation::Document(document) => {
+                        egui::CollapsingHeader::new("parameters")
+                            .id_source(("pipeline_document", index))
+                            .show(ui, |ui| document_controls(ui, document));
+                    }
+                    Operation::Posterize { levels, dither } => {
+                        ui.add(egui::Slider::new(levels, MIN_LEVELS..=MAX_LEVELS).text("levels"));
+                        dither_combo(ui, ("pipeline_dither", index), dither);
+                    }
+                }
+
+                if ui.add_enabled(index > 0, egui::Button::new("⏶")).clicked() {
+                    move_up = Some(index);
+                }
+                if ui.add_enabled(index + 1 < count, egui::Button::new("⏷")).clicked() {
+                    move_down = Some(index);
+                }
+                if ui.button("✖").clicked() {
+                    remove = Some(index);
+                }
+            });
+        }
+
+        let slider_pipeline = self.settings.slider_pipeline();
+        let loaded_lut = self.settings.lut.clone();
+        let notches = self.settings.notches.clone();
+        let source_size = self.original_image.as_ref().map_or((1024, 768), |img| (img.width(), img.height()));
+        let pipeline = &mut self.settings.custom_pipeline;
+        if let Some(index) = move_up {
+            pipeline.move_up(index);
+        }
+        if let Some(index) = move_down {
+            pipeline.move_down(index);
+        }
+        if let Some(index) = remove {
+            pipeline.0.remove(index);
+        }
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("pipeline_add")
+                .selected_text("Add operation")
+                .show_ui(ui, |ui| {
+                    if ui.add_enabled(!notches.is_empty(), egui::SelectableLabel::new(false, "Notch Filter"))
+                        .on_disabled_hover_text("Place notches in the Periodic Noise window first")
+                        .clicked()
+                    {
+                        pipeline.0.push(Operation::NotchFilter(notches.clone()));
+                    }
+                    if ui.selectable_label(false, "Hot Pixels").clicked() {
+                        pipeline.0.push(Operation::HotPixels {
+                            window: 3,
+                            threshold: DEFAULT_HOT_PIXEL_THRESHOLD,
+                        });
+                    }
+                    if ui.selectable_label(false, "Denoise").clicked() {
+                        // The slider pipeline always contains the slider denoise settings
+                        if let Some(denoise) = slider_pipeline
+                            .0
+                            .iter()
+                            .find(|operation| matches!(operation, Operation::Denoise { .. }))
+                        {
+                            pipeline.0.push(denoise.clone());
+                        }
+                    }
+                    if ui.selectable_label(false, "Deconvolve").clicked() {
+                        pipeline.0.push(Operation::Deconvolve(DeconvolutionSettings::default()));
+                    }
+                    if ui.selectable_label(false, "White Balance").clicked() {
+                        pipeline.0.push(Operation::WhiteBalance(WhiteBalance::default()));
+                    }
+                    if ui.selectable_label(false, "Exposure").clicked() {
+                        pipeline.0.push(Operation::Exposure(0.0));
+                    }
+                    if ui.selectable_label(false, "Shadows/Highlights").clicked() {
+                        pipeline.0.push(Operation::ShadowsHighlights {
+                            shadows: 0.0,
+                            highlights: 0.0,
+                            radius: 30.0,
+                        });
+                    }
+                    if ui.selectable_label(false, "Dehaze").clicked() {
+                        pipeline.0.push(Operation::Dehaze(0.5));
+                    }
+                    if ui.selectable_label(false, "Brightness").clicked() {
+                        pipeline.0.push(Operation::Brightness(0.0));
+                    }
+                    if ui.selectable_label(false, "Contrast").clicked() {
+                        pipeline.0.push(Operation::Contrast(0.0));
+                    }
+                    if ui.selectable_label(false, "HSL").clicked() {
+                        pipeline.0.push(Operation::Hsl(HslRange::ALL.map(HslBand::neutral).to_vec()));
+                    }
+                    if ui.selectable_label(false, "Sharpen").clicked() {
+                        pipeline.0.push(Operation::Sharpen {
+                            amount: 0.0,
+                            kernel: SharpenKernel::default(),
+                            linear_light: true,
+                            backend: Backend::Cpu,
+                            border: BorderMode::default(),
+                        });
+                    }
+                    if ui.selectable_label(false, "Resize").clicked() {
+                        pipeline.0.push(Operation::Resize(ResizeSettings {
+                            width: source_size.0,
+                            height: source_size.1,
+                            filter: ResampleFilter::Lanczos3,
+                        }));
+                    }
+                    if ui.selectable_label(false, "Grayscale").clicked() {
+                        pipeline.0.push(Operation::Grayscale);
+                    }
+                    if ui.selectable_label(false, "Document").clicked() {
+                        pipeline.0.push(Operation::Document(DocumentSettings::default()));
+                    }
+                    if ui.selectable_label(false, "Posterize").clicked() {
+                        pipeline.0.push(Operation::Posterize {
+                            levels: 4,
+                            dither: Dither::Ordered,
+                        });
+                    }
+                    if ui.add_enabled(loaded_lut.is_some(), egui::SelectableLabel::new(false, "LUT"))
+                        .on_disabled_hover_text("Load a LUT in the image adjustments first")
+                        .clicked()
+                    {
+                        if let Some(lut) = &loaded_lut {
+                            pipeline.0.push(Operation::Lut { lut: Arc::clone(lut), intensity: 1.0 });
+                        }
+                    }
+                });
+
+            if ui.button("Load from sliders").clicked() {
+                *pipeline = slider_pipeline.clone();
+            }
+        });
+    }
+}
+
+// One row of hue, saturation and lightness sliders per band
+fn hsl_sliders(ui: &mut egui::Ui, id_source: impl std::hash::Hash, bands: &mut [HslBand]) {
+    ui.spacing_mut().slider_width = 80.0;
+    egui::Grid::new(id_source).num_columns(4).show(ui, |ui| {
+        ui.label("");
+        ui.label("Hue");
+        ui.label("Saturation");
+        ui.label("Lightness");
+        ui.end_row();
+        for band in bands {
+            ui.label(format!("{:?}", band.range));
+            ui.add(egui::Slider::new(&mut band.hue, -MAX_HUE_SHIFT..=MAX_HUE_SHIFT).step_by(1.0).suffix("°"));
+            ui.add(egui::Slider::new(&mut band.saturation, -1.0..=1.0).step_by(0.01));
+            ui.add(egui::Slider::new(&mut band.lightness, -1.0..=1.0).step_by(0.01));
+            ui.end_row();
+        }
+    });
+}
+
+// Background flattening and threshold parameters of document mode
+fn document_controls(ui: &mut egui::Ui, document: &mut DocumentSettings) {
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut document.flatten, "Flatten background")
+            .on_hover_text("Divide by a heavily blurred copy to even out uneven lighting");
+        if document.flatten {
+            ui.add(egui::Slider::new(&mut document.flatten_radius, 5..=200).suffix(" px").text("radius"))
+                .on_hover_text("Should be well above the height of the writing");
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.radio_value(&mut document.method, ThresholdMethod::Sauvola, "Sauvola");
+        ui.radio_value(&mut document.method, ThresholdMethod::MeanC, "Mean-C");
+        ui.add(egui::Slider::new(&mut document.window, 3..=151).suffix(" px").text("window"));
+        match document.method {
+            ThresholdMethod::Sauvola => ui.add(egui::Slider::new(&mut document.k, 0.05..=0.8).step_by(0.01).text("k"))
+                .on_hover_text("Higher values turn more faint marks white"),
+            ThresholdMethod::MeanC => ui.add(egui::Slider::new(&mut document.c, 0.0..=0.3).step_by(0.005).text("C"))
+                .on_hover_text("How much darker than its surroundings a pixel has to be to turn black"),
+        };
+    });
+}
 
-                if self.sharpness > 0.0 {
-                    block_img = sharpen_image(&block_img, self.sharpness);
+// Point spread function, iterations and prior of the deconvolution
+fn deconvolution_controls(ui: &mut egui::Ui, id_source: impl std::hash::Hash, deconvolution: &mut DeconvolutionSettings) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(id_source)
+            .selected_text(deconvolution.shape.label())
+            .show_ui(ui, |ui| {
+                for shape in PsfShape::ALL {
+                    ui.selectable_value(&mut deconvolution.shape, shape, shape.label());
                 }
+            });
+        match deconvolution.shape {
+            PsfShape::Gaussian => {
+                ui.add(egui::Slider::new(&mut deconvolution.sigma, 0.3..=5.0).step_by(0.1).suffix(" px").text("sigma"))
+                    .on_hover_text("Width of the blur, too large a value draws halos around edges");
+            }
+            PsfShape::Motion => {
+                ui.add(egui::Slider::new(&mut deconvolution.length, 1.0..=50.0).step_by(0.5).suffix(" px").text("length"));
+                ui.add(egui::Slider::new(&mut deconvolution.angle, -90.0..=90.0).step_by(1.0).suffix("°").text("angle"))
+                    .on_hover_text("Direction of the shake, counterclockwise from horizontal");
+            }
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.add(egui::Slider::new(&mut deconvolution.iterations, 1..=100).text("iterations"))
+            .on_hover_text("More iterations recover more detail, and more noise");
+        ui.add(egui::Slider::new(&mut deconvolution.regularization, 0.0..=MAX_REGULARIZATION).step_by(0.001).text("regularization"))
+            .on_hover_text("Total variation prior against ringing next to edges, 0 turns it off");
+    });
+}
 
-                let rgb = block_img.to_rgb8();
-                ImageBlock {
-                    x: block.x,
-                    y: block.y,
-                    width: block.width,
-                    height: block.height,
-                    data: rgb.into_raw(),
-                    overlap: block.overlap,
+// Time taken by each stage of the last run, slowest marked
+fn timing_breakdown(ui: &mut egui::Ui, timings: &[(String, std::time::Duration)]) {
+    egui::CollapsingHeader::new(egui::RichText::new("Breakdown").size(14.0))
+        .id_source("timing_breakdown")
+        .show(ui, |ui| {
+            let slowest = timings.iter().map(|(_, duration)| *duration).max().unwrap_or_default();
+            egui::Grid::new("timing_breakdown_grid").num_columns(2).show(ui, |ui| {
+                for (stage, duration) in timings {
+                    let text = format!("{:.3} s", duration.as_secs_f64());
+                    ui.label(stage.as_str());
+                    if *duration == slowest {
+                        ui.label(egui::RichText::new(text).strong());
+                    } else {
+                        ui.label(text);
+                    }
+                    ui.end_row();
                 }
             });
-        } else {
-            current_img = denoise_image(
-                &current_img, 
-                denoise_type, 
-                kernel_size,
-                self.tv_lambda,
-                self.tv_iterations
-            );
-
-            if self.brightness != 0.0 {
-                current_img = adjust_brightness(&current_img, self.brightness);
+            if timings.iter().any(|(stage, _)| stage == SPLIT_STAGE) {
+                ui.label(egui::RichText::new("Block-wise run: operation times add up over blocks processed in parallel").weak());
             }
+        });
+}
 
-            if self.contrast != 1.0 {
-                current_img = adjust_contrast(&current_img, self.contrast);
+// How posterization hides its banding
+fn dither_combo(ui: &mut egui::Ui, id_source: impl std::hash::Hash, dither: &mut Dither) {
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(dither.label())
+        .show_ui(ui, |ui| {
+            for option in Dither::ALL {
+                ui.selectable_value(dither, option, option.label());
             }
+        });
+}
 
-            if self.sharpness > 0.0 {
-                current_img = sharpen_image(&current_img, self.sharpness);
+// What sharpening measures detail against
+fn sharpen_kernel_combo(ui: &mut egui::Ui, id_source: impl std::hash::Hash, kernel: &mut SharpenKernel) {
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(kernel.label())
+        .show_ui(ui, |ui| {
+            for option in SharpenKernel::ALL {
+                ui.selectable_value(kernel, option, option.label());
             }
+        });
+}
+
+// The denoisers to pick from, in low-memory mode without the ones that
+// need the whole image, see `DenoiseType::streamable`
+fn denoise_type_options(ui: &mut egui::Ui, selected: &mut DenoiseType, low_memory: bool) {
+    for option in DENOISE_TYPES {
+        if ui
+            .add_enabled(!low_memory || option.streamable(), egui::SelectableLabel::new(*selected == option, format!("{:?}", option)))
+            .on_disabled_hover_text("Needs the whole image, unavailable in low-memory mode")
+            .clicked()
+        {
+            *selected = option;
         }
+    }
+}
+
+// Choice between the 3×3 and 5×5 neighbourhood of the hot pixel repair
+fn hot_pixel_window(ui: &mut egui::Ui, id_source: impl std::hash::Hash, window: &mut usize) {
+    egui::ComboBox::from_id_source(id_source)
+        .selected_text(format!("{0}×{0}", window))
+        .show_ui(ui, |ui| {
+            for size in [3, 5] {
+                ui.selectable_value(window, size, format!("{0}×{0}", size));
+            }
+        });
+}
+
+// Title and size of a loaded LUT
+fn lut_name(lut: &Lut3d) -> String {
+    let title = lut.title.as_deref().unwrap_or("Untitled");
+    match lut.size() {
+        0 => format!("{} (1D)", title),
+        size => format!("{} ({}³)", title, size),
+    }
+}
+
+// Says so when `img` is shown through a downscaled copy
+fn proxy_note(ui: &mut egui::Ui, img: &DynamicImage) {
+    if let Some((width, height)) = viewer::display_proxy_size(ui.ctx(), img.width(), img.height()) {
+        ui.label(egui::RichText::new(format!(
+            "Preview downscaled to {}x{}, processing and export use the full {}x{}",
+            width, height, img.width(), img.height()
+        )).size(14.0).weak());
+    }
+}
+
+// The copy of `img` live previews are computed from
+fn preview_copy(img: &DynamicImage) -> DynamicImage {
+    if img.width().max(img.height()) > PREVIEW_MAX_SIDE {
+        img.resize(PREVIEW_MAX_SIDE, PREVIEW_MAX_SIDE, FilterType::Triangle)
+    } else {
+        img.clone()
+    }
+}
 
-        let duration = start_time.elapsed();
-        (current_img, duration)
+fn cached_texture<'a>(
+    ctx: &egui::Context,
+    slot: &'a mut Option<egui::TextureHandle>,
+    name: &str,
+    img: &DynamicImage,
+) -> &'a egui::TextureHandle {
+    slot.get_or_insert_with(|| {
+        // Images too large for a texture are shown through a downscaled copy
+        let proxy = viewer::display_proxy_size(ctx, img.width(), img.height())
+            .map(|(width, height)| img.thumbnail_exact(width, height));
+        let shown = proxy.as_ref().unwrap_or(img);
+        let rgba = shown.to_rgba8();
+        let color_image = egui::ColorImage::from_rgba_unmultiplied(
+            [shown.width() as usize, shown.height() as usize],
+            rgba.as_raw(),
+        );
+        ctx.load_texture(name, color_image, viewer::TEXTURE_OPTIONS)
+    })
+}
+
+// `img` as the panes show it, see `ChannelView`
+fn channel_plane(view: ChannelView, img: &DynamicImage) -> Cow<'_, DynamicImage> {
+    match view {
+        ChannelView::Rgb => Cow::Borrowed(img),
+        view => Cow::Owned(view.extract(img)),
+    }
+}
+
+// The texture of `img`, or of what `overlay` derives from it when given.
+// Overlays are rebuilt whenever `base` was dropped, which happens every
+// time the image changes, and have to be dropped by hand when the way
+// they are derived changes.
+fn displayed_texture<'a>(
+    ctx: &egui::Context,
+    base: &'a mut Option<egui::TextureHandle>,
+    overlay_slot: &'a mut Option<egui::TextureHandle>,
+    name: &str,
+    img: &DynamicImage,
+    overlay: Option<&dyn Fn(&DynamicImage) -> DynamicImage>,
+) -> &'a egui::TextureHandle {
+    if base.is_none() {
+        *overlay_slot = None;
+    }
+    let base = cached_texture(ctx, base, name, img);
+    let Some(overlay) = overlay else {
+        return base;
+    };
+    if overlay_slot.is_none() {
+        cached_texture(ctx, overlay_slot, &format!("{}_overlay", name), &overlay(img));
     }
+    overlay_slot.as_ref().unwrap()
 }
 
 impl eframe::App for MyApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        let saved = SavedState {
+            settings: self.settings.clone(),
+            live_preview: self.live_preview,
+            history_depth: self.history.max_depth(),
+            export_options: self.export_dialog.options,
+            watermark: self.export_dialog.watermark.clone(),
+            open_directory: self.open_directory.clone(),
+            export_directory: self.export_directory.clone(),
+            low_memory_megapixels: self.low_memory_megapixels,
+        };
+        eframe::set_value(storage, SavedState::KEY, &saved);
+        // Also called on exit
+        self.write_recovery();
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_job(ctx);
+        self.poll_frames_job(ctx);
+        self.poll_session_job(ctx);
+        self.poll_export(ctx);
+        self.poll_stream_job(ctx);
+        self.autosave(ctx);
+        if let Some(dialog) = &mut self.recovery_dialog {
+            match dialog.show(ctx) {
+                Some(RecoveryChoice::Restore { rerun }) => {
+                    let dialog = self.recovery_dialog.take().expect("shown above");
+                    self.restore_session(dialog.session, rerun);
+                }
+                Some(RecoveryChoice::Discard) => {
+                    self.recovery_dialog = None;
+                    self.autosave.discard();
+                }
+                None => {}
+            }
+        }
+        self.update_preview(ctx);
+        self.inspector.begin_frame();
+
+        self.handle_shortcuts(ctx);
+        // Holding Space shows the original in place of the result, to compare
+        let show_original = !ctx.wants_keyboard_input() && ctx.input(|i| i.key_down(egui::Key::Space));
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.add_space(25.0);
             ui.horizontal(|ui| {
@@ -170,37 +1812,407 @@ impl eframe::App for MyApp {
                     ui.heading(egui::RichText::new("Image Processing").size(30.0));
 
                     ui.horizontal(|ui| {
-                        if ui.add(egui::Button::new(egui::RichText::new("Select Image").size(16.0)).min_size(egui::vec2(120.0, 40.0))).clicked() {
-                            self.original_image = load_image();
-                            self.denoised_image = None;
-                            self.processing_time = None;
+                        if ui.add(egui::Button::new(egui::RichText::new("Select Image").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                            .on_hover_text("Ctrl+O")
+                            .clicked()
+                        {
+                            self.open_image();
+                        }
+                        if ui.add(egui::Button::new(egui::RichText::new("Open Several...").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                            .on_hover_text("Work on several images in one session, switching between them in a filmstrip")
+                            .clicked()
+                        {
+                            self.open_session();
+                        }
+                        if ui.add(egui::Button::new(egui::RichText::new("Paste").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                            .on_hover_text("Ctrl+V")
+                            .clicked()
+                        {
+                            self.paste_image();
+                        }
+                        if ui.add(egui::Button::new(egui::RichText::new("Stack Images...").size(16.0)).min_size(egui::vec2(120.0, 40.0))).clicked() {
+                            self.stack_dialog.open = true;
+                        }
+                        if ui.add(egui::Button::new(egui::RichText::new("Load Record...").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                            .on_hover_text("Settings of an exported image from the processing record written next to it, processing its source again")
+                            .clicked()
+                        {
+                            self.load_processing_record();
+                        }
+                        if ui.add_enabled(self.original_image.is_some(), egui::Button::new(egui::RichText::new("Compare Algorithms...").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                            .on_hover_text("Time every denoiser with the current parameters")
+                            .clicked()
+                        {
+                            self.benchmark_dialog.open = true;
                         }
 
-                        if self.denoised_image.is_some() {
+                        let session_results = self.session.as_ref().is_some_and(|session| {
+                            session.images.iter().any(|image| image.processed.is_some())
+                        });
+                        if self.denoised_image.is_some() || session_results || self.large_image.is_some() {
                             ui.add_space(300.0);
-                            if ui.add(egui::Button::new(egui::RichText::new("Export Image").size(16.0)).min_size(egui::vec2(120.0, 40.0))).clicked() {
-                                self.export_image();
+                            let exporting = self.export_job.is_some() || self.stream_job.is_some();
+                            let export_text = if exporting { "Exporting..." } else { "Export Image" };
+                            if ui.add_enabled(!exporting, egui::Button::new(egui::RichText::new(export_text).size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                                .on_hover_text("Ctrl+Shift+S, or Ctrl+S to export with the last options")
+                                .clicked()
+                            {
+                                self.export_dialog.open = true;
+                            }
+                            if ui.add_enabled(self.denoised_image.is_some(), egui::Button::new(egui::RichText::new("Copy Result").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                                .on_hover_text("Ctrl+C")
+                                .clicked()
+                            {
+                                self.copy_result();
                             }
                         }
                     });
 
+                    let (active_processed, idle) = (self.denoised_image.is_some(), self.job.is_none());
+                    let session_action = self.session.as_mut().and_then(|session| session.show(ui, active_processed, idle));
+                    match session_action {
+                        Some(SessionAction::Select(index)) => self.select_session_image(index),
+                        Some(SessionAction::ApplyToAll) => self.process_session(),
+                        Some(SessionAction::Close) => self.end_session(),
+                        None => {}
+                    }
+
+                    if let Some(message) = &self.status_message {
+                        ui.label(egui::RichText::new(message).size(14.0));
+                    }
+                    if let Some(err) = &self.last_error {
+                        ui.label(egui::RichText::new(err).size(14.0).color(egui::Color32::RED));
+                    }
+
+                    let mut crop_request = None;
+                    let mut rotate_request = None;
+                    let mut transform_request = None;
+                    let mut frame_request = None;
+                    let mut process_frames = false;
+                    let mut load_lut_request = false;
                     if let Some(original) = &self.original_image {
                         let original_width = original.width();
                         let original_height = original.height();
-                        let original_data = original.to_rgba8().to_vec();
+                        // Linked panes share one view, sized relative to the original
+                        let reference_size = egui::vec2(original_width as f32, original_height as f32);
+                        let viewport = egui::vec2(((ui.available_width() - 20.0) / 2.0).max(200.0), 450.0);
+                        // The preview is compared with the downscaled copy it was made from
+                        let residual_pair = match &self.preview_image {
+                            Some(preview) => self.preview_source.as_ref().map(|source| (source, preview)),
+                            None => self.denoised_image.as_ref().map(|denoised| (original, denoised)),
+                        };
+                        let residual_available = residual_pair.is_some_and(|(source, result)| {
+                            (source.width(), source.height()) == (result.width(), result.height())
+                        });
+
+                        ui.horizontal(|ui| {
+                            if ui.button("Fit").clicked() {
+                                self.viewer.fit();
+                                self.result_viewer.fit();
+                            }
+                            if ui.button("100%").clicked() {
+                                self.viewer.actual_size();
+                                self.result_viewer.actual_size();
+                            }
+                            let pinned = self.inspector.pinned();
+                            if ui.add_enabled(pinned.is_some(), egui::Button::new("Center on Pin"))
+                                .on_hover_text("Right-click an image to pin a point")
+                                .clicked()
+                            {
+                                if let Some(point) = pinned {
+                                    self.viewer.center_on(point);
+                                    self.result_viewer.center_on(point);
+                                }
+                            }
+                            if ui.checkbox(&mut self.link_views, "Link Views").changed() && !self.link_views {
+                                // Start out where the shared view was
+                                self.result_viewer = self.viewer.clone();
+                            }
+
+                            ui.separator();
+                            if ui.add_enabled(residual_available, egui::Checkbox::new(&mut self.show_residual, "Residual"))
+                                .on_hover_text("Show original - result amplified around mid-gray, to see what processing removed")
+                                .on_disabled_hover_text("Needs a result the size of the original")
+                                .changed()
+                            {
+                                self.result_overlay_texture = None;
+                            }
+                            if self.show_residual && residual_available {
+                                if ui.add(egui::Slider::new(&mut self.residual_gain, 1.0..=20.0).suffix("×")).changed() {
+                                    self.result_overlay_texture = None;
+                                }
+                                if ui.checkbox(&mut self.residual_luma_only, "Luminance only").changed() {
+                                    self.result_overlay_texture = None;
+                                }
+                            }
+                            ui.separator();
+
+                            ui.label("Channel:");
+                            egui::ComboBox::from_id_source("channel_view")
+                                .selected_text(self.channel_view.label())
+                                .show_ui(ui, |ui| {
+                                    for view in ChannelView::ALL {
+                                        if ui.selectable_value(&mut self.channel_view, view, view.label()).changed() {
+                                            self.original_overlay_texture = None;
+                                            self.result_overlay_texture = None;
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text("Show a single channel in gray, to see which one carries the noise. Processing and export keep every channel");
+                            ui.separator();
+
+                            let mut edges_changed = false;
+                            ui.label("Edges:");
+                            egui::ComboBox::from_id_source("edge_display")
+                                .selected_text(format!("{:?}", self.edge_display))
+                                .show_ui(ui, |ui| {
+                                    for display in EdgeDisplay::ALL {
+                                        edges_changed |= ui.selectable_value(&mut self.edge_display, display, format!("{:?}", display)).changed();
+                                    }
+                                });
+                            if self.edge_display == EdgeDisplay::Overlay {
+                                edges_changed |= ui.add(egui::Slider::new(&mut self.edge_threshold, 1..=255).text("threshold"))
+                                    .on_hover_text("Gradient strength above which a pixel is tinted, 255 is a black to white step")
+                                    .changed();
+                            }
+                            edges_changed |= ui.checkbox(&mut self.sharpness_overlay, "Sharpness map")
+                                .on_hover_text("Tint regions from blue where they are soft to red where they are sharpest, to find what is in focus")
+                                .changed();
+                            if edges_changed {
+                                self.original_overlay_texture = None;
+                                self.result_overlay_texture = None;
+                            }
+                            ui.separator();
+
+                            let zoom = self.viewer.zoom(viewport, reference_size);
+                            ui.label(egui::RichText::new(format!("Zoom: {:.0}%", zoom * 100.0)).size(14.0));
+                        });
+
+                        if let Some(animation) = &self.animation {
+                            ui.horizontal(|ui| {
+                                let count = animation.frame_count();
+                                let mut frame = animation.frame + 1;
+                                ui.label(egui::RichText::new("Frame:").size(16.0));
+                                if ui.add(egui::Slider::new(&mut frame, 1..=count)).changed() {
+                                    frame_request = Some(frame - 1);
+                                }
+                                let delay = animation.source.delays[animation.frame];
+                                ui.label(egui::RichText::new(format!("of {}, shown for {} ms", count, delay.as_millis())).size(14.0));
+                                if ui.add_enabled(!self.is_processing(), egui::Button::new(egui::RichText::new("Process All Frames").size(16.0)))
+                                    .on_hover_text("Run the current settings on every frame, needed to export the animation")
+                                    .clicked()
+                                {
+                                    process_frames = true;
+                                }
+                                if animation.processed.is_some() {
+                                    ui.label(egui::RichText::new("All frames processed").size(14.0).weak());
+                                }
+                            });
+                        }
+
+                        let channel_view = self.channel_view;
+                        let (edge_display, edge_threshold, sharpness_overlay) = (self.edge_display, self.edge_threshold, self.sharpness_overlay);
+                        let render_overlays = |img: &DynamicImage| {
+                            let plane = channel_plane(channel_view, img);
+                            let shown = edge_display.render(&plane, edge_threshold);
+                            if sharpness_overlay {
+                                heat_overlay(&shown, &sharpness_map(&plane, SHARPNESS_TILE))
+                            } else {
+                                shown
+                            }
+                        };
+                        let overlays: Option<&dyn Fn(&DynamicImage) -> DynamicImage> =
+                            (channel_view != ChannelView::Rgb || edge_display != EdgeDisplay::Off || sharpness_overlay).then_some(&render_overlays);
 
                         ui.horizontal(|ui| {
                             // Left side - Original image
                             ui.vertical(|ui| {
                                 ui.label(egui::RichText::new("Original Image:").size(18.0));
-                                let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                                    [original_width as usize, original_height as usize],
-                                    &original_data,
+                                let texture_handle = displayed_texture(
+                                    ctx,
+                                    &mut self.original_texture,
+                                    &mut self.original_overlay_texture,
+                                    "original",
+                                    original,
+                                    overlays,
+                                );
+                                let texture_id = texture_handle.id();
+                                let (response, mapping) = self.viewer.show(
+                                    ui,
+                                    texture_handle,
+                                    reference_size,
+                                    reference_size,
+                                    viewport,
+                                    self.tool == ImageTool::None,
                                 );
-                                let texture_handle = ctx.load_texture("original", color_image, Default::default());
-                                let scale = 400.0 / original_height as f32;
-                                let size = egui::vec2(original_width as f32 * scale, 400.0);
-                                ui.image((texture_handle.id(), size));
+
+                                // Selections are kept in original pixel coordinates, independent of the zoom.
+                                // Middle-button drags pan the view instead of editing them.
+                                if !ui.input(|i| i.pointer.middle_down()) {
+                                    match self.tool {
+                                        ImageTool::Select => self.selection.interact(&response, &mapping),
+                                        ImageTool::Crop => self.crop.interact(&response, &mapping),
+                                        ImageTool::Mask => {
+                                            if self.mask_painter.interact(&response, &mapping, (original_width, original_height)) {
+                                                // Refresh the preview once the stroke pauses
+                                                self.preview_requested_at = Some(Instant::now());
+                                            }
+                                        }
+                                        ImageTool::Straighten => {
+                                            self.straighten.interact(&response);
+                                        }
+                                        ImageTool::None => {}
+                                    }
+                                }
+                                self.inspector.interact(&response, &mapping);
+                                let painter = ui.painter_at(response.rect);
+                                self.selection.paint(&painter, &mapping);
+                                match self.tool {
+                                    ImageTool::Crop => self.crop.paint(&painter, &mapping),
+                                    ImageTool::Mask => self.mask_painter.paint(ctx, &painter, &mapping, response.hover_pos()),
+                                    ImageTool::Straighten => {
+                                        self.straighten.paint(&painter, &mapping, texture_id, ui.visuals().extreme_bg_color);
+                                    }
+                                    ImageTool::None | ImageTool::Select => {}
+                                }
+                                self.inspector.paint(&painter, &mapping);
+                                proxy_note(ui, original);
+
+                                ui.horizontal(|ui| {
+                                    if ui.button("Rotate Left").clicked() {
+                                        transform_request = Some(rotate_left as fn(&DynamicImage) -> DynamicImage);
+                                    }
+                                    if ui.button("Rotate Right").clicked() {
+                                        transform_request = Some(rotate_right);
+                                    }
+                                    if ui.button("Rotate 180°").clicked() {
+                                        transform_request = Some(rotate_180);
+                                    }
+                                    if ui.button("Flip Horizontal").clicked() {
+                                        transform_request = Some(flip_horizontal);
+                                    }
+                                    if ui.button("Flip Vertical").clicked() {
+                                        transform_request = Some(flip_vertical);
+                                    }
+                                    if ui.button("Resize...").clicked() {
+                                        self.resize_dialog.open((original_width, original_height), &self.settings);
+                                    }
+                                });
+
+                                if let Some(resize) = self.settings.resize {
+                                    ui.horizontal(|ui| {
+                                        ui.label(egui::RichText::new(format!(
+                                            "Resize to {}x{} ({:?})",
+                                            resize.width, resize.height, resize.filter
+                                        )).size(14.0));
+                                        if ui.small_button("Remove").clicked() {
+                                            self.settings.resize = None;
+                                        }
+                                    });
+                                }
+
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("Tool:").size(16.0));
+                                    ui.selectable_value(&mut self.tool, ImageTool::None, "None");
+                                    ui.selectable_value(&mut self.tool, ImageTool::Select, "Select Region");
+                                    ui.selectable_value(&mut self.tool, ImageTool::Crop, "Crop");
+                                    ui.selectable_value(&mut self.tool, ImageTool::Mask, "Paint Mask")
+                                        .on_hover_text("Paint where processing takes effect, unpainted areas keep the original");
+                                    ui.selectable_value(&mut self.tool, ImageTool::Straighten, "Straighten")
+                                        .on_hover_text("Rotate by an arbitrary angle, drag a line along something that should be level to set it");
+                                });
+
+                                let mut clear_mask = false;
+
+                                match self.tool {
+                                    ImageTool::Select | ImageTool::None => {
+                                        if let Some(region) = self.selection.region() {
+                                            ui.horizontal(|ui| {
+                                                ui.label(egui::RichText::new(format!(
+                                                    "Selection: {}x{} at ({}, {})",
+                                                    region.width, region.height, region.x, region.y
+                                                )).size(14.0));
+                                                if ui.button("Clear").clicked() {
+                                                    self.selection.clear();
+                                                }
+                                            });
+                                        }
+                                        if self.mask_painter.mask().is_some() {
+                                            ui.horizontal(|ui| {
+                                                ui.label(egui::RichText::new("Processing is limited to the painted mask").size(14.0));
+                                                clear_mask = ui.button("Clear Mask").clicked();
+                                            });
+                                        }
+                                    }
+                                    ImageTool::Mask => {
+                                        ui.horizontal(|ui| {
+                                            ui.selectable_value(&mut self.mask_painter.erase, false, "Paint");
+                                            ui.selectable_value(&mut self.mask_painter.erase, true, "Erase");
+                                            ui.add(egui::Slider::new(&mut self.mask_painter.brush_size, 2.0..=500.0).logarithmic(true).text("size"));
+                                            ui.add(egui::Slider::new(&mut self.mask_painter.hardness, 0.0..=1.0).text("hardness"))
+                                                .on_hover_text("Share of the brush painted at full strength, the rest fades out");
+                                            clear_mask = ui.add_enabled(self.mask_painter.mask().is_some(), egui::Button::new("Clear Mask")).clicked();
+                                        });
+                                    }
+                                    ImageTool::Straighten => {
+                                        let settings = &mut self.straighten.settings;
+                                        ui.horizontal(|ui| {
+                                            ui.add(egui::Slider::new(&mut settings.angle, -MAX_ROTATE_ANGLE..=MAX_ROTATE_ANGLE)
+                                                .step_by(0.05)
+                                                .suffix("°")
+                                                .text("angle"))
+                                                .on_hover_text("Positive turns clockwise");
+                                            egui::ComboBox::from_id_source("rotate_interpolation")
+                                                .selected_text(format!("{:?}", settings.interpolation))
+                                                .show_ui(ui, |ui| {
+                                                    for interpolation in RotateInterpolation::ALL {
+                                                        ui.selectable_value(&mut settings.interpolation, interpolation, format!("{:?}", interpolation));
+                                                    }
+                                                });
+                                        });
+                                        ui.horizontal(|ui| {
+                                            ui.checkbox(&mut settings.auto_crop, "Auto Crop")
+                                                .on_hover_text("Crop to the largest rectangle inside the rotated image instead of growing the canvas");
+                                            if !settings.auto_crop {
+                                                ui.label("Fill:");
+                                                ui.color_edit_button_srgba_unmultiplied(&mut settings.fill);
+                                            }
+                                            if ui.button("Reset").clicked() {
+                                                settings.angle = 0.0;
+                                            }
+                                            if ui.add_enabled(settings.angle != 0.0, egui::Button::new("Apply Rotation")).clicked() {
+                                                rotate_request = Some(*settings);
+                                            }
+                                        });
+                                    }
+                                    ImageTool::Crop => {
+                                        ui.horizontal(|ui| {
+                                            let mut aspect_ratio = self.crop.aspect_ratio();
+                                            egui::ComboBox::from_id_source("crop_aspect")
+                                                .selected_text(aspect_ratio.label())
+                                                .show_ui(ui, |ui| {
+                                                    for option in AspectRatio::ALL {
+                                                        ui.selectable_value(&mut aspect_ratio, option, option.label());
+                                                    }
+                                                });
+                                            if aspect_ratio != self.crop.aspect_ratio() {
+                                                self.crop.set_aspect_ratio(aspect_ratio);
+                                            }
+
+                                            if let Some(region) = self.crop.region() {
+                                                ui.label(egui::RichText::new(format!("{}x{}", region.width, region.height)).size(14.0));
+                                                if ui.button("Apply Crop").clicked() {
+                                                    crop_request = Some(region);
+                                                }
+                                            }
+                                        });
+                                    }
+                                }
+                                if clear_mask {
+                                    self.mask_painter.clear();
+                                    self.preview_requested_at = Some(Instant::now());
+                                }
                             });
 
                             // Add spacing between images
@@ -208,70 +2220,286 @@ impl eframe::App for MyApp {
 
                             // Right side - Denoised image
                             ui.vertical(|ui| {
-                                ui.label(egui::RichText::new("Denoised Image:").size(18.0));
-
-                                if let Some(denoised) = &self.denoised_image {
-                                    let denoised_width = denoised.width();
-                                    let denoised_height = denoised.height();
-                                    let denoised_data = denoised.to_rgba8().to_vec();
-                                    
-                                    let color_image = egui::ColorImage::from_rgba_unmultiplied(
-                                        [denoised_width as usize, denoised_height as usize],
-                                        &denoised_data,
-                                    );
-                                    let texture_handle = ctx.load_texture("denoised", color_image, Default::default());
-                                    let scale = 400.0 / denoised_height as f32;
-                                    let size = egui::vec2(denoised_width as f32 * scale, 400.0);
-                                    ui.image((texture_handle.id(), size));
+                                let (denoised, is_preview) = match &self.preview_image {
+                                    Some(preview) => (Some(preview), true),
+                                    None => (self.denoised_image.as_ref(), false),
+                                };
+
+                                let show_original = show_original && denoised.is_some();
+                                let show_residual = self.show_residual && residual_available && !show_original;
+                                if show_original {
+                                    ui.label(egui::RichText::new("Original Image (release Space for the result):").size(18.0));
+                                } else if show_residual {
+                                    ui.label(egui::RichText::new(format!("Residual (original - result, {:.0}×):", self.residual_gain)).size(18.0));
+                                } else if is_preview {
+                                    ui.label(egui::RichText::new("Denoised Image (preview):").size(18.0));
+                                } else {
+                                    ui.label(egui::RichText::new("Denoised Image:").size(18.0))
+                                        .on_hover_text("Hold Space to show the original");
+                                }
+
+                                if let Some(denoised) = denoised {
+                                    let (shown, texture_handle) = if show_original {
+                                        let texture = displayed_texture(
+                                            ctx,
+                                            &mut self.original_texture,
+                                            &mut self.original_overlay_texture,
+                                            "original",
+                                            original,
+                                            overlays,
+                                        );
+                                        (original, texture)
+                                    } else {
+                                        let (gain, luma_only) = (self.residual_gain, self.residual_luma_only);
+                                        let residual = residual_pair.filter(|_| show_residual).map(|(source, _)| {
+                                            move |img: &DynamicImage| {
+                                                residual_image(&channel_plane(channel_view, source), &channel_plane(channel_view, img), gain, luma_only)
+                                                    .expect("residual_available checked the dimensions")
+                                            }
+                                        });
+                                        let overlay: Option<&dyn Fn(&DynamicImage) -> DynamicImage> = match &residual {
+                                            Some(residual) => Some(residual),
+                                            None => overlays,
+                                        };
+                                        let texture = displayed_texture(
+                                            ctx,
+                                            &mut self.result_texture,
+                                            &mut self.result_overlay_texture,
+                                            "denoised",
+                                            denoised,
+                                            overlay,
+                                        );
+                                        (denoised, texture)
+                                    };
+                                    let texture_id = texture_handle.id();
+                                    let image_size = egui::vec2(shown.width() as f32, shown.height() as f32);
+                                    // An independent view measures zoom in the result's own pixels,
+                                    // the downscaled preview stands in for the original
+                                    let (viewer, reference_size) = if self.link_views {
+                                        (&mut self.viewer, reference_size)
+                                    } else if is_preview && !show_original {
+                                        (&mut self.result_viewer, reference_size)
+                                    } else {
+                                        (&mut self.result_viewer, image_size)
+                                    };
+                                    let (response, mapping) = viewer.show(ui, texture_handle, image_size, reference_size, viewport, true);
+                                    self.inspector.interact(&response, &mapping);
+                                    self.inspector.paint(&ui.painter_at(response.rect), &mapping);
+                                    proxy_note(ui, shown);
 
-                                    if let Some(duration) = self.processing_time {
+                                    if is_preview {
+                                        ui.label(egui::RichText::new("Expensive denoisers are skipped, press Apply for the full result").size(14.0).weak());
+                                    } else if let Some(duration) = self.processing_time {
+                                        let depth = if is_high_depth(denoised) { ", 16-bit" } else { "" };
+                                        ui.label(egui::RichText::new(format!("Size: {}x{}{}", denoised.width(), denoised.height(), depth)).size(16.0));
                                         ui.label(egui::RichText::new(format!("Processing Time: {:.3} seconds", duration.as_secs_f64())).size(16.0));
+                                        if self.processing_resumed {
+                                            ui.label(egui::RichText::new("Only the adjustments ran, the denoised image was reused").size(14.0).weak());
+                                        }
+                                        if !self.stage_timings.is_empty() {
+                                            timing_breakdown(ui, &self.stage_timings);
+                                        }
+                                        if let Some(count) = self.repaired_pixels {
+                                            ui.label(egui::RichText::new(format!("Hot Pixels Repaired: {}", count)).size(16.0));
+                                        }
                                     }
+
+                                    // The run counted its result already, anything else is counted when shown
+                                    let known = self
+                                        .result_histograms
+                                        .as_ref()
+                                        .filter(|_| !show_original && !show_residual && !is_preview && channel_view == ChannelView::Rgb);
+                                    let (gain, luma_only) = (self.residual_gain, self.residual_luma_only);
+                                    self.histogram_panel.show(ui, texture_id, known, channel_view, || match residual_pair {
+                                        _ if show_original => channel_plane(channel_view, original),
+                                        Some((source, result)) if show_residual => Cow::Owned(
+                                            residual_image(&channel_plane(channel_view, source), &channel_plane(channel_view, result), gain, luma_only)
+                                                .expect("residual_available checked the dimensions"),
+                                        ),
+                                        _ => channel_plane(channel_view, denoised),
+                                    });
                                 }
                             });
                         });
 
+                        // Pixel inspector readout
+                        let processed = self.preview_image.as_ref().or(self.denoised_image.as_ref());
+                        match self.inspector.hovered().and_then(|point| inspector::readout(original, processed, point)) {
+                            Some(text) => ui.label(egui::RichText::new(text).monospace()),
+                            None => ui.label(egui::RichText::new("Hover an image to inspect pixels, right-click to pin a point").weak()),
+                        };
+                        if let Some(text) = self.inspector.pinned().and_then(|point| inspector::readout(original, processed, point)) {
+                            ui.horizontal(|ui| {
+                                ui.label(egui::RichText::new(format!("Pinned {}", text)).monospace());
+                                if ui.small_button("Unpin").clicked() {
+                                    self.inspector.unpin();
+                                }
+                            });
+                        }
+
                         // Image adjustments section
                         ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new("Mode:").size(16.0));
+                            ui.radio_value(&mut self.settings.document_mode, false, egui::RichText::new("Photo").size(16.0));
+                            ui.radio_value(&mut self.settings.document_mode, true, egui::RichText::new("Document").size(16.0))
+                                .on_hover_text("Turn scanned pages into black writing on white");
+                        });
+                        if self.settings.document_mode {
+                            document_controls(ui, &mut self.settings.document);
+                            ui.label(egui::RichText::new("The photographic adjustments below are not used in document mode").weak());
+                        }
                         ui.horizontal(|ui| {
                             // Denoising parameters
                             ui.vertical(|ui| {
                                 ui.label(egui::RichText::new("Denoising Parameters:").size(16.0));
+                                ui.horizontal(|ui| {
+                                    ui.checkbox(&mut self.settings.hot_pixels, egui::RichText::new("Repair Hot Pixels").size(16.0))
+                                        .on_hover_text("Replace isolated pixels that stand out from all their neighbours with the neighbourhood median, before denoising");
+                                    if self.settings.hot_pixels {
+                                        hot_pixel_window(ui, "hot_pixel_window", &mut self.settings.hot_pixel_window);
+                                        ui.add(egui::Slider::new(&mut self.settings.hot_pixel_threshold, 0.05..=1.0).step_by(0.01).text("threshold"))
+                                            .on_hover_text("How far, as a share of the full range, a pixel has to be from the median of its neighbours");
+                                    }
+                                });
                                 ui.horizontal(|ui| {
                                     ui.label(egui::RichText::new("Denoise type:").size(16.0));
                                     egui::ComboBox::from_id_source("denoise_type")
-                                        .selected_text(format!("{:?}", self.denoise_type))
-                                        .show_ui(ui, |ui| {
-                                            for denoise_type in [
-                                                DenoiseType::MeanFilter,
-                                                DenoiseType::GaussianFilter,
-                                                DenoiseType::MedianFilter,
-                                                DenoiseType::BilateralFilter,
-                                                DenoiseType::NonLocalMeans,
-                                                DenoiseType::TotalVariation,
-                                            ] {
-                                                ui.selectable_value(&mut self.denoise_type, denoise_type, format!("{:?}", denoise_type));
-                                            }
-                                        });
+                                        .selected_text(format!("{:?}", self.settings.denoise_type))
+                                        .show_ui(ui, |ui| denoise_type_options(ui, &mut self.settings.denoise_type, self.large_image.is_some()));
                                 });
 
-                                if self.denoise_type != DenoiseType::NonLocalMeans {
+                                if self.settings.denoise_type == DenoiseType::ChambolleTV {
+                                    ui.horizontal(|ui| {
+                                        ui.label(egui::RichText::new("Lambda:").size(16.0));
+                                        ui.add(egui::Slider::new(&mut self.settings.tv_lambda, 0.01..=0.5).logarithmic(true))
+                                            .on_hover_text("Smoothing strength, roughly the noise level removed");
+                                        ui.label(egui::RichText::new("Tolerance:").size(16.0));
+                                        ui.add(egui::Slider::new(&mut self.settings.tv_tolerance, 1e-5..=1e-2).logarithmic(true))
+                                            .on_hover_text("Stop once an iteration changes the image by less than this fraction");
+                                    });
+                                    ui.horizontal(|ui| {
+                                        ui.label(egui::RichText::new("Max iterations:").size(16.0));
+                                        ui.add(egui::Slider::new(&mut self.settings.tv_iterations, 10..=500));
+                                    });
+                                } else if !matches!(self.settings.denoise_type, DenoiseType::NonLocalMeans | DenoiseType::BlockMatching | DenoiseType::TotalVariation) {
+                                    ui.horizontal(|ui| {
+                                        if self.settings.denoise_type == DenoiseType::AdaptiveMedian {
+                                            ui.label(egui::RichText::new("Max window:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.kernel_size, 3..=15).text("size"));
+                                        } else {
+                                            ui.label(egui::RichText::new("Kernel size:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.kernel_size, 3..=9).text("size"));
+                                        }
+                                    });
+                                    if self.settings.separate_planes {
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Color kernel size:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.chroma_kernel_size, 3..=15).text("size"))
+                                                .on_hover_text("Color noise is coarser than luminance noise, the luminance keeps the kernel size above");
+                                        });
+                                    }
+                                }
+
+                                ui.checkbox(&mut self.settings.separate_planes, egui::RichText::new("Separate Luminance and Color NR").size(16.0))
+                                    .on_hover_text("Filter in YCbCr, strong color noise reduction removes color blotches while mild luminance noise reduction keeps the detail");
+                                if self.settings.separate_planes {
+                                    ui.horizontal(|ui| {
+                                        ui.label(egui::RichText::new("Luminance NR:").size(16.0));
+                                        ui.add(egui::Slider::new(&mut self.settings.luma_nr, 0.0..=1.0).step_by(0.01))
+                                            .on_hover_text("0 leaves the luminance untouched");
+                                    });
                                     ui.horizontal(|ui| {
-                                        ui.label(egui::RichText::new("Kernel size:").size(16.0));
-                                        ui.add(egui::Slider::new(&mut self.kernel_size, 3..=9).text("size"));
+                                        ui.label(egui::RichText::new("Color NR:").size(16.0));
+                                        ui.add(egui::Slider::new(&mut self.settings.color_nr, 0.0..=1.0).step_by(0.01))
+                                            .on_hover_text("0 leaves the color untouched");
                                     });
                                 }
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("Detail:").size(16.0));
+                                    ui.add(egui::Slider::new(&mut self.settings.detail, 0.0..=1.0).step_by(0.01))
+                                        .on_hover_text("Puts the original's fine texture back over the denoised base, 1 restores all of it including the grain");
+                                    if self.settings.detail > 0.0 {
+                                        ui.label(egui::RichText::new("Radius:").size(16.0));
+                                        ui.add(egui::Slider::new(&mut self.settings.detail_radius, 1.0..=20.0).step_by(0.5).suffix(" px"))
+                                            .on_hover_text("Structures smaller than this count as detail");
+                                    }
+                                });
+                                ui.checkbox(&mut self.settings.deconvolve, egui::RichText::new("Deblur (Deconvolution)").size(16.0))
+                                    .on_hover_text("Undo a mild defocus or motion blur with Richardson-Lucy deconvolution, after denoising");
+                                if self.settings.deconvolve {
+                                    ui.colored_label(
+                                        ui.visuals().warn_fg_color,
+                                        "Deconvolution amplifies noise with every iteration, denoise first and keep the iterations low",
+                                    );
+                                    deconvolution_controls(ui, "psf_shape", &mut self.settings.deconvolution);
+                                }
+                                ui.horizontal(|ui| {
+                                    ui.label(egui::RichText::new("Periodic Noise:").size(16.0));
+                                    let notches = match self.settings.notches.len() {
+                                        0 => "off".to_string(),
+                                        count => format!("{} notches", count),
+                                    };
+                                    ui.label(egui::RichText::new(notches).size(16.0));
+                                    if ui.add_enabled(self.original_image.is_some(), egui::Button::new("Spectrum..."))
+                                        .on_hover_text("Remove halftone screens and moiré by notching their spikes in the frequency spectrum")
+                                        .clicked()
+                                    {
+                                        self.spectrum_dialog.open = true;
+                                    }
+                                });
+                                ui.checkbox(&mut self.settings.linear_light, egui::RichText::new("Filter in Linear Light").size(16.0))
+                                    .on_hover_text("Blur and sharpen light rather than gamma encoded values, keeps high contrast edges from darkening");
+                                ui.add_enabled_ui(Backend::GPU_BUILT, |ui| {
+                                    let mut use_gpu = self.settings.backend == Backend::Gpu;
+                                    if ui.checkbox(&mut use_gpu, egui::RichText::new("Use GPU").size(16.0))
+                                        .on_hover_text("Runs mean, gaussian, bilateral and sharpening as compute shaders, falls back to the CPU without a usable GPU")
+                                        .on_disabled_hover_text("Built without the gpu feature")
+                                        .changed()
+                                    {
+                                        self.settings.backend = if use_gpu { Backend::Gpu } else { Backend::Cpu };
+                                    }
+                                });
+                                egui::CollapsingHeader::new(egui::RichText::new("Advanced").size(16.0))
+                                    .id_source("advanced")
+                                    .show(ui, |ui| {
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Image borders:").size(16.0));
+                                            egui::ComboBox::from_id_source("border_mode")
+                                                .selected_text(self.settings.border.label())
+                                                .show_ui(ui, |ui| {
+                                                    for border in BorderMode::ALL {
+                                                        ui.selectable_value(&mut self.settings.border, border, border.label());
+                                                    }
+                                                })
+                                                .response
+                                                .on_hover_text("What denoising and sharpening read past the edges: the edge pixel, the mirrored image, the opposite edge, or nothing");
+                                        });
+                                    });
 
                                 // Parallel processing options
                                 ui.vertical(|ui| {
-                                    ui.checkbox(&mut self.use_parallel, egui::RichText::new("Use Parallel Processing").size(16.0));
-                                    if self.use_parallel {
+                                    ui.checkbox(&mut self.settings.use_parallel, egui::RichText::new("Use Parallel Processing").size(16.0));
+                                    if self.settings.use_parallel {
                                         ui.horizontal(|ui| {
                                             ui.add_space(20.0);
                                             ui.label(egui::RichText::new("Block Size:").size(16.0));
-                                            ui.add(egui::Slider::new(&mut self.block_size, 32..=256).step_by(32.0).text("pixels"));
+                                            ui.add(egui::Slider::new(&mut self.settings.block_size, 32..=256).step_by(32.0).text("pixels"));
+                                        });
+                                        ui.horizontal(|ui| {
+                                            ui.add_space(20.0);
+                                            ui.label(egui::RichText::new("Threads:").size(16.0));
+                                            let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+                                            ui.add(egui::Slider::new(&mut self.settings.threads, 0..=cores)
+                                                .custom_formatter(|threads, _| if threads == 0.0 { "auto".to_string() } else { format!("{}", threads) }))
+                                                .on_hover_text("Leave cores free for other programs, auto uses all of them");
                                         });
                                     }
+                                    ui.horizontal(|ui| {
+                                        ui.label(egui::RichText::new("Low-memory mode above:").size(16.0));
+                                        ui.add(egui::DragValue::new(&mut self.low_memory_megapixels).clamp_range(10..=10_000).suffix(" MP"))
+                                            .on_hover_text("Opening a larger image offers to work on a downscaled copy and stream the full image band by band on export");
+                                    });
                                 });
                             });
 
@@ -281,40 +2509,249 @@ impl eframe::App for MyApp {
                                     ui.add_space(150.0);
                                     ui.vertical(|ui| {
                                         ui.label(egui::RichText::new("Image Adjustments:").size(16.0));
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Temperature:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.white_balance.temperature, -MAX_SHIFT..=MAX_SHIFT).step_by(0.01).suffix(" stops"))
+                                                .on_hover_text("Positive warms the image, negative cools it");
+                                        });
+
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Tint:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.white_balance.tint, -MAX_SHIFT..=MAX_SHIFT).step_by(0.01).suffix(" stops"))
+                                                .on_hover_text("Positive removes a green cast, negative a magenta one");
+                                        });
+
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Exposure:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.exposure, -3.0..=3.0).step_by(0.1).suffix(" EV"));
+                                        });
+
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Shadows:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.shadows, 0.0..=1.0).step_by(0.01));
+                                        });
+
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Highlights:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.highlights, 0.0..=1.0).step_by(0.01));
+                                        });
+
+                                        if self.settings.shadows != 0.0 || self.settings.highlights != 0.0 {
+                                            ui.horizontal(|ui| {
+                                                ui.label(egui::RichText::new("Mask Radius:").size(16.0));
+                                                ui.add(egui::Slider::new(&mut self.settings.tone_mask_radius, 5.0..=100.0).step_by(1.0).suffix(" px"))
+                                                    .on_hover_text("Larger radii keep more local contrast, smaller ones reduce halos at strong edges");
+                                            });
+                                        }
+
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("Dehaze:").size(16.0));
+                                            ui.add(egui::Slider::new(&mut self.settings.dehaze, 0.0..=1.0).step_by(0.01));
+                                        });
+
                                         ui.horizontal(|ui| {
                                             ui.label(egui::RichText::new("Brightness:").size(16.0));
-                                            ui.add(egui::Slider::new(&mut self.brightness, -1.0..=1.0).step_by(0.01));
+                                            ui.add(egui::Slider::new(&mut self.settings.brightness, -1.0..=1.0).step_by(0.01));
                                         });
                                         
                                         ui.horizontal(|ui| {
                                             ui.label(egui::RichText::new("Contrast:").size(16.0));
-                                            ui.add(egui::Slider::new(&mut self.contrast, -1.0..=1.0).step_by(0.01));
+                                            ui.add(egui::Slider::new(&mut self.settings.contrast, -1.0..=1.0).step_by(0.01));
                                         });
                                         
                                         ui.horizontal(|ui| {
                                             ui.label(egui::RichText::new("Sharpness:").size(16.0));
-                                            ui.add(egui::Slider::new(&mut self.sharpness, -1.0..=1.0).step_by(0.01));
+                                            ui.add(egui::Slider::new(&mut self.settings.sharpness, 0.0..=3.0).step_by(0.01))
+                                                .on_hover_text("How many times the fine detail is added back, 1 doubles its contrast");
+                                            sharpen_kernel_combo(ui, "sharpen_kernel", &mut self.settings.sharpen_kernel);
+                                        });
+
+                                        ui.collapsing(egui::RichText::new("HSL").size(16.0), |ui| {
+                                            hsl_sliders(ui, "hsl", &mut self.settings.hsl);
+                                            if ui.button("Reset HSL").clicked() {
+                                                self.settings.hsl = HslRange::ALL.map(HslBand::neutral).to_vec();
+                                            }
+                                        });
+
+                                        ui.horizontal(|ui| {
+                                            ui.label(egui::RichText::new("LUT:").size(16.0));
+                                            if ui.button("Load LUT...").on_hover_text("Apply a .cube color grade as the last step").clicked() {
+                                                load_lut_request = true;
+                                            }
+                                            if let Some(lut) = &self.settings.lut {
+                                                ui.label(egui::RichText::new(lut_name(lut)).size(14.0));
+                                                ui.add(egui::Slider::new(&mut self.settings.lut_intensity, 0.0..=1.0).step_by(0.01).text("intensity"));
+                                                if ui.small_button("Remove").clicked() {
+                                                    self.settings.lut = None;
+                                                }
+                                            }
+                                        });
+
+                                        ui.horizontal(|ui| {
+                                            ui.checkbox(&mut self.settings.posterize, egui::RichText::new("Posterize").size(16.0))
+                                                .on_hover_text("Reduce every channel to a few levels as the very last step");
+                                            if self.settings.posterize {
+                                                ui.add(egui::Slider::new(&mut self.settings.posterize_levels, MIN_LEVELS..=MAX_LEVELS).text("levels"));
+                                                dither_combo(ui, "posterize_dither", &mut self.settings.posterize_dither);
+                                            }
                                         });
+
+                                        ui.checkbox(&mut self.settings.force_grayscale, egui::RichText::new("Force Grayscale").size(16.0));
+                                        ui.checkbox(&mut self.live_preview, egui::RichText::new("Live Preview").size(16.0));
                                     });
                                 });
                             });
                         });
 
+                        // Processing pipeline
+                        ui.separator();
+                        ui.collapsing(egui::RichText::new("Processing Pipeline").size(16.0), |ui| {
+                            self.pipeline_editor(ui);
+                        });
+
                         // Action buttons
                         ui.add_space(20.0);
                         ui.horizontal(|ui| {
-                            if ui.add(egui::Button::new(egui::RichText::new("Apply Denoising").size(16.0)).min_size(egui::vec2(120.0, 40.0))).clicked() {
-                                if let Some(img) = &self.original_image {
-                                    let (denoised, duration) = self.apply_denoising(img, self.denoise_type, self.kernel_size);
-                                    self.denoised_image = Some(denoised);
-                                    self.processing_time = Some(duration);
-                                }
+                            let idle = !self.is_processing();
+                            if ui.add_enabled(idle, egui::Button::new(egui::RichText::new("Apply Denoising").size(16.0)).min_size(egui::vec2(120.0, 40.0)))
+                                .on_hover_text("Enter")
+                                .clicked()
+                            {
+                                self.apply_denoising(true);
                             }
 
-                            if ui.add(egui::Button::new(egui::RichText::new("Auto Optimize").size(16.0)).min_size(egui::vec2(120.0, 40.0))).clicked() {
+                            ui.add_enabled(
+                                self.selection.region().is_some(),
+                                egui::Checkbox::new(&mut self.apply_to_selection, egui::RichText::new("Apply to Selection").size(16.0)),
+                            );
+
+                            if ui.add_enabled(idle, egui::Button::new(egui::RichText::new("Auto Optimize").size(16.0)).min_size(egui::vec2(120.0, 40.0))).clicked() {
                                 self.auto_optimize();
                             }
+
+                            ui.add_space(20.0);
+                            if ui.add_enabled(idle && self.history.can_undo(), egui::Button::new(egui::RichText::new("Undo").size(16.0)).min_size(egui::vec2(80.0, 40.0)))
+                                .on_hover_text("Ctrl+Z")
+                                .clicked()
+                            {
+                                self.undo();
+                            }
+
+                            if ui.add_enabled(idle && self.history.can_redo(), egui::Button::new(egui::RichText::new("Redo").size(16.0)).min_size(egui::vec2(80.0, 40.0)))
+                                .on_hover_text("Ctrl+Shift+Z")
+                                .clicked()
+                            {
+                                self.redo();
+                            }
+
+                            ui.add_space(20.0);
+                            let mut depth = self.history.max_depth();
+                            ui.label(egui::RichText::new("History depth:").size(16.0));
+                            if ui.add(egui::DragValue::new(&mut depth).clamp_range(1..=50)).changed() {
+                                self.history.set_max_depth(depth);
+                            }
                         });
+
+                        if let Some(job) = &self.job {
+                            ui.add_space(10.0);
+                            ui.horizontal(|ui| {
+                                let text = if job.is_cancelled() { "Cancelling..." } else { "Processing..." };
+                                ui.add(egui::ProgressBar::new(job.progress()).desired_width(400.0).show_percentage().text(text));
+                                if ui.add_enabled(!job.is_cancelled(), egui::Button::new(egui::RichText::new("Cancel").size(16.0))).clicked() {
+                                    job.cancel();
+                                }
+                            });
+                        }
+                        if let Some(job) = &self.stream_job {
+                            ui.add_space(10.0);
+                            ui.horizontal(|ui| {
+                                let text = if job.is_cancelled() { "Cancelling...".to_string() } else { format!("Exporting {}", job.path.display()) };
+                                ui.add(egui::ProgressBar::new(job.progress()).desired_width(400.0).show_percentage().text(text));
+                                if ui.add_enabled(!job.is_cancelled(), egui::Button::new(egui::RichText::new("Cancel").size(16.0))).clicked() {
+                                    job.cancel();
+                                }
+                            });
+                        }
+                        if let Some(job) = self.animation.as_ref().and_then(|animation| animation.job.as_ref()) {
+                            ui.add_space(10.0);
+                            ui.horizontal(|ui| {
+                                let text = if job.is_cancelled() {
+                                    "Cancelling...".to_string()
+                                } else {
+                                    format!("Frame {} of {}", job.current_frame() + 1, job.frame_count())
+                                };
+                                ui.add(egui::ProgressBar::new(job.progress()).desired_width(400.0).show_percentage().text(text));
+                                if ui.add_enabled(!job.is_cancelled(), egui::Button::new(egui::RichText::new("Cancel").size(16.0))).clicked() {
+                                    job.cancel();
+                                }
+                            });
+                        }
+                    }
+
+                    if let Some(region) = crop_request {
+                        self.apply_crop(region);
+                    }
+                    if let Some(settings) = rotate_request {
+                        self.apply_rotation(settings);
+                    }
+                    if let Some(transform) = transform_request {
+                        self.transform_original(transform);
+                    }
+                    if let Some(index) = frame_request {
+                        self.show_frame(index);
+                    }
+                    if process_frames {
+                        self.process_all_frames();
+                    }
+                    if load_lut_request {
+                        match load_lut(&mut self.open_directory) {
+                            Ok(Some(lut)) => self.settings.lut = Some(Arc::new(lut)),
+                            Ok(None) => {}
+                            Err(err) => self.report(Err(err)),
+                        }
+                    }
+
+                    if let Some(original) = &self.original_image {
+                        let source_size = (original.width(), original.height());
+                        self.resize_dialog.show(ctx, source_size, &mut self.settings);
+                    }
+                    let session_results = self.session.as_ref().map(|session| {
+                        let others = session.images.iter().filter(|image| image.processed.is_some()).count();
+                        others + self.denoised_image.is_some() as usize
+                    });
+                    let active_size = match &self.large_image {
+                        Some(large) => Some((large.width, large.height)),
+                        None => self.denoised_image.as_ref().map(|img| (img.width(), img.height())),
+                    };
+                    match self.export_dialog.show(ctx, &mut self.open_directory, active_size, session_results) {
+                        Some(ExportTarget::Active) => self.export_image(),
+                        Some(ExportTarget::All) => self.export_all(),
+                        None => {}
+                    }
+                    if self.toast.as_ref().is_some_and(|toast| !toast.show(ctx)) {
+                        self.toast = None;
+                    }
+                    if let Some(stacked) = self.stack_dialog.show(ctx, &mut self.open_directory) {
+                        self.end_session();
+                        self.set_original_image(Some(stacked));
+                        self.set_image_path(None);
+                        self.status_message = Some("Stacked images loaded as the original".to_string());
+                        self.last_error = None;
+                    }
+                    self.spectrum_dialog.show(ctx, self.original_image.as_ref(), &mut self.settings.notches);
+                    if let Some(result) = self.benchmark_dialog.show(ctx, self.original_image.as_ref(), &self.settings, &DENOISE_TYPES, self.channel_view) {
+                        let downscaled = if result.downscaled { ", downscaled" } else { "" };
+                        self.status_message = Some(format!("Showing the {:?} result of the comparison{}", result.spec.denoise_type, downscaled));
+                        self.last_error = None;
+                        self.denoised_image = Some(result.image);
+                        self.result_histograms = None;
+                        self.processing_time = Some(result.duration);
+                        self.stage_timings.clear();
+                        self.processing_resumed = false;
+                        self.last_run = None;
+                        self.repaired_pixels = None;
+                        self.preview_image = None;
+                        self.result_texture = None;
                     }
                 });
             });
@@ -0,0 +1,144 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Stroke};
+use image::{imageops, GrayImage};
+
+use crate::algorithms::mask::paint_dab;
+use crate::selection::ScreenMapping;
+use crate::viewer;
+
+// Color of painted areas in the overlay, at full opacity where fully masked
+const OVERLAY_COLOR: [u8; 3] = [255, 96, 0];
+const OVERLAY_OPACITY: f32 = 0.45;
+// Dabs are stamped this fraction of the radius apart along a stroke
+const DAB_SPACING: f32 = 0.25;
+
+/// Brush painting a mask over the original image that limits where
+/// processing takes effect. Unpainted areas keep the original.
+///
+/// The mask is stored at the original's resolution, independent of the
+/// zoom it was painted at.
+pub struct MaskPainter {
+    mask: Option<GrayImage>,
+    /// Brush diameter in image pixels
+    pub brush_size: f32,
+    /// Share of the brush radius painted at full strength, 0-1
+    pub hardness: f32,
+    pub erase: bool,
+    // Last dab of the stroke in progress, in image pixels
+    last_point: Option<Pos2>,
+    texture: Option<egui::TextureHandle>,
+    texture_outdated: bool,
+    // Counts changes to the mask, see `revision`
+    revision: u64,
+}
+
+impl Default for MaskPainter {
+    fn default() -> Self {
+        Self {
+            mask: None,
+            brush_size: 50.0,
+            hardness: 0.5,
+            erase: false,
+            last_point: None,
+            texture: None,
+            texture_outdated: false,
+            revision: 0,
+        }
+    }
+}
+
+impl MaskPainter {
+    /// The painted mask, `None` until something was painted.
+    pub fn mask(&self) -> Option<&GrayImage> {
+        self.mask.as_ref()
+    }
+
+    pub fn clear(&mut self) {
+        self.mask = None;
+        self.last_point = None;
+        self.texture = None;
+        self.revision += 1;
+    }
+
+    /// Replaces the mask with one painted before, which has to have the
+    /// original's size.
+    pub fn set_mask(&mut self, mask: GrayImage) {
+        self.clear();
+        self.mask = Some(mask);
+    }
+
+    /// Changes whenever the mask does, to tell whether it needs saving again.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Paints or erases along primary button drags over an image pane
+    /// showing an image of `image_size` pixels. Returns whether the mask
+    /// changed.
+    pub fn interact(&mut self, response: &egui::Response, mapping: &ScreenMapping, image_size: (u32, u32)) -> bool {
+        let painting = response.dragged_by(egui::PointerButton::Primary) || response.clicked();
+        let pointer = response.interact_pointer_pos().filter(|_| painting);
+        let Some(pointer) = pointer else {
+            self.last_point = None;
+            return false;
+        };
+
+        let point = mapping.to_image(pointer);
+        let mask = self.mask.get_or_insert_with(|| GrayImage::new(image_size.0, image_size.1));
+        let radius = self.brush_size / 2.0;
+        // Fill in between pointer events so fast strokes stay continuous
+        let from = self.last_point.unwrap_or(point);
+        let steps = ((point - from).length() / (radius * DAB_SPACING).max(1.0)).ceil().max(1.0) as usize;
+        for step in 1..=steps {
+            let dab = from.lerp(point, step as f32 / steps as f32);
+            paint_dab(mask, dab.x, dab.y, radius, self.hardness, self.erase);
+        }
+        self.last_point = Some(point);
+        self.texture_outdated = true;
+        self.revision += 1;
+        true
+    }
+
+    /// Draws the mask as a translucent overlay, plus the brush outline
+    /// under the pointer.
+    pub fn paint(&mut self, ctx: &egui::Context, painter: &egui::Painter, mapping: &ScreenMapping, hover: Option<Pos2>) {
+        if let Some(mask) = &self.mask {
+            if self.texture.is_none() || self.texture_outdated {
+                let image = match viewer::display_proxy_size(ctx, mask.width(), mask.height()) {
+                    Some((width, height)) => overlay_image(&imageops::thumbnail(mask, width, height)),
+                    None => overlay_image(mask),
+                };
+                match &mut self.texture {
+                    Some(texture) => texture.set(image, viewer::TEXTURE_OPTIONS),
+                    None => self.texture = Some(ctx.load_texture("mask", image, viewer::TEXTURE_OPTIONS)),
+                }
+                self.texture_outdated = false;
+            }
+
+            if let Some(texture) = &self.texture {
+                let uv = Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+                painter.image(texture.id(), mapping.screen_rect, uv, Color32::WHITE);
+            }
+        }
+
+        if let Some(pointer) = hover {
+            let radius = self.brush_size / 2.0 * mapping.scale();
+            painter.circle_stroke(pointer, radius, Stroke::new(1.0, Color32::WHITE));
+            painter.circle_stroke(pointer, radius * self.hardness, Stroke::new(1.0, Color32::from_white_alpha(96)));
+        }
+    }
+}
+
+fn overlay_image(mask: &GrayImage) -> egui::ColorImage {
+    let pixels = mask
+        .as_raw()
+        .iter()
+        .map(|&value| {
+            let alpha = (value as f32 * OVERLAY_OPACITY).round() as u8;
+            Color32::from_rgba_unmultiplied(OVERLAY_COLOR[0], OVERLAY_COLOR[1], OVERLAY_COLOR[2], alpha)
+        })
+        .collect();
+    egui::ColorImage {
+        size: [mask.width() as usize, mask.height() as usize],
+        pixels,
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+
+/// Resolution below which prints start to look soft.
+pub const LOW_DPI: f32 = 150.0;
+const CM_PER_INCH: f32 = 2.54;
+
+/// Unit print dimensions are entered in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LengthUnit {
+    Centimeters,
+    Inches,
+}
+
+impl LengthUnit {
+    pub const ALL: [LengthUnit; 2] = [LengthUnit::Centimeters, LengthUnit::Inches];
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            LengthUnit::Centimeters => " cm",
+            LengthUnit::Inches => " in",
+        }
+    }
+
+    fn per_inch(self) -> f32 {
+        match self {
+            LengthUnit::Centimeters => CM_PER_INCH,
+            LengthUnit::Inches => 1.0,
+        }
+    }
+}
+
+/// How large exported files come out on paper.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintOptions {
+    /// Write a resolution into PNG, JPEG and TIFF files
+    pub enabled: bool,
+    /// Dots per inch the image is printed at, unless fitted without resampling
+    pub dpi: f32,
+    /// Scale the print to fit into `width` x `height`, keeping the aspect
+    /// ratio and turned to the image's orientation
+    pub fit: bool,
+    pub unit: LengthUnit,
+    pub width: f32,
+    pub height: f32,
+    /// Resample to the pixels `dpi` needs at the fitted size. Otherwise the
+    /// pixels stay and the resolution written is whatever fits them into it.
+    pub resample: bool,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dpi: 300.0,
+            fit: false,
+            unit: LengthUnit::Centimeters,
+            width: 15.0,
+            height: 10.0,
+            resample: true,
+        }
+    }
+}
+
+/// Pixels and resolution of an image printed as `PrintOptions` say.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrintLayout {
+    /// Pixel dimensions of the exported file
+    pub pixels: (u32, u32),
+    /// Resolution written into the file
+    pub dpi: f32,
+    /// Source pixels per printed inch, the detail the print really has
+    pub source_dpi: f32,
+    /// Printed dimensions in the options' unit
+    pub size: (f32, f32),
+}
+
+impl PrintLayout {
+    /// Whether the source has too few pixels for a sharp print.
+    pub fn is_low_resolution(&self) -> bool {
+        self.source_dpi < LOW_DPI
+    }
+}
+
+impl PrintOptions {
+    /// Layout of a `width` x `height` image, `None` when no resolution is written.
+    pub fn layout(&self, width: u32, height: u32) -> Option<PrintLayout> {
+        if !self.enabled || width == 0 || height == 0 {
+            return None;
+        }
+        let dpi = self.dpi.max(1.0);
+        if !self.fit {
+            return Some(PrintLayout {
+                pixels: (width, height),
+                dpi,
+                source_dpi: dpi,
+                size: self.in_unit(width as f32 / dpi, height as f32 / dpi),
+            });
+        }
+
+        let mut box_inches = (self.width.max(0.01) / self.unit.per_inch(), self.height.max(0.01) / self.unit.per_inch());
+        // Paper is turned to the image, a 10 x 15 print fits landscape photos too
+        if (width > height) != (box_inches.0 > box_inches.1) {
+            box_inches = (box_inches.1, box_inches.0);
+        }
+        let source_dpi = (width as f32 / box_inches.0).max(height as f32 / box_inches.1);
+        let inches = (width as f32 / source_dpi, height as f32 / source_dpi);
+        let (pixels, dpi) = if self.resample {
+            let pixels = ((inches.0 * dpi).round().max(1.0) as u32, (inches.1 * dpi).round().max(1.0) as u32);
+            (pixels, dpi)
+        } else {
+            ((width, height), source_dpi)
+        };
+        Some(PrintLayout {
+            pixels,
+            dpi,
+            source_dpi,
+            size: self.in_unit(inches.0, inches.1),
+        })
+    }
+
+    fn in_unit(&self, width: f32, height: f32) -> (f32, f32) {
+        (width * self.unit.per_inch(), height * self.unit.per_inch())
+    }
+}
@@ -0,0 +1,793 @@
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, GrayImage, ImageBuffer};
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use crate::algorithms::histogram::Histograms;
+use crate::export::{save_bands, ExportOptions};
+use crate::image_loader::{load_image_from_path, LargeImage, RowReader};
+use crate::algorithms::mask::blend_with_mask;
+use crate::algorithms::parallel::{in_pool, merge_blocks_into_image, split_image_into_blocks, thread_pool, ImageBlock, MERGE_STAGE, SPLIT_STAGE};
+use crate::algorithms::pipeline::{Operation, Pipeline};
+use crate::algorithms::progress::Progress;
+use crate::algorithms::region::{process_region, Region};
+use crate::algorithms::sample::{with_pixel_type, FilterPixel, PixelFormat, Sample};
+use crate::algorithms::streaming::{RowSource, StreamedBands};
+use crate::settings::ProcessingSettings;
+
+/// Runs the configured pipeline on `img`, either whole or block by block,
+/// with all parallel work on `pool` or the global pool without one.
+/// Returns `None` if `progress` was cancelled before the run finished.
+pub fn process_image(
+    img: &DynamicImage,
+    settings: &ProcessingSettings,
+    pool: Option<&ThreadPool>,
+    progress: &Progress,
+) -> Option<DynamicImage> {
+    process_stages(img, settings, None, pool, progress).map(|(processed, _)| processed)
+}
+
+// How a pipeline runs on an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Plan {
+    Whole,
+    // Block by block, after the first `leading` operations ran on the whole image
+    Blocks { block_size: u32, overlap: u32, leading: usize },
+}
+
+impl Plan {
+    fn of(pipeline: &Pipeline, settings: &ProcessingSettings) -> Plan {
+        // Blocks can't be merged back once their size changes, and global
+        // operations would treat each block differently. Filters reaching
+        // further than the blocks allow would leave them nothing but overlap.
+        let overlap = block_overlap(pipeline);
+        if !settings.use_parallel
+            || pipeline.changes_dimensions()
+            || pipeline.is_global()
+            || settings.block_size <= 2 * overlap
+        {
+            return Plan::Whole;
+        }
+
+        // Blocks keep the format they were split in, so a leading grayscale
+        // conversion has to happen up front for them to run single-channel.
+        // Leading hot pixel repair too, or pixels in the overlaps would be
+        // counted twice.
+        let leading = pipeline
+            .0
+            .iter()
+            .take_while(|operation| matches!(operation, Operation::Grayscale | Operation::HotPixels { .. }))
+            .count();
+        Plan::Blocks { block_size: settings.block_size, overlap, leading }
+    }
+
+    // How many operations at the start of `pipeline` are run before the
+    // rest can be started over: the restoration steps, see
+    // `Pipeline::split_restoration`, and the ones blocks need done up front
+    fn prefix_len(self, pipeline: &Pipeline) -> usize {
+        let restoration = pipeline.split_restoration().0 .0.len();
+        match self {
+            Plan::Whole => restoration,
+            Plan::Blocks { leading, .. } => restoration.max(leading),
+        }
+    }
+}
+
+/// The result of the restoration steps of a run on a whole image, kept so
+/// that a later run on the same image changing only the adjustments can
+/// start from it and give exactly what running everything gives.
+#[derive(Clone)]
+pub struct Intermediate {
+    plan: Plan,
+    // The operations already applied, the start of the pipeline
+    done: Pipeline,
+    restored: Restored,
+    // What the operations done reported, given again by the runs resuming
+    repaired_pixels: usize,
+    notes: Vec<String>,
+}
+
+#[derive(Clone)]
+enum Restored {
+    Whole(Arc<DynamicImage>),
+    // Block-wise runs merge their blocks once, at the end, so the blocks
+    // are kept as they were before the rest of the pipeline ran on them
+    Blocks {
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+        blocks: Arc<Vec<RestoredBlock>>,
+    },
+}
+
+struct RestoredBlock {
+    x: u32,
+    y: u32,
+    overlap: u32,
+    image: DynamicImage,
+}
+
+impl Intermediate {
+    /// Whether a run with `settings` goes through the same restoration
+    /// steps, split into the same blocks.
+    pub fn matches(&self, settings: &ProcessingSettings) -> bool {
+        let pipeline = settings.pipeline();
+        Plan::of(&pipeline, settings) == self.plan && pipeline.0.starts_with(&self.done.0)
+    }
+}
+
+// Like `process_image`, but starts from `intermediate` when given, which
+// has to be of `img` and match `settings`. Also returns the intermediate
+// of the run, `None` if the pipeline has no restoration steps. Everything
+// below runs on `pool`.
+fn process_stages(
+    img: &DynamicImage,
+    settings: &ProcessingSettings,
+    intermediate: Option<Intermediate>,
+    pool: Option<&ThreadPool>,
+    progress: &Progress,
+) -> Option<(DynamicImage, Option<Intermediate>)> {
+    in_pool(pool, || {
+        let pipeline = settings.pipeline();
+        let intermediate = match intermediate {
+            Some(intermediate) => {
+                progress.add_repaired_pixels(intermediate.repaired_pixels);
+                for note in &intermediate.notes {
+                    progress.add_note(note.clone());
+                }
+                intermediate
+            }
+            None => restore(img, &pipeline, Plan::of(&pipeline, settings), progress)?,
+        };
+        let rest = Pipeline(pipeline.0[intermediate.done.0.len()..].to_vec());
+        let processed = finish(&intermediate.restored, &rest, progress)?;
+        let keep = !pipeline.split_restoration().0 .0.is_empty();
+        Some((processed, keep.then_some(intermediate)))
+    })
+}
+
+// Runs the start of `pipeline` on `img` as `plan` says, up to where later
+// runs can pick up
+fn restore(img: &DynamicImage, pipeline: &Pipeline, plan: Plan, progress: &Progress) -> Option<Intermediate> {
+    let (repaired_before, notes_before) = (progress.repaired_pixels(), progress.notes().len());
+    let done = Pipeline(pipeline.0[..plan.prefix_len(pipeline)].to_vec());
+    let restored = match plan {
+        Plan::Whole => Restored::Whole(Arc::new(done.apply_with_progress(img, progress)?)),
+        Plan::Blocks { block_size, overlap, leading } => {
+            let prepared;
+            let img = if leading == 0 {
+                img
+            } else {
+                prepared = Pipeline(done.0[..leading].to_vec()).apply_with_progress(img, progress)?;
+                &prepared
+            };
+            let in_blocks = Pipeline(done.0[leading..].to_vec());
+            let blocks = with_pixel_type!(img, |P| restore_blocks::<P>(img, block_size, overlap, &in_blocks, progress));
+            if progress.is_cancelled() {
+                return None;
+            }
+            Restored::Blocks {
+                format: PixelFormat::of(img),
+                width: img.width(),
+                height: img.height(),
+                blocks: Arc::new(blocks),
+            }
+        }
+    };
+    Some(Intermediate {
+        plan,
+        done,
+        restored,
+        repaired_pixels: progress.repaired_pixels() - repaired_before,
+        notes: progress.notes().split_off(notes_before),
+    })
+}
+
+// Runs `rest` on what `restored` holds, merging the blocks of block-wise runs
+fn finish(restored: &Restored, rest: &Pipeline, progress: &Progress) -> Option<DynamicImage> {
+    let result = match restored {
+        Restored::Whole(image) => return rest.apply_with_progress(image, progress),
+        Restored::Blocks { format, width, height, blocks } => {
+            with_pixel_type!(format *format, |P| finish_blocks::<P>(blocks, *width, *height, rest, progress))
+        }
+    };
+    if progress.is_cancelled() {
+        return None;
+    }
+    Some(result)
+}
+
+// Blocks overlap by twice the reach of the pipeline, so the blended seams
+// only use pixels that had their full neighbourhood
+fn block_overlap(pipeline: &Pipeline) -> u32 {
+    2 * pipeline.context_radius()
+}
+
+// Splits `img` into blocks in the working format `P` and runs `pipeline`
+// on each
+fn restore_blocks<P: FilterPixel>(img: &DynamicImage, block_size: u32, overlap: u32, pipeline: &Pipeline, progress: &Progress) -> Vec<RestoredBlock>
+where
+    P::Subpixel: Sample,
+{
+    let start_time = Instant::now();
+    let blocks = split_image_into_blocks(&P::from_dynamic(img), block_size, overlap);
+    progress.add_timing(SPLIT_STAGE, start_time.elapsed());
+
+    blocks
+        .into_par_iter()
+        .map(|block| {
+            let block_img = P::into_dynamic(ImageBuffer::from_raw(block.width, block.height, block.data).unwrap());
+            // A cancelled block is passed on untouched, the result is discarded by the caller
+            let image = pipeline.apply_with_progress(&block_img, progress).unwrap_or(block_img);
+            RestoredBlock { x: block.x, y: block.y, overlap: block.overlap, image }
+        })
+        .collect()
+}
+
+// Runs `pipeline` on restored blocks in the working format `P` and merges them
+fn finish_blocks<P: FilterPixel>(
+    blocks: &[RestoredBlock],
+    width: u32,
+    height: u32,
+    pipeline: &Pipeline,
+    progress: &Progress,
+) -> DynamicImage
+where
+    P::Subpixel: Sample,
+{
+    let processed: Vec<ImageBlock<P>> = blocks
+        .par_iter()
+        .map(|block| {
+            let processed = pipeline.apply_with_progress(&block.image, progress);
+            let image = processed.as_ref().unwrap_or(&block.image);
+            ImageBlock {
+                x: block.x,
+                y: block.y,
+                width: image.width(),
+                height: image.height(),
+                data: P::from_dynamic(image).into_raw(),
+                overlap: block.overlap,
+            }
+        })
+        .collect();
+
+    let start_time = Instant::now();
+    let merged = merge_blocks_into_image(processed, width, height);
+    progress.add_timing(MERGE_STAGE, start_time.elapsed());
+    P::into_dynamic(merged)
+}
+
+// Processes `img`, or only `region` of it, and blends the result with `img`
+// according to `mask`. Whole-image runs start from `intermediate` when
+// given and return the result of their restoration steps.
+fn process_selected(
+    img: &DynamicImage,
+    settings: &ProcessingSettings,
+    region: Option<Region>,
+    mask: Option<&GrayImage>,
+    intermediate: Option<Intermediate>,
+    pool: Option<&ThreadPool>,
+    progress: &Progress,
+) -> Option<(DynamicImage, Option<Intermediate>)> {
+    // A resized result can't be composited back into the original
+    let region = region.filter(|_| !settings.pipeline().changes_dimensions());
+    let (processed, intermediate) = match region {
+        Some(region) => {
+            let margin = settings.pipeline().context_radius();
+            (process_region(img, region, margin, |patch| process_image(patch, settings, pool, progress))?, None)
+        }
+        None => process_stages(img, settings, intermediate, pool, progress)?,
+    };
+    let processed = match mask {
+        // A resized result no longer lines up with the mask
+        Some(mask) if processed.width() == img.width() && processed.height() == img.height() => {
+            let start_time = Instant::now();
+            let blended = blend_with_mask(img, &processed, mask);
+            progress.add_timing("Mask Blend", start_time.elapsed());
+            blended
+        }
+        _ => processed,
+    };
+    Some((processed, intermediate))
+}
+
+/// What a finished `ProcessingJob` produced.
+pub struct ProcessingOutput {
+    pub image: DynamicImage,
+    /// Wall time of the whole run
+    pub duration: Duration,
+    /// Time spent in each stage, in the order they first ran. Operations of
+    /// block-wise runs add up over blocks processed in parallel, so they
+    /// can exceed `duration`.
+    pub timings: Vec<(String, Duration)>,
+    pub histograms: Histograms,
+    /// Result of the restoration steps, `None` for runs on a selection
+    pub intermediate: Option<Intermediate>,
+    /// The run started from an intermediate and only did the adjustments
+    pub resumed: bool,
+}
+
+/// A processing run executing on a background thread.
+pub struct ProcessingJob {
+    progress: Arc<Progress>,
+    receiver: Receiver<Option<ProcessingOutput>>,
+    /// Settings the run was started with, sliders may move while it runs
+    pub settings: ProcessingSettings,
+    pub region: Option<Region>,
+    /// The result gets blended with the original through a mask
+    pub masked: bool,
+    pub record_history: bool,
+}
+
+impl ProcessingJob {
+    /// Starts processing `img`, or only `region` of it when given. With a
+    /// `mask` the result is blended with `img` according to it. A run on
+    /// the whole image starts from `intermediate` when given, which has to
+    /// be of `img` and match `settings`.
+    pub fn spawn(
+        img: DynamicImage,
+        settings: ProcessingSettings,
+        region: Option<Region>,
+        mask: Option<GrayImage>,
+        intermediate: Option<Intermediate>,
+        record_history: bool,
+    ) -> Self {
+        let progress = Arc::new(Progress::new());
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = Arc::clone(&progress);
+        let thread_settings = settings.clone();
+        let resumed = intermediate.is_some() && region.is_none();
+        let masked = mask.is_some();
+        thread::spawn(move || {
+            let start_time = Instant::now();
+            let pool = thread_pool(thread_settings.threads);
+            let result = process_selected(&img, &thread_settings, region, mask.as_ref(), intermediate, pool.as_deref(), &thread_progress)
+                .map(|(processed, intermediate)| {
+                    let duration = start_time.elapsed();
+                    // Counted here rather than when the result is first shown
+                    let histograms = Histograms::of(&processed);
+                    ProcessingOutput {
+                        image: processed,
+                        duration,
+                        timings: thread_progress.timings(),
+                        histograms,
+                        intermediate,
+                        resumed,
+                    }
+                });
+            let _ = sender.send(result);
+        });
+
+        Self {
+            progress,
+            receiver,
+            settings,
+            region,
+            masked,
+            record_history,
+        }
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.progress.fraction()
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.progress.is_cancelled()
+    }
+
+    /// What the filters noted about the run, see `Progress::add_note`.
+    pub fn notes(&self) -> Vec<String> {
+        self.progress.notes()
+    }
+
+    /// Hot pixels the run repaired so far.
+    pub fn repaired_pixels(&self) -> usize {
+        self.progress.repaired_pixels()
+    }
+
+    /// Returns `None` while the job is still running, otherwise the result
+    /// (`Some(None)` if it was cancelled or the worker died).
+    pub fn poll(&self) -> Option<Option<ProcessingOutput>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+}
+
+// Rows a `StreamJob` processes at a time, on top of the margins the
+// pipeline needs
+const STREAM_BAND_ROWS: u32 = 256;
+
+/// A full run on a `LargeImage`, streamed from its file band by band,
+/// each processed like `process_image`, and written straight into the
+/// exported file. Selections, masks and watermarks would need the whole
+/// image and are left out.
+pub struct StreamJob {
+    progress: Arc<Progress>,
+    rows_done: Arc<AtomicUsize>,
+    height: u32,
+    receiver: Receiver<Result<(), String>>,
+    /// File being exported
+    pub path: PathBuf,
+}
+
+impl StreamJob {
+    /// Starts the run, or says why the pipeline of `settings` can't be
+    /// streamed, see `Operation::streamable`.
+    pub fn spawn(image: &LargeImage, path: PathBuf, settings: ProcessingSettings, options: ExportOptions) -> Result<Self, String> {
+        let pipeline = settings.pipeline();
+        if let Some(operation) = pipeline.0.iter().find(|operation| !operation.streamable()) {
+            let name = match operation {
+                Operation::Denoise { denoise_type, .. } => format!("{:?}", denoise_type),
+                operation => operation.name().to_string(),
+            };
+            return Err(format!("{} needs the whole image, it is unavailable in low-memory mode", name));
+        }
+
+        let progress = Arc::new(Progress::new());
+        let rows_done = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = Arc::clone(&progress);
+        let thread_rows_done = Arc::clone(&rows_done);
+        let (source, thread_path) = (image.path.clone(), path.clone());
+        thread::spawn(move || {
+            let pool = thread_pool(settings.threads);
+            let margin = pipeline.context_radius();
+            let result = RowReader::open(&source).map_err(|err| err.to_string()).and_then(|reader| {
+                let size = (reader.width(), reader.height());
+                let bands = StreamedBands::new(reader, STREAM_BAND_ROWS, margin, |band| {
+                    process_image(band, &settings, pool.as_deref(), &thread_progress).ok_or_else(|| "Cancelled".to_string())
+                })
+                .inspect(|band| {
+                    if let Ok(band) = band {
+                        thread_rows_done.fetch_add(band.height() as usize, Ordering::Relaxed);
+                    }
+                });
+                save_bands(bands, size, &thread_path, &options)
+            });
+            // Half a file is worse than none
+            if result.is_err() {
+                let _ = fs::remove_file(&thread_path);
+            }
+            let _ = sender.send(result);
+        });
+
+        Ok(Self {
+            progress,
+            rows_done,
+            height: image.height,
+            receiver,
+            path,
+        })
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.rows_done.load(Ordering::Relaxed) as f32 / self.height.max(1) as f32
+    }
+
+    pub fn cancel(&self) {
+        self.progress.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.progress.is_cancelled()
+    }
+
+    /// Returns `None` while the job is still running, otherwise whether
+    /// the file was written.
+    pub fn poll(&self) -> Option<Result<(), String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(format!("Exporting {} failed unexpectedly", self.path.display()))),
+        }
+    }
+}
+
+/// Every frame of an animation processed one after another on a background
+/// thread, each the same way a `ProcessingJob` would.
+pub struct FramesJob {
+    // One per frame, all cancelled together
+    progress: Arc<Vec<Progress>>,
+    frames_done: Arc<AtomicUsize>,
+    receiver: Receiver<Option<Vec<DynamicImage>>>,
+}
+
+impl FramesJob {
+    pub fn spawn(
+        frames: Vec<DynamicImage>,
+        settings: ProcessingSettings,
+        region: Option<Region>,
+        mask: Option<GrayImage>,
+    ) -> Self {
+        let progress: Arc<Vec<Progress>> = Arc::new(frames.iter().map(|_| Progress::new()).collect());
+        let frames_done = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = Arc::clone(&progress);
+        let thread_frames_done = Arc::clone(&frames_done);
+        thread::spawn(move || {
+            let pool = thread_pool(settings.threads);
+            let mut processed = Vec::with_capacity(frames.len());
+            for (frame, progress) in frames.iter().zip(thread_progress.iter()) {
+                let Some((result, _)) = process_selected(frame, &settings, region, mask.as_ref(), None, pool.as_deref(), progress) else {
+                    let _ = sender.send(None);
+                    return;
+                };
+                processed.push(restore_transparency(frame, result));
+                thread_frames_done.fetch_add(1, Ordering::Relaxed);
+            }
+            let _ = sender.send(Some(processed));
+        });
+
+        Self {
+            progress,
+            frames_done,
+            receiver,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.progress.len()
+    }
+
+    /// Index of the frame being processed.
+    pub fn current_frame(&self) -> usize {
+        self.frames_done.load(Ordering::Relaxed).min(self.frame_count().saturating_sub(1))
+    }
+
+    pub fn progress(&self) -> f32 {
+        let total: f32 = self.progress.iter().map(Progress::fraction).sum();
+        total / self.frame_count().max(1) as f32
+    }
+
+    pub fn cancel(&self) {
+        self.progress.iter().for_each(Progress::cancel);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.progress.iter().any(Progress::is_cancelled)
+    }
+
+    /// Returns `None` while the job is still running, otherwise the
+    /// processed frames (`Some(None)` if it was cancelled or the worker died).
+    pub fn poll(&self) -> Option<Option<Vec<DynamicImage>>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(None),
+        }
+    }
+}
+
+/// Where a `BatchJob` takes an image from.
+pub enum BatchSource {
+    /// Loaded on the worker, so only one full image is in memory at a time
+    File(PathBuf),
+    Image(DynamicImage),
+}
+
+/// Index of an image in a `BatchJob` with its result, or why it failed.
+pub type BatchResult = (usize, Result<DynamicImage, String>);
+
+/// Several images processed one after another on a background thread with
+/// the same settings, each as a whole.
+pub struct BatchJob {
+    // One per image, all cancelled together
+    progress: Arc<Vec<Progress>>,
+    receiver: Receiver<BatchResult>,
+    /// Settings every image is processed with
+    pub settings: ProcessingSettings,
+}
+
+impl BatchJob {
+    pub fn spawn(sources: Vec<BatchSource>, settings: ProcessingSettings) -> Self {
+        let progress: Arc<Vec<Progress>> = Arc::new(sources.iter().map(|_| Progress::new()).collect());
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_progress = Arc::clone(&progress);
+        let thread_settings = settings.clone();
+        thread::spawn(move || {
+            let settings = thread_settings;
+            let pool = thread_pool(settings.threads);
+            for (index, (source, progress)) in sources.into_iter().zip(thread_progress.iter()).enumerate() {
+                let img = match source {
+                    BatchSource::File(path) => match load_image_from_path(&path) {
+                        Ok(img) => img,
+                        Err(err) => {
+                            let _ = sender.send((index, Err(err.to_string())));
+                            continue;
+                        }
+                    },
+                    BatchSource::Image(img) => img,
+                };
+                let Some(result) = process_image(&img, &settings, pool.as_deref(), progress) else {
+                    return;
+                };
+                if sender.send((index, Ok(result))).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Self { progress, receiver, settings }
+    }
+
+    /// How far along the image at `index` is, 0-1.
+    pub fn progress_of(&self, index: usize) -> f32 {
+        self.progress.get(index).map_or(0.0, Progress::fraction)
+    }
+
+    pub fn cancel(&self) {
+        self.progress.iter().for_each(Progress::cancel);
+    }
+
+    /// The images finished since the last call, by index, and whether the
+    /// job is over, because every image is done, it was cancelled or the
+    /// worker died.
+    pub fn poll(&self) -> (Vec<BatchResult>, bool) {
+        let mut finished = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(result) => finished.push(result),
+                Err(TryRecvError::Empty) => return (finished, false),
+                Err(TryRecvError::Disconnected) => return (finished, true),
+            }
+        }
+    }
+}
+
+// Processing drops the alpha channel, frames of an animation get theirs
+// back so transparent parts stay transparent
+fn restore_transparency(frame: &DynamicImage, processed: DynamicImage) -> DynamicImage {
+    let DynamicImage::ImageRgba8(source) = frame else {
+        return processed;
+    };
+    if source.dimensions() != (processed.width(), processed.height()) || source.pixels().all(|pixel| pixel[3] == u8::MAX) {
+        return processed;
+    }
+    let mut result = processed.to_rgba8();
+    for (pixel, source) in result.pixels_mut().zip(source.pixels()) {
+        pixel[3] = source[3];
+    }
+    DynamicImage::ImageRgba8(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::algorithms::benchmark::add_gaussian_noise;
+    use crate::algorithms::denoise::DenoiseType;
+    use crate::algorithms::parallel::process_image_parallel;
+
+    fn noisy_image() -> DynamicImage {
+        let gradient = RgbImage::from_fn(48, 48, |x, y| Rgb([(x * 5) as u8, (y * 5) as u8, 128]));
+        add_gaussian_noise(&DynamicImage::ImageRgb8(gradient), 20.0, 7)
+    }
+
+    // Runs `settings` on `img`, then `adjusted` once from scratch and once
+    // from the intermediate of the first run, and checks both agree
+    fn assert_resumes_exactly(img: &DynamicImage, settings: &ProcessingSettings, adjusted: &ProcessingSettings) {
+        let (_, intermediate) = process_stages(img, settings, None, None, &Progress::new()).unwrap();
+        let intermediate = intermediate.expect("the pipeline has restoration steps");
+        assert!(intermediate.matches(adjusted));
+
+        let full = process_image(img, adjusted, None, &Progress::new()).unwrap();
+        let (resumed, _) = process_stages(img, adjusted, Some(intermediate), None, &Progress::new()).unwrap();
+        assert_eq!(resumed.color(), full.color());
+        assert!(resumed.as_bytes() == full.as_bytes(), "resuming from the intermediate changed the result");
+    }
+
+    // Changes to the point operations after the restoration steps, which
+    // leave the block layout as it is
+    fn adjust(settings: &ProcessingSettings) -> ProcessingSettings {
+        ProcessingSettings {
+            exposure: 0.4,
+            brightness: 0.1,
+            contrast: 0.3,
+            ..settings.clone()
+        }
+    }
+
+    fn block_wise() -> ProcessingSettings {
+        ProcessingSettings {
+            denoise_type: DenoiseType::MedianFilter,
+            use_parallel: true,
+            block_size: 20,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resumed_run_matches_full_run() {
+        let settings = ProcessingSettings {
+            denoise_type: DenoiseType::NonLocalMeans,
+            ..Default::default()
+        };
+        assert_resumes_exactly(&noisy_image(), &settings, &ProcessingSettings { sharpness: 0.8, ..adjust(&settings) });
+    }
+
+    #[test]
+    fn resumed_block_wise_run_matches_full_run() {
+        let settings = block_wise();
+        assert_resumes_exactly(&noisy_image(), &settings, &adjust(&settings));
+    }
+
+    // Every block through the whole pipeline, then merged once
+    #[test]
+    fn block_wise_run_merges_once() {
+        let img = noisy_image();
+        let settings = ProcessingSettings { sharpness: 0.5, ..adjust(&block_wise()) };
+        let pipeline = settings.pipeline();
+        let expected = with_pixel_type!(&img, |P| {
+            let buffer = P::from_dynamic(&img);
+            P::into_dynamic(process_image_parallel(&buffer, settings.block_size, block_overlap(&pipeline), None, &Progress::new(), |block| {
+                let block_img = P::into_dynamic(ImageBuffer::from_raw(block.width, block.height, block.data.clone()).unwrap());
+                ImageBlock { data: P::from_dynamic(&pipeline.apply(&block_img)).into_raw(), ..block.clone() }
+            }))
+        });
+        let processed = process_image(&img, &settings, None, &Progress::new()).unwrap();
+        assert!(processed.as_bytes() == expected.as_bytes(), "the block-wise run differs from a single pass per block");
+    }
+
+    #[test]
+    fn restoration_changes_invalidate_the_intermediate() {
+        let settings = ProcessingSettings::default();
+        let (_, intermediate) = process_stages(&noisy_image(), &settings, None, None, &Progress::new()).unwrap();
+        let intermediate = intermediate.unwrap();
+
+        assert!(intermediate.matches(&adjust(&settings)));
+        assert!(intermediate.matches(&ProcessingSettings { sharpness: 0.8, ..settings.clone() }));
+        assert!(!intermediate.matches(&ProcessingSettings { kernel_size: 5, ..settings.clone() }));
+        assert!(!intermediate.matches(&ProcessingSettings { denoise_type: DenoiseType::GaussianFilter, ..settings.clone() }));
+        assert!(!intermediate.matches(&ProcessingSettings { use_parallel: true, ..settings.clone() }));
+    }
+
+    #[test]
+    fn block_layout_changes_invalidate_the_intermediate() {
+        let settings = block_wise();
+        let (_, intermediate) = process_stages(&noisy_image(), &settings, None, None, &Progress::new()).unwrap();
+        let intermediate = intermediate.unwrap();
+
+        assert!(intermediate.matches(&adjust(&settings)));
+        // Sharpening reaches further, which widens the overlap of the blocks
+        assert!(!intermediate.matches(&ProcessingSettings { sharpness: 0.8, ..settings.clone() }));
+        assert!(!intermediate.matches(&ProcessingSettings { block_size: 24, ..settings.clone() }));
+    }
+
+    #[test]
+    fn gray_results_stay_gray() {
+        let gray = DynamicImage::ImageLuma8(noisy_image().to_luma8());
+        for settings in [ProcessingSettings::default(), block_wise()] {
+            let processed = process_image(&gray, &settings, None, &Progress::new()).unwrap();
+            assert_eq!(processed.color(), image::ColorType::L8, "{:?}", settings.use_parallel);
+        }
+
+        // Forced to gray with the BT.601 weights, a flat color keeps its luma
+        let color = DynamicImage::ImageRgb8(RgbImage::from_pixel(16, 16, Rgb([200, 100, 50])));
+        let settings = ProcessingSettings { force_grayscale: true, ..Default::default() };
+        let processed = process_image(&color, &settings, None, &Progress::new()).unwrap();
+        let DynamicImage::ImageLuma8(processed) = processed else {
+            panic!("forced grayscale came out as {:?}", processed.color());
+        };
+        assert!(processed.pixels().all(|pixel| pixel.0[0].abs_diff(124) <= 1), "{:?}", processed.get_pixel(8, 8));
+    }
+}
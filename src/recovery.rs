@@ -0,0 +1,261 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use eframe::egui;
+use image::{GrayImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::algorithms::region::Region;
+use crate::settings::ProcessingSettings;
+
+/// How often the session is written while the app runs.
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(10);
+// Older recovery files are not offered any more
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// Bumped whenever `RecoveryFile` changes incompatibly
+const FORMAT_VERSION: u32 = 1;
+const FILE_NAME: &str = "recovery.ron";
+// The mask is kept next to it as an image, it is too large for the text file
+const MASK_FILE_NAME: &str = "recovery_mask.png";
+
+/// What it takes to pick up the work on an image where it was left: the
+/// settings and the selection and mask, but not the image itself, which is
+/// loaded again from its file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recovery {
+    pub image_path: PathBuf,
+    /// The image was cropped or rotated in the app, which the file doesn't show
+    pub image_edited: bool,
+    /// Including the custom pipeline and whether it was used
+    pub settings: ProcessingSettings,
+    pub selection: Option<Region>,
+    pub apply_to_selection: bool,
+    /// A mask was painted, see `MASK_FILE_NAME`
+    pub has_mask: bool,
+    /// There was a processed result, which re-running brings back
+    pub processed: bool,
+}
+
+// Layout of the recovery file
+#[derive(Serialize, Deserialize)]
+struct RecoveryFile {
+    version: u32,
+    saved_at: SystemTime,
+    recovery: Recovery,
+}
+
+/// A recovery file the previous run left behind.
+pub struct RecoveredSession {
+    pub recovery: Recovery,
+    pub saved_at: SystemTime,
+    pub mask: Option<GrayImage>,
+}
+
+/// Writes the session to the app's data folder every `AUTOSAVE_INTERVAL`
+/// and on exit, if it changed.
+pub struct Autosave {
+    /// `None` when the platform has no data folder, nothing is saved then
+    directory: Option<PathBuf>,
+    last_write: Instant,
+    written: Option<Recovery>,
+    // `MaskPainter::revision` of the mask file written last
+    mask_revision: Option<u64>,
+}
+
+impl Autosave {
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        Self {
+            directory,
+            last_write: Instant::now(),
+            written: None,
+            mask_revision: None,
+        }
+    }
+
+    /// Time left until the next write is due, zero once it is.
+    pub fn due_in(&self) -> Duration {
+        AUTOSAVE_INTERVAL.saturating_sub(self.last_write.elapsed())
+    }
+
+    /// Reads what the previous run left behind. Files that can't be read,
+    /// are too old or refer to an image that is gone are deleted, with a line
+    /// on stderr saying why.
+    pub fn load(&self) -> Option<RecoveredSession> {
+        let directory = self.directory.as_ref()?;
+        let path = directory.join(FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(err) => return ignore(directory, &format!("could not read {}: {}", path.display(), err)),
+        };
+        let file: RecoveryFile = match ron::from_str(&text) {
+            Ok(file) => file,
+            Err(err) => return ignore(directory, &format!("could not parse {}: {}", path.display(), err)),
+        };
+        if file.version != FORMAT_VERSION {
+            return ignore(directory, &format!("{} has format version {}, expected {}", path.display(), file.version, FORMAT_VERSION));
+        }
+        if file.saved_at.elapsed().is_ok_and(|age| age > MAX_AGE) {
+            return ignore(directory, &format!("{} is older than {} days", path.display(), MAX_AGE.as_secs() / 86400));
+        }
+        if !file.recovery.image_path.is_file() {
+            return ignore(directory, &format!("the image {} no longer exists", file.recovery.image_path.display()));
+        }
+
+        let mask = if file.recovery.has_mask {
+            let mask_path = directory.join(MASK_FILE_NAME);
+            match image::open(&mask_path) {
+                Ok(mask) => Some(mask.into_luma8()),
+                Err(err) => {
+                    eprintln!("Restoring without the mask, could not read {}: {}", mask_path.display(), err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Some(RecoveredSession { recovery: file.recovery, saved_at: file.saved_at, mask })
+    }
+
+    /// Writes `recovery`, or removes the files when there is nothing to
+    /// recover, unless that is what was written last. The mask is only
+    /// written again when its `revision` changed.
+    pub fn write(&mut self, recovery: Option<Recovery>, mask: Option<&GrayImage>, mask_revision: u64) {
+        self.last_write = Instant::now();
+        let mask_changed = self.mask_revision != Some(mask_revision);
+        if self.written == recovery && !mask_changed {
+            return;
+        }
+        let Some(directory) = &self.directory else {
+            return;
+        };
+
+        let result = match &recovery {
+            Some(recovery) => write_files(directory, recovery, mask.filter(|_| mask_changed)),
+            None => {
+                discard(directory);
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => {
+                self.written = recovery;
+                self.mask_revision = Some(mask_revision);
+            }
+            // Tried again at the next interval
+            Err(err) => eprintln!("Could not write the recovery file to {}: {}", directory.display(), err),
+        }
+    }
+
+    /// Removes the recovery files, the user chose not to restore them.
+    pub fn discard(&mut self) {
+        if let Some(directory) = &self.directory {
+            discard(directory);
+        }
+        self.written = None;
+        self.mask_revision = None;
+    }
+}
+
+fn ignore(directory: &Path, reason: &str) -> Option<RecoveredSession> {
+    eprintln!("Ignoring the recovery file, {}", reason);
+    discard(directory);
+    None
+}
+
+fn discard(directory: &Path) {
+    // Missing files are what we want
+    let _ = fs::remove_file(directory.join(FILE_NAME));
+    let _ = fs::remove_file(directory.join(MASK_FILE_NAME));
+}
+
+// The mask goes first, a recovery file is never left pointing at a mask
+// that was not written yet
+fn write_files(directory: &Path, recovery: &Recovery, mask: Option<&GrayImage>) -> Result<(), String> {
+    fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+    if let Some(mask) = mask {
+        let mut encoded = std::io::Cursor::new(Vec::new());
+        mask.write_to(&mut encoded, ImageFormat::Png).map_err(|err| err.to_string())?;
+        write_atomically(&directory.join(MASK_FILE_NAME), encoded.get_ref())?;
+    }
+
+    let file = RecoveryFile {
+        version: FORMAT_VERSION,
+        saved_at: SystemTime::now(),
+        recovery: recovery.clone(),
+    };
+    let text = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string())?;
+    write_atomically(&directory.join(FILE_NAME), text.as_bytes())
+}
+
+// Writes a temporary file next to `path` and renames it over `path`, so a
+// crash halfway leaves either the old or the new contents
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let temporary = path.with_extension("tmp");
+    let result = fs::File::create(&temporary)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temporary, path));
+    result.map_err(|err| {
+        let _ = fs::remove_file(&temporary);
+        format!("{}: {}", path.display(), err)
+    })
+}
+
+/// What the user decided about a recovered session.
+pub enum RecoveryChoice {
+    /// Load the image with the recovered settings, processing it again if `rerun`
+    Restore { rerun: bool },
+    Discard,
+}
+
+/// Window offering to restore the session a previous run left behind.
+pub struct RecoveryDialog {
+    pub session: RecoveredSession,
+    rerun: bool,
+}
+
+impl RecoveryDialog {
+    pub fn new(session: RecoveredSession) -> Self {
+        let rerun = session.recovery.processed;
+        Self { session, rerun }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<RecoveryChoice> {
+        let mut choice = None;
+        let recovery = &self.session.recovery;
+        egui::Window::new("Restore Previous Session")
+            .resizable(false)
+            .collapsible(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                let minutes = self.session.saved_at.elapsed().map_or(0, |age| age.as_secs() / 60);
+                let age = match minutes {
+                    0 => "less than a minute".to_string(),
+                    1 => "1 minute".to_string(),
+                    minutes if minutes < 120 => format!("{} minutes", minutes),
+                    minutes => format!("{} hours", minutes / 60),
+                };
+                ui.label(format!("The app was last working on {} {} ago.", recovery.image_path.display(), age));
+                ui.label("Its settings, pipeline, selection and mask can be restored.");
+                if recovery.image_edited {
+                    ui.colored_label(ui.visuals().warn_fg_color, "The image is loaded from its file again, crops and rotations are lost");
+                }
+                ui.checkbox(&mut self.rerun, "Process it again with these settings");
+
+                ui.horizontal(|ui| {
+                    if ui.button("Restore").clicked() {
+                        choice = Some(RecoveryChoice::Restore { rerun: self.rerun });
+                    }
+                    if ui.button("Start Fresh").clicked() {
+                        choice = Some(RecoveryChoice::Discard);
+                    }
+                });
+            });
+        choice
+    }
+}
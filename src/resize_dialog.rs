@@ -0,0 +1,134 @@
+use eframe::egui;
+
+use crate::algorithms::geometry::{ResampleFilter, ResizeSettings};
+use crate::settings::ProcessingSettings;
+
+/// Window for configuring the resize step of the slider pipeline.
+pub struct ResizeDialog {
+    pub open: bool,
+    use_percent: bool,
+    percent: f32,
+    width: u32,
+    height: u32,
+    lock_aspect: bool,
+    filter: ResampleFilter,
+    resize_first: bool,
+}
+
+impl Default for ResizeDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            use_percent: true,
+            percent: 50.0,
+            width: 0,
+            height: 0,
+            lock_aspect: true,
+            filter: ResampleFilter::Lanczos3,
+            resize_first: false,
+        }
+    }
+}
+
+impl ResizeDialog {
+    /// Opens the dialog, starting from the active resize settings if there are any.
+    pub fn open(&mut self, source_size: (u32, u32), settings: &ProcessingSettings) {
+        self.open = true;
+        self.resize_first = settings.resize_first;
+        match settings.resize {
+            Some(resize) => {
+                self.use_percent = false;
+                self.width = resize.width;
+                self.height = resize.height;
+                self.filter = resize.filter;
+            }
+            None => {
+                self.width = source_size.0;
+                self.height = source_size.1;
+            }
+        }
+    }
+
+    fn target_size(&self, source_size: (u32, u32)) -> (u32, u32) {
+        if self.use_percent {
+            let scale = self.percent / 100.0;
+            (
+                ((source_size.0 as f32 * scale).round() as u32).max(1),
+                ((source_size.1 as f32 * scale).round() as u32).max(1),
+            )
+        } else {
+            (self.width.max(1), self.height.max(1))
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, source_size: (u32, u32), settings: &mut ProcessingSettings) {
+        let mut open = self.open;
+        let mut close = false;
+        let (source_width, source_height) = source_size;
+        let aspect = source_width as f32 / source_height.max(1) as f32;
+
+        egui::Window::new("Resize")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Source: {}x{}", source_width, source_height));
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.use_percent, true, "Percentage");
+                    ui.radio_value(&mut self.use_percent, false, "Pixels");
+                });
+
+                if self.use_percent {
+                    ui.add(egui::Slider::new(&mut self.percent, 1.0..=400.0).suffix("%"));
+                } else {
+                    ui.checkbox(&mut self.lock_aspect, "Lock aspect ratio");
+                    ui.horizontal(|ui| {
+                        ui.label("Width:");
+                        let width_changed = ui.add(egui::DragValue::new(&mut self.width).clamp_range(1..=65535)).changed();
+                        ui.label("Height:");
+                        let height_changed = ui.add(egui::DragValue::new(&mut self.height).clamp_range(1..=65535)).changed();
+
+                        if self.lock_aspect && width_changed {
+                            self.height = ((self.width as f32 / aspect).round() as u32).max(1);
+                        } else if self.lock_aspect && height_changed {
+                            self.width = ((self.height as f32 * aspect).round() as u32).max(1);
+                        }
+                    });
+                }
+
+                egui::ComboBox::from_label("Filter")
+                    .selected_text(format!("{:?}", self.filter))
+                    .show_ui(ui, |ui| {
+                        for filter in ResampleFilter::ALL {
+                            ui.selectable_value(&mut self.filter, filter, format!("{:?}", filter));
+                        }
+                    });
+
+                ui.checkbox(&mut self.resize_first, "Resize before denoising");
+
+                let (width, height) = self.target_size(source_size);
+                ui.label(format!("Result: {}x{}", width, height));
+                if width > source_width || height > source_height {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(230, 160, 40),
+                        "Upscaling beyond the original size cannot add detail",
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Apply").clicked() {
+                        settings.resize = Some(ResizeSettings { width, height, filter: self.filter });
+                        settings.resize_first = self.resize_first;
+                        close = true;
+                    }
+                    if settings.resize.is_some() && ui.button("Remove Resize").clicked() {
+                        settings.resize = None;
+                        close = true;
+                    }
+                });
+            });
+
+        self.open = open && !close;
+    }
+}
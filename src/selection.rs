@@ -0,0 +1,235 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Stroke, Vec2};
+
+use crate::algorithms::region::Region;
+
+// Pointer distance in screen points within which a corner handle is grabbed
+const HANDLE_GRAB_DISTANCE: f32 = 8.0;
+const HANDLE_SIZE: f32 = 6.0;
+
+/// Maps between screen positions and image pixel coordinates for an image
+/// drawn scaled into `screen_rect`.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreenMapping {
+    pub screen_rect: Rect,
+    pub image_size: Vec2,
+}
+
+impl ScreenMapping {
+    pub fn scale(self) -> f32 {
+        self.screen_rect.width() / self.image_size.x
+    }
+
+    pub fn to_image(self, pos: Pos2) -> Pos2 {
+        ((pos - self.screen_rect.min) / self.scale()).to_pos2()
+    }
+
+    pub fn to_screen(self, pos: Pos2) -> Pos2 {
+        self.screen_rect.min + pos.to_vec2() * self.scale()
+    }
+
+    pub fn rect_to_screen(self, rect: Rect) -> Rect {
+        Rect::from_min_max(self.to_screen(rect.min), self.to_screen(rect.max))
+    }
+
+    fn image_bounds(self) -> Rect {
+        Rect::from_min_size(Pos2::ZERO, self.image_size)
+    }
+}
+
+/// Width-to-height constraint for a selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AspectRatio {
+    Free,
+    Square,
+    ThreeTwo,
+    FourThree,
+    SixteenNine,
+}
+
+impl AspectRatio {
+    pub const ALL: [AspectRatio; 5] = [
+        AspectRatio::Free,
+        AspectRatio::Square,
+        AspectRatio::ThreeTwo,
+        AspectRatio::FourThree,
+        AspectRatio::SixteenNine,
+    ];
+
+    pub fn ratio(self) -> Option<f32> {
+        match self {
+            AspectRatio::Free => None,
+            AspectRatio::Square => Some(1.0),
+            AspectRatio::ThreeTwo => Some(3.0 / 2.0),
+            AspectRatio::FourThree => Some(4.0 / 3.0),
+            AspectRatio::SixteenNine => Some(16.0 / 9.0),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            AspectRatio::Free => "Free",
+            AspectRatio::Square => "1:1",
+            AspectRatio::ThreeTwo => "3:2",
+            AspectRatio::FourThree => "4:3",
+            AspectRatio::SixteenNine => "16:9",
+        }
+    }
+}
+
+enum DragMode {
+    // Creating or resizing: the rectangle spans from this fixed corner to the pointer
+    Span(Pos2),
+    // Moving: offset from the rectangle's top-left corner to the grab point
+    Move(Vec2),
+}
+
+/// A click-drag rectangle selection stored in image pixel coordinates.
+pub struct RectSelection {
+    rect: Option<Rect>,
+    drag: Option<DragMode>,
+    aspect_ratio: AspectRatio,
+    color: Color32,
+}
+
+impl Default for RectSelection {
+    fn default() -> Self {
+        Self::new(Color32::YELLOW)
+    }
+}
+
+impl RectSelection {
+    pub fn new(color: Color32) -> Self {
+        Self {
+            rect: None,
+            drag: None,
+            aspect_ratio: AspectRatio::Free,
+            color,
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> AspectRatio {
+        self.aspect_ratio
+    }
+
+    /// Changes the aspect constraint, shrinking an existing selection to fit it.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: AspectRatio) {
+        self.aspect_ratio = aspect_ratio;
+        if let Some(rect) = self.rect {
+            // Shrinking towards the top-left corner keeps the rectangle inside the image
+            self.rect = Some(Rect::from_min_size(rect.min, self.constrain_size(rect.size())));
+        }
+    }
+
+    // Largest size with the locked aspect ratio that fits inside `size`
+    fn constrain_size(&self, size: Vec2) -> Vec2 {
+        match self.aspect_ratio.ratio() {
+            Some(ratio) if size.x / ratio > size.y => Vec2::new(size.y * ratio, size.y),
+            Some(ratio) => Vec2::new(size.x, size.x / ratio),
+            None => size,
+        }
+    }
+
+
+    pub fn clear(&mut self) {
+        self.rect = None;
+        self.drag = None;
+    }
+
+    /// Selects `region`, as if it had been dragged out.
+    pub fn set_region(&mut self, region: Region) {
+        let min = Pos2::new(region.x as f32, region.y as f32);
+        self.rect = Some(Rect::from_min_size(min, Vec2::new(region.width as f32, region.height as f32)));
+        self.drag = None;
+    }
+
+    /// The selection rounded outwards to whole pixels.
+    pub fn region(&self) -> Option<Region> {
+        let rect = self.rect?;
+        let x = rect.min.x.floor().max(0.0) as u32;
+        let y = rect.min.y.floor().max(0.0) as u32;
+        let right = rect.max.x.ceil().max(0.0) as u32;
+        let bottom = rect.max.y.ceil().max(0.0) as u32;
+        let region = Region::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y));
+        (!region.is_empty()).then_some(region)
+    }
+
+    /// Updates the selection from pointer drags on `response`, the widget
+    /// showing the image mapped by `mapping`.
+    pub fn interact(&mut self, response: &egui::Response, mapping: &ScreenMapping) {
+        let bounds = mapping.image_bounds();
+        let Some(pointer) = response.interact_pointer_pos() else {
+            return;
+        };
+        let image_pos = bounds.clamp(mapping.to_image(pointer));
+
+        if response.drag_started() {
+            self.drag = Some(self.drag_mode_at(pointer, image_pos, mapping));
+        }
+
+        if response.dragged() {
+            match self.drag {
+                Some(DragMode::Span(anchor)) => {
+                    // The pointer is inside the image, so a rectangle fitted between
+                    // it and the anchor is too
+                    let delta = image_pos - anchor;
+                    let size = self.constrain_size(delta.abs());
+                    let corner = anchor + Vec2::new(size.x.copysign(delta.x), size.y.copysign(delta.y));
+                    self.rect = Some(Rect::from_two_pos(anchor, corner));
+                }
+                Some(DragMode::Move(offset)) => {
+                    if let Some(rect) = self.rect {
+                        let size = rect.size();
+                        let max_min = (bounds.max - size).max(Pos2::ZERO);
+                        let min = (image_pos - offset).clamp(Pos2::ZERO, max_min);
+                        self.rect = Some(Rect::from_min_size(min, size));
+                    }
+                }
+                None => {}
+            }
+        }
+
+        if response.drag_released() {
+            self.drag = None;
+            if self.region().is_none() {
+                self.rect = None;
+            }
+        }
+    }
+
+    fn drag_mode_at(&self, pointer: Pos2, image_pos: Pos2, mapping: &ScreenMapping) -> DragMode {
+        let Some(rect) = self.rect else {
+            return DragMode::Span(image_pos);
+        };
+
+        let corners = [rect.left_top(), rect.right_top(), rect.right_bottom(), rect.left_bottom()];
+        for (index, corner) in corners.iter().enumerate() {
+            if mapping.to_screen(*corner).distance(pointer) <= HANDLE_GRAB_DISTANCE {
+                // Resizing keeps the opposite corner fixed
+                return DragMode::Span(corners[(index + 2) % 4]);
+            }
+        }
+
+        if rect.contains(image_pos) {
+            DragMode::Move(image_pos - rect.min)
+        } else {
+            DragMode::Span(image_pos)
+        }
+    }
+
+    pub fn paint(&self, painter: &egui::Painter, mapping: &ScreenMapping) {
+        let Some(rect) = self.rect else {
+            return;
+        };
+
+        let screen_rect = mapping.rect_to_screen(rect);
+        painter.rect_stroke(screen_rect, 0.0, Stroke::new(1.5, self.color));
+        for corner in [
+            screen_rect.left_top(),
+            screen_rect.right_top(),
+            screen_rect.right_bottom(),
+            screen_rect.left_bottom(),
+        ] {
+            painter.rect_filled(Rect::from_center_size(corner, Vec2::splat(HANDLE_SIZE)), 0.0, self.color);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use eframe::egui;
+use image::DynamicImage;
+use rayon::prelude::*;
+
+use crate::image_loader::load_image_from_path;
+use crate::processing::{BatchJob, BatchSource};
+use crate::settings::ProcessingSettings;
+use crate::viewer;
+
+/// Longest side of a filmstrip thumbnail in pixels.
+const THUMBNAIL_SIZE: u32 = 96;
+
+/// One image of a session. Only its thumbnail stays in memory, the full
+/// image is loaded again from `path` whenever it becomes active.
+pub struct SessionImage {
+    pub path: PathBuf,
+    thumbnail: DynamicImage,
+    texture: Option<egui::TextureHandle>,
+    /// Settings its result was processed with, restored when it becomes active
+    pub settings: Option<ProcessingSettings>,
+    /// Its result, `None` while it is the active image, whose result is the app's
+    pub processed: Option<Arc<DynamicImage>>,
+    /// The original was cropped, rotated or otherwise edited in the app
+    pub modified: bool,
+    /// Edited original kept while another image is active, the file no longer matches it
+    pub original: Option<DynamicImage>,
+    /// Why the last batch run failed on it
+    pub error: Option<String>,
+    /// Waiting for its result from the running batch
+    pub pending: bool,
+}
+
+impl SessionImage {
+    /// The full original, the edited copy if there is one.
+    pub fn load(&mut self) -> Result<DynamicImage, String> {
+        match self.original.take() {
+            Some(original) => Ok(original),
+            None => load_image_from_path(&self.path).map_err(|err| err.to_string()),
+        }
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path.file_name().map_or_else(|| self.path.display().to_string(), |name| name.to_string_lossy().into_owned())
+    }
+}
+
+/// What the user asked for in the filmstrip.
+pub enum SessionAction {
+    /// Make this image the active one
+    Select(usize),
+    /// Process every image with the current settings
+    ApplyToAll,
+    /// Leave the session, keeping only the active image
+    Close,
+}
+
+/// Several images worked on one at a time, see `MyApp::select_session_image`.
+pub struct Session {
+    pub images: Vec<SessionImage>,
+    /// Index of the image in the panes
+    pub active: usize,
+    pub job: Option<BatchJob>,
+}
+
+impl Session {
+    /// Loads every file once to make its thumbnail, in parallel. Returns the
+    /// session with its first image loaded fully and the files that failed,
+    /// or only those when none could be loaded.
+    pub fn open(paths: Vec<PathBuf>) -> Result<(Self, DynamicImage, Vec<String>), Vec<String>> {
+        // Full images are dropped as soon as their thumbnail is made, except
+        // for the first file which becomes active
+        let loaded: Vec<_> = paths
+            .into_par_iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let img = load_image_from_path(&path).map_err(|err| err.to_string())?;
+                let thumbnail = img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+                Ok((path, thumbnail, (index == 0).then_some(img)))
+            })
+            .collect();
+
+        let mut errors = Vec::new();
+        let mut first = None;
+        let mut images = Vec::new();
+        for result in loaded {
+            match result {
+                Ok((path, thumbnail, full)) => {
+                    first = first.or(full);
+                    images.push(SessionImage {
+                        path,
+                        thumbnail,
+                        texture: None,
+                        settings: None,
+                        processed: None,
+                        modified: false,
+                        original: None,
+                        error: None,
+                        pending: false,
+                    });
+                }
+                Err(err) => errors.push(err),
+            }
+        }
+        if images.is_empty() {
+            return Err(errors);
+        }
+
+        let first = match first {
+            Some(first) => first,
+            // The first file failed, the first one that loaded takes its place
+            None => images[0].load().map_err(|err| vec![err])?,
+        };
+        Ok((Self { images, active: 0, job: None }, first, errors))
+    }
+
+    pub fn active_mut(&mut self) -> &mut SessionImage {
+        &mut self.images[self.active]
+    }
+
+    /// Starts processing every image with `settings`. The active image is
+    /// processed from `active_original`, the others from their files or
+    /// edited copies.
+    pub fn process_all(&mut self, active_original: &DynamicImage, settings: &ProcessingSettings) {
+        let sources = self
+            .images
+            .iter()
+            .enumerate()
+            .map(|(index, image)| match &image.original {
+                _ if index == self.active && image.modified => BatchSource::Image(active_original.clone()),
+                Some(original) => BatchSource::Image(original.clone()),
+                None => BatchSource::File(image.path.clone()),
+            })
+            .collect();
+        for image in &mut self.images {
+            image.error = None;
+            image.pending = true;
+        }
+        self.job = Some(BatchJob::spawn(sources, settings.clone()));
+    }
+
+    /// A horizontal strip with a thumbnail per image, marked once it has a
+    /// result. `active_processed` says whether the active image has one,
+    /// `idle` whether nothing else is being processed.
+    pub fn show(&mut self, ui: &mut egui::Ui, active_processed: bool, idle: bool) -> Option<SessionAction> {
+        let mut action = None;
+        ui.horizontal(|ui| {
+            egui::ScrollArea::horizontal().id_source("filmstrip").max_width((ui.available_width() - 220.0).max(100.0)).show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (index, image) in self.images.iter_mut().enumerate() {
+                        let texture = image.texture.get_or_insert_with(|| {
+                            let rgba = image.thumbnail.to_rgba8();
+                            let color_image = egui::ColorImage::from_rgba_unmultiplied(
+                                [rgba.width() as usize, rgba.height() as usize],
+                                rgba.as_raw(),
+                            );
+                            ui.ctx().load_texture(format!("thumbnail_{}", index), color_image, viewer::TEXTURE_OPTIONS)
+                        });
+                        let thumbnail = (texture.id(), texture.size_vec2());
+                        let processed = if index == self.active { active_processed } else { image.processed.is_some() };
+                        let progress = self.job.as_ref().filter(|_| image.pending).map(|job| job.progress_of(index));
+
+                        ui.vertical(|ui| {
+                            ui.set_width(THUMBNAIL_SIZE as f32);
+                            let button = egui::ImageButton::new(thumbnail).selected(index == self.active);
+                            let response = ui.add(button).on_hover_text(image.path.display().to_string());
+                            if response.clicked() && index != self.active {
+                                action = Some(SessionAction::Select(index));
+                            }
+
+                            let name = egui::RichText::new(image.file_name()).small();
+                            match (&image.error, progress) {
+                                (Some(err), _) => {
+                                    ui.label(name.color(ui.visuals().error_fg_color)).on_hover_text(err);
+                                }
+                                (None, Some(progress)) => {
+                                    ui.add(egui::ProgressBar::new(progress).desired_width(THUMBNAIL_SIZE as f32));
+                                }
+                                (None, None) if processed => {
+                                    ui.label(egui::RichText::new(format!("✔ {}", image.file_name())).small());
+                                }
+                                (None, None) => {
+                                    ui.label(name.weak());
+                                }
+                            }
+                        });
+                    }
+                });
+            });
+
+            ui.vertical(|ui| {
+                match &self.job {
+                    Some(job) => {
+                        if ui.button("Cancel").clicked() {
+                            job.cancel();
+                        }
+                        ui.label(egui::RichText::new("Processing all images...").small());
+                    }
+                    None => {
+                        if ui.add_enabled(idle, egui::Button::new("Apply current settings to all"))
+                            .on_hover_text("Process every image of the session with the settings shown below")
+                            .clicked()
+                        {
+                            action = Some(SessionAction::ApplyToAll);
+                        }
+                    }
+                }
+                if ui.button("Close Session").on_hover_text("Keep working on the active image only").clicked() {
+                    action = Some(SessionAction::Close);
+                }
+            });
+        });
+        action
+    }
+}
@@ -0,0 +1,348 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::algorithms::backend::Backend;
+use crate::algorithms::border::BorderMode;
+use crate::algorithms::deconvolution::DeconvolutionSettings;
+use crate::algorithms::denoise::{DenoiseType, PlaneStrengths};
+use crate::algorithms::document::DocumentSettings;
+use crate::algorithms::geometry::ResizeSettings;
+use crate::algorithms::hot_pixels::DEFAULT_HOT_PIXEL_THRESHOLD;
+use crate::algorithms::hsl::{HslBand, HslRange};
+use crate::algorithms::lut::Lut3d;
+use crate::algorithms::notch::Notch;
+use crate::algorithms::pipeline::{default_detail_radius, Operation, Pipeline};
+use crate::algorithms::quantize::Dither;
+use crate::algorithms::sharpness::SharpenKernel;
+use crate::algorithms::white_balance::WhiteBalance;
+use crate::export::ExportOptions;
+use crate::history::DEFAULT_HISTORY_DEPTH;
+use crate::watermark::Watermark;
+
+/// Every user-tweakable processing parameter, grouped so a run can be
+/// snapshotted and restored as a whole.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+// Fields added since the settings were stored keep their defaults
+#[serde(default)]
+pub struct ProcessingSettings {
+    /// Binarize scanned pages instead of the photographic processing
+    pub document_mode: bool,
+    pub document: DocumentSettings,
+    /// Periodic noise frequencies removed first, placed on the spectrum
+    pub notches: Vec<Notch>,
+    /// Repair stuck pixels before denoising
+    pub hot_pixels: bool,
+    /// Neighbourhood side of the hot pixel repair, 3 or 5
+    pub hot_pixel_window: usize,
+    /// Share of the full range a hot pixel stands out by
+    pub hot_pixel_threshold: f32,
+    pub denoise_type: DenoiseType,
+    pub kernel_size: usize,
+    /// Removes a color cast, applied before exposure
+    pub white_balance: WhiteBalance,
+    /// In stops, applied before brightness
+    pub exposure: f32,
+    /// 0-1, lifts dark regions
+    pub shadows: f32,
+    /// 0-1, darkens bright regions
+    pub highlights: f32,
+    /// Blur radius of the shadows/highlights mask in pixels
+    pub tone_mask_radius: f32,
+    /// 0-1
+    pub dehaze: f32,
+    pub brightness: f32,
+    pub contrast: f32,
+    /// 0-3, see `sharpen_image`
+    pub sharpness: f32,
+    pub sharpen_kernel: SharpenKernel,
+    /// One band per `HslRange`, in its order
+    pub hsl: Vec<HslBand>,
+    /// Color grade applied last, see `apply_lut`
+    pub lut: Option<Arc<Lut3d>>,
+    /// 0-1
+    pub lut_intensity: f32,
+    /// Reduce the channels to a few levels after everything else
+    pub posterize: bool,
+    /// 2-32 per channel
+    pub posterize_levels: u32,
+    pub posterize_dither: Dither,
+    pub tv_lambda: f32,
+    pub tv_iterations: usize,
+    /// See `Operation::Denoise::tv_tolerance`
+    pub tv_tolerance: f32,
+    /// Denoise luminance and color separately, see `denoise_ycbcr_with_progress`
+    #[serde(alias = "chroma_only")]
+    pub separate_planes: bool,
+    /// 0-1, strength of the luminance noise reduction in separate mode
+    pub luma_nr: f32,
+    /// 0-1, strength of the color noise reduction in separate mode
+    pub color_nr: f32,
+    /// Average light instead of gamma encoded values when blurring and sharpening
+    pub linear_light: bool,
+    /// Kernel size of the color planes in separate mode, color noise is
+    /// coarser than luma noise. The luminance keeps `kernel_size`
+    pub chroma_kernel_size: usize,
+    /// 0-1, share of the original's fine detail put back after denoising
+    pub detail: f32,
+    /// Radius in pixels of what counts as fine detail
+    pub detail_radius: f32,
+    /// Deblur after denoising, see `deconvolve`
+    pub deconvolve: bool,
+    pub deconvolution: DeconvolutionSettings,
+    pub use_parallel: bool,
+    /// Where denoising and sharpening run, when the filter has a GPU version
+    pub backend: Backend,
+    /// What denoising and sharpening read past the image edges
+    pub border: BorderMode,
+    pub block_size: u32,
+    /// Worker threads for a processing run, 0 to use every core
+    pub threads: usize,
+    pub resize: Option<ResizeSettings>,
+    /// Resize before denoising instead of after sharpening
+    pub resize_first: bool,
+    /// Convert color sources to gray before anything else
+    pub force_grayscale: bool,
+    pub use_custom_pipeline: bool,
+    pub custom_pipeline: Pipeline,
+}
+
+impl Default for ProcessingSettings {
+    fn default() -> Self {
+        Self {
+            document_mode: false,
+            document: DocumentSettings::default(),
+            notches: Vec::new(),
+            hot_pixels: true,
+            hot_pixel_window: 3,
+            hot_pixel_threshold: DEFAULT_HOT_PIXEL_THRESHOLD,
+            denoise_type: DenoiseType::MeanFilter,
+            kernel_size: 3,
+            white_balance: WhiteBalance::default(),
+            exposure: 0.0,
+            shadows: 0.0,
+            highlights: 0.0,
+            tone_mask_radius: 30.0,
+            dehaze: 0.0,
+            brightness: 0.0,
+            contrast: 0.0,
+            sharpness: 0.0,
+            sharpen_kernel: SharpenKernel::default(),
+            hsl: HslRange::ALL.map(HslBand::neutral).to_vec(),
+            lut: None,
+            lut_intensity: 1.0,
+            posterize: false,
+            posterize_levels: 4,
+            posterize_dither: Dither::Ordered,
+            tv_lambda: 0.1,
+            tv_iterations: 50,
+            tv_tolerance: 1e-4,
+            separate_planes: false,
+            luma_nr: PlaneStrengths::CHROMA_ONLY.luma,
+            color_nr: PlaneStrengths::CHROMA_ONLY.chroma,
+            linear_light: true,
+            chroma_kernel_size: 7,
+            detail: 0.0,
+            detail_radius: default_detail_radius(),
+            deconvolve: false,
+            deconvolution: DeconvolutionSettings::default(),
+            use_parallel: false,
+            backend: Backend::Cpu,
+            border: BorderMode::default(),
+            block_size: 64,
+            threads: 0,
+            resize: None,
+            resize_first: false,
+            force_grayscale: false,
+            use_custom_pipeline: false,
+            custom_pipeline: Pipeline::default(),
+        }
+    }
+}
+
+impl ProcessingSettings {
+    /// The classic fixed order driven by the sliders:
+    /// notch filter, hot pixel repair, denoise, deconvolution, then white balance, exposure, shadows/highlights, dehaze, brightness,
+    /// contrast, HSL, sharpening, the LUT and posterization.
+    /// In document mode only the luma is kept, resized if asked to, and
+    /// binarized, resizing last would bring back shades of gray.
+    pub fn slider_pipeline(&self) -> Pipeline {
+        if self.document_mode {
+            let mut operations = vec![Operation::Grayscale];
+            operations.extend(self.resize.map(Operation::Resize));
+            operations.push(Operation::Document(self.document));
+            return Pipeline(operations);
+        }
+
+        let mut operations = Vec::new();
+
+        if self.force_grayscale {
+            operations.push(Operation::Grayscale);
+        }
+
+        // The notches were placed on the spectrum at the source resolution
+        if !self.notches.is_empty() {
+            operations.push(Operation::NotchFilter(self.notches.clone()));
+        }
+
+        if let (Some(resize), true) = (self.resize, self.resize_first) {
+            operations.push(Operation::Resize(resize));
+        }
+
+        if self.hot_pixels {
+            operations.push(Operation::HotPixels {
+                window: self.hot_pixel_window,
+                threshold: self.hot_pixel_threshold,
+            });
+        }
+
+        operations.push(Operation::Denoise {
+            denoise_type: self.denoise_type,
+            kernel_size: self.kernel_size,
+            tv_lambda: self.tv_lambda,
+            tv_iterations: self.tv_iterations,
+            tv_tolerance: self.tv_tolerance,
+            planes: self.separate_planes.then_some(PlaneStrengths {
+                luma: self.luma_nr,
+                chroma: self.color_nr,
+                chroma_kernel_size: Some(self.chroma_kernel_size),
+            }),
+            linear_light: self.linear_light,
+            backend: self.backend,
+            border: self.border,
+            detail: self.detail,
+            detail_radius: self.detail_radius,
+        });
+
+        if self.deconvolve {
+            operations.push(Operation::Deconvolve(self.deconvolution));
+        }
+
+        if !self.white_balance.is_neutral() {
+            operations.push(Operation::WhiteBalance(self.white_balance));
+        }
+
+        if self.exposure != 0.0 {
+            operations.push(Operation::Exposure(self.exposure));
+        }
+
+        if self.shadows != 0.0 || self.highlights != 0.0 {
+            operations.push(Operation::ShadowsHighlights {
+                shadows: self.shadows,
+                highlights: self.highlights,
+                radius: self.tone_mask_radius,
+            });
+        }
+
+        if self.dehaze != 0.0 {
+            operations.push(Operation::Dehaze(self.dehaze));
+        }
+
+        if self.brightness != 0.0 {
+            operations.push(Operation::Brightness(self.brightness));
+        }
+
+        if self.contrast != 0.0 {
+            operations.push(Operation::Contrast(self.contrast));
+        }
+
+        if !self.hsl.iter().all(HslBand::is_neutral) {
+            operations.push(Operation::Hsl(self.hsl.clone()));
+        }
+
+        if self.sharpness > 0.0 {
+            operations.push(Operation::Sharpen {
+                amount: self.sharpness,
+                kernel: self.sharpen_kernel,
+                linear_light: self.linear_light,
+                backend: self.backend,
+                border: self.border,
+            });
+        }
+
+        if let (Some(resize), false) = (self.resize, self.resize_first) {
+            operations.push(Operation::Resize(resize));
+        }
+
+        if let Some(lut) = &self.lut {
+            operations.push(Operation::Lut {
+                lut: Arc::clone(lut),
+                intensity: self.lut_intensity,
+            });
+        }
+
+        if self.posterize {
+            operations.push(Operation::Posterize {
+                levels: self.posterize_levels,
+                dither: self.posterize_dither,
+            });
+        }
+
+        Pipeline(operations)
+    }
+
+    /// The pipeline a processing run should execute.
+    pub fn pipeline(&self) -> Pipeline {
+        if self.use_custom_pipeline {
+            self.custom_pipeline.clone().with_backend(self.backend).with_border(self.border)
+        } else {
+            self.slider_pipeline()
+        }
+    }
+
+    /// The same pipeline with the slow denoisers left out, for live previews.
+    /// Resizing is skipped too since the preview works on a downscaled copy.
+    pub fn preview_pipeline(&self) -> Pipeline {
+        Pipeline(
+            self.pipeline()
+                .0
+                .into_iter()
+                .filter(|operation| !operation.is_expensive() && !operation.changes_dimensions())
+                .collect(),
+        )
+    }
+}
+
+/// Size above which opened images are offered to be worked on in
+/// low-memory mode, see `LargeImage`.
+pub const DEFAULT_LOW_MEMORY_MEGAPIXELS: u32 = 100;
+
+/// Everything remembered between sessions, which is all the user's choices
+/// but not the images themselves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    pub settings: ProcessingSettings,
+    pub live_preview: bool,
+    pub history_depth: usize,
+    pub export_options: ExportOptions,
+    /// Drawn onto exported files, see `apply_watermark`
+    pub watermark: Watermark,
+    /// Folder the open dialogs start in
+    pub open_directory: Option<PathBuf>,
+    /// Folder the export dialog starts in
+    pub export_directory: Option<PathBuf>,
+    /// Images larger than this are offered to open in low-memory mode
+    pub low_memory_megapixels: u32,
+}
+
+impl SavedState {
+    /// Key the state is stored under in eframe's storage.
+    pub const KEY: &'static str = "saved_state";
+}
+
+impl Default for SavedState {
+    fn default() -> Self {
+        Self {
+            settings: ProcessingSettings::default(),
+            live_preview: true,
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            export_options: ExportOptions::default(),
+            watermark: Watermark::default(),
+            open_directory: None,
+            export_directory: None,
+            low_memory_megapixels: DEFAULT_LOW_MEMORY_MEGAPIXELS,
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use image::DynamicImage;
+use rfd::FileDialog;
+use serde::{Deserialize, Serialize};
+
+use crate::algorithms::pipeline::Operation;
+use crate::algorithms::region::Region;
+use crate::export::ExportOptions;
+use crate::image_loader::remember_directory;
+use crate::settings::ProcessingSettings;
+
+// Bumped whenever `ProcessingRecord` changes incompatibly
+const FORMAT_VERSION: u32 = 1;
+
+/// How the result in the panes was made, known once the run finishes.
+#[derive(Debug, Clone)]
+pub struct ProcessingRun {
+    pub settings: ProcessingSettings,
+    pub selection: Option<Region>,
+    /// The result was blended with the original through a painted mask
+    pub masked: bool,
+    /// `None` for results brought back from the history or a session,
+    /// which were not timed when they were shown again
+    pub duration: Option<Duration>,
+    pub timings: Vec<(String, Duration)>,
+    /// Only the adjustments ran, see `Intermediate`
+    pub resumed: bool,
+}
+
+impl ProcessingRun {
+    /// A run known only by what it was asked to do.
+    pub fn untimed(settings: ProcessingSettings, selection: Option<Region>, masked: bool) -> Self {
+        Self {
+            settings,
+            selection,
+            masked,
+            duration: None,
+            timings: Vec::new(),
+            resumed: false,
+        }
+    }
+}
+
+/// What was done to an exported image, written next to it as JSON (see
+/// `sidecar_path`) to document the work and to reproduce it later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessingRecord {
+    pub format_version: u32,
+    /// Version of the app that did the processing
+    pub app_version: String,
+    /// When the file was exported, in UTC
+    pub exported_at: String,
+    /// File the image was loaded from, `None` for pasted or stacked images
+    pub source_path: Option<PathBuf>,
+    /// The image was cropped, rotated or resized in the app before it was
+    /// processed, which the file doesn't show
+    pub source_edited: bool,
+    /// `pixel_hash` of the image that was processed
+    pub source_hash: String,
+    pub source_size: (u32, u32),
+    /// Every operation in the order it ran, with its parameters. Spelled
+    /// out for the reader, `settings` is what reproduces them.
+    pub pipeline: Vec<Operation>,
+    pub settings: ProcessingSettings,
+    /// Only this part of the image was processed
+    pub selection: Option<Region>,
+    /// The result was blended with the original through a painted mask,
+    /// which isn't part of the record
+    pub masked: bool,
+    pub export: ExportOptions,
+    /// A watermark was drawn onto the exported file
+    pub watermarked: bool,
+    /// Wall time of the run in seconds, `None` when it wasn't timed
+    pub duration: Option<f64>,
+    /// Seconds spent in each stage, in the order they first ran
+    pub stage_durations: Vec<(String, f64)>,
+    /// The run reused the restoration result of the previous one and only
+    /// timed the adjustments
+    pub resumed: bool,
+}
+
+impl ProcessingRecord {
+    /// The record of `run` on `source`, exported with `export`.
+    pub fn new(
+        source: &DynamicImage,
+        source_path: Option<PathBuf>,
+        source_edited: bool,
+        run: &ProcessingRun,
+        export: ExportOptions,
+        watermarked: bool,
+    ) -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            exported_at: utc_timestamp(SystemTime::now()),
+            source_path,
+            source_edited,
+            source_hash: pixel_hash(source),
+            source_size: (source.width(), source.height()),
+            pipeline: run.settings.pipeline().0,
+            settings: run.settings.clone(),
+            selection: run.selection,
+            masked: run.masked,
+            export,
+            watermarked,
+            duration: run.duration.map(|duration| duration.as_secs_f64()),
+            stage_durations: run.timings.iter().map(|(name, duration)| (name.clone(), duration.as_secs_f64())).collect(),
+            resumed: run.resumed,
+        }
+    }
+
+    /// Whether `img` is the image the record was made from.
+    pub fn matches_source(&self, img: &DynamicImage) -> bool {
+        self.source_size == (img.width(), img.height()) && self.source_hash == pixel_hash(img)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(path, text).map_err(|err| format!("Could not write {}: {}", path.display(), err))
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        // Checked on its own first, a newer record may not parse at all
+        #[derive(Deserialize)]
+        struct Version {
+            format_version: u32,
+        }
+        let version: Version = serde_json::from_str(&text).map_err(|err| format!("{} is not a processing record: {}", path.display(), err))?;
+        if version.format_version != FORMAT_VERSION {
+            return Err(format!(
+                "{} has record format {}, this version reads {}",
+                path.display(),
+                version.format_version,
+                FORMAT_VERSION
+            ));
+        }
+        serde_json::from_str(&text).map_err(|err| format!("Could not parse {}: {}", path.display(), err))
+    }
+}
+
+/// Where the record of an export to `path` goes: the file name with
+/// `.json` appended, so exports differing only in format keep theirs apart.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".json");
+    path.with_file_name(name)
+}
+
+/// Lets the user pick a processing record and reads it. Returns `Ok(None)`
+/// when the dialog was cancelled. `directory` is handled like in
+/// `crate::image_loader::pick_image_file`.
+pub fn load_record(directory: &mut Option<PathBuf>) -> Result<Option<ProcessingRecord>, String> {
+    let Some(path) = FileDialog::new()
+        .add_filter("Processing Record", &["json"])
+        .set_directory(directory.as_deref().unwrap_or(Path::new(".")))
+        .pick_file()
+    else {
+        return Ok(None);
+    };
+    remember_directory(directory, &path);
+    ProcessingRecord::read(&path).map(Some)
+}
+
+/// 64-bit FNV-1a hash of the size, format and samples of `img`, as 16 hex
+/// digits. Tells images apart, it is no protection against tampering.
+pub fn pixel_hash(img: &DynamicImage) -> String {
+    let header = format!("{}x{} {:?}", img.width(), img.height(), img.color());
+    let hash = header
+        .as_bytes()
+        .iter()
+        .chain(img.as_bytes())
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3));
+    format!("{:016x}", hash)
+}
+
+// `time` as RFC 3339 in UTC, to the second
+fn utc_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date of a day count, after Howard Hinnant's `civil_from_days`
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::algorithms::benchmark::add_gaussian_noise;
+    use crate::algorithms::denoise::DenoiseType;
+    use crate::algorithms::progress::Progress;
+    use crate::export::{save_image, ExportFormat};
+    use crate::processing::process_image;
+
+    fn noisy_image() -> DynamicImage {
+        let gradient = RgbImage::from_fn(40, 32, |x, y| Rgb([(x * 6) as u8, (y * 7) as u8, 90]));
+        add_gaussian_noise(&DynamicImage::ImageRgb8(gradient), 18.0, 11)
+    }
+
+    #[test]
+    fn record_reproduces_export() {
+        let folder = std::env::temp_dir().join(format!("sidecar-round-trip-{}", std::process::id()));
+        fs::create_dir_all(&folder).unwrap();
+        let source = noisy_image();
+        let source_path = folder.join("source.png");
+        source.save(&source_path).unwrap();
+
+        // Export a result with its record, the way the export dialog does
+        let mut settings = ProcessingSettings {
+            denoise_type: DenoiseType::BilateralFilter,
+            exposure: 0.3,
+            sharpness: 0.6,
+            ..Default::default()
+        };
+        let export = ExportOptions { format: ExportFormat::Png, sidecar: true, ..Default::default() };
+        let processed = process_image(&source, &settings, None, &Progress::new()).unwrap();
+        let exported = folder.join("result.png");
+        save_image(&processed, &exported, &export).unwrap();
+        let run = ProcessingRun::untimed(settings.clone(), None, false);
+        let record = ProcessingRecord::new(&source, Some(source_path.clone()), false, &run, export, false);
+        record.write(&sidecar_path(&exported)).unwrap();
+        assert_eq!(sidecar_path(&exported), folder.join("result.png.json"));
+
+        // Forget everything, then rebuild the export from the record alone
+        settings = ProcessingSettings::default();
+        let read = ProcessingRecord::read(&sidecar_path(&exported)).unwrap();
+        assert_eq!(read, record);
+        let reloaded = image::open(read.source_path.as_ref().unwrap()).unwrap();
+        assert!(read.matches_source(&reloaded));
+        assert_ne!(settings, read.settings);
+        settings = read.settings.clone();
+        let reproduced = folder.join("reproduced.png");
+        save_image(&process_image(&reloaded, &settings, None, &Progress::new()).unwrap(), &reproduced, &read.export).unwrap();
+
+        let identical = fs::read(&exported).unwrap() == fs::read(&reproduced).unwrap();
+        fs::remove_dir_all(&folder).unwrap();
+        assert!(identical, "the record didn't reproduce the exported file");
+    }
+
+    #[test]
+    fn rejects_other_format_versions() {
+        let path = std::env::temp_dir().join(format!("sidecar-version-{}.json", std::process::id()));
+        fs::write(&path, r#"{"format_version": 99}"#).unwrap();
+        let result = ProcessingRecord::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().contains("format 99"));
+    }
+
+    #[test]
+    fn pixel_hash_tells_images_apart() {
+        let img = noisy_image();
+        assert_eq!(pixel_hash(&img), pixel_hash(&img.clone()));
+        assert_ne!(pixel_hash(&img), pixel_hash(&DynamicImage::ImageRgb16(img.to_rgb16())));
+        let mut changed = img.to_rgb8();
+        changed.get_pixel_mut(5, 5).0[0] ^= 1;
+        assert_ne!(pixel_hash(&img), pixel_hash(&DynamicImage::ImageRgb8(changed)));
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(utc_timestamp(SystemTime::UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = SystemTime::UNIX_EPOCH + Duration::from_secs(1_709_217_045);
+        assert_eq!(utc_timestamp(leap_day), "2024-02-29T14:30:45Z");
+    }
+}
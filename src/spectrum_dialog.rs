@@ -0,0 +1,192 @@
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use eframe::egui::{self, Color32, Pos2, Rect, Stroke};
+use image::DynamicImage;
+
+use crate::algorithms::notch::{detect_notches, Notch, Spectrum, DEFAULT_NOTCH_RADIUS};
+use crate::viewer;
+
+// Cells a side of the spectrum, which peak detection works on as well
+const SPECTRUM_SIDE: u32 = 512;
+// Frequencies this close to zero, in cycles per pixel, are the image
+// itself, clicks there place nothing
+const MIN_NOTCH_FREQUENCY: f32 = 0.01;
+// Points a right click may miss a notch by and still remove it
+const PICK_DISTANCE: f32 = 10.0;
+const NOTCH_COLOR: Color32 = Color32::from_rgb(255, 90, 40);
+
+/// Window showing the log-magnitude spectrum of the original, where
+/// notches against periodic noise are placed.
+pub struct SpectrumDialog {
+    pub open: bool,
+    /// Radius of the notches placed next, in cycles per pixel
+    radius: f32,
+    spectrum: Option<(Spectrum, egui::TextureHandle)>,
+    // The spectrum being computed on a background thread
+    receiver: Option<Receiver<Spectrum>>,
+    // Outcome of the last peak detection
+    detected: Option<String>,
+}
+
+impl Default for SpectrumDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            radius: DEFAULT_NOTCH_RADIUS,
+            spectrum: None,
+            receiver: None,
+            detected: None,
+        }
+    }
+}
+
+impl SpectrumDialog {
+    /// Forgets the spectrum, which belonged to the previous original.
+    pub fn clear(&mut self) {
+        self.spectrum = None;
+        self.receiver = None;
+        self.detected = None;
+    }
+
+    /// Shows the window for `image`, adding to and removing from `notches`.
+    pub fn show(&mut self, ctx: &egui::Context, image: Option<&DynamicImage>, notches: &mut Vec<Notch>) {
+        if !self.open {
+            return;
+        }
+        self.poll(ctx);
+        if let (None, None, Some(image)) = (&self.spectrum, &self.receiver, image) {
+            let (sender, receiver) = mpsc::channel();
+            let image = image.clone();
+            thread::spawn(move || {
+                let _ = sender.send(Spectrum::of(&image, SPECTRUM_SIDE));
+            });
+            self.receiver = Some(receiver);
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Periodic Noise")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Click a bright spike away from the center to notch it and its mirror image, right-click a notch to remove it.");
+                ui.horizontal(|ui| {
+                    ui.add(egui::Slider::new(&mut self.radius, 0.001..=0.05).logarithmic(true).text("radius"))
+                        .on_hover_text("Width of new notches in cycles per pixel, wider ones also catch smeared spikes but take more detail");
+                    if ui.add_enabled(self.spectrum.is_some(), egui::Button::new("Detect Peaks"))
+                        .on_hover_text("Place notches on the strongest spikes, the mark of a halftone screen")
+                        .clicked()
+                    {
+                        if let Some((spectrum, _)) = &self.spectrum {
+                            let detected = detect_notches(spectrum, self.radius);
+                            self.detected = Some(match detected.len() {
+                                0 => "No spikes stand out, nothing to notch".to_string(),
+                                count => format!("Found {} spikes", count),
+                            });
+                            for notch in detected {
+                                if nearest(notches, notch.fx, notch.fy).is_none() {
+                                    notches.push(notch);
+                                }
+                            }
+                        }
+                    }
+                    if ui.add_enabled(!notches.is_empty(), egui::Button::new("Clear")).clicked() {
+                        notches.clear();
+                    }
+                });
+                if let Some(detected) = &self.detected {
+                    ui.label(egui::RichText::new(detected).weak());
+                }
+
+                match &self.spectrum {
+                    Some((spectrum, texture)) => spectrum_view(ui, spectrum, texture, self.radius, notches),
+                    None if image.is_some() => {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label("Transforming...");
+                        });
+                    }
+                    None => {
+                        ui.label("Open an image first");
+                    }
+                }
+                ui.label(match notches.len() {
+                    0 => "No notches, the filter is off".to_string(),
+                    1 => "1 notch, applied with the next full run".to_string(),
+                    count => format!("{} notches, applied with the next full run", count),
+                });
+            });
+        self.open = open;
+    }
+
+    fn poll(&mut self, ctx: &egui::Context) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok(spectrum) => {
+                let gray = spectrum.to_image();
+                let color_image = egui::ColorImage::from_gray([gray.width() as usize, gray.height() as usize], gray.as_raw());
+                let texture = ctx.load_texture("spectrum", color_image, viewer::TEXTURE_OPTIONS);
+                self.spectrum = Some((spectrum, texture));
+                self.receiver = None;
+            }
+            Err(TryRecvError::Empty) => ctx.request_repaint_after(Duration::from_millis(100)),
+            Err(TryRecvError::Disconnected) => self.receiver = None,
+        }
+    }
+}
+
+// Index of the notch whose center, or mirror image, is closest to
+// (`fx`, `fy`) and within a notch radius of it
+fn nearest(notches: &[Notch], fx: f32, fy: f32) -> Option<usize> {
+    notches
+        .iter()
+        .enumerate()
+        .map(|(index, notch)| {
+            let distance = (fx - notch.fx).hypot(fy - notch.fy).min((fx + notch.fx).hypot(fy + notch.fy));
+            (index, distance, notch.radius)
+        })
+        .filter(|&(_, distance, radius)| distance < radius)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _, _)| index)
+}
+
+fn spectrum_view(ui: &mut egui::Ui, spectrum: &Spectrum, texture: &egui::TextureHandle, radius: f32, notches: &mut Vec<Notch>) {
+    // Small images have few frequencies, they are shown enlarged
+    let scale = SPECTRUM_SIDE as f32 / spectrum.width.max(spectrum.height) as f32;
+    let size = egui::vec2(spectrum.width as f32, spectrum.height as f32) * scale;
+    let (rect, response) = ui.allocate_exact_size(size, egui::Sense::click());
+    let painter = ui.painter_at(rect);
+    painter.image(texture.id(), rect, Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)), Color32::WHITE);
+
+    let frequency_at = |pos: Pos2| spectrum.frequency_at((pos.x - rect.left()) / scale, (pos.y - rect.top()) / scale);
+    if let Some(pos) = response.interact_pointer_pos() {
+        let (fx, fy) = frequency_at(pos);
+        if response.clicked() && fx.hypot(fy) >= MIN_NOTCH_FREQUENCY {
+            notches.push(Notch { fx, fy, radius });
+        } else if response.secondary_clicked() {
+            // Small notches are hard to hit, a few points of slack help
+            let slack = PICK_DISTANCE / size.x;
+            let widened: Vec<Notch> = notches.iter().map(|notch| Notch { radius: notch.radius + slack, ..*notch }).collect();
+            if let Some(index) = nearest(&widened, fx, fy) {
+                notches.remove(index);
+            }
+        }
+    }
+
+    for notch in notches.iter() {
+        let radius = notch.radius * size.x;
+        for (fx, fy) in [(notch.fx, notch.fy), (-notch.fx, -notch.fy)] {
+            let (x, y) = spectrum.position_of(fx, fy);
+            painter.circle_stroke(rect.min + egui::vec2(x, y) * scale, radius.max(2.0), Stroke::new(1.5, NOTCH_COLOR));
+        }
+    }
+
+    if let Some(pos) = response.hover_pos() {
+        let (fx, fy) = frequency_at(pos);
+        let period = 1.0 / fx.hypot(fy).max(f32::EPSILON);
+        response.on_hover_text_at_pointer(format!("{:+.3}, {:+.3} cycles/pixel, a period of {:.1} px", fx, fy, period));
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::PathBuf;
+
+use eframe::egui;
+use image::DynamicImage;
+
+use crate::algorithms::stack::{stack_images, StackMethod};
+use crate::image_loader::{load_image_from_path, pick_image_files};
+
+struct StackFrame {
+    path: PathBuf,
+    image: Result<DynamicImage, String>,
+}
+
+/// Window collecting exposures of the same scene and combining them into
+/// a new original image.
+pub struct StackDialog {
+    pub open: bool,
+    frames: Vec<StackFrame>,
+    method: StackMethod,
+    error: Option<String>,
+}
+
+impl Default for StackDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            frames: Vec::new(),
+            method: StackMethod::Mean,
+            error: None,
+        }
+    }
+}
+
+impl StackDialog {
+    // Size every frame has to match, taken from the first one that loaded
+    fn frame_size(&self) -> Option<(u32, u32)> {
+        self.frames
+            .iter()
+            .find_map(|frame| frame.image.as_ref().ok())
+            .map(|image| (image.width(), image.height()))
+    }
+
+    // Why a frame is left out of the stack, if it is
+    fn problem(&self, frame: &StackFrame) -> Option<String> {
+        match (&frame.image, self.frame_size()) {
+            (Err(err), _) => Some(err.clone()),
+            (Ok(image), Some((width, height))) if (image.width(), image.height()) != (width, height) => Some(format!(
+                "{}x{}, expected {}x{}",
+                image.width(),
+                image.height(),
+                width,
+                height
+            )),
+            _ => None,
+        }
+    }
+
+    fn add_files(&mut self, directory: &mut Option<PathBuf>) {
+        for path in pick_image_files(directory) {
+            let image = load_image_from_path(&path).map_err(|err| err.to_string());
+            self.frames.push(StackFrame { path, image });
+        }
+    }
+
+    /// Returns the stacked image when the user asked to combine the frames.
+    /// Files are picked starting in `directory`, see `pick_image_files`.
+    pub fn show(&mut self, ctx: &egui::Context, directory: &mut Option<PathBuf>) -> Option<DynamicImage> {
+        let mut open = self.open;
+        let mut stack = false;
+        let mut remove = None;
+
+        egui::Window::new("Stack Images")
+            .open(&mut open)
+            .resizable(false)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label("Exposures of the same scene, combined to reduce noise");
+
+                egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                    for (index, frame) in self.frames.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            if ui.button("✖").clicked() {
+                                remove = Some(index);
+                            }
+                            let name = frame.path.file_name().unwrap_or_default().to_string_lossy();
+                            match self.problem(frame) {
+                                Some(problem) => {
+                                    ui.label(egui::RichText::new(format!("{}: {}", name, problem)).color(egui::Color32::RED));
+                                }
+                                None => {
+                                    ui.label(name);
+                                }
+                            }
+                        });
+                    }
+                });
+
+                if ui.button("Add Images...").clicked() {
+                    self.add_files(directory);
+                }
+
+                egui::ComboBox::from_label("Combine")
+                    .selected_text(format!("{:?}", self.method))
+                    .show_ui(ui, |ui| {
+                        for method in StackMethod::ALL {
+                            ui.selectable_value(&mut self.method, method, format!("{:?}", method));
+                        }
+                    });
+
+                let usable = self.frames.iter().filter(|frame| self.problem(frame).is_none()).count();
+                if ui
+                    .add_enabled(usable >= 2, egui::Button::new(format!("Stack {} Images", usable)))
+                    .clicked()
+                {
+                    stack = true;
+                }
+
+                if let Some(err) = &self.error {
+                    ui.label(egui::RichText::new(err).color(egui::Color32::RED));
+                }
+            });
+
+        if let Some(index) = remove {
+            self.frames.remove(index);
+        }
+
+        let mut result = None;
+        if stack {
+            let frames: Vec<DynamicImage> = self
+                .frames
+                .iter()
+                .filter(|frame| self.problem(frame).is_none())
+                .filter_map(|frame| frame.image.as_ref().ok().cloned())
+                .collect();
+            match stack_images(&frames, self.method) {
+                Ok(image) => {
+                    result = Some(image);
+                    self.error = None;
+                    open = false;
+                }
+                Err(err) => self.error = Some(err),
+            }
+        }
+
+        self.open = open;
+        result
+    }
+}
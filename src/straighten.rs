@@ -0,0 +1,109 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Stroke, Vec2};
+
+use crate::algorithms::geometry::{rotated_size, RotateSettings, MAX_ROTATE_ANGLE};
+use crate::selection::ScreenMapping;
+
+// Distance between the lines of the alignment grid, in screen points
+const GRID_SPACING: f32 = 40.0;
+// Guidelines shorter than this, in screen points, are taken as a click
+const MIN_GUIDE_LENGTH: f32 = 8.0;
+const GUIDE_COLOR: Color32 = Color32::from_rgb(255, 210, 0);
+
+/// Rotation by an arbitrary angle, previewed live over the original pane.
+///
+/// Besides setting the angle directly, a guideline can be dragged along
+/// something that should be level or plumb, the angle is then adjusted to
+/// make it so.
+#[derive(Default)]
+pub struct Straighten {
+    pub settings: RotateSettings,
+    // Guideline being dragged, in screen points
+    guide: Option<(Pos2, Pos2)>,
+}
+
+impl Straighten {
+    pub fn reset(&mut self) {
+        self.settings.angle = 0.0;
+        self.guide = None;
+    }
+
+    /// Drags a guideline with the primary button. Returns whether the
+    /// angle changed when it was released.
+    pub fn interact(&mut self, response: &egui::Response) -> bool {
+        if response.drag_started_by(egui::PointerButton::Primary) {
+            self.guide = response.interact_pointer_pos().map(|pos| (pos, pos));
+        }
+        if let (Some((_, end)), Some(pointer)) = (&mut self.guide, response.interact_pointer_pos()) {
+            *end = pointer;
+        }
+        if !response.drag_released_by(egui::PointerButton::Primary) {
+            return false;
+        }
+
+        let Some((start, end)) = self.guide.take() else {
+            return false;
+        };
+        let delta = end - start;
+        if delta.length() < MIN_GUIDE_LENGTH {
+            return false;
+        }
+        // The preview is already turned by the current angle, so the
+        // guideline's tilt from the nearest axis is what is left to correct
+        let tilt = delta.y.atan2(delta.x).to_degrees();
+        let tilt = (tilt + 45.0).rem_euclid(90.0) - 45.0;
+        self.settings.angle = (self.settings.angle - tilt).clamp(-MAX_ROTATE_ANGLE, MAX_ROTATE_ANGLE);
+        true
+    }
+
+    /// Draws the original turned by the current angle over its unrotated
+    /// version, the bounds of the result, an alignment grid and the
+    /// guideline being dragged.
+    pub fn paint(&self, painter: &egui::Painter, mapping: &ScreenMapping, texture: egui::TextureId, background: Color32) {
+        let image_rect = mapping.screen_rect;
+        let center = image_rect.center();
+        let (sin, cos) = self.settings.angle.to_radians().sin_cos();
+        let turn = |pos: Pos2| {
+            let offset = pos - center;
+            center + Vec2::new(offset.x * cos - offset.y * sin, offset.x * sin + offset.y * cos)
+        };
+
+        if self.settings.angle != 0.0 {
+            painter.rect_filled(image_rect, 0.0, background);
+            let mut mesh = egui::Mesh::with_texture(texture);
+            let corners = [
+                (image_rect.left_top(), Pos2::new(0.0, 0.0)),
+                (image_rect.right_top(), Pos2::new(1.0, 0.0)),
+                (image_rect.right_bottom(), Pos2::new(1.0, 1.0)),
+                (image_rect.left_bottom(), Pos2::new(0.0, 1.0)),
+            ];
+            for (pos, uv) in corners {
+                mesh.vertices.push(egui::epaint::Vertex { pos: turn(pos), uv, color: Color32::WHITE });
+            }
+            mesh.add_triangle(0, 1, 2);
+            mesh.add_triangle(0, 2, 3);
+            painter.add(egui::Shape::mesh(mesh));
+
+            let image_size = mapping.image_size;
+            let (width, height) = rotated_size(image_size.x as u32, image_size.y as u32, &self.settings);
+            let result_rect = Rect::from_center_size(center, Vec2::new(width as f32, height as f32) * mapping.scale());
+            painter.rect_stroke(result_rect, 0.0, Stroke::new(1.5, GUIDE_COLOR));
+        }
+
+        let clip = painter.clip_rect();
+        let grid_stroke = Stroke::new(1.0, Color32::from_white_alpha(60));
+        let mut x = center.x - ((center.x - clip.left()) / GRID_SPACING).floor() * GRID_SPACING;
+        while x <= clip.right() {
+            painter.vline(x, clip.y_range(), grid_stroke);
+            x += GRID_SPACING;
+        }
+        let mut y = center.y - ((center.y - clip.top()) / GRID_SPACING).floor() * GRID_SPACING;
+        while y <= clip.bottom() {
+            painter.hline(clip.x_range(), y, grid_stroke);
+            y += GRID_SPACING;
+        }
+
+        if let Some((start, end)) = self.guide {
+            painter.line_segment([start, end], Stroke::new(2.0, GUIDE_COLOR));
+        }
+    }
+}
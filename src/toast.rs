@@ -0,0 +1,50 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+
+// How long a notification stays up before fading out on its own
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+/// A short notification in the bottom right corner that doesn't block
+/// the rest of the UI and disappears after a few seconds.
+pub struct Toast {
+    message: String,
+    is_error: bool,
+    shown_at: Instant,
+}
+
+impl Toast {
+    pub fn new(result: Result<String, String>) -> Self {
+        let (message, is_error) = match result {
+            Ok(message) => (message, false),
+            Err(err) => (err, true),
+        };
+        Self {
+            message,
+            is_error,
+            shown_at: Instant::now(),
+        }
+    }
+
+    /// Draws the notification, returning false once it has expired or was
+    /// clicked away.
+    pub fn show(&self, ctx: &egui::Context) -> bool {
+        let elapsed = self.shown_at.elapsed();
+        if elapsed >= TOAST_DURATION {
+            return false;
+        }
+        ctx.request_repaint_after(TOAST_DURATION - elapsed);
+
+        let color = if self.is_error { egui::Color32::RED } else { egui::Color32::LIGHT_GREEN };
+        let response = egui::Area::new(egui::Id::new("toast"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-20.0, -20.0))
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(egui::RichText::new(&self.message).size(14.0).color(color));
+                })
+            })
+            .response;
+        !response.interact(egui::Sense::click()).clicked()
+    }
+}
@@ -0,0 +1,149 @@
+use eframe::egui::{self, Color32, Pos2, Rect, Vec2};
+
+use crate::selection::ScreenMapping;
+
+const MIN_ZOOM: f32 = 0.02;
+const MAX_ZOOM: f32 = 32.0;
+// Zoom factor per point of mouse wheel scrolling
+const SCROLL_ZOOM_SPEED: f32 = 0.0015;
+
+// Longest side of the textures images are shown with, unless the GPU
+// allows less. Processing and export always use the full image.
+const MAX_DISPLAY_SIDE: u32 = 8192;
+
+/// Texture options for displayed images: smooth when zoomed out, but
+/// individual pixels stay visible past 100%.
+pub const TEXTURE_OPTIONS: egui::TextureOptions = egui::TextureOptions {
+    magnification: egui::TextureFilter::Nearest,
+    minification: egui::TextureFilter::Linear,
+    wrap_mode: egui::TextureWrapMode::ClampToEdge,
+};
+
+/// Size of the downscaled copy an image of `width` x `height` is displayed
+/// through, or `None` when it fits into a texture as it is.
+pub fn display_proxy_size(ctx: &egui::Context, width: u32, height: u32) -> Option<(u32, u32)> {
+    let max_side = (ctx.input(|i| i.max_texture_side) as u32).min(MAX_DISPLAY_SIDE);
+    let long_side = width.max(height);
+    if long_side <= max_side {
+        return None;
+    }
+    let scale = max_side as f64 / long_side as f64;
+    let scaled = |side: u32| ((side as f64 * scale).round() as u32).clamp(1, max_side);
+    Some((scaled(width), scaled(height)))
+}
+
+/// Zoom and pan state of an image pane, shared by both panes while their
+/// views are linked.
+///
+/// The view is stored relative to a reference size (the original image), so
+/// panes showing a downscaled preview or a resized result still show the same
+/// part of the picture at the same on-screen size.
+#[derive(Clone)]
+pub struct ImageViewer {
+    /// Screen points per reference pixel, ignored while `fit` is set
+    zoom: f32,
+    /// Point of the image shown at the viewport center, in 0..1 image coordinates
+    center: Pos2,
+    fit: bool,
+}
+
+impl Default for ImageViewer {
+    fn default() -> Self {
+        Self {
+            zoom: 1.0,
+            center: Pos2::new(0.5, 0.5),
+            fit: true,
+        }
+    }
+}
+
+impl ImageViewer {
+    /// Goes back to showing the whole image.
+    pub fn fit(&mut self) {
+        self.fit = true;
+        self.center = Pos2::new(0.5, 0.5);
+    }
+
+    /// Shows the reference image pixel for pixel, keeping the current center.
+    pub fn actual_size(&mut self) {
+        self.fit = false;
+        self.zoom = 1.0;
+    }
+
+    /// Moves `point`, in 0..1 image coordinates, to the viewport center.
+    /// A fitted view zooms in to 100% so there is something to center.
+    pub fn center_on(&mut self, point: Pos2) {
+        if self.fit {
+            self.actual_size();
+        }
+        self.center = point.clamp(Pos2::ZERO, Pos2::new(1.0, 1.0));
+    }
+
+    /// Current zoom for a viewport of `viewport` points.
+    pub fn zoom(&self, viewport: Vec2, reference_size: Vec2) -> f32 {
+        if self.fit {
+            (viewport.x / reference_size.x).min(viewport.y / reference_size.y)
+        } else {
+            self.zoom
+        }
+    }
+
+    /// Draws `texture` into a `viewport`-sized area and handles wheel zoom.
+    ///
+    /// Panning by dragging is done with the middle button, and with the
+    /// primary button too when `drag_pans` is set. The returned mapping
+    /// converts between screen and image pixel coordinates of `image_size`,
+    /// whatever the size of the texture, which may be a display proxy.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        texture: &egui::TextureHandle,
+        image_size: Vec2,
+        reference_size: Vec2,
+        viewport: Vec2,
+        drag_pans: bool,
+    ) -> (egui::Response, ScreenMapping) {
+        let (rect, response) = ui.allocate_exact_size(viewport, egui::Sense::click_and_drag());
+        let zoom = self.zoom(viewport, reference_size);
+        let display_size = reference_size * zoom;
+
+        let panning = response.dragged_by(egui::PointerButton::Middle)
+            || (drag_pans && response.dragged_by(egui::PointerButton::Primary));
+        if panning {
+            self.center -= response.drag_delta() / display_size;
+        }
+
+        if let Some(pointer) = response.hover_pos() {
+            let (scroll, pinch) = ui.input(|i| (i.smooth_scroll_delta.y, i.zoom_delta()));
+            let factor = (scroll * SCROLL_ZOOM_SPEED).exp() * pinch;
+            if factor != 1.0 {
+                let new_zoom = (zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+                // Keep the image point under the cursor where it is
+                let offset = pointer - rect.center();
+                let image_point = self.center + offset / display_size;
+                self.center = image_point - offset / (reference_size * new_zoom);
+                self.zoom = new_zoom;
+                self.fit = false;
+            }
+        }
+
+        self.center = self.center.clamp(Pos2::ZERO, Pos2::new(1.0, 1.0));
+        let display_size = reference_size * self.zoom(viewport, reference_size);
+        let image_rect = Rect::from_min_size(rect.center() - self.center.to_vec2() * display_size, display_size);
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+        painter.image(
+            texture.id(),
+            image_rect,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+
+        let mapping = ScreenMapping {
+            screen_rect: image_rect,
+            image_size,
+        };
+        (response, mapping)
+    }
+}
@@ -0,0 +1,231 @@
+use std::path::PathBuf;
+
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use eframe::egui;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageBuffer, Pixel, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::algorithms::sample::{is_high_depth, Sample};
+
+/// What a watermark shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WatermarkKind {
+    Text,
+    Logo,
+}
+
+/// Where in the image a watermark is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Row by row, top left first.
+    pub const ALL: [Anchor; 9] = [
+        Anchor::TopLeft,
+        Anchor::Top,
+        Anchor::TopRight,
+        Anchor::Left,
+        Anchor::Center,
+        Anchor::Right,
+        Anchor::BottomLeft,
+        Anchor::Bottom,
+        Anchor::BottomRight,
+    ];
+
+    // Position within the space left over, 0 is left / top and 1 right / bottom
+    fn fractions(self) -> (f32, f32) {
+        let index = Anchor::ALL.iter().position(|&anchor| anchor == self).expect("every anchor is listed");
+        ((index % 3) as f32 / 2.0, (index / 3) as f32 / 2.0)
+    }
+}
+
+/// An overlay baked into exported files only, never into the processed image.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Watermark {
+    pub enabled: bool,
+    pub kind: WatermarkKind,
+    pub text: String,
+    /// Height of the text in pixels of the exported image
+    pub font_size: f32,
+    pub color: [u8; 3],
+    /// PNG file, its transparency is kept
+    pub logo_path: Option<PathBuf>,
+    /// Logo width as a percentage of the image width
+    pub logo_width: f32,
+    /// 0-1
+    pub opacity: f32,
+    pub anchor: Anchor,
+    /// Distance from the image edges in pixels
+    pub margin: u32,
+}
+
+impl Default for Watermark {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: WatermarkKind::Text,
+            text: "©".to_string(),
+            font_size: 48.0,
+            color: [255, 255, 255],
+            logo_path: None,
+            logo_width: 20.0,
+            opacity: 0.6,
+            anchor: Anchor::BottomRight,
+            margin: 24,
+        }
+    }
+}
+
+/// Draws `watermark` onto a copy of `img`. Overlays larger than the image
+/// minus its margins are scaled down to fit. Gray images come out as RGB
+/// so colored text and logos stay colored.
+pub fn apply_watermark(img: &DynamicImage, watermark: &Watermark) -> Result<DynamicImage, String> {
+    let (width, height) = (img.width(), img.height());
+    // Small images keep at least half of each side for the overlay
+    let margin = watermark.margin.min(width / 4).min(height / 4);
+    let available = ((width - 2 * margin) as f32, (height - 2 * margin) as f32);
+
+    let overlay = match watermark.kind {
+        WatermarkKind::Text => {
+            let font = bundled_font();
+            let (text_width, text_height) = text_size(&font, &watermark.text, watermark.font_size);
+            let fit = fit_scale((text_width, text_height), available);
+            render_text(&font, &watermark.text, watermark.font_size * fit, watermark.color)
+        }
+        WatermarkKind::Logo => {
+            let path = watermark.logo_path.as_ref().ok_or("Choose a logo file for the watermark")?;
+            let logo = image::open(path)
+                .map_err(|err| format!("Could not load the watermark logo {}: {}", path.display(), err))?
+                .to_rgba8();
+            let logo_width = width as f32 * watermark.logo_width / 100.0;
+            let logo_height = logo_width * logo.height() as f32 / logo.width() as f32;
+            let fit = fit_scale((logo_width, logo_height), available);
+            let (logo_width, logo_height) = ((logo_width * fit).round() as u32, (logo_height * fit).round() as u32);
+            (logo_width > 0 && logo_height > 0).then(|| imageops::resize(&logo, logo_width, logo_height, FilterType::Lanczos3))
+        }
+    };
+    let Some(overlay) = overlay else {
+        return Ok(img.clone());
+    };
+
+    let (fraction_x, fraction_y) = watermark.anchor.fractions();
+    let left = margin + ((available.0 - overlay.width() as f32).max(0.0) * fraction_x).round() as u32;
+    let top = margin + ((available.1 - overlay.height() as f32).max(0.0) * fraction_y).round() as u32;
+    let opacity = watermark.opacity.clamp(0.0, 1.0);
+    Ok(match (is_high_depth(img), img.color().has_alpha()) {
+        (false, false) => DynamicImage::ImageRgb8(composite(img.to_rgb8(), &overlay, left, top, opacity)),
+        (false, true) => DynamicImage::ImageRgba8(composite(img.to_rgba8(), &overlay, left, top, opacity)),
+        (true, false) => DynamicImage::ImageRgb16(composite(img.to_rgb16(), &overlay, left, top, opacity)),
+        (true, true) => DynamicImage::ImageRgba16(composite(img.to_rgba16(), &overlay, left, top, opacity)),
+    })
+}
+
+// Factor shrinking `size` to fit into `available`, never above 1
+fn fit_scale(size: (f32, f32), available: (f32, f32)) -> f32 {
+    if size.0 <= 0.0 || size.1 <= 0.0 {
+        return 1.0;
+    }
+    (available.0 / size.0).min(available.1 / size.1).min(1.0)
+}
+
+// The proportional font egui ships with
+fn bundled_font() -> FontArc {
+    let data = egui::FontDefinitions::default()
+        .font_data
+        .remove("Ubuntu-Light")
+        .expect("egui bundles Ubuntu-Light")
+        .font;
+    FontArc::try_from_vec(data.into_owned()).expect("the bundled font parses")
+}
+
+// Width and height of `text` on one line
+fn text_size(font: &FontArc, text: &str, size: f32) -> (f32, f32) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let (width, _) = layout(font, text, size);
+    (width, scaled.ascent() - scaled.descent())
+}
+
+// Advance of the whole line and the glyphs placed along it, on the baseline
+fn layout(font: &FontArc, text: &str, size: f32) -> (f32, Vec<ab_glyph::Glyph>) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut caret = 0.0;
+    let mut previous = None;
+    let mut glyphs = Vec::new();
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push(id.with_scale_and_position(size, point(caret, scaled.ascent())));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    (caret, glyphs)
+}
+
+// `text` in `color` on a transparent background, `None` if nothing would show
+fn render_text(font: &FontArc, text: &str, size: f32, color: [u8; 3]) -> Option<RgbaImage> {
+    let (width, height) = text_size(font, text, size);
+    let (width, height) = (width.ceil() as u32, height.ceil() as u32);
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut overlay = RgbaImage::from_pixel(width, height, image::Rgba([color[0], color[1], color[2], 0]));
+    let (_, glyphs) = layout(font, text, size);
+    for glyph in glyphs {
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|x, y, coverage| {
+            let (x, y) = (bounds.min.x as i32 + x as i32, bounds.min.y as i32 + y as i32);
+            if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                return;
+            }
+            let alpha = &mut overlay.get_pixel_mut(x as u32, y as u32)[3];
+            // Overlapping glyphs keep the stronger coverage
+            *alpha = (*alpha).max((coverage.clamp(0.0, 1.0) * 255.0).round() as u8);
+        });
+    }
+    Some(overlay)
+}
+
+// Blends `overlay` over `base` with its top left corner at (`left`, `top`),
+// the overlay's alpha scaled by `opacity`
+fn composite<P: Pixel>(mut base: ImageBuffer<P, Vec<P::Subpixel>>, overlay: &RgbaImage, left: u32, top: u32, opacity: f32) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P::Subpixel: Sample,
+{
+    let max = P::Subpixel::MAX_VALUE;
+    for (x, y, source) in overlay.enumerate_pixels() {
+        let alpha = source[3] as f32 / 255.0 * opacity;
+        let (x, y) = (left + x, top + y);
+        if alpha <= 0.0 || x >= base.width() || y >= base.height() {
+            continue;
+        }
+        let target = base.get_pixel_mut(x, y).channels_mut();
+        for (value, &over) in target.iter_mut().zip(&source.0[..3]) {
+            let over = over as f32 / 255.0 * max;
+            *value = P::Subpixel::from_f32((value.to_f32() + (over - value.to_f32()) * alpha).round());
+        }
+        // Covered parts of transparent images become as opaque as the overlay
+        if let Some(target_alpha) = target.get_mut(3) {
+            let under = target_alpha.to_f32();
+            *target_alpha = P::Subpixel::from_f32((under + (max - under) * alpha).round());
+        }
+    }
+    base
+}
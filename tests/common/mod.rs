@@ -0,0 +1,210 @@
+//! Fixtures and golden image comparison shared by the integration tests.
+//!
+//! Goldens are small PNGs under `tests/golden`, one per test, in the working
+//! format the filter returned (gray or RGB, 8 or 16 bits). After a change in
+//! behavior that is meant to happen, regenerate them with
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test --test golden
+//! ```
+//!
+//! and look over the changed PNGs before committing them.
+
+#![allow(dead_code)] // Not every test file uses every fixture
+
+use std::path::PathBuf;
+
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
+use image_denoising::algorithms::benchmark::add_gaussian_noise;
+
+/// Side of the fixtures, large enough for every filter's neighbourhood and
+/// small enough to keep the slow filters fast in a debug build.
+pub const SIZE: u32 = 24;
+
+/// Set to regenerate the goldens instead of comparing against them.
+pub const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+
+/// A diagonal color ramp with red across, green down and blue against both.
+pub fn gradient() -> DynamicImage {
+    let scale = 255.0 / (SIZE - 1) as f32;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(SIZE, SIZE, |x, y| {
+        let (x, y) = (x as f32 * scale, y as f32 * scale);
+        Rgb([x.round() as u8, y.round() as u8, (255.0 - (x + y) / 2.0).round() as u8])
+    }))
+}
+
+/// Dark and light squares of `cell` pixels, off pure black and white so
+/// filters that push past them show it.
+pub fn checkerboard(cell: u32) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(SIZE, SIZE, |x, y| {
+        Luma([match (x / cell + y / cell) % 2 {
+            0 => 40,
+            _ => 210,
+        }])
+    }))
+}
+
+/// Mid-gray with single bright and dark pixels scattered over it, the
+/// salt-and-pepper impulses the median filters are for.
+pub fn impulses() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(SIZE, SIZE, |x, y| match (x * 7 + y * 13) % 23 {
+        0 => Rgb([255, 255, 255]),
+        11 => Rgb([0, 0, 0]),
+        _ => Rgb([128, 128, 128]),
+    }))
+}
+
+/// `img` with gaussian noise of `sigma` in 8-bit units, the same every run.
+pub fn noisy(img: &DynamicImage, sigma: f32) -> DynamicImage {
+    add_gaussian_noise(img, sigma, 0x5eed)
+}
+
+/// `img` with a share `density` of its pixels turned black or white, half
+/// of each, the same every run.
+pub fn salt_and_pepper(img: &DynamicImage, density: f64) -> DynamicImage {
+    let mut rgb = img.to_rgb8();
+    let mut state = 0x5eedu64;
+    for pixel in rgb.pixels_mut() {
+        // SplitMix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let draw = (z ^ (z >> 31)) as f64 / u64::MAX as f64;
+        if draw < density / 2.0 {
+            *pixel = Rgb([0, 0, 0]);
+        } else if draw < density {
+            *pixel = Rgb([255, 255, 255]);
+        }
+    }
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// The gradient with moderate noise, the general fixture for the denoisers.
+pub fn noisy_gradient() -> DynamicImage {
+    noisy(&gradient(), 20.0)
+}
+
+/// `img` with 16 bits per channel, keeping the 8-bit values exactly.
+pub fn to_16_bit(img: &DynamicImage) -> DynamicImage {
+    match img {
+        DynamicImage::ImageLuma8(_) => DynamicImage::ImageLuma16(img.to_luma16()),
+        _ => DynamicImage::ImageRgb16(img.to_rgb16()),
+    }
+}
+
+/// A horizontal and vertical ramp in gray, `x * 40 + y * 10`, small enough
+/// to check the border handling of a 3x3 filter value by value.
+pub fn ramp() -> DynamicImage {
+    DynamicImage::ImageLuma8(ImageBuffer::from_fn(5, 4, |x, y| Luma([(x * 40 + y * 10) as u8])))
+}
+
+/// How far an output may stray from its golden.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    /// Largest difference allowed in any sample, in the units of the
+    /// image's bit depth
+    pub max_difference: u16,
+    /// How many samples may differ at all, for the filters whose float
+    /// math rounds a value the other way on some platforms
+    pub max_differing: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance { max_difference: 0, max_differing: 0 };
+
+    /// Every sample within `max_difference` of the golden.
+    pub const fn within(max_difference: u16) -> Tolerance {
+        Tolerance { max_difference, max_differing: usize::MAX }
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(format!("{name}.png"))
+}
+
+/// Compares `img` against the golden called `name`, or writes it as the new
+/// golden with `UPDATE_GOLDEN` set.
+pub fn assert_golden(name: &str, img: &DynamicImage, tolerance: Tolerance) {
+    let path = golden_path(name);
+    if std::env::var_os(UPDATE_VAR).is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        img.save(&path).unwrap_or_else(|err| panic!("writing {}: {err}", path.display()));
+        return;
+    }
+
+    let golden = match image::open(&path) {
+        Ok(golden) => golden,
+        Err(err) => panic!("no golden for {name} at {} ({err}), run with {UPDATE_VAR}=1 to create it", path.display()),
+    };
+    assert_eq!(img.color(), golden.color(), "{name}: working format differs from the golden");
+    assert_eq!(img.dimensions(), golden.dimensions(), "{name}: size differs from the golden");
+    assert_close(name, img, &golden, tolerance);
+}
+
+/// Compares two images of the same format sample by sample.
+pub fn assert_close(name: &str, img: &DynamicImage, expected: &DynamicImage, tolerance: Tolerance) {
+    let (actual, wanted) = (samples(img), samples(expected));
+    assert_eq!(actual.len(), wanted.len(), "{name}: sample counts differ");
+
+    let channels = img.color().channel_count() as usize;
+    let mut differing = 0;
+    // Largest difference and the index of the sample it is at
+    let mut worst = (0, 0);
+    for (i, (&a, &b)) in actual.iter().zip(&wanted).enumerate() {
+        let difference = a.abs_diff(b);
+        if difference > 0 {
+            differing += 1;
+            if difference > worst.0 {
+                worst = (difference, i);
+            }
+        }
+    }
+
+    if differing > 0 {
+        let (difference, i) = worst;
+        let pixel = i / channels;
+        let (x, y) = (pixel as u32 % img.width(), pixel as u32 / img.width());
+        assert!(
+            difference <= tolerance.max_difference && differing <= tolerance.max_differing,
+            "{name}: {differing} samples differ, worst by {difference} at ({x}, {y}) channel {} ({} vs {}), \
+             allowed {tolerance:?}. Run with {UPDATE_VAR}=1 if the change is intended",
+            i % channels,
+            actual[i],
+            wanted[i],
+        );
+    }
+}
+
+// Every sample of `img`, widened to 16 bits without scaling
+fn samples(img: &DynamicImage) -> Vec<u16> {
+    match img {
+        DynamicImage::ImageLuma8(buffer) => buffer.iter().map(|&v| v as u16).collect(),
+        DynamicImage::ImageRgb8(buffer) => buffer.iter().map(|&v| v as u16).collect(),
+        DynamicImage::ImageLuma16(buffer) => buffer.to_vec(),
+        DynamicImage::ImageRgb16(buffer) => buffer.to_vec(),
+        other => panic!("unexpected output format {:?}", other.color()),
+    }
+}
+
+/// Declares one test per entry, each running an operation on a fixture and
+/// comparing the result with the golden named after the test:
+///
+/// ```ignore
+/// golden_tests! {
+///     name: fixture() => |img| operation(img), Tolerance::EXACT;
+/// }
+/// ```
+#[macro_export]
+macro_rules! golden_tests {
+    ($($name:ident: $fixture:expr => $operation:expr, $tolerance:expr;)*) => {
+        $(
+            #[test]
+            fn $name() {
+                let input: image::DynamicImage = $fixture;
+                let operation: &dyn Fn(&image::DynamicImage) -> image::DynamicImage = &$operation;
+                $crate::common::assert_golden(stringify!($name), &operation(&input), $tolerance);
+            }
+        )*
+    };
+}
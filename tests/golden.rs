@@ -0,0 +1,210 @@
+//! Every filter run on small generated fixtures and compared with the
+//! goldens under `tests/golden`, see `common` for how to regenerate them.
+
+mod common;
+
+use image::{DynamicImage, GrayImage, Luma};
+use image_denoising::algorithms::border::BorderMode;
+use image_denoising::algorithms::channels::ChannelView;
+use image_denoising::algorithms::deconvolution::{deconvolve, DeconvolutionSettings, PsfShape};
+use image_denoising::algorithms::dehaze::dehaze;
+use image_denoising::algorithms::denoise::{denoise_image, denoise_ycbcr_with_progress, DenoiseType, PlaneStrengths};
+use image_denoising::algorithms::detail::restore_detail;
+use image_denoising::algorithms::document::{binarize_document, DocumentSettings, ThresholdMethod};
+use image_denoising::algorithms::hot_pixels::repair_hot_pixels;
+use image_denoising::algorithms::hsl::{adjust_hsl, HslBand, HslRange};
+use image_denoising::algorithms::lut::{apply_lut, Lut3d};
+use image_denoising::algorithms::notch::{notch_filter, Notch};
+use image_denoising::algorithms::pipeline::{Operation, Pipeline};
+use image_denoising::algorithms::point_ops::{apply_point_ops, PointOp, PointOps};
+use image_denoising::algorithms::progress::Progress;
+use image_denoising::algorithms::quantize::{posterize, Dither};
+use image_denoising::algorithms::sharpness::{sharpen_image, SharpenKernel};
+use image_denoising::algorithms::tone::shadows_highlights;
+use image_denoising::algorithms::white_balance::{apply_white_balance, WhiteBalance};
+
+use common::{checkerboard, gradient, impulses, noisy, noisy_gradient, ramp, to_16_bit, Tolerance, SIZE};
+
+// Float heavy filters may round a sample the other way on another platform
+const FLOAT: Tolerance = Tolerance::within(1);
+
+fn denoise(img: &DynamicImage, denoise_type: DenoiseType, kernel_size: usize, border: BorderMode) -> DynamicImage {
+    denoise_image(img, denoise_type, kernel_size, 0.1, 50, 1e-4, border)
+}
+
+// Horizontal stripes four pixels apart, the kind of pattern a notch removes
+fn stripes() -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(SIZE, SIZE, |x, y| {
+        let stripe = (std::f32::consts::TAU * x as f32 / 4.0).sin() * 40.0;
+        Luma([(100.0 + y as f32 * 3.0 + stripe).round() as u8])
+    }))
+}
+
+// Dark strokes on paper lit unevenly from the left
+fn document() -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(SIZE, SIZE, |x, y| {
+        let paper = 150 + x as u8 * 4;
+        Luma([if x % 6 == 2 || y == 12 { paper / 3 } else { paper }])
+    }))
+}
+
+// Pure black and white squares of `cell` pixels, where blurring gamma
+// encoded values and blurring light differ the most
+fn black_and_white(cell: u32) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(SIZE, SIZE, |x, y| {
+        Luma([match (x / cell + y / cell) % 2 {
+            0 => 0,
+            _ => 255,
+        }])
+    }))
+}
+
+// A 7x7 mean filter the way the pipeline runs it, in linear light or not
+fn blur(img: &DynamicImage, linear_light: bool) -> DynamicImage {
+    let pipeline = Pipeline(vec![Operation::Denoise {
+        denoise_type: DenoiseType::MeanFilter,
+        kernel_size: 7,
+        tv_lambda: 0.1,
+        tv_iterations: 50,
+        tv_tolerance: 1e-4,
+        planes: None,
+        linear_light,
+        backend: Default::default(),
+        border: BorderMode::Mirror,
+        detail: 0.0,
+        detail_radius: 1.5,
+    }]);
+    pipeline.apply(img)
+}
+
+fn mean(img: &DynamicImage) -> f64 {
+    let samples = img.to_luma8().into_raw();
+    samples.iter().map(|&value| value as f64).sum::<f64>() / samples.len() as f64
+}
+
+// Swaps red and blue
+const SWAP_CUBE: &str = "TITLE \"swap\"\nLUT_3D_SIZE 2\n\
+    0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n";
+
+golden_tests! {
+    mean_filter: noisy_gradient() => |img| denoise(img, DenoiseType::MeanFilter, 3, BorderMode::Mirror), Tolerance::EXACT;
+    gaussian_filter: noisy_gradient() => |img| denoise(img, DenoiseType::GaussianFilter, 5, BorderMode::Mirror), FLOAT;
+    median_filter: noisy(&impulses(), 8.0) => |img| denoise(img, DenoiseType::MedianFilter, 3, BorderMode::Mirror), Tolerance::EXACT;
+    bilateral_filter: noisy_gradient() => |img| denoise(img, DenoiseType::BilateralFilter, 5, BorderMode::Mirror), FLOAT;
+    non_local_means: noisy_gradient() => |img| denoise(img, DenoiseType::NonLocalMeans, 3, BorderMode::Mirror), FLOAT;
+    total_variation: noisy_gradient() => |img| denoise(img, DenoiseType::TotalVariation, 3, BorderMode::Mirror), FLOAT;
+    chambolle_tv: noisy_gradient() => |img| denoise(img, DenoiseType::ChambolleTV, 3, BorderMode::Mirror), FLOAT;
+    block_matching: noisy_gradient() => |img| denoise(img, DenoiseType::BlockMatching, 3, BorderMode::Mirror), FLOAT;
+    adaptive_median: noisy(&impulses(), 8.0) => |img| denoise(img, DenoiseType::AdaptiveMedian, 7, BorderMode::Mirror), Tolerance::EXACT;
+
+    // The other working formats, gray and 16 bits
+    mean_filter_gray: noisy(&checkerboard(4), 15.0) => |img| denoise(img, DenoiseType::MeanFilter, 3, BorderMode::Mirror), Tolerance::EXACT;
+    median_filter_16_bit: to_16_bit(&noisy(&impulses(), 8.0)) => |img| denoise(img, DenoiseType::MedianFilter, 3, BorderMode::Mirror), Tolerance::EXACT;
+    bilateral_filter_gray_16_bit: to_16_bit(&noisy(&checkerboard(4), 15.0)) => |img| denoise(img, DenoiseType::BilateralFilter, 5, BorderMode::Mirror), FLOAT;
+    non_local_means_16_bit: to_16_bit(&noisy_gradient()) => |img| denoise(img, DenoiseType::NonLocalMeans, 3, BorderMode::Mirror), FLOAT;
+
+    // Borders, on a ramp small enough that every pixel is near an edge
+    mean_filter_clamp: ramp() => |img| denoise(img, DenoiseType::MeanFilter, 3, BorderMode::Clamp), Tolerance::EXACT;
+    mean_filter_wrap: ramp() => |img| denoise(img, DenoiseType::MeanFilter, 3, BorderMode::Wrap), Tolerance::EXACT;
+    mean_filter_skip: ramp() => |img| denoise(img, DenoiseType::MeanFilter, 3, BorderMode::Skip), Tolerance::EXACT;
+    median_filter_wrap: ramp() => |img| denoise(img, DenoiseType::MedianFilter, 3, BorderMode::Wrap), Tolerance::EXACT;
+    median_filter_skip: ramp() => |img| denoise(img, DenoiseType::MedianFilter, 3, BorderMode::Skip), Tolerance::EXACT;
+
+    mean_filter_linear_light: black_and_white(2) => |img| blur(img, true), FLOAT;
+    mean_filter_linear_light_16_bit: to_16_bit(&black_and_white(3)) => |img| blur(img, true), FLOAT;
+
+    ycbcr_chroma_only: noisy_gradient() => |img| {
+        denoise_ycbcr_with_progress(img, DenoiseType::GaussianFilter, 5, 0.1, 50, 1e-4, PlaneStrengths::CHROMA_ONLY, BorderMode::Mirror, &Progress::new()).unwrap()
+    }, FLOAT;
+
+    // Adjustments
+    exposure: gradient() => |img| apply_point_ops(img.clone(), &PointOps(vec![PointOp::Exposure(0.5)])), Tolerance::EXACT;
+    brightness: gradient() => |img| apply_point_ops(img.clone(), &PointOps(vec![PointOp::Brightness(-0.2)])), Tolerance::EXACT;
+    contrast: gradient() => |img| apply_point_ops(img.clone(), &PointOps(vec![PointOp::Contrast(0.4)])), Tolerance::EXACT;
+    point_ops_chain_16_bit: to_16_bit(&gradient()) => |img| {
+        apply_point_ops(img.clone(), &PointOps(vec![PointOp::Exposure(-0.3), PointOp::Contrast(0.2), PointOp::Brightness(0.1)]))
+    }, Tolerance::EXACT;
+    shadows_highlights_both: gradient() => |img| shadows_highlights(img, 0.6, 0.4, 4.0), FLOAT;
+    dehaze_half: gradient() => |img| dehaze(img, 0.5), FLOAT;
+    hsl_bands: gradient() => |img| {
+        let reds = HslBand { saturation: -0.5, ..HslBand::neutral(HslRange::Reds) };
+        let blues = HslBand { hue: 30.0, lightness: 0.2, ..HslBand::neutral(HslRange::Blues) };
+        adjust_hsl(img, &[reds, blues])
+    }, FLOAT;
+    white_balance_warm: gradient() => |img| apply_white_balance(img, WhiteBalance { temperature: 0.3, tint: -0.2 }), FLOAT;
+    lut_swap: gradient() => |img| apply_lut(img, &Lut3d::parse_cube(SWAP_CUBE).unwrap(), 0.75), FLOAT;
+    posterize_plain: gradient() => |img| posterize(img, 4, Dither::None), Tolerance::EXACT;
+    posterize_ordered: gradient() => |img| posterize(img, 4, Dither::Ordered), Tolerance::EXACT;
+    posterize_floyd_steinberg: gradient() => |img| posterize(img, 4, Dither::FloydSteinberg), FLOAT;
+
+    // Sharpening and restoration
+    sharpen_laplacian_4: checkerboard(3) => |img| sharpen_image(img, 0.5, SharpenKernel::Laplacian4, BorderMode::Mirror), Tolerance::EXACT;
+    sharpen_laplacian_8: checkerboard(3) => |img| sharpen_image(img, 0.5, SharpenKernel::Laplacian8, BorderMode::Mirror), Tolerance::EXACT;
+    sharpen_unsharp_mask: noisy_gradient() => |img| sharpen_image(img, 1.0, SharpenKernel::UnsharpMask, BorderMode::Mirror), Tolerance::EXACT;
+    sharpen_unsharp_mask_skip_16_bit: to_16_bit(&checkerboard(3)) => |img| {
+        sharpen_image(img, 1.0, SharpenKernel::UnsharpMask, BorderMode::Skip)
+    }, Tolerance::EXACT;
+    deconvolve_gaussian: denoise(&checkerboard(4), DenoiseType::GaussianFilter, 5, BorderMode::Mirror) => |img| {
+        let settings = DeconvolutionSettings { sigma: 1.0, iterations: 8, ..Default::default() };
+        deconvolve(img, &settings, &Progress::new()).unwrap()
+    }, FLOAT;
+    deconvolve_motion: gradient() => |img| {
+        let settings = DeconvolutionSettings { shape: PsfShape::Motion, length: 5.0, angle: 30.0, iterations: 5, ..Default::default() };
+        deconvolve(img, &settings, &Progress::new()).unwrap()
+    }, FLOAT;
+    restore_detail_half: noisy_gradient() => |img| {
+        restore_detail(img, &denoise(img, DenoiseType::MeanFilter, 5, BorderMode::Mirror), 0.5, 1.5)
+    }, FLOAT;
+    hot_pixels: impulses() => |img| repair_hot_pixels(img, 3, 0.25).0, Tolerance::EXACT;
+    notch_stripes: stripes() => |img| notch_filter(img, &[Notch { fx: 0.25, fy: 0.0, radius: 0.03 }]), FLOAT;
+    document_sauvola: document() => |img| {
+        binarize_document(img, &DocumentSettings { flatten_radius: 8, window: 9, ..Default::default() })
+    }, Tolerance::EXACT;
+    document_mean_c: document() => |img| {
+        binarize_document(img, &DocumentSettings { flatten: false, method: ThresholdMethod::MeanC, window: 9, ..Default::default() })
+    }, Tolerance::EXACT;
+
+    // Views
+    channel_luma: gradient() => |img| ChannelView::Luma.extract(img), Tolerance::EXACT;
+    channel_cr_16_bit: to_16_bit(&gradient()) => |img| ChannelView::Cr.extract(img), Tolerance::EXACT;
+}
+
+#[test]
+fn linear_light_blurs_keep_the_light() {
+    // Half the pixels lit: blurred to half the light, which sRGB encodes as
+    // 188, while averaging the encoded values makes it the much darker 128
+    let checkerboard = black_and_white(2);
+    let gamma = mean(&blur(&checkerboard, false));
+    let linear = mean(&blur(&checkerboard, true));
+    assert!((122.0..=134.0).contains(&gamma), "gamma encoded blur averaged {gamma}");
+    assert!((182.0..=194.0).contains(&linear), "linear light blur averaged {linear}");
+}
+
+// Every filter that reads past the edges, in every border mode, on the ramp
+// small enough that every pixel is near an edge. Pins what each mode makes
+// of the border so a refactor can't quietly change it.
+#[test]
+fn border_modes_of_every_filter() {
+    let denoisers = [
+        ("mean_filter", DenoiseType::MeanFilter, Tolerance::EXACT),
+        ("gaussian_filter", DenoiseType::GaussianFilter, FLOAT),
+        ("median_filter", DenoiseType::MedianFilter, Tolerance::EXACT),
+        ("bilateral_filter", DenoiseType::BilateralFilter, FLOAT),
+        ("non_local_means", DenoiseType::NonLocalMeans, FLOAT),
+        ("adaptive_median", DenoiseType::AdaptiveMedian, Tolerance::EXACT),
+    ];
+    let sharpeners = [
+        ("sharpen_laplacian_4", SharpenKernel::Laplacian4),
+        ("sharpen_laplacian_8", SharpenKernel::Laplacian8),
+        ("sharpen_unsharp_mask", SharpenKernel::UnsharpMask),
+    ];
+    for border in BorderMode::ALL {
+        let mode = border.label().to_lowercase();
+        for (name, denoise_type, tolerance) in denoisers {
+            common::assert_golden(&format!("borders/{name}_{mode}"), &denoise(&ramp(), denoise_type, 3, border), tolerance);
+        }
+        for (name, kernel) in sharpeners {
+            common::assert_golden(&format!("borders/{name}_{mode}"), &sharpen_image(&ramp(), 0.5, kernel, border), Tolerance::EXACT);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! What the denoisers promise on synthetic noise, measured against the
+//! clean image: PSNR where one should beat another, the noise left in each
+//! plane where luminance and color are filtered apart.
+
+mod common;
+
+use image::{DynamicImage, Rgb, RgbImage};
+use image_denoising::algorithms::benchmark::psnr;
+use image_denoising::algorithms::border::BorderMode;
+use image_denoising::algorithms::colorspace::rgb_to_ycbcr;
+use image_denoising::algorithms::denoise::{denoise_image, denoise_ycbcr_with_progress, DenoiseType, PlaneStrengths};
+use image_denoising::algorithms::progress::Progress;
+
+use common::{noisy, salt_and_pepper};
+
+// Soft gradients with a few hard edged shapes on them, larger than the
+// golden fixtures so the patch based filters find similar patches
+fn scene() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(48, 48, |x, y| {
+        let in_square = (8..22).contains(&x) && (10..26).contains(&y);
+        let in_band = (30..40).contains(&x);
+        match (in_square, in_band) {
+            (true, _) => Rgb([200, 60, 50]),
+            (_, true) => Rgb([40, 70, 160]),
+            _ => Rgb([(60 + x * 3) as u8, (80 + y * 2) as u8, 120]),
+        }
+    }))
+}
+
+fn denoised_psnr(noisy: &DynamicImage, denoise_type: DenoiseType, kernel_size: usize) -> f64 {
+    psnr(&denoise_image(noisy, denoise_type, kernel_size, 0.1, 50, 1e-4, BorderMode::Mirror), &scene())
+}
+
+#[test]
+fn block_matching_beats_non_local_means_at_sigma_25() {
+    let noisy = noisy(&scene(), 25.0);
+    let block_matching = denoised_psnr(&noisy, DenoiseType::BlockMatching, 3);
+    let non_local_means = denoised_psnr(&noisy, DenoiseType::NonLocalMeans, 3);
+    let unfiltered = psnr(&noisy, &scene());
+    assert!(non_local_means > unfiltered, "non-local means {non_local_means:.2} dB, noisy {unfiltered:.2} dB");
+    assert!(
+        block_matching > non_local_means,
+        "block matching {block_matching:.2} dB, non-local means {non_local_means:.2} dB"
+    );
+}
+
+#[test]
+fn adaptive_median_beats_fixed_medians_at_20_percent_impulses() {
+    let noisy = salt_and_pepper(&scene(), 0.2);
+    let adaptive = denoised_psnr(&noisy, DenoiseType::AdaptiveMedian, 7);
+    for kernel_size in [3, 5, 7] {
+        let fixed = denoised_psnr(&noisy, DenoiseType::MedianFilter, kernel_size);
+        assert!(adaptive > fixed + 1.0, "adaptive median {adaptive:.2} dB, {kernel_size}x{kernel_size} median {fixed:.2} dB");
+    }
+}
+
+// The soft gradients of the scene alone, no edges for the blurs to smear
+fn smooth() -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(48, 48, |x, y| Rgb([(60 + x * 3) as u8, (80 + y * 2) as u8, 120])))
+}
+
+// Standard deviation of the difference from `clean` in the luma and in the
+// chroma planes, in 8-bit units
+fn plane_noise(img: &DynamicImage, clean: &DynamicImage) -> (f64, f64) {
+    let (planes, clean) = (rgb_to_ycbcr(&img.to_rgb32f()), rgb_to_ycbcr(&clean.to_rgb32f()));
+    let deviation = |planes: &[(&Vec<f32>, &Vec<f32>)]| {
+        let differences: Vec<f64> = planes
+            .iter()
+            .flat_map(|(plane, clean)| plane.iter().zip(clean.iter()).map(|(&a, &b)| (a - b) as f64 * 255.0))
+            .collect();
+        let mean = differences.iter().sum::<f64>() / differences.len() as f64;
+        (differences.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / differences.len() as f64).sqrt()
+    };
+    (deviation(&[(&planes.y, &clean.y)]), deviation(&[(&planes.cb, &clean.cb), (&planes.cr, &clean.cr)]))
+}
+
+#[test]
+fn color_noise_reduction_keeps_the_grain() {
+    let noisy = noisy(&smooth(), 15.0);
+    let (noisy_luma, noisy_chroma) = plane_noise(&noisy, &smooth());
+    // Strong color noise reduction and a touch of luminance noise reduction,
+    // the usual camera settings
+    let strengths = PlaneStrengths { luma: 0.2, chroma: 1.0, chroma_kernel_size: None };
+    for denoise_type in [DenoiseType::MeanFilter, DenoiseType::GaussianFilter, DenoiseType::MedianFilter, DenoiseType::NonLocalMeans] {
+        let denoised = denoise_ycbcr_with_progress(&noisy, denoise_type, 5, 0.1, 50, 1e-4, strengths, BorderMode::Mirror, &Progress::new()).unwrap();
+        let (luma, chroma) = plane_noise(&denoised, &smooth());
+        assert!(chroma < 0.35 * noisy_chroma, "{denoise_type:?}: color noise {chroma:.2}, was {noisy_chroma:.2}");
+        assert!(luma > 0.7 * noisy_luma, "{denoise_type:?}: grain {luma:.2}, was {noisy_luma:.2}");
+    }
+
+    // A strength of 0 leaves the luma alone, up to rounding back to 8 bits
+    let color_only = denoise_ycbcr_with_progress(&noisy, DenoiseType::MeanFilter, 5, 0.1, 50, 1e-4, PlaneStrengths::CHROMA_ONLY, BorderMode::Mirror, &Progress::new()).unwrap();
+    let (luma, _) = plane_noise(&color_only, &smooth());
+    assert!((luma - noisy_luma).abs() < 0.3, "grain {luma:.2}, was {noisy_luma:.2}");
+}
@@ -0,0 +1,209 @@
+//! Processing an image in pieces, as blocks, tiles or streamed bands, must give
+//! what processing it whole does, and neutral settings must change nothing.
+
+mod common;
+
+use image::{DynamicImage, ImageBuffer};
+use image_denoising::algorithms::border::BorderMode;
+use image_denoising::algorithms::denoise::{denoise_image, DenoiseType};
+use image_denoising::algorithms::parallel::{merge_blocks_into_image, process_image_parallel, split_image_into_blocks, ImageBlock};
+use image_denoising::algorithms::pipeline::{Operation, Pipeline};
+use image_denoising::algorithms::progress::Progress;
+use image_denoising::algorithms::sample::{with_pixel_type, FilterPixel};
+use image_denoising::algorithms::sharpness::{sharpen_image, SharpenKernel};
+use image_denoising::algorithms::streaming::{RowSource, StreamedBands};
+
+use common::{assert_close, checkerboard, noisy, noisy_gradient, to_16_bit, Tolerance};
+
+fn fixtures() -> [DynamicImage; 4] {
+    let gray = checkerboard(5);
+    [noisy_gradient(), to_16_bit(&noisy_gradient()), to_16_bit(&gray), gray]
+}
+
+fn denoise_pipeline(denoise_type: DenoiseType, kernel_size: usize) -> Pipeline {
+    Pipeline(vec![Operation::Denoise {
+        denoise_type,
+        kernel_size,
+        tv_lambda: 0.1,
+        tv_iterations: 50,
+        tv_tolerance: 1e-4,
+        planes: None,
+        linear_light: false,
+        backend: Default::default(),
+        border: BorderMode::Mirror,
+        detail: 0.0,
+        detail_radius: 1.5,
+    }])
+}
+
+#[test]
+fn split_merge_round_trip() {
+    for img in fixtures() {
+        let merged = with_pixel_type!(&img, |P| {
+            let buffer = P::from_dynamic(&img);
+            let blocks = split_image_into_blocks(&buffer, 10, 4);
+            P::into_dynamic(merge_blocks_into_image(blocks, buffer.width(), buffer.height()))
+        });
+        assert_close("split_merge_round_trip", &merged, &img, Tolerance::EXACT);
+    }
+}
+
+// `pipeline` run block by block, the way the editor does for large images
+fn process_in_blocks(img: &DynamicImage, pipeline: &Pipeline, block_size: u32, overlap: u32) -> DynamicImage {
+    with_pixel_type!(img, |P| {
+        let result = process_image_parallel(&P::from_dynamic(img), block_size, overlap, None, &Progress::new(), |block| {
+            let block_img = P::into_dynamic(ImageBuffer::from_raw(block.width, block.height, block.data.clone()).unwrap());
+            ImageBlock { data: P::from_dynamic(&pipeline.apply(&block_img)).into_raw(), ..block.clone() }
+        });
+        P::into_dynamic(result)
+    })
+}
+
+#[test]
+fn blocks_match_whole_image() {
+    for denoise_type in [DenoiseType::MeanFilter, DenoiseType::MedianFilter, DenoiseType::BilateralFilter] {
+        let pipeline = denoise_pipeline(denoise_type, 3);
+        // The overlap left to blend in is at least the filters' reach
+        let overlap = 2 * pipeline.context_radius().max(1) + 2;
+        for img in fixtures() {
+            let whole = pipeline.apply(&img);
+            let blocks = process_in_blocks(&img, &pipeline, 12, overlap);
+            // Blending rounds once more
+            assert_close(&format!("{denoise_type:?} in blocks"), &blocks, &whole, Tolerance::within(1));
+        }
+    }
+}
+
+// A smooth ramp over the whole range, where a seam of one level between
+// blocks would show as a grid
+fn smooth_gradient() -> DynamicImage {
+    DynamicImage::ImageRgb16(ImageBuffer::from_fn(150, 110, |x, y| {
+        image::Rgb([(x * 430 + y * 20) as u16, (y * 590) as u16, (65_000 - x * 200 - y * 250) as u16])
+    }))
+}
+
+#[test]
+fn blocks_leave_no_grid_in_smooth_gradients() {
+    for img in [smooth_gradient(), DynamicImage::ImageRgb8(smooth_gradient().to_rgb8())] {
+        for (denoise_type, kernel_size) in [(DenoiseType::GaussianFilter, 7), (DenoiseType::MeanFilter, 5), (DenoiseType::BilateralFilter, 5)] {
+            let pipeline = denoise_pipeline(denoise_type, kernel_size);
+            // Blocks small next to the kernel, overlapping like the editor's
+            let overlap = 2 * pipeline.context_radius();
+            let whole = pipeline.apply(&img);
+            let blocks = process_in_blocks(&img, &pipeline, 4 * overlap, overlap);
+            assert_close(&format!("{denoise_type:?} in blocks on a smooth {:?} gradient", img.color()), &blocks, &whole, Tolerance::within(1));
+        }
+    }
+}
+
+// Hands out the rows of an image held in memory
+struct Rows {
+    img: DynamicImage,
+    next: u32,
+}
+
+impl RowSource for Rows {
+    fn width(&self) -> u32 {
+        self.img.width()
+    }
+
+    fn height(&self) -> u32 {
+        self.img.height()
+    }
+
+    fn read_rows(&mut self, rows: u32) -> Result<DynamicImage, String> {
+        let band = self.img.crop_imm(0, self.next, self.img.width(), rows);
+        self.next += rows;
+        Ok(band)
+    }
+}
+
+#[test]
+fn streamed_bands_match_whole_image() {
+    for denoise_type in [DenoiseType::MeanFilter, DenoiseType::MedianFilter, DenoiseType::AdaptiveMedian] {
+        let pipeline = denoise_pipeline(denoise_type, 5);
+        for img in fixtures() {
+            let whole = pipeline.apply(&img);
+            let source = Rows { img: img.clone(), next: 0 };
+            let bands: Vec<DynamicImage> = StreamedBands::new(source, 7, pipeline.context_radius(), |band| Ok(pipeline.apply(band)))
+                .collect::<Result<_, _>>()
+                .unwrap();
+
+            assert_eq!(bands.iter().map(DynamicImage::height).sum::<u32>(), img.height());
+            let mut top = 0;
+            for band in &bands {
+                let expected = whole.crop_imm(0, top, whole.width(), band.height());
+                assert_close(&format!("{denoise_type:?} streamed from row {top}"), band, &expected, Tolerance::EXACT);
+                top += band.height();
+            }
+        }
+    }
+}
+
+// Large enough to span several of non-local means' tiles
+fn large_noisy_gradient() -> DynamicImage {
+    let (width, height) = (160, 140);
+    let img = ImageBuffer::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 255 / (width - 1)) as u8, (y * 255 / (height - 1)) as u8, (255 - (x + y) * 127 / (width + height)) as u8])
+    });
+    noisy(&DynamicImage::ImageRgb8(img), 20.0)
+}
+
+#[test]
+fn tiled_non_local_means_matches_untiled() {
+    // Written once by the implementation that filtered the whole image in
+    // one go, never regenerated
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/reference/non_local_means_untiled.png");
+    let untiled = image::open(path).unwrap();
+    let tiled = denoise_image(&large_noisy_gradient(), DenoiseType::NonLocalMeans, 3, 0.1, 50, 1e-4, BorderMode::Mirror);
+    assert_close("tiled non-local means", &tiled, &untiled, Tolerance::within(1));
+}
+
+#[test]
+fn sharpen_nothing_is_identity() {
+    for img in fixtures() {
+        for kernel in SharpenKernel::ALL {
+            for border in BorderMode::ALL {
+                assert_close("sharpen by 0", &sharpen_image(&img, 0.0, kernel, border), &img, Tolerance::EXACT);
+            }
+        }
+    }
+}
+
+#[test]
+fn flat_image_survives_every_filter() {
+    let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(12, 12, image::Rgb([90, 140, 200])));
+    for denoise_type in [
+        DenoiseType::MeanFilter,
+        DenoiseType::GaussianFilter,
+        DenoiseType::MedianFilter,
+        DenoiseType::BilateralFilter,
+        DenoiseType::NonLocalMeans,
+        DenoiseType::TotalVariation,
+        DenoiseType::ChambolleTV,
+        DenoiseType::BlockMatching,
+        DenoiseType::AdaptiveMedian,
+    ] {
+        for border in BorderMode::ALL {
+            let denoised = denoise_image(&flat, denoise_type, 3, 0.1, 50, 1e-4, border);
+            assert_close(&format!("flat {denoise_type:?} {border:?}"), &denoised, &flat, Tolerance::within(1));
+        }
+    }
+}
+
+#[test]
+fn no_detail_is_plain_denoising() {
+    for denoise_type in [DenoiseType::MeanFilter, DenoiseType::MedianFilter, DenoiseType::BilateralFilter, DenoiseType::NonLocalMeans] {
+        for img in fixtures() {
+            let plain = denoise_image(&img, denoise_type, 3, 0.1, 50, 1e-4, BorderMode::Mirror);
+            for detail_radius in [0.5, 1.5, 6.0] {
+                let mut pipeline = denoise_pipeline(denoise_type, 3);
+                if let Operation::Denoise { detail, detail_radius: radius, .. } = &mut pipeline.0[0] {
+                    (*detail, *radius) = (0.0, detail_radius);
+                }
+                let name = format!("{denoise_type:?} with no detail over {detail_radius} px");
+                assert_close(&name, &pipeline.apply(&img), &plain, Tolerance::EXACT);
+            }
+        }
+    }
+}